@@ -0,0 +1,1393 @@
+//! Backups: passphrase-encrypted archives of the app data, and the scheduler
+//! that copies a snapshot or an archive to a folder the user picks.
+//!
+//! `export_backup` writes the app data directory (and, on request, the scraper
+//! windows' WebKit data stores) into one passphrase-encrypted file, so Freed
+//! can move to a new machine. Layout:
+//!
+//!   [0..4]    BACKUP_MAGIC
+//!   [4..8]    PBKDF2-HMAC-SHA256 iterations (u32, big endian)
+//!   [8..24]   salt
+//!   [24..]    chunks: ciphertext length (u32, big endian), then AES-256-GCM
+//!             ciphertext. The nonce is the chunk index (u64, big endian)
+//!             followed by 0u32, or 1u32 on the final chunk; the header is the
+//!             associated data of every chunk.
+//!
+//! The decrypted stream is a run of records. A file record is `F`, the path
+//! length (u16) and path, content segments (u32 length, bytes) ended by an
+//! empty one, then the SHA-256 of the content. The last record is `M` with a
+//! u32 length and the JSON `BackupManifest`, which lists every file again so
+//! the importer can tell nothing was dropped or reordered.
+
+use log::{error, info, warn};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::time::Duration;
+
+use crate::relay::{
+    load_or_create_payload_key, load_relay_credentials, restore_held_relay_doc, RelayDisconnect,
+    RelayEventSink, RelayEvents, RelayState,
+};
+use crate::snapshots::{list_snapshots_in, read_snapshot, snapshot_dir, SnapshotRetention};
+use crate::{
+    now_unix_ms, quiesce_sync_relay, write_file_atomically, RUNTIME_HEALTH_WRITER_LOCK_FILE,
+};
+
+const BACKUP_SCHEDULE_FILE: &str = "backup-schedule.json";
+/// Passphrase for scheduled archive backups; never included in an archive.
+pub(crate) const BACKUP_PASSPHRASE_FILE: &str = "backup-passphrase";
+
+const BACKUP_MAGIC: [u8; 4] = *b"FBA\x01";
+const BACKUP_HEADER_BYTES: usize = 24;
+const BACKUP_VERSION: u32 = 1;
+pub(crate) const BACKUP_PBKDF2_ITERATIONS: u32 = 600_000;
+/// Upper bound accepted on import, so a crafted header cannot stall the app.
+const BACKUP_MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+const BACKUP_CHUNK_BYTES: usize = 1024 * 1024;
+pub(crate) const BACKUP_MIN_PASSPHRASE_CHARS: usize = 8;
+/// Where an import is unpacked before it replaces anything.
+const BACKUP_STAGING_DIR: &str = ".backup-import";
+/// Archive path prefixes for each `BackupRoots` tree.
+const BACKUP_DATA_PREFIX: &str = "data";
+const BACKUP_SCRAPER_DATA_PREFIX: &str = "scraper-data";
+
+/// Directory trees a backup covers.
+pub(crate) struct BackupRoots {
+    /// App data: pairing token, sync key, snapshots, local AI models,
+    /// runtime health, the media vault, and the held relay document.
+    pub(crate) data_dir: PathBuf,
+    /// The scraper windows' WebKit data stores. `None` leaves them out.
+    pub(crate) scraper_data: Option<PathBuf>,
+}
+
+impl BackupRoots {
+    fn roots(&self) -> Vec<(&'static str, &Path)> {
+        let mut roots = vec![(BACKUP_DATA_PREFIX, self.data_dir.as_path())];
+        if let Some(scraper_data) = &self.scraper_data {
+            roots.push((BACKUP_SCRAPER_DATA_PREFIX, scraper_data.as_path()));
+        }
+        roots
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupManifestEntry {
+    /// `/`-separated, starting with a `BackupRoots` prefix.
+    path: String,
+    bytes: u64,
+    /// Lowercase hex SHA-256 of the content.
+    sha256: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupManifest {
+    version: u32,
+    created_at_ms: u64,
+    entries: Vec<BackupManifestEntry>,
+}
+
+/// What an export wrote or an import restored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BackupSummary {
+    pub(crate) files: usize,
+    pub(crate) bytes: u64,
+}
+
+fn backup_key(passphrase: &str, salt: &[u8], iterations: u32) -> ring::aead::LessSafeKey {
+    let mut secret = [0u8; 32];
+    ring::pbkdf2::derive(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        std::num::NonZeroU32::new(iterations).expect("backup iterations are never zero"),
+        salt,
+        passphrase.as_bytes(),
+        &mut secret,
+    );
+    let key = ring::aead::UnboundKey::new(&ring::aead::AES_256_GCM, &secret)
+        .expect("PBKDF2 output is one AES-256 key");
+    ring::aead::LessSafeKey::new(key)
+}
+
+fn backup_chunk_nonce(index: u64, last: bool) -> ring::aead::Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    ring::aead::Nonce::assume_unique_for_key(nonce)
+}
+
+pub(crate) fn sha256_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Encrypts everything written to it into `BACKUP_CHUNK_BYTES` chunks.
+struct BackupWriter<W: std::io::Write> {
+    out: W,
+    key: ring::aead::LessSafeKey,
+    header: [u8; BACKUP_HEADER_BYTES],
+    buffer: Vec<u8>,
+    index: u64,
+}
+
+impl<W: std::io::Write> BackupWriter<W> {
+    fn new(mut out: W, passphrase: &str, iterations: u32) -> std::io::Result<Self> {
+        let mut header = [0u8; BACKUP_HEADER_BYTES];
+        header[..4].copy_from_slice(&BACKUP_MAGIC);
+        header[4..8].copy_from_slice(&iterations.to_be_bytes());
+        rand::thread_rng().fill_bytes(&mut header[8..24]);
+        out.write_all(&header)?;
+        Ok(Self {
+            out,
+            key: backup_key(passphrase, &header[8..24], iterations),
+            header,
+            buffer: Vec::with_capacity(BACKUP_CHUNK_BYTES),
+            index: 0,
+        })
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> std::io::Result<()> {
+        let mut chunk = std::mem::take(&mut self.buffer);
+        self.key
+            .seal_in_place_append_tag(
+                backup_chunk_nonce(self.index, last),
+                ring::aead::Aad::from(&self.header),
+                &mut chunk,
+            )
+            .map_err(|_| std::io::Error::other("failed to seal backup chunk"))?;
+        self.out.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        self.out.write_all(&chunk)?;
+        self.index += 1;
+        self.buffer = Vec::with_capacity(BACKUP_CHUNK_BYTES);
+        Ok(())
+    }
+
+    /// Seals the final chunk. Without it the importer treats the archive as
+    /// truncated.
+    fn finish(mut self) -> std::io::Result<W> {
+        self.seal_chunk(true)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for BackupWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let taken = bytes.len().min(BACKUP_CHUNK_BYTES - self.buffer.len());
+        self.buffer.extend_from_slice(&bytes[..taken]);
+        if self.buffer.len() == BACKUP_CHUNK_BYTES {
+            self.seal_chunk(false)?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decrypts a `BackupWriter` stream, failing on a wrong passphrase, a
+/// damaged chunk, or a missing final chunk.
+struct BackupReader<R: std::io::Read> {
+    input: R,
+    key: ring::aead::LessSafeKey,
+    header: [u8; BACKUP_HEADER_BYTES],
+    chunk: Vec<u8>,
+    offset: usize,
+    index: u64,
+    finished: bool,
+}
+
+impl<R: std::io::Read> BackupReader<R> {
+    fn new(mut input: R, passphrase: &str) -> Result<Self, String> {
+        let mut header = [0u8; BACKUP_HEADER_BYTES];
+        input
+            .read_exact(&mut header)
+            .map_err(|_| "not a Freed backup".to_string())?;
+        if header[..4] != BACKUP_MAGIC {
+            return Err("not a Freed backup".to_string());
+        }
+        let iterations = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        if iterations == 0 || iterations > BACKUP_MAX_PBKDF2_ITERATIONS {
+            return Err("backup header is damaged".to_string());
+        }
+        Ok(Self {
+            input,
+            key: backup_key(passphrase, &header[8..24], iterations),
+            header,
+            chunk: Vec::new(),
+            offset: 0,
+            index: 0,
+            finished: false,
+        })
+    }
+
+    fn open_chunk(&mut self) -> std::io::Result<()> {
+        let damaged = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let mut len = [0u8; 4];
+        self.input
+            .read_exact(&mut len)
+            .map_err(|_| damaged("backup is truncated"))?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > BACKUP_CHUNK_BYTES + ring::aead::MAX_TAG_LEN {
+            return Err(damaged("backup chunk is too large"));
+        }
+        let mut chunk = vec![0u8; len];
+        self.input
+            .read_exact(&mut chunk)
+            .map_err(|_| damaged("backup is truncated"))?;
+        // A chunk opens under exactly one of the two nonces, which tells the
+        // final chunk apart without trusting anything unauthenticated.
+        let aad = ring::aead::Aad::from(self.header);
+        let mut last = false;
+        let mut opened = chunk.clone();
+        let opened_len =
+            match self
+                .key
+                .open_in_place(backup_chunk_nonce(self.index, false), aad, &mut opened)
+            {
+                Ok(plaintext) => plaintext.len(),
+                Err(_) => {
+                    last = true;
+                    opened = chunk;
+                    self.key
+                        .open_in_place(backup_chunk_nonce(self.index, true), aad, &mut opened)
+                        .map_err(|_| damaged("wrong passphrase, or the backup is damaged"))?
+                        .len()
+                }
+            };
+        opened.truncate(opened_len);
+        self.chunk = opened;
+        self.offset = 0;
+        self.index += 1;
+        self.finished = last;
+        Ok(())
+    }
+
+    /// Whether the final chunk was consumed and nothing follows it.
+    fn at_end(&mut self) -> bool {
+        let mut extra = [0u8; 1];
+        self.finished
+            && self.offset == self.chunk.len()
+            && matches!(self.input.read(&mut extra), Ok(0))
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for BackupReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset == self.chunk.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.open_chunk()?;
+        }
+        let taken = out.len().min(self.chunk.len() - self.offset);
+        out[..taken].copy_from_slice(&self.chunk[self.offset..self.offset + taken]);
+        self.offset += taken;
+        Ok(taken)
+    }
+}
+
+/// Whether a file under the data directory stays out of backups: partial
+/// writes, the runtime-health writer lock, an unfinished import, and the
+/// scheduled-backup passphrase the archive would otherwise carry.
+fn backup_skips_data_path(relative: &Path) -> bool {
+    let name = relative
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    relative.starts_with(BACKUP_STAGING_DIR)
+        || relative == Path::new(BACKUP_PASSPHRASE_FILE)
+        || name == RUNTIME_HEALTH_WRITER_LOCK_FILE
+        || name.ends_with(".tmp")
+}
+
+/// Every regular file under `root`, as `/`-separated relative paths, sorted.
+fn backup_files(root: &Path, skip: &dyn Fn(&Path) -> bool) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(format!("failed to read {}: {}", dir.display(), error)),
+        };
+        for entry in entries {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap();
+            let file_type = entry.file_type().map_err(|e| e.to_string())?;
+            if skip(relative) {
+                continue;
+            }
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() {
+                let Some(relative) = relative.to_str() else {
+                    warn!("[Backup] Skipping non-UTF-8 path {}", path.display());
+                    continue;
+                };
+                files.push(relative.replace(std::path::MAIN_SEPARATOR, "/"));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Writes an encrypted archive of `roots` to `out`, replacing it atomically.
+pub(crate) fn export_backup_in(
+    roots: &BackupRoots,
+    out: &Path,
+    passphrase: &str,
+    iterations: u32,
+) -> Result<BackupSummary, String> {
+    use std::io::{Read, Write};
+
+    if passphrase.chars().count() < BACKUP_MIN_PASSPHRASE_CHARS {
+        return Err(format!(
+            "passphrase must be at least {} characters",
+            BACKUP_MIN_PASSPHRASE_CHARS
+        ));
+    }
+    let mut tmp_path = out.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let file = std::fs::File::create(&tmp_path)
+        .map_err(|e| format!("failed to create {}: {}", tmp_path.display(), e))?;
+    let mut writer = BackupWriter::new(std::io::BufWriter::new(file), passphrase, iterations)
+        .map_err(|e| format!("failed to write backup: {}", e))?;
+
+    let mut manifest = BackupManifest {
+        version: BACKUP_VERSION,
+        created_at_ms: now_unix_ms(),
+        entries: Vec::new(),
+    };
+    let mut write_entries = || -> Result<(), String> {
+        let io_error = |e: std::io::Error| format!("failed to write backup: {}", e);
+        let mut buffer = vec![0u8; 64 * 1024];
+        for (prefix, root) in roots.roots() {
+            let out = out.to_path_buf();
+            let skip = |relative: &Path| {
+                root.join(relative) == out
+                    || root.join(relative) == tmp_path
+                    || (prefix == BACKUP_DATA_PREFIX && backup_skips_data_path(relative))
+            };
+            for relative in backup_files(root, &skip)? {
+                let path = format!("{}/{}", prefix, relative);
+                let mut file = match std::fs::File::open(root.join(&relative)) {
+                    Ok(file) => file,
+                    // Removed since it was listed, e.g. a pruned snapshot.
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(error) => return Err(format!("failed to read {}: {}", path, error)),
+                };
+                let path_len = u16::try_from(path.len())
+                    .map_err(|_| format!("path is too long to back up: {}", path))?;
+                writer.write_all(b"F").map_err(io_error)?;
+                writer
+                    .write_all(&path_len.to_be_bytes())
+                    .map_err(io_error)?;
+                writer.write_all(path.as_bytes()).map_err(io_error)?;
+                let mut digest = Sha256::new();
+                let mut bytes = 0u64;
+                loop {
+                    let read = file
+                        .read(&mut buffer)
+                        .map_err(|e| format!("failed to read {}: {}", path, e))?;
+                    writer
+                        .write_all(&(read as u32).to_be_bytes())
+                        .map_err(io_error)?;
+                    if read == 0 {
+                        break;
+                    }
+                    writer.write_all(&buffer[..read]).map_err(io_error)?;
+                    digest.update(&buffer[..read]);
+                    bytes += read as u64;
+                }
+                let digest = digest.finalize();
+                writer.write_all(&digest).map_err(io_error)?;
+                manifest.entries.push(BackupManifestEntry {
+                    path,
+                    bytes,
+                    sha256: sha256_hex(&digest),
+                });
+            }
+        }
+        let json = serde_json::to_vec(&manifest).map_err(|e| e.to_string())?;
+        writer.write_all(b"M").map_err(io_error)?;
+        writer
+            .write_all(&(json.len() as u32).to_be_bytes())
+            .map_err(io_error)?;
+        writer.write_all(&json).map_err(io_error)
+    };
+    let written = write_entries().and_then(|()| {
+        writer
+            .finish()
+            .and_then(|out| out.into_inner().map_err(|e| e.into_error()))
+            .and_then(|file| file.sync_all())
+            .map_err(|e| format!("failed to write backup: {}", e))
+    });
+    if let Err(error) = written {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(error);
+    }
+    std::fs::rename(&tmp_path, out)
+        .map_err(|e| format!("failed to replace {}: {}", out.display(), e))?;
+    Ok(BackupSummary {
+        files: manifest.entries.len(),
+        bytes: manifest.entries.iter().map(|entry| entry.bytes).sum(),
+    })
+}
+
+/// Splits an archive path into its root prefix and a relative path that
+/// cannot escape that root.
+fn backup_entry_path(path: &str) -> Result<(&str, PathBuf), String> {
+    let invalid = || format!("backup contains an invalid path: {}", path);
+    let (prefix, rest) = path.split_once('/').ok_or_else(invalid)?;
+    if prefix != BACKUP_DATA_PREFIX && prefix != BACKUP_SCRAPER_DATA_PREFIX {
+        return Err(invalid());
+    }
+    let mut relative = PathBuf::new();
+    for component in rest.split('/') {
+        if component.is_empty()
+            || component == "."
+            || component == ".."
+            || component.contains(['\\', ':', '\0'])
+        {
+            return Err(invalid());
+        }
+        relative.push(component);
+    }
+    Ok((prefix, relative))
+}
+
+/// Decrypts `archive` into `staging`, checking every file against its hash
+/// and the manifest. Nothing outside `staging` is touched; with no `staging`
+/// the archive is only verified.
+fn unpack_backup(
+    archive: &Path,
+    passphrase: &str,
+    staging: Option<&Path>,
+) -> Result<BackupManifest, String> {
+    use std::io::{Read, Write};
+
+    let file = std::fs::File::open(archive)
+        .map_err(|e| format!("failed to open {}: {}", archive.display(), e))?;
+    let mut reader = BackupReader::new(std::io::BufReader::new(file), passphrase)?;
+    let read_error = |e: std::io::Error| e.to_string();
+    let mut entries = Vec::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let mut kind = [0u8; 1];
+        reader.read_exact(&mut kind).map_err(read_error)?;
+        match kind[0] {
+            b'F' => {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len).map_err(read_error)?;
+                let mut path = vec![0u8; u16::from_be_bytes(len) as usize];
+                reader.read_exact(&mut path).map_err(read_error)?;
+                let path = String::from_utf8(path)
+                    .map_err(|_| "backup contains an invalid path".to_string())?;
+                let (prefix, relative) = backup_entry_path(&path)?;
+                let mut out: Box<dyn Write> = match staging {
+                    Some(staging) => {
+                        let target = staging.join(prefix).join(relative);
+                        std::fs::create_dir_all(target.parent().unwrap())
+                            .map_err(|e| e.to_string())?;
+                        Box::new(
+                            std::fs::File::create(&target)
+                                .map_err(|e| format!("failed to stage {}: {}", path, e))?,
+                        )
+                    }
+                    None => Box::new(std::io::sink()),
+                };
+                let mut digest = Sha256::new();
+                let mut bytes = 0u64;
+                loop {
+                    let mut len = [0u8; 4];
+                    reader.read_exact(&mut len).map_err(read_error)?;
+                    let len = u32::from_be_bytes(len) as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    if len > buffer.len() {
+                        return Err("backup segment is too large".to_string());
+                    }
+                    reader.read_exact(&mut buffer[..len]).map_err(read_error)?;
+                    out.write_all(&buffer[..len])
+                        .map_err(|e| format!("failed to stage {}: {}", path, e))?;
+                    digest.update(&buffer[..len]);
+                    bytes += len as u64;
+                }
+                let mut expected = [0u8; 32];
+                reader.read_exact(&mut expected).map_err(read_error)?;
+                if digest.finalize()[..] != expected {
+                    return Err(format!("{} failed its integrity check", path));
+                }
+                entries.push(BackupManifestEntry {
+                    path,
+                    bytes,
+                    sha256: sha256_hex(&expected),
+                });
+            }
+            b'M' => {
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len).map_err(read_error)?;
+                let mut json = vec![0u8; u32::from_be_bytes(len) as usize];
+                reader.read_exact(&mut json).map_err(read_error)?;
+                let manifest: BackupManifest =
+                    serde_json::from_slice(&json).map_err(|e| e.to_string())?;
+                if manifest.version != BACKUP_VERSION {
+                    return Err(format!(
+                        "backup version {} is not supported",
+                        manifest.version
+                    ));
+                }
+                if manifest.entries != entries || !reader.at_end() {
+                    return Err("backup does not match its manifest".to_string());
+                }
+                return Ok(manifest);
+            }
+            _ => return Err("backup is damaged".to_string()),
+        }
+    }
+}
+
+/// Items `apply_staged_backup` swapped into one root. The items they replaced
+/// wait in the root's `.pre-import` sibling until the whole import finishes or
+/// rolls back.
+struct StagedBackupSwap {
+    root: PathBuf,
+    pre_import: PathBuf,
+    /// Names moved in from the backup, and whether an original was set aside.
+    moved: Vec<(std::ffi::OsString, bool)>,
+}
+
+impl StagedBackupSwap {
+    /// Drops the replaced items once every root is in place.
+    fn finish(self) {
+        if let Err(error) = std::fs::remove_dir_all(&self.pre_import) {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                warn!(
+                    "[Backup] Failed to remove {}: {}",
+                    self.pre_import.display(),
+                    error
+                );
+            }
+        }
+    }
+
+    /// Removes the imported items and moves the replaced ones back. Anything
+    /// that cannot be restored stays in `.pre-import`.
+    fn roll_back(self) {
+        let mut restored = true;
+        for (name, set_aside) in self.moved.iter().rev() {
+            let target = self.root.join(name);
+            let result = remove_backup_target(&target).and_then(|()| {
+                if *set_aside {
+                    std::fs::rename(self.pre_import.join(name), &target)
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(error) = result {
+                error!("[Backup] Failed to restore {}: {}", target.display(), error);
+                restored = false;
+            }
+        }
+        if restored {
+            self.finish();
+        }
+    }
+}
+
+/// The sibling of `root` that holds the items an import replaces. Keeping it
+/// next to `root` keeps every rename on one filesystem.
+fn pre_import_dir(root: &Path) -> PathBuf {
+    let mut name = root.file_name().unwrap_or_default().to_os_string();
+    name.push(".pre-import");
+    root.with_file_name(name)
+}
+
+fn remove_backup_target(target: &Path) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(target),
+        Ok(_) => std::fs::remove_file(target),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Moves each top-level item staged under `staged` into `root`. Whatever was
+/// there is renamed into `.pre-import` first; if any move fails, the items
+/// already swapped are put back. Items the backup does not contain are left
+/// alone.
+fn apply_staged_backup(staged: &Path, root: &Path) -> Result<StagedBackupSwap, String> {
+    let mut swap = StagedBackupSwap {
+        root: root.to_path_buf(),
+        pre_import: pre_import_dir(root),
+        moved: Vec::new(),
+    };
+    let entries = match std::fs::read_dir(staged) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(swap),
+        Err(error) => return Err(error.to_string()),
+    };
+    std::fs::create_dir_all(root).map_err(|e| e.to_string())?;
+    // Left behind only if an earlier import was interrupted, in which case it
+    // holds the only copy of what that import replaced.
+    if swap.pre_import.exists() {
+        return Err(format!(
+            "an earlier import did not finish; the files it replaced are in {}",
+            swap.pre_import.display()
+        ));
+    }
+    std::fs::create_dir(&swap.pre_import)
+        .map_err(|e| format!("failed to create {}: {}", swap.pre_import.display(), e))?;
+
+    let mut move_in = |entry: std::io::Result<std::fs::DirEntry>| -> Result<(), String> {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name();
+        let target = root.join(&name);
+        let set_aside = match std::fs::rename(&target, swap.pre_import.join(&name)) {
+            Ok(()) => true,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => false,
+            Err(error) => return Err(format!("failed to replace {}: {}", target.display(), error)),
+        };
+        swap.moved.push((name, set_aside));
+        std::fs::rename(entry.path(), &target)
+            .map_err(|e| format!("failed to restore {}: {}", target.display(), e))
+    };
+    if let Err(error) = entries.into_iter().try_for_each(&mut move_in) {
+        swap.roll_back();
+        return Err(error);
+    }
+    Ok(swap)
+}
+
+/// Swaps the staged items into every root, or into none: when one root fails,
+/// the roots already swapped are rolled back.
+fn apply_staged_backups(staging: &Path, roots: &BackupRoots) -> Result<(), String> {
+    let mut swaps = Vec::new();
+    for (prefix, root) in roots.roots() {
+        match apply_staged_backup(&staging.join(prefix), root) {
+            Ok(swap) => swaps.push(swap),
+            Err(error) => {
+                swaps
+                    .into_iter()
+                    .rev()
+                    .for_each(StagedBackupSwap::roll_back);
+                return Err(error);
+            }
+        }
+    }
+    swaps.into_iter().for_each(StagedBackupSwap::finish);
+    Ok(())
+}
+
+/// Restores `archive` over `roots`. The archive is fully unpacked and checked
+/// before the relay is quiesced the way a factory reset quiesces it; then the
+/// files are swapped in and the relay reloads its credentials and held
+/// document from them. Document updates stay paused until the renderer has
+/// reloaded and calls `resume_sync_relay_after_factory_reset`.
+pub(crate) async fn import_backup_in(
+    roots: &BackupRoots,
+    archive: &Path,
+    passphrase: &str,
+    state: &RelayState,
+) -> Result<BackupSummary, String> {
+    let staging = roots.data_dir.join(BACKUP_STAGING_DIR);
+    let _ = std::fs::remove_dir_all(&staging);
+    let manifest = {
+        let (archive, passphrase, staging) = (
+            archive.to_path_buf(),
+            passphrase.to_string(),
+            staging.clone(),
+        );
+        tokio::task::spawn_blocking(move || unpack_backup(&archive, &passphrase, Some(&staging)))
+            .await
+            .map_err(|e| e.to_string())?
+    };
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(error);
+        }
+    };
+
+    let applied = {
+        let _epoch = state.epoch_gate.write().await;
+        let generation = quiesce_sync_relay(state).await;
+        let applied = apply_staged_backups(&staging, roots);
+        load_relay_credentials(state, &roots.data_dir);
+        match load_or_create_payload_key(&roots.data_dir) {
+            Ok(key) => *state.payload_key.write().unwrap() = Some(Arc::new(key)),
+            Err(error) => error!("[Backup] Sync key unavailable after import: {}", error),
+        }
+        state
+            .snapshot_retention_tx
+            .send_replace(SnapshotRetention::load(&roots.data_dir));
+        state
+            .backup_schedule_tx
+            .send_replace(BackupSchedule::load(&roots.data_dir));
+        restore_held_relay_doc(state, &roots.data_dir).await;
+        let _ = state
+            .disconnect_tx
+            .send(RelayDisconnect::FactoryReset(generation));
+        applied
+    };
+    let _ = std::fs::remove_dir_all(&staging);
+    applied?;
+    Ok(BackupSummary {
+        files: manifest.entries.len(),
+        bytes: manifest.entries.iter().map(|entry| entry.bytes).sum(),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Scheduled backups
+// ---------------------------------------------------------------------------
+//
+// Snapshots share a disk with the app. The scheduler copies the newest
+// snapshot, or a full backup archive, to a folder the user picks (an external
+// drive or a synced folder) every `interval_minutes`. Each copy is read back
+// and re-hashed before it counts, and the folder keeps its own `keep` newest
+// copies. Outcomes are `backup_completed` / `backup_failed` health events.
+
+const SCHEDULED_BACKUP_PREFIX: &str = "freed-backup-";
+const SCHEDULED_BACKUP_ARCHIVE_EXTENSION: &str = "freedbackup";
+/// A failed run is retried after this, or the interval if that is shorter.
+const SCHEDULED_BACKUP_RETRY_DELAY: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ScheduledBackupKind {
+    /// The newest local snapshot, as a plain Automerge document.
+    #[default]
+    Snapshot,
+    /// An encrypted `export_backup` archive of the app data.
+    Archive,
+}
+
+impl ScheduledBackupKind {
+    fn extension(self) -> &'static str {
+        match self {
+            ScheduledBackupKind::Snapshot => "automerge",
+            ScheduledBackupKind::Archive => SCHEDULED_BACKUP_ARCHIVE_EXTENSION,
+        }
+    }
+}
+
+/// Where and how often scheduled backups run. Every field is optional in the
+/// file.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct BackupSchedule {
+    enabled: bool,
+    pub(crate) directory: Option<PathBuf>,
+    interval_minutes: u32,
+    pub(crate) kind: ScheduledBackupKind,
+    /// Copies kept in `directory`, newest first.
+    keep: usize,
+}
+
+impl Default for BackupSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: None,
+            interval_minutes: 24 * 60,
+            kind: ScheduledBackupKind::Snapshot,
+            keep: 14,
+        }
+    }
+}
+
+impl BackupSchedule {
+    pub(crate) fn load(data_dir: &Path) -> Self {
+        let Ok(raw) = std::fs::read(data_dir.join(BACKUP_SCHEDULE_FILE)) else {
+            return Self::default();
+        };
+        match serde_json::from_slice::<Self>(&raw)
+            .map_err(|e| e.to_string())
+            .and_then(|schedule| schedule.validate().map(|()| schedule))
+        {
+            Ok(schedule) => schedule,
+            Err(error) => {
+                warn!("[Backup] Ignoring unusable backup schedule: {}", error);
+                Self::default()
+            }
+        }
+    }
+
+    pub(crate) fn persist(&self, data_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_file_atomically(&data_dir.join(BACKUP_SCHEDULE_FILE), &json)
+            .map_err(|e| format!("failed to persist backup schedule: {}", e))
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.directory.is_none() {
+            return Err("choose a backup folder".to_string());
+        }
+        if self
+            .directory
+            .as_ref()
+            .is_some_and(|directory| !directory.is_absolute())
+        {
+            return Err("backup folder must be an absolute path".to_string());
+        }
+        if self.interval_minutes == 0 {
+            return Err("backup interval must be at least 1 minute".to_string());
+        }
+        if self.keep == 0 {
+            return Err("keep at least one backup".to_string());
+        }
+        Ok(())
+    }
+
+    /// The folder to back up into, when backups are on.
+    fn active_directory(&self) -> Option<&Path> {
+        self.directory.as_deref().filter(|_| self.enabled)
+    }
+}
+
+/// A scheduled backup copy, named `freed-backup-{ts}.{ext}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ScheduledBackupFile {
+    name: String,
+    timestamp: u64,
+}
+
+fn list_scheduled_backups(directory: &Path) -> Vec<ScheduledBackupFile> {
+    let mut backups: Vec<ScheduledBackupFile> = std::fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let (ts, extension) = name
+                .strip_prefix(SCHEDULED_BACKUP_PREFIX)?
+                .split_once('.')?;
+            if extension != "automerge" && extension != SCHEDULED_BACKUP_ARCHIVE_EXTENSION {
+                return None;
+            }
+            let timestamp = ts.parse().ok()?;
+            Some(ScheduledBackupFile { name, timestamp })
+        })
+        .collect();
+    backups.sort_unstable_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.name.cmp(&a.name)));
+    backups
+}
+
+/// Deletes all but the `keep` newest copies. Other files in the folder are
+/// never touched. Returns how many were removed.
+fn prune_scheduled_backups(directory: &Path, keep: usize) -> usize {
+    let mut removed = 0;
+    for backup in list_scheduled_backups(directory).into_iter().skip(keep) {
+        match std::fs::remove_file(directory.join(&backup.name)) {
+            Ok(()) => removed += 1,
+            Err(error) => warn!("[Backup] Failed to remove {}: {}", backup.name, error),
+        }
+    }
+    removed
+}
+
+/// A verified copy written by `run_scheduled_backup`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScheduledBackup {
+    file: String,
+    bytes: u64,
+    /// Lowercase hex SHA-256 of the copy as read back from the folder.
+    sha256: String,
+    /// Older copies pruned afterwards.
+    removed: usize,
+}
+
+fn hash_backup_copy(path: &Path) -> Result<(String, u64), String> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("failed to read back {}: {}", path.display(), e))?;
+    let mut digest = Sha256::new();
+    let mut bytes = 0u64;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("failed to read back {}: {}", path.display(), e))?;
+        if read == 0 {
+            return Ok((sha256_hex(&digest.finalize()), bytes));
+        }
+        digest.update(&buffer[..read]);
+        bytes += read as u64;
+    }
+}
+
+/// Writes one backup copy into the schedule's folder and verifies it: a
+/// snapshot copy is re-hashed against the stored snapshot, and an archive is
+/// decrypted and checked against its manifest. A copy that fails is deleted.
+/// Returns `None` when there is nothing to copy yet.
+fn run_scheduled_backup(
+    data_dir: &Path,
+    schedule: &BackupSchedule,
+    now_secs: u64,
+    iterations: u32,
+) -> Result<Option<ScheduledBackup>, String> {
+    let directory = schedule
+        .directory
+        .as_deref()
+        .ok_or_else(|| "no backup folder is configured".to_string())?;
+    std::fs::create_dir_all(directory).map_err(|e| {
+        format!(
+            "backup folder {} is unavailable: {}",
+            directory.display(),
+            e
+        )
+    })?;
+    let file = format!(
+        "{}{}.{}",
+        SCHEDULED_BACKUP_PREFIX,
+        now_secs,
+        schedule.kind.extension()
+    );
+    let path = directory.join(&file);
+    let verified = match schedule.kind {
+        ScheduledBackupKind::Snapshot => {
+            let snapshot_dir = snapshot_dir(data_dir);
+            let Some(newest) = list_snapshots_in(&snapshot_dir).into_iter().next() else {
+                return Ok(None);
+            };
+            let doc = read_snapshot(&snapshot_dir, &newest.name)?;
+            let expected = sha256_hex(&Sha256::digest(&doc));
+            write_file_atomically(&path, &doc)?;
+            hash_backup_copy(&path).and_then(|(sha256, bytes)| match sha256 == expected {
+                true => Ok((sha256, bytes)),
+                false => Err(format!(
+                    "{} did not match {} when read back",
+                    file, newest.name
+                )),
+            })
+        }
+        ScheduledBackupKind::Archive => {
+            let passphrase = std::fs::read_to_string(data_dir.join(BACKUP_PASSPHRASE_FILE))
+                .map_err(|_| "archive backups need a passphrase".to_string())?;
+            let roots = BackupRoots {
+                data_dir: data_dir.to_path_buf(),
+                scraper_data: None,
+            };
+            export_backup_in(&roots, &path, &passphrase, iterations).and_then(|summary| {
+                let manifest = unpack_backup(&path, &passphrase, None)?;
+                if manifest.entries.len() != summary.files {
+                    return Err(format!("{} did not match its export when read back", file));
+                }
+                hash_backup_copy(&path)
+            })
+        }
+    };
+    let (sha256, bytes) = match verified {
+        Ok(verified) => verified,
+        Err(error) => {
+            let _ = std::fs::remove_file(&path);
+            return Err(error);
+        }
+    };
+    let removed = prune_scheduled_backups(directory, schedule.keep);
+    Ok(Some(ScheduledBackup {
+        file,
+        bytes,
+        sha256,
+        removed,
+    }))
+}
+
+/// Runs one scheduled backup off the async runtime and records its outcome.
+pub(crate) async fn run_scheduled_backup_and_report(
+    data_dir: PathBuf,
+    schedule: BackupSchedule,
+    events: &dyn RelayEventSink,
+) -> Result<Option<ScheduledBackup>, String> {
+    let kind = schedule.kind;
+    let started = Instant::now();
+    let result = tokio::task::spawn_blocking(move || {
+        run_scheduled_backup(
+            &data_dir,
+            &schedule,
+            now_unix_ms() / 1000,
+            BACKUP_PBKDF2_ITERATIONS,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result);
+    match &result {
+        Ok(Some(backup)) => {
+            info!(
+                "[Backup] Wrote {} ({} bytes, {} older removed)",
+                backup.file, backup.bytes, backup.removed
+            );
+            events.record_health(serde_json::json!({
+                "event": "backup_completed",
+                "kind": kind,
+                "file": backup.file,
+                "bytes": backup.bytes,
+                "sha256": backup.sha256,
+                "removed": backup.removed,
+                "durationMs": started.elapsed().as_millis() as u64,
+            }));
+        }
+        Ok(None) => info!("[Backup] No snapshot to back up yet"),
+        Err(error) => {
+            warn!("[Backup] Scheduled backup failed: {}", error);
+            events.record_health(serde_json::json!({
+                "event": "backup_failed",
+                "kind": kind,
+                "error": error,
+            }));
+        }
+    }
+    result
+}
+
+/// Runs scheduled backups for as long as the schedule sender lives. A backup
+/// is due `interval_minutes` after the newest copy in the folder, so restarts
+/// neither skip nor repeat one.
+pub(crate) async fn run_backup_scheduler(
+    data_dir: PathBuf,
+    mut schedule_rx: tokio::sync::watch::Receiver<BackupSchedule>,
+    events: RelayEvents,
+) {
+    let mut retry_after_ms: Option<u64> = None;
+    loop {
+        let schedule = schedule_rx.borrow_and_update().clone();
+        let due_in = schedule.active_directory().map(|directory| {
+            let interval_ms = u64::from(schedule.interval_minutes) * 60_000;
+            let mut due_ms = list_scheduled_backups(directory)
+                .first()
+                .map_or(0, |newest| newest.timestamp * 1000 + interval_ms);
+            if let Some(retry_after_ms) = retry_after_ms {
+                due_ms = due_ms.max(retry_after_ms);
+            }
+            Duration::from_millis(due_ms.saturating_sub(now_unix_ms()))
+        });
+        let wait = async {
+            match due_in {
+                Some(due_in) => tokio::time::sleep(due_in).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            changed = schedule_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                retry_after_ms = None;
+                continue;
+            }
+            () = wait => {
+                // A manual run may have moved the due time; look again.
+                if due_in.is_some_and(|due_in| !due_in.is_zero()) {
+                    continue;
+                }
+            }
+        }
+        let retry_delay = SCHEDULED_BACKUP_RETRY_DELAY.min(Duration::from_secs(
+            u64::from(schedule.interval_minutes) * 60,
+        ));
+        // Nothing to copy yet is retried like a failure, not in a tight loop.
+        retry_after_ms =
+            match run_scheduled_backup_and_report(data_dir.clone(), schedule, &*events).await {
+                Ok(Some(_)) => None,
+                _ => Some(now_unix_ms() + retry_delay.as_millis() as u64),
+            };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::{
+        generate_token,
+        tests::{
+            automerge_doc_bytes, automerge_like_bytes, snapshot_test_relay_state,
+            RecordedRelayEvents,
+        },
+        write_private_file, write_relay_doc,
+    };
+    use crate::snapshots::store_snapshot;
+    use crate::STARTUP_RECOVERY_STATE_FILE;
+
+    #[tokio::test]
+    async fn backup_round_trips_app_data_and_reloads_the_relay() {
+        let source = tempfile::tempdir().unwrap();
+        let scraper = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let target_scraper = tempfile::tempdir().unwrap();
+        let src = source.path();
+
+        let source_token = generate_token();
+        std::fs::write(src.join("pairing-token"), &source_token).unwrap();
+        let key = load_or_create_payload_key(src).unwrap();
+        let sealed = key
+            .seal(&automerge_doc_bytes("title", "backed up"))
+            .unwrap();
+        write_relay_doc(src, &sealed, &[]).unwrap();
+        let model: Vec<u8> = (0..3 * BACKUP_CHUNK_BYTES + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::create_dir_all(src.join("local-ai-models")).unwrap();
+        std::fs::write(src.join("local-ai-models").join("model.bin"), &model).unwrap();
+        std::fs::write(src.join(RUNTIME_HEALTH_WRITER_LOCK_FILE), "").unwrap();
+        std::fs::write(src.join("relay-network.json.tmp"), "partial").unwrap();
+        std::fs::create_dir_all(src.join(BACKUP_STAGING_DIR)).unwrap();
+        std::fs::write(src.join(BACKUP_STAGING_DIR).join("stale"), "x").unwrap();
+        std::fs::create_dir_all(scraper.path().join("store")).unwrap();
+        std::fs::write(scraper.path().join("store").join("Cookies"), "session").unwrap();
+
+        let mut roots = BackupRoots {
+            data_dir: src.to_path_buf(),
+            scraper_data: None,
+        };
+        let archive = src.join("freed.backup");
+        assert!(export_backup_in(&roots, &archive, "short", 1_000).is_err());
+        let summary = export_backup_in(&roots, &archive, "correct horse", 1_000).unwrap();
+        assert_eq!(summary.files, 4);
+        assert!(summary.bytes > model.len() as u64);
+        let bytes = std::fs::read(&archive).unwrap();
+        roots.scraper_data = Some(scraper.path().to_path_buf());
+        let scraper_summary = export_backup_in(&roots, &archive, "correct horse", 1_000).unwrap();
+        assert_eq!(scraper_summary.files, 5);
+
+        let dst = target.path().join("data");
+        std::fs::create_dir_all(dst.join("local-ai-models")).unwrap();
+        std::fs::write(dst.join("local-ai-models").join("old.bin"), "old").unwrap();
+        std::fs::write(dst.join("pairing-token"), "target-token").unwrap();
+        std::fs::write(dst.join(STARTUP_RECOVERY_STATE_FILE), "{}").unwrap();
+        let target_roots = BackupRoots {
+            data_dir: dst.clone(),
+            scraper_data: Some(target_scraper.path().to_path_buf()),
+        };
+        let held = automerge_doc_bytes("title", "current");
+        let state = snapshot_test_relay_state(Some(held.clone()));
+        let mut disconnect_rx = state.disconnect_tx.subscribe();
+
+        let damaged = target.path().join("damaged.backup");
+        let mut flipped = bytes.clone();
+        flipped[BACKUP_HEADER_BYTES + 100] ^= 1;
+        let truncated = bytes[..bytes.len() - 1].to_vec();
+        for (archive_bytes, passphrase) in [
+            (&bytes, "wrong horse"),
+            (&flipped, "correct horse"),
+            (&truncated, "correct horse"),
+        ] {
+            std::fs::write(&damaged, archive_bytes).unwrap();
+            assert!(
+                import_backup_in(&target_roots, &damaged, passphrase, &state)
+                    .await
+                    .is_err()
+            );
+            assert_eq!(
+                std::fs::read_to_string(dst.join("pairing-token")).unwrap(),
+                "target-token"
+            );
+            assert!(dst.join("local-ai-models").join("old.bin").exists());
+            assert!(!dst.join(BACKUP_STAGING_DIR).exists());
+            assert_eq!(state.current_doc.read().await.as_deref(), Some(&held[..]));
+            assert!(state
+                .accepting_doc_updates
+                .load(std::sync::atomic::Ordering::SeqCst));
+        }
+
+        let imported = import_backup_in(&target_roots, &archive, "correct horse", &state)
+            .await
+            .unwrap();
+        assert_eq!(imported, scraper_summary);
+        assert_eq!(
+            std::fs::read_to_string(dst.join("pairing-token")).unwrap(),
+            source_token
+        );
+        assert_eq!(state.pairing_token.read().unwrap().as_str(), source_token);
+        assert_eq!(
+            std::fs::read(dst.join("local-ai-models").join("model.bin")).unwrap(),
+            model
+        );
+        assert!(!dst.join("local-ai-models").join("old.bin").exists());
+        assert!(dst.join(STARTUP_RECOVERY_STATE_FILE).exists());
+        assert!(!dst.join(RUNTIME_HEALTH_WRITER_LOCK_FILE).exists());
+        assert!(!dst.join("relay-network.json.tmp").exists());
+        assert!(!dst.join("freed.backup").exists());
+        assert!(!dst.join(BACKUP_STAGING_DIR).exists());
+        assert!(!pre_import_dir(&dst).exists());
+        assert!(!pre_import_dir(target_scraper.path()).exists());
+        assert_eq!(
+            std::fs::read_to_string(target_scraper.path().join("store").join("Cookies")).unwrap(),
+            "session"
+        );
+        assert_eq!(
+            state.payload_key.read().unwrap().as_ref().unwrap().key_id,
+            key.key_id
+        );
+        assert_eq!(state.current_doc.read().await.as_deref(), Some(&sealed[..]));
+        assert!(!state
+            .accepting_doc_updates
+            .load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(
+            disconnect_rx.recv().await.unwrap(),
+            RelayDisconnect::FactoryReset(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn backup_import_rolls_every_root_back_when_one_fails() {
+        let source = tempfile::tempdir().unwrap();
+        let scraper = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let src = source.path();
+        std::fs::write(src.join("pairing-token"), generate_token()).unwrap();
+        std::fs::create_dir_all(src.join("local-ai-models")).unwrap();
+        std::fs::write(src.join("local-ai-models").join("model.bin"), "new").unwrap();
+        std::fs::create_dir_all(scraper.path().join("store")).unwrap();
+        std::fs::write(scraper.path().join("store").join("Cookies"), "session").unwrap();
+        let archive = target.path().join("freed.backup");
+        export_backup_in(
+            &BackupRoots {
+                data_dir: src.to_path_buf(),
+                scraper_data: Some(scraper.path().to_path_buf()),
+            },
+            &archive,
+            "correct horse",
+            1_000,
+        )
+        .unwrap();
+
+        let dst = target.path().join("data");
+        std::fs::create_dir_all(dst.join("local-ai-models")).unwrap();
+        std::fs::write(dst.join("local-ai-models").join("old.bin"), "old").unwrap();
+        let target_token = generate_token();
+        std::fs::write(dst.join("pairing-token"), &target_token).unwrap();
+        // The data root swaps in first; the scraper root cannot be created
+        // because a file sits where its directory should be.
+        let blocked = target.path().join("scraper");
+        std::fs::write(&blocked, "not a directory").unwrap();
+        let roots = BackupRoots {
+            data_dir: dst.clone(),
+            scraper_data: Some(blocked.clone()),
+        };
+        let state = snapshot_test_relay_state(None);
+
+        assert!(import_backup_in(&roots, &archive, "correct horse", &state)
+            .await
+            .is_err());
+        assert_eq!(
+            std::fs::read_to_string(dst.join("pairing-token")).unwrap(),
+            target_token
+        );
+        assert_eq!(
+            std::fs::read_to_string(dst.join("local-ai-models").join("old.bin")).unwrap(),
+            "old"
+        );
+        assert!(!dst.join("local-ai-models").join("model.bin").exists());
+        assert_eq!(*state.pairing_token.read().unwrap(), target_token);
+        assert!(!pre_import_dir(&dst).exists());
+        assert!(!dst.join(BACKUP_STAGING_DIR).exists());
+
+        // An import interrupted mid-swap leaves `.pre-import` behind; the
+        // next one refuses to overwrite it.
+        std::fs::remove_file(&blocked).unwrap();
+        std::fs::create_dir_all(pre_import_dir(&dst)).unwrap();
+        assert!(import_backup_in(&roots, &archive, "correct horse", &state)
+            .await
+            .is_err());
+        assert!(dst.join("local-ai-models").join("old.bin").exists());
+        std::fs::remove_dir(pre_import_dir(&dst)).unwrap();
+        import_backup_in(&roots, &archive, "correct horse", &state)
+            .await
+            .unwrap();
+        assert!(dst.join("local-ai-models").join("model.bin").exists());
+        assert!(!pre_import_dir(&dst).exists());
+        assert!(!pre_import_dir(&blocked).exists());
+    }
+
+    #[tokio::test]
+    async fn scheduled_backups_are_verified_pruned_and_reported() {
+        let data = tempfile::tempdir().unwrap();
+        let folder = tempfile::tempdir().unwrap();
+        let data_dir = data.path();
+        let mut schedule = BackupSchedule {
+            enabled: true,
+            directory: Some(folder.path().join("Freed")),
+            keep: 2,
+            ..BackupSchedule::default()
+        };
+        let backups = schedule.directory.clone().unwrap();
+        assert_eq!(
+            run_scheduled_backup(data_dir, &schedule, 100, 1_000),
+            Ok(None)
+        );
+
+        store_snapshot(&snapshot_dir(data_dir), 90, &automerge_like_bytes(1)).unwrap();
+        let newest = automerge_like_bytes(2);
+        store_snapshot(&snapshot_dir(data_dir), 95, &newest).unwrap();
+        std::fs::write(backups.join("notes.txt"), "keep me").unwrap();
+        for now in [100, 200, 300] {
+            let backup = run_scheduled_backup(data_dir, &schedule, now, 1_000)
+                .unwrap()
+                .unwrap();
+            assert_eq!(backup.file, format!("freed-backup-{now}.automerge"));
+            assert_eq!(backup.sha256, sha256_hex(&Sha256::digest(&newest)));
+            assert_eq!(backup.removed, usize::from(now == 300));
+        }
+        assert_eq!(
+            std::fs::read(backups.join("freed-backup-300.automerge")).unwrap(),
+            newest
+        );
+        assert_eq!(
+            list_scheduled_backups(&backups)
+                .iter()
+                .map(|backup| backup.timestamp)
+                .collect::<Vec<_>>(),
+            [300, 200]
+        );
+        assert!(backups.join("notes.txt").exists());
+
+        schedule.kind = ScheduledBackupKind::Archive;
+        assert!(run_scheduled_backup(data_dir, &schedule, 400, 1_000).is_err());
+        assert!(!backups.join("freed-backup-400.freedbackup").exists());
+        write_private_file(&data_dir.join(BACKUP_PASSPHRASE_FILE), b"correct horse").unwrap();
+        let archive = run_scheduled_backup(data_dir, &schedule, 500, 1_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(archive.file, "freed-backup-500.freedbackup");
+        let manifest = unpack_backup(&backups.join(&archive.file), "correct horse", None).unwrap();
+        assert!(manifest
+            .entries
+            .iter()
+            .all(|entry| entry.path != format!("data/{BACKUP_PASSPHRASE_FILE}")));
+        assert!(manifest
+            .entries
+            .iter()
+            .any(|entry| entry.path.starts_with("data/snapshots/")));
+
+        let events = RecordedRelayEvents::default();
+        schedule.kind = ScheduledBackupKind::Snapshot;
+        run_scheduled_backup_and_report(data_dir.to_path_buf(), schedule.clone(), &events)
+            .await
+            .unwrap();
+        std::fs::write(folder.path().join("blocked"), "").unwrap();
+        schedule.directory = Some(folder.path().join("blocked").join("Freed"));
+        assert!(
+            run_scheduled_backup_and_report(data_dir.to_path_buf(), schedule, &events)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            events.health_events(),
+            ["backup_completed", "backup_failed"]
+        );
+    }
+}
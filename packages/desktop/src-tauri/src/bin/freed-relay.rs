@@ -0,0 +1,54 @@
+//! Headless Freed sync relay.
+//!
+//! Runs the same relay the desktop app embeds, without a webview, so a home
+//! server or NAS can hold and forward sealed documents between devices.
+//!
+//! Usage: freed-relay --data-dir <path> [--port <port>]
+
+use std::path::PathBuf;
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{:<5} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn usage() -> ! {
+    eprintln!("Usage: freed-relay --data-dir <path> [--port <port>]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut data_dir = None;
+    let mut port = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--data-dir" => data_dir = args.next().map(PathBuf::from),
+            "--port" => match args.next().and_then(|value| value.parse::<u16>().ok()) {
+                Some(value) => port = Some(value),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    let Some(data_dir) = data_dir else { usage() };
+
+    let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(log::LevelFilter::Info));
+    if let Err(error) = freed_desktop_lib::run_headless_relay(data_dir, port) {
+        log::error!("[Sync] {}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,55 @@
+//! Minimal Freed rendezvous server.
+//!
+//! Desktop relays keep an outbound tunnel open to it, and phones away from
+//! the LAN reach their relay through it. It serves plain `ws://`; put it
+//! behind a TLS-terminating proxy before handing its URL to phones.
+//!
+//! Usage: freed-rendezvous [--listen <addr:port>]
+
+use std::net::SocketAddr;
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{:<5} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn usage() -> ! {
+    eprintln!("Usage: freed-rendezvous [--listen <addr:port>]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut listen = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => match args
+                .next()
+                .and_then(|value| value.parse::<SocketAddr>().ok())
+            {
+                Some(value) => listen = Some(value),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
+    let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(log::LevelFilter::Info));
+    if let Err(error) = freed_desktop_lib::run_rendezvous_server(listen) {
+        log::error!("[Rendezvous] {}", error);
+        std::process::exit(1);
+    }
+}
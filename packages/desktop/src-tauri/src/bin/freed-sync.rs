@@ -0,0 +1,68 @@
+//! Command-line Freed sync client.
+//!
+//! Pulls, pushes, or watches the relay's document from the pairing URL that
+//! the desktop app's Mobile Sync tab shows, so backups and imports can be
+//! scripted.
+//!
+//! Usage: freed-sync pull <pairing-url> <out-file>
+//!        freed-sync push <pairing-url> <file>
+//!        freed-sync watch <pairing-url> <snapshot-dir> [data-dir]
+//!
+//! `watch` prunes with the `snapshot-retention.json` in `data-dir`, or in
+//! `snapshot-dir` when no data dir is given.
+
+use freed_desktop_lib::SyncCliCommand;
+use std::path::PathBuf;
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{:<5} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn usage() -> ! {
+    eprintln!("Usage: freed-sync pull <pairing-url> <out-file>");
+    eprintln!("       freed-sync push <pairing-url> <file>");
+    eprintln!("       freed-sync watch <pairing-url> <snapshot-dir> [data-dir]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (action, url, path, data_dir) = match args.as_slice() {
+        [action, url, path] => (action, url, path, None),
+        [action, url, path, data_dir] if action == "watch" => {
+            (action, url, path, Some(PathBuf::from(data_dir)))
+        }
+        _ => usage(),
+    };
+    let (url, path) = (url.clone(), PathBuf::from(path));
+    let command = match action.as_str() {
+        "pull" => SyncCliCommand::Pull { url, out: path },
+        "push" => SyncCliCommand::Push { url, file: path },
+        "watch" => SyncCliCommand::Watch {
+            url,
+            dir: path,
+            data_dir,
+        },
+        _ => usage(),
+    };
+
+    let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(log::LevelFilter::Info));
+    if let Err(error) = freed_desktop_lib::run_sync_cli(command) {
+        log::error!("[Sync] {}", error);
+        std::process::exit(1);
+    }
+}
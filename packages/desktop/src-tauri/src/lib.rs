@@ -1,75 +1,133 @@
 //! Freed Desktop Application
 //!
 //! Native desktop app that bundles capture, sync relay, and reader UI.
-
+//!
+//! Without the `desktop` feature the crate builds only the relay, `freed-sync`,
+//! and the rendezvous server.
+
+#[cfg(feature = "desktop")]
+mod backups;
+#[cfg(feature = "desktop")]
+mod peers;
+mod relay;
+mod rendezvous;
+mod snapshots;
+mod sync_cli;
+#[cfg(feature = "desktop")]
 mod youtube;
 
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
-use futures_util::{SinkExt, StreamExt};
+pub use relay::run_headless_relay;
+pub use rendezvous::run_rendezvous_server;
+pub use sync_cli::{run_sync_cli, SyncCliCommand};
+
+#[cfg(feature = "desktop")]
+use backups::{
+    export_backup_in, import_backup_in, run_backup_scheduler, run_scheduled_backup_and_report,
+    sha256_hex, BackupRoots, BackupSchedule, BackupSummary, ScheduledBackup, ScheduledBackupKind,
+    BACKUP_MIN_PASSPHRASE_CHARS, BACKUP_PASSPHRASE_FILE, BACKUP_PBKDF2_ITERATIONS,
+};
+#[cfg(feature = "desktop")]
+use peers::{
+    browse_sync_peers, parse_peer_pairing_link, start_sync_peer, start_trusted_sync_peers,
+    sync_peer_local_name, update_sync_peers, SyncPeerSummary,
+};
+#[cfg(feature = "desktop")]
+use relay::{
+    advertise_mdns, apply_relay_update, decode_relay_doc_file, generate_token,
+    load_or_create_payload_key, load_relay_credentials, local_relay_candidate_hosts,
+    open_relay_payload, parse_relay_heads_hex, persist_held_relay_doc, relay_interface_addresses,
+    restore_held_relay_doc, rotate_relay_payload_key, seal_relay_update, sealed_payload_key_id,
+    start_sync_relay, sync_pairing_url, sync_relay_port, write_private_file, MdnsState,
+    PairedDeviceSummary, RelayDisconnect, RelayEventSink, RelayEvents, RelayNetworkSettings,
+    RelayPayloadKey, RelayState, RelayTlsIdentity, RelayUpdate, SyncClientSummary, SyncRelayState,
+    RELAY_DOC_FILE,
+};
+#[cfg(feature = "desktop")]
+use rendezvous::{
+    relay_rendezvous_status, rendezvous_pairing_url, set_relay_rendezvous_in, RelayRendezvousStatus,
+};
+#[cfg(feature = "desktop")]
+use snapshots::{
+    compact_automerge_doc, diff_snapshots_in, inspect_snapshot_in, list_snapshots_in,
+    load_snapshot_doc, prune_snapshots, read_snapshot, report_snapshot_prune,
+    run_debounced_doc_writer, run_snapshot_writer, snapshot_dir, validate_snapshot_bytes,
+    DocumentCompaction, SnapshotDiff, SnapshotInspection, SnapshotRetention, SnapshotSummary,
+    DEFAULT_SNAPSHOT_WRITER_POLICY,
+};
+
+#[cfg(feature = "desktop")]
+use futures_util::StreamExt;
+#[cfg(feature = "desktop")]
 use log::{error, info, warn};
-use rand::{Rng, RngCore};
+#[cfg(feature = "desktop")]
+use rand::Rng;
+#[cfg(feature = "desktop")]
 use sha2::{Digest, Sha256};
+#[cfg(feature = "desktop")]
 use std::collections::{HashMap, HashSet, VecDeque};
-#[cfg(unix)]
+#[cfg(all(feature = "desktop", unix))]
 use std::mem::MaybeUninit;
-use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+#[cfg(feature = "desktop")]
 use std::process::Command;
+#[cfg(feature = "desktop")]
 use std::sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "desktop")]
+use std::time::Instant;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "desktop")]
 use sysinfo::{Disks, Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+#[cfg(feature = "desktop")]
 use tauri::menu::{Menu, MenuItem};
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 use tauri::menu::{PredefinedMenuItem, Submenu};
+#[cfg(feature = "desktop")]
 use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+#[cfg(feature = "desktop")]
 use tauri::{Emitter, Listener, Manager};
+#[cfg(feature = "desktop")]
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(feature = "desktop")]
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, RwLock};
-use tokio::time::{timeout, Duration};
-use tokio_tungstenite::{
-    accept_hdr_async,
-    tungstenite::{
-        handshake::server::{ErrorResponse, Request as WsRequest, Response as WsResponse},
-        Message,
-    },
-};
+#[cfg(feature = "desktop")]
+use tokio::time::timeout;
+use tokio::time::Duration;
 
-#[cfg(target_os = "macos")]
-use objc2::rc::Retained;
-#[cfg(target_os = "macos")]
-use objc2::{msg_send, runtime::AnyObject};
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
+use objc2::{msg_send, rc::Retained, runtime::AnyObject};
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 use objc2_app_kit::{
     NSApplication, NSApplicationActivationOptions, NSApplicationActivationPolicy,
     NSRunningApplication,
 };
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 use objc2_foundation::{ns_string, MainThreadMarker, NSObjectNSKeyValueCoding, NSRect, NSString};
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 use objc2_web_kit::WKWebViewConfiguration;
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 use window_vibrancy::{apply_vibrancy, NSVisualEffectMaterial};
 
-const DEFAULT_SYNC_RELAY_PORT: u16 = 8765;
+#[cfg(feature = "desktop")]
 const FACTORY_RESET_RELAY_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(feature = "desktop")]
 const FACTORY_RESET_RELAY_DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
-const SYNC_RELAY_DOC_SEND_TIMEOUT: Duration = Duration::from_secs(2);
+const SYNC_PEER_SERVICE_TYPE: &str = "_freed-sync._tcp.local.";
+const SYNC_PEER_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const SYNC_PEER_RETRY_MIN_DELAY: Duration = Duration::from_secs(2);
+const SYNC_PEER_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+#[cfg(feature = "desktop")]
 const MAIN_WINDOW_LABEL: &str = "main";
+#[cfg(feature = "desktop")]
 const MAIN_WINDOW_RECOVERY_KEEPALIVE_LABEL: &str = "main-recovery-keepalive";
+#[cfg(feature = "desktop")]
 const PRIMARY_MENU_ITEM_SHOW: &str = "show";
+#[cfg(feature = "desktop")]
 const PRIMARY_MENU_ITEM_QUIT: &str = "quit";
 
-fn sync_relay_port() -> u16 {
-    std::env::var("FREED_SYNC_PORT")
-        .ok()
-        .and_then(|raw| raw.parse::<u16>().ok())
-        .filter(|port| *port > 0)
-        .unwrap_or(DEFAULT_SYNC_RELAY_PORT)
-}
-
+#[cfg(feature = "desktop")]
 const DEFAULT_WEBKIT_SAFARI_UA: &str =
     "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.3 Safari/605.1.15";
+#[cfg(feature = "desktop")]
 const SOCIAL_SCRAPER_WINDOW_LABELS: [&str; 5] = [
     "fb-scraper",
     "ig-scraper",
@@ -77,90 +135,166 @@ const SOCIAL_SCRAPER_WINDOW_LABELS: [&str; 5] = [
     "substack-scraper",
     "medium-scraper",
 ];
+#[cfg(feature = "desktop")]
 const FB_SCRAPER_DATA_STORE_IDENTIFIER: [u8; 16] = [
     0x66, 0x72, 0x65, 0x65, 0x64, 0xfb, 0x00, 0x01, 0x9a, 0x7d, 0x37, 0x01, 0x02, 0xfb, 0x00, 0x01,
 ];
+#[cfg(feature = "desktop")]
 const IG_SCRAPER_DATA_STORE_IDENTIFIER: [u8; 16] = [
     0x66, 0x72, 0x65, 0x65, 0x64, 0x1a, 0x00, 0x02, 0x9a, 0x7d, 0x37, 0x01, 0x02, 0x1a, 0x00, 0x02,
 ];
+#[cfg(feature = "desktop")]
 const LI_SCRAPER_DATA_STORE_IDENTIFIER: [u8; 16] = [
     0x66, 0x72, 0x65, 0x65, 0x64, 0x1d, 0x00, 0x03, 0x9a, 0x7d, 0x37, 0x01, 0x02, 0x1d, 0x00, 0x03,
 ];
+#[cfg(feature = "desktop")]
 const SUBSTACK_SCRAPER_DATA_STORE_IDENTIFIER: [u8; 16] = [
     0x66, 0x72, 0x65, 0x65, 0x64, 0x5b, 0x00, 0x04, 0x9a, 0x7d, 0x37, 0x01, 0x02, 0x5b, 0x00, 0x04,
 ];
+#[cfg(feature = "desktop")]
 const MEDIUM_SCRAPER_DATA_STORE_IDENTIFIER: [u8; 16] = [
     0x66, 0x72, 0x65, 0x65, 0x64, 0x6d, 0x00, 0x05, 0x9a, 0x7d, 0x37, 0x01, 0x02, 0x6d, 0x00, 0x05,
 ];
+#[cfg(feature = "desktop")]
 const BYTES_PER_GIB: u64 = 1024 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const MIN_CRITICAL_MEMORY_BYTES: u64 = 7 * BYTES_PER_GIB / 2;
+#[cfg(feature = "desktop")]
 const MAX_CRITICAL_MEMORY_BYTES: u64 = 12 * BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const WEBKIT_CACHE_TRIM_AT_BYTES: u64 = 768 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const WEBKIT_CACHE_TRIM_TARGET_BYTES: u64 = 512 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const OPTIONAL_STORY_MEMORY_BUDGET_PERCENT: u64 = 85;
+#[cfg(feature = "desktop")]
 const SCRAPE_MEMORY_HEADROOM_BYTES: u64 = 384 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const SCRAPE_REDUCED_PASS_MARGIN_BYTES: u64 = 1536 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const SCRAPE_MINIMAL_PASS_MARGIN_BYTES: u64 = 768 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const SCRAPE_WEBKIT_RESIDENT_START_BUDGET_BYTES: u64 = 4 * BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const POST_SOCIAL_SCRAPE_WEBKIT_FOOTPRINT_RECOVERY_BYTES: u64 = 2 * BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const POST_SOCIAL_SCRAPE_WEBKIT_FOOTPRINT_GROWTH_BYTES: u64 = 768 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const POST_SOCIAL_SCRAPE_WEBKIT_RESIDENT_RECOVERY_BYTES: u64 = 4 * BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const POST_SOCIAL_SCRAPE_WEBKIT_TAIL_FOOTPRINT_RECOVERY_BYTES: u64 = BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const POST_SOCIAL_SCRAPE_PRESSURE_RECOVERY_PERCENT: u64 = 35;
+#[cfg(feature = "desktop")]
 const WEBKIT_PROCESS_START_GRACE_SECONDS: u64 = 10;
+#[cfg(feature = "desktop")]
 const STARTUP_RECOVERY_STATE_FILE: &str = "startup-recovery.json";
+/// Tier actions kept in the startup recovery file.
+#[cfg(feature = "desktop")]
+const STARTUP_RECOVERY_ACTION_LIMIT: usize = 16;
+#[cfg(feature = "desktop")]
 const RUNTIME_HEALTH_FILE: &str = "runtime-health.jsonl";
+#[cfg(feature = "desktop")]
 const DEV_SYNC_TRIGGER_FILE: &str = "dev-sync-trigger.json";
+#[cfg(feature = "desktop")]
 const DEV_SYNC_TRIGGER_RESULT_FILE: &str = "dev-sync-trigger-result.json";
+#[cfg(feature = "desktop")]
 const DEV_SYNC_TRIGGER_POLL_INTERVAL: Duration = Duration::from_secs(5);
+#[cfg(feature = "desktop")]
 const DEV_SYNC_TRIGGER_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(2);
+#[cfg(feature = "desktop")]
 const DEV_SYNC_TRIGGER_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+#[cfg(feature = "desktop")]
 const DEV_SYNC_TRIGGER_REQUEST_MAX_AGE_MS: u64 = 10 * 60 * 1000;
+#[cfg(feature = "desktop")]
 const DEV_SYNC_TRIGGER_STALE_STARTED_RECOVERY_MS: u64 = 45_000;
-const SYNC_RELAY_BIND_RETRY_DELAY: Duration = Duration::from_secs(5);
-const SYNC_RELAY_BIND_MAX_RETRIES: usize = 60;
 /// Only bounds the non-unix single-file fallback; unix installs rotate
 /// runtime-health daily instead (see append_runtime_health_line).
 #[cfg(not(unix))]
 const RUNTIME_HEALTH_MAX_BYTES: u64 = 5 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const RUNTIME_DIAGNOSTICS_FILE: &str = "runtime-diagnostics.jsonl";
+#[cfg(feature = "desktop")]
 const RUNTIME_DIAGNOSTICS_MAX_BYTES: u64 = 5 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const RUNTIME_DIAGNOSTICS_COOLDOWN: Duration = Duration::from_secs(180);
+#[cfg(feature = "desktop")]
 const STARTUP_DIAGNOSTICS_MAX_FILE_BYTES: u64 = 2 * 1024 * 1024;
+/// Longest panic message kept in a `rust_panic` record.
+#[cfg(feature = "desktop")]
+const RUST_PANIC_MESSAGE_MAX_CHARS: usize = 2048;
+/// `rust_panic` records included in the diagnostics bundle.
+#[cfg(feature = "desktop")]
+const RUST_PANIC_DIAGNOSTICS_LIMIT: usize = 20;
+#[cfg(feature = "desktop")]
 const RECOVERY_WINDOW_LABEL: &str = "startup-recovery";
+#[cfg(feature = "desktop")]
 const RECOVERY_WINDOW_ROUTE: &str = "startup-recovery.html";
+#[cfg(feature = "desktop")]
 const RENDERER_HEARTBEAT_WATCHDOG_INTERVAL: Duration = Duration::from_secs(15);
+#[cfg(feature = "desktop")]
 const RENDERER_HEARTBEAT_MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+#[cfg(feature = "desktop")]
 const RENDERER_STALE_LOG_AFTER: Duration = Duration::from_secs(45);
+#[cfg(feature = "desktop")]
 const WEBKIT_HIDDEN_TIMER_THROTTLE_AFTER: Duration = Duration::from_secs(480);
+#[cfg(feature = "desktop")]
 const RENDERER_HIDDEN_STALE_LOG_AFTER: Duration = Duration::from_secs(570);
+#[cfg(feature = "desktop")]
 const RENDERER_VISIBLE_RECOVERY_AFTER: Duration = Duration::from_secs(75);
+#[cfg(feature = "desktop")]
 const RENDERER_HIDDEN_RECOVERY_AFTER: Duration = Duration::from_secs(900);
+#[cfg(feature = "desktop")]
 const MAIN_RENDERER_MEMORY_RECOVERY_MIN_AGE_SECONDS: u64 = 5 * 60;
+#[cfg(feature = "desktop")]
 const MAIN_RENDERER_IDLE_WEBKIT_RESIDENT_RECOVERY_BYTES: u64 = 4 * BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const MAIN_RENDERER_HOT_WEBKIT_RESIDENT_RECOVERY_BYTES: u64 = 5 * BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const MAIN_RENDERER_HOT_WEBKIT_FOOTPRINT_RECOVERY_BYTES: u64 = 2 * BYTES_PER_GIB;
+#[cfg(feature = "desktop")]
 const MAIN_RENDERER_HOT_WEBKIT_CPU_RECOVERY_PERCENT: f32 = 20.0;
+#[cfg(feature = "desktop")]
 const MAIN_RENDERER_RECOVERY_VERIFY_AFTER: Duration = Duration::from_secs(3);
+#[cfg(feature = "desktop")]
 const MAIN_RENDERER_RECOVERY_MIN_RECLAIM_BYTES: u64 = 512 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const RENDERER_EVENT_LOOP_LAG_RECOVERY_MS: f64 = 45_000.0;
+#[cfg(feature = "desktop")]
 const BACKGROUND_REQUIRED_HEALTHY_HEARTBEATS: u64 = 2;
+#[cfg(feature = "desktop")]
 const BACKGROUND_RECOVERY_COOLDOWN: Duration = Duration::from_secs(120);
+#[cfg(feature = "desktop")]
 const BACKGROUND_MEMORY_HIGH_COOLDOWN: Duration = Duration::from_secs(120);
+#[cfg(feature = "desktop")]
 const BACKGROUND_MEMORY_CRITICAL_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+#[cfg(feature = "desktop")]
 const BACKGROUND_SAFE_MODE_RECOVERY_WINDOW_SHORT: Duration = Duration::from_secs(10 * 60);
+#[cfg(feature = "desktop")]
 const BACKGROUND_SAFE_MODE_RECOVERY_WINDOW_LONG: Duration = Duration::from_secs(30 * 60);
+#[cfg(feature = "desktop")]
 const BACKGROUND_SAFE_MODE_DURATION: Duration = Duration::from_secs(10 * 60);
+#[cfg(feature = "desktop")]
 const BACKGROUND_SAFE_MODE_SHORT_LIMIT: usize = 2;
+#[cfg(feature = "desktop")]
 const BACKGROUND_SAFE_MODE_LONG_LIMIT: usize = 3;
+#[cfg(feature = "desktop")]
 const BACKGROUND_JOB_MAX_HELD: Duration = Duration::from_secs(120);
+#[cfg(feature = "desktop")]
 const FORCE_EXIT_AFTER_RESTART_REQUEST: Duration = Duration::from_secs(8);
+#[cfg(feature = "desktop")]
 const MAIN_WINDOW_RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+#[cfg(feature = "desktop")]
 const MAIN_WINDOW_RELEASE_POLL_ATTEMPTS: usize = 100;
+#[cfg(feature = "desktop")]
 const MAIN_WINDOW_OCCLUSION_RECOVERY_AFTER: Duration = Duration::from_secs(5);
+#[cfg(feature = "desktop")]
 const NS_WINDOW_OCCLUSION_STATE_VISIBLE: usize = 1 << 1;
+#[cfg(feature = "desktop")]
 const MAIN_THREAD_WINDOW_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(feature = "desktop")]
 const LOCAL_AI_DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+#[cfg(feature = "desktop")]
 const ENABLE_BACKGROUND_SCRAPER_CLOAK_JS: &str = r#"
     (function() {
         var token = "__freed_background_scraper__";
@@ -174,6 +308,7 @@ const ENABLE_BACKGROUND_SCRAPER_CLOAK_JS: &str = r#"
         }
     })();
 "#;
+#[cfg(feature = "desktop")]
 const DISABLE_BACKGROUND_SCRAPER_CLOAK_JS: &str = r#"
     (function() {
         var token = "__freed_background_scraper__";
@@ -186,6 +321,7 @@ const DISABLE_BACKGROUND_SCRAPER_CLOAK_JS: &str = r#"
         }
     })();
 "#;
+#[cfg(feature = "desktop")]
 const INITIALIZE_BACKGROUND_SCRAPER_CLOAK_JS: &str = r#"
     (function() {
         var token = "__freed_background_scraper__";
@@ -199,6 +335,7 @@ const INITIALIZE_BACKGROUND_SCRAPER_CLOAK_JS: &str = r#"
         }
     })();
 "#;
+#[cfg(feature = "desktop")]
 const ENABLE_BACKGROUND_SCRAPER_MEDIA_GUARD_JS: &str = r#"
     (function() {
         var token = "__freed_media_guard__";
@@ -212,6 +349,7 @@ const ENABLE_BACKGROUND_SCRAPER_MEDIA_GUARD_JS: &str = r#"
         }
     })();
 "#;
+#[cfg(feature = "desktop")]
 const DISABLE_BACKGROUND_SCRAPER_MEDIA_GUARD_JS: &str = r#"
     (function() {
         var token = "__freed_media_guard__";
@@ -224,6 +362,7 @@ const DISABLE_BACKGROUND_SCRAPER_MEDIA_GUARD_JS: &str = r#"
         }
     })();
 "#;
+#[cfg(feature = "desktop")]
 const INITIALIZE_BACKGROUND_SCRAPER_MEDIA_GUARD_JS: &str = r#"
     (function() {
         var token = "__freed_media_guard__";
@@ -237,6 +376,7 @@ const INITIALIZE_BACKGROUND_SCRAPER_MEDIA_GUARD_JS: &str = r#"
         }
     })();
 "#;
+#[cfg(feature = "desktop")]
 const CLEANUP_BACKGROUND_SCRAPER_MEDIA_JS: &str = r#"
     (function() {
         try {
@@ -267,6 +407,7 @@ const CLEANUP_BACKGROUND_SCRAPER_MEDIA_JS: &str = r#"
     })();
 "#;
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum ScraperWindowMode {
@@ -275,6 +416,7 @@ enum ScraperWindowMode {
     Hidden,
 }
 
+#[cfg(feature = "desktop")]
 impl ScraperWindowMode {
     fn as_str(self) -> &'static str {
         match self {
@@ -285,6 +427,7 @@ impl ScraperWindowMode {
     }
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum ScraperCursorIgnoreActivation {
     BeforeVisibilityChange,
@@ -292,6 +435,7 @@ enum ScraperCursorIgnoreActivation {
     Skip,
 }
 
+#[cfg(feature = "desktop")]
 fn scraper_cursor_ignore_activation(
     window_mode: ScraperWindowMode,
     is_linux: bool,
@@ -305,6 +449,7 @@ fn scraper_cursor_ignore_activation(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn stored_or_default_user_agent(agent: &std::sync::Mutex<String>) -> String {
     let stored = agent.lock().unwrap();
     if stored.trim().is_empty() {
@@ -314,6 +459,7 @@ fn stored_or_default_user_agent(agent: &std::sync::Mutex<String>) -> String {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn social_scraper_data_store_identifier(label: &str) -> Option<[u8; 16]> {
     match label {
         "fb-login" | "fb-scraper" => Some(FB_SCRAPER_DATA_STORE_IDENTIFIER),
@@ -325,6 +471,7 @@ fn social_scraper_data_store_identifier(label: &str) -> Option<[u8; 16]> {
     }
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct SocialProviderCookieState {
@@ -336,6 +483,7 @@ struct SocialProviderCookieState {
     error: Option<String>,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct DesktopSessionState {
@@ -352,6 +500,7 @@ fn parse_screen_locked_from_ioreg_plist(text: &str) -> Option<bool> {
         .map(|tail| tail.trim_start().starts_with("<true/>"))
 }
 
+#[cfg(feature = "desktop")]
 fn data_store_identifier_folder(identifier: [u8; 16]) -> String {
     format!(
         "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
@@ -374,6 +523,7 @@ fn data_store_identifier_folder(identifier: [u8; 16]) -> String {
     )
 }
 
+#[cfg(feature = "desktop")]
 fn social_auth_cookie_config(
     provider: &str,
 ) -> Option<(&'static str, [u8; 16], &'static [&'static str])> {
@@ -393,16 +543,19 @@ fn social_auth_cookie_config(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
     let bytes = data.get(offset..offset + 4)?;
     Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
 }
 
+#[cfg(feature = "desktop")]
 fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
     let bytes = data.get(offset..offset + 4)?;
     Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
 }
 
+#[cfg(feature = "desktop")]
 fn cookie_record_string(record: &[u8], offset: usize) -> Option<String> {
     if offset == 0 || offset >= record.len() {
         return None;
@@ -418,6 +571,7 @@ fn cookie_record_string(record: &[u8], offset: usize) -> Option<String> {
         .map(|value| value.to_string())
 }
 
+#[cfg(feature = "desktop")]
 fn parse_webkit_binary_cookie_names(data: &[u8]) -> Result<Vec<String>, String> {
     if data.len() < 8 || data.get(0..4) != Some(b"cook") {
         return Err("Invalid WebKit cookie store header".to_string());
@@ -486,22 +640,29 @@ fn parse_webkit_binary_cookie_names(data: &[u8]) -> Result<Vec<String>, String>
     Ok(names)
 }
 
-#[cfg(target_os = "macos")]
-fn webkit_cookie_store_path(
-    app: &tauri::AppHandle,
-    identifier: [u8; 16],
-) -> Result<PathBuf, String> {
+/// Where WebKit keeps the scraper windows' isolated data stores.
+#[cfg(all(feature = "desktop", target_os = "macos"))]
+fn scraper_data_store_root(app: &tauri::AppHandle) -> Result<PathBuf, String> {
     let home = app.path().home_dir().map_err(|error| error.to_string())?;
     Ok(home
         .join("Library")
         .join("WebKit")
         .join(app.config().identifier.as_str())
-        .join("WebsiteDataStore")
+        .join("WebsiteDataStore"))
+}
+
+#[cfg(all(feature = "desktop", target_os = "macos"))]
+fn webkit_cookie_store_path(
+    app: &tauri::AppHandle,
+    identifier: [u8; 16],
+) -> Result<PathBuf, String> {
+    Ok(scraper_data_store_root(app)?
         .join(data_store_identifier_folder(identifier))
         .join("Cookies")
         .join("Cookies.binarycookies"))
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_social_provider_cookie_state(
     app: tauri::AppHandle,
@@ -580,6 +741,7 @@ fn get_social_provider_cookie_state(
     }
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_desktop_session_state() -> DesktopSessionState {
     #[cfg(not(target_os = "macos"))]
@@ -626,6 +788,7 @@ fn get_desktop_session_state() -> DesktopSessionState {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_lock_deferral_detail(
     session_state: &DesktopSessionState,
 ) -> Option<&'static str> {
@@ -641,6 +804,7 @@ fn dev_sync_trigger_lock_deferral_detail(
 /// Structured reason for a `window_destroyed` runtime-health record
 /// (stability program P0-02). Serialized snake_case; the free-form
 /// destruction detail travels alongside as `requestedBy`.
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 enum WindowDestroyedReason {
@@ -655,6 +819,7 @@ enum WindowDestroyedReason {
     StartupRecovery,
 }
 
+#[cfg(feature = "desktop")]
 static WINDOW_CREATED_AT: std::sync::LazyLock<StdMutex<HashMap<String, Instant>>> =
     std::sync::LazyLock::new(|| StdMutex::new(HashMap::new()));
 
@@ -664,6 +829,7 @@ static WINDOW_CREATED_AT: std::sync::LazyLock<StdMutex<HashMap<String, Instant>>
 /// window already existed. Windows destroyed outside the instrumented paths
 /// (e.g. an OS-level close) leave a stale entry behind, so the next cycle's
 /// age can be overstated — acceptable for telemetry.
+#[cfg(feature = "desktop")]
 fn observe_window_created(label: &str) {
     let mut created = WINDOW_CREATED_AT.lock().unwrap();
     created
@@ -671,6 +837,7 @@ fn observe_window_created(label: &str) {
         .or_insert_with(Instant::now);
 }
 
+#[cfg(feature = "desktop")]
 fn take_window_age_seconds(label: &str) -> Option<u64> {
     let mut created = WINDOW_CREATED_AT.lock().unwrap();
     created
@@ -681,6 +848,7 @@ fn take_window_age_seconds(label: &str) -> Option<u64> {
 /// Append the one-line structured kill record for a destroyed window.
 /// `victimPid` stays null until deterministic PID→window attribution exists
 /// (Wave 6); the WebKit sampler's age heuristic must not be reused here.
+#[cfg(feature = "desktop")]
 fn record_window_destroyed(
     app: &tauri::AppHandle,
     label: &str,
@@ -711,6 +879,7 @@ fn record_window_destroyed(
     );
 }
 
+#[cfg(feature = "desktop")]
 fn recycle_webview_window(
     app: &tauri::AppHandle,
     label: &str,
@@ -732,6 +901,7 @@ fn recycle_webview_window(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn recycle_social_scraper_windows(
     app: &tauri::AppHandle,
     reason: WindowDestroyedReason,
@@ -742,6 +912,7 @@ fn recycle_social_scraper_windows(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn active_job_uses_social_scraper(active_job: Option<&str>) -> bool {
     active_job
         .map(|operation| {
@@ -754,6 +925,7 @@ fn active_job_uses_social_scraper(active_job: Option<&str>) -> bool {
         .unwrap_or(false)
 }
 
+#[cfg(feature = "desktop")]
 fn recycle_social_scraper_windows_unless_active(
     app: &tauri::AppHandle,
     runtime: &BackgroundRuntimeCoordinator,
@@ -774,18 +946,21 @@ fn recycle_social_scraper_windows_unless_active(
     recycle_social_scraper_windows(app, reason, detail);
 }
 
+#[cfg(feature = "desktop")]
 struct WebviewRecycleGuard {
     app: tauri::AppHandle,
     label: &'static str,
     reason: &'static str,
 }
 
+#[cfg(feature = "desktop")]
 impl WebviewRecycleGuard {
     fn new(app: tauri::AppHandle, label: &'static str, reason: &'static str) -> Self {
         Self { app, label, reason }
     }
 }
 
+#[cfg(feature = "desktop")]
 impl Drop for WebviewRecycleGuard {
     fn drop(&mut self) {
         recycle_webview_window(
@@ -797,6 +972,7 @@ impl Drop for WebviewRecycleGuard {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn scrub_webview_before_destroy(window: &tauri::WebviewWindow) {
     let _ = window.eval(
         r#"
@@ -840,6 +1016,7 @@ fn scrub_webview_before_destroy(window: &tauri::WebviewWindow) {
     let _ = window.navigate("about:blank".parse().unwrap());
 }
 
+#[cfg(feature = "desktop")]
 fn build_hidden_scraper_window(
     app: &tauri::AppHandle,
     label: &str,
@@ -875,6 +1052,7 @@ fn build_hidden_scraper_window(
     .inspect(|_| observe_window_created(label))
 }
 
+#[cfg(feature = "desktop")]
 fn set_background_scraper_window_cloak(
     window: &tauri::WebviewWindow,
     enabled: bool,
@@ -888,6 +1066,7 @@ fn set_background_scraper_window_cloak(
         .map_err(|e| e.to_string())
 }
 
+#[cfg(feature = "desktop")]
 fn set_background_scraper_media_guard(
     window: &tauri::WebviewWindow,
     enabled: bool,
@@ -901,10 +1080,12 @@ fn set_background_scraper_media_guard(
         .map_err(|e| e.to_string())
 }
 
+#[cfg(feature = "desktop")]
 fn cleanup_background_scraper_media(window: &tauri::WebviewWindow) {
     let _ = window.eval(CLEANUP_BACKGROUND_SCRAPER_MEDIA_JS);
 }
 
+#[cfg(feature = "desktop")]
 fn prepare_background_scraper_window(
     window: &tauri::WebviewWindow,
     window_mode: ScraperWindowMode,
@@ -962,6 +1143,7 @@ fn prepare_background_scraper_window(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 fn unix_millis_now() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -969,6 +1151,7 @@ fn unix_millis_now() -> u64 {
         .unwrap_or(0)
 }
 
+#[cfg(feature = "desktop")]
 fn emit_social_scrape_lifecycle(
     app: &tauri::AppHandle,
     event_name: &str,
@@ -992,6 +1175,7 @@ fn emit_social_scrape_lifecycle(
     );
 }
 
+#[cfg(feature = "desktop")]
 async fn restore_scraper_feed(
     window: &tauri::WebviewWindow,
     feed_url: &str,
@@ -1007,49 +1191,91 @@ async fn restore_scraper_feed(
     Ok(())
 }
 
-// ---------------------------------------------------------------------------
-// Token management
-// ---------------------------------------------------------------------------
-
-/// Generates a cryptographically random 256-bit pairing token encoded as
-/// base64url without padding (43 ASCII characters).
-fn generate_token() -> String {
-    let mut bytes = [0u8; 32];
-    rand::thread_rng().fill_bytes(&mut bytes);
-    URL_SAFE_NO_PAD.encode(bytes)
-}
-
-/// Loads the pairing token from `data_dir/pairing-token`, or creates and
-/// persists a fresh one if the file is missing or malformed.
-fn load_or_create_token(data_dir: &std::path::Path) -> String {
-    let path = data_dir.join("pairing-token");
-    if let Ok(raw) = std::fs::read_to_string(&path) {
-        let token = raw.trim().to_string();
-        // 32 bytes base64url-no-pad → exactly 43 chars, all URL-safe
-        let looks_valid = token.len() == 43
-            && token
-                .chars()
-                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
-        if looks_valid {
-            return token;
-        }
-    }
-    let token = generate_token();
-    let _ = std::fs::write(&path, &token);
-    token
-}
-
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 struct StartupRecoveryState {
     consecutive_failed_boots: u32,
     pending_boot_started_at_ms: Option<u64>,
     last_failed_boot_at_ms: Option<u64>,
     last_successful_boot_at_ms: Option<u64>,
+    /// Escalates with every failed boot, unlike `consecutive_failed_boots`
+    /// which a manual retry resets; only a healthy startup clears it.
+    recovery_tier: StartupRecoveryTier,
+    /// Most recent tier actions, oldest first.
+    recovery_actions: Vec<StartupRecoveryAction>,
+    /// Set by the `DocumentRollback` tier.
+    document_check: Option<StartupDocumentCheck>,
+}
+
+/// Escalating recovery for a boot that follows failed ones. Each tier also
+/// applies the ones below it.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[cfg(feature = "desktop")]
+#[serde(rename_all = "snake_case")]
+enum StartupRecoveryTier {
+    #[default]
+    None,
+    /// Scrapers stay off for the launch.
+    ScrapersDisabled,
+    /// WebKit network caches are cleared through the cache trimmer.
+    WebkitCachesCleared,
+    /// The persisted document is validated and the newest snapshot that
+    /// parses is offered as a rollback.
+    DocumentRollback,
+}
+
+#[cfg(feature = "desktop")]
+impl StartupRecoveryTier {
+    fn escalate(self) -> Self {
+        match self {
+            StartupRecoveryTier::None => StartupRecoveryTier::ScrapersDisabled,
+            StartupRecoveryTier::ScrapersDisabled => StartupRecoveryTier::WebkitCachesCleared,
+            StartupRecoveryTier::WebkitCachesCleared | StartupRecoveryTier::DocumentRollback => {
+                StartupRecoveryTier::DocumentRollback
+            }
+        }
+    }
+}
+
+#[cfg(feature = "desktop")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct StartupRecoveryAction {
+    tier: StartupRecoveryTier,
+    at_ms: u64,
+    detail: String,
+}
+
+/// What the `DocumentRollback` tier found on disk.
+#[cfg(feature = "desktop")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct StartupDocumentCheck {
+    checked_at_ms: u64,
+    /// Why the held relay document failed to load. `None` when it loaded or
+    /// there is none.
+    held_document_error: Option<String>,
+    /// Newest snapshot that loads as an Automerge document.
+    last_good_snapshot: Option<String>,
+    last_good_snapshot_timestamp: Option<u64>,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Default)]
 struct LocalAIModelDownloadState(StdRwLock<HashSet<String>>);
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct LocalAIModelFileDownloadRequest {
@@ -1061,6 +1287,7 @@ struct LocalAIModelFileDownloadRequest {
     progress_event: String,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct LocalAIModelFileDownloadProgress {
@@ -1075,11 +1302,12 @@ fn now_unix_ms() -> u64 {
         .as_millis() as u64
 }
 
+#[cfg(feature = "desktop")]
 fn startup_recovery_state_path(data_dir: &Path) -> PathBuf {
     data_dir.join(STARTUP_RECOVERY_STATE_FILE)
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn clear_saved_window_state(app: &tauri::AppHandle) {
     let Some(home_dir) = std::env::var_os("HOME") else {
         return;
@@ -1108,6 +1336,7 @@ fn clear_saved_window_state(app: &tauri::AppHandle) {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn load_startup_recovery_state(data_dir: &Path) -> StartupRecoveryState {
     let path = startup_recovery_state_path(data_dir);
     let Ok(raw) = std::fs::read_to_string(&path) else {
@@ -1127,6 +1356,7 @@ fn load_startup_recovery_state(data_dir: &Path) -> StartupRecoveryState {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn save_startup_recovery_state(data_dir: &Path, state: &StartupRecoveryState) {
     let path = startup_recovery_state_path(data_dir);
     let serialized = match serde_json::to_vec_pretty(state) {
@@ -1149,6 +1379,7 @@ fn save_startup_recovery_state(data_dir: &Path, state: &StartupRecoveryState) {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn runtime_health_path(data_dir: &Path) -> PathBuf {
     data_dir.join(RUNTIME_HEALTH_FILE)
 }
@@ -1164,8 +1395,11 @@ fn runtime_health_path(data_dir: &Path) -> PathBuf {
 // trend evidence; rotation replaces it with plain appends + 14-day retention.
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "desktop")]
 const RUNTIME_HEALTH_RETAIN_DAYS: usize = 14;
+#[cfg(feature = "desktop")]
 const RUNTIME_HEALTH_HISTORY_MAX_BYTES: u64 = 8 * 1024 * 1024;
+#[cfg(feature = "desktop")]
 const RUNTIME_HEALTH_WRITER_LOCK_FILE: &str = ".runtime-health.writer.lock";
 
 // Serializes every runtime-health mutation on every platform. On Unix this
@@ -1173,15 +1407,18 @@ const RUNTIME_HEALTH_WRITER_LOCK_FILE: &str = ".runtime-health.writer.lock";
 // keep the bounded legacy file it also covers the read, rewrite, and append
 // sequence. Factory reset takes the same lock before removing runtime-health
 // files so a concurrent writer cannot recreate or append to a file mid-reset.
+#[cfg(feature = "desktop")]
 struct RuntimeHealthWriterState {
     active_target: Option<(PathBuf, String)>,
 }
 
+#[cfg(feature = "desktop")]
 static RUNTIME_HEALTH_WRITER_STATE: StdMutex<RuntimeHealthWriterState> =
     StdMutex::new(RuntimeHealthWriterState {
         active_target: None,
     });
 
+#[cfg(feature = "desktop")]
 struct RuntimeHealthWriteGuard {
     // Fields drop in declaration order. Release the operating-system lock
     // before allowing another local writer through the process mutex.
@@ -1189,6 +1426,7 @@ struct RuntimeHealthWriteGuard {
     state: std::sync::MutexGuard<'static, RuntimeHealthWriterState>,
 }
 
+#[cfg(feature = "desktop")]
 fn runtime_health_write_guard(data_dir: &Path) -> std::io::Result<RuntimeHealthWriteGuard> {
     std::fs::create_dir_all(data_dir)?;
     let state = RUNTIME_HEALTH_WRITER_STATE
@@ -1202,14 +1440,17 @@ fn runtime_health_write_guard(data_dir: &Path) -> std::io::Result<RuntimeHealthW
     })
 }
 
+#[cfg(feature = "desktop")]
 fn runtime_health_dated_file_name(date: &str) -> String {
     format!("runtime-health-{}.jsonl", date)
 }
 
+#[cfg(feature = "desktop")]
 fn runtime_health_dated_path(data_dir: &Path, date: &str) -> PathBuf {
     data_dir.join(runtime_health_dated_file_name(date))
 }
 
+#[cfg(feature = "desktop")]
 fn runtime_health_dated_file_date(file_name: &str) -> Option<&str> {
     let date = file_name
         .strip_prefix("runtime-health-")?
@@ -1218,6 +1459,7 @@ fn runtime_health_dated_file_date(file_name: &str) -> Option<&str> {
 }
 
 /// Days-since-epoch to (year, month, day). Howard Hinnant's civil_from_days.
+#[cfg(feature = "desktop")]
 fn civil_from_days(days: i64) -> (i64, u32, u32) {
     let z = days + 719_468;
     let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
@@ -1231,6 +1473,7 @@ fn civil_from_days(days: i64) -> (i64, u32, u32) {
     (if month <= 2 { year + 1 } else { year }, month, day)
 }
 
+#[cfg(feature = "desktop")]
 fn utc_date_yyyymmdd() -> String {
     let secs = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -1241,7 +1484,7 @@ fn utc_date_yyyymmdd() -> String {
 }
 
 /// Local calendar date as YYYYMMDD; runtime-health files rotate on this.
-#[cfg(unix)]
+#[cfg(all(feature = "desktop", unix))]
 fn local_date_yyyymmdd() -> String {
     let now = unsafe { libc::time(std::ptr::null_mut()) };
     let mut tm = MaybeUninit::<libc::tm>::uninit();
@@ -1264,6 +1507,7 @@ fn local_date_yyyymmdd() -> String {
     utc_date_yyyymmdd()
 }
 
+#[cfg(feature = "desktop")]
 fn list_runtime_health_dated_files(data_dir: &Path) -> Vec<(String, PathBuf)> {
     std::fs::read_dir(data_dir)
         .into_iter()
@@ -1277,6 +1521,7 @@ fn list_runtime_health_dated_files(data_dir: &Path) -> Vec<(String, PathBuf)> {
 }
 
 /// Delete dated runtime-health files beyond the newest `keep`.
+#[cfg(feature = "desktop")]
 fn prune_runtime_health_files(data_dir: &Path, keep: usize) -> Vec<PathBuf> {
     let mut dated = list_runtime_health_dated_files(data_dir);
     dated.sort_by(|a, b| b.0.cmp(&a.0));
@@ -1292,7 +1537,7 @@ fn prune_runtime_health_files(data_dir: &Path, keep: usize) -> Vec<PathBuf> {
 /// Start (or resume) the dated file for `date`: migrate the legacy plain
 /// file once, repoint the runtime-health.jsonl symlink for existing readers,
 /// and enforce retention. Runs on the first append of each local day.
-#[cfg(unix)]
+#[cfg(all(feature = "desktop", unix))]
 fn roll_runtime_health_files(data_dir: &Path, date: &str) -> std::io::Result<PathBuf> {
     let dated = runtime_health_dated_path(data_dir, date);
     let legacy = runtime_health_path(data_dir);
@@ -1325,7 +1570,7 @@ fn roll_runtime_health_files(data_dir: &Path, date: &str) -> std::io::Result<Pat
 
 /// Plain append to today's dated file — no size cap and no whole-file
 /// rewrite on the event path (retention bounds total disk use instead).
-#[cfg(unix)]
+#[cfg(all(feature = "desktop", unix))]
 fn append_runtime_health_line(data_dir: &Path, line: &str) -> std::io::Result<()> {
     let mut record = Vec::with_capacity(line.len() + 1);
     record.extend_from_slice(line.as_bytes());
@@ -1357,6 +1602,7 @@ fn append_runtime_health_line(data_dir: &Path, line: &str) -> std::io::Result<()
     append_runtime_health_bounded_line(data_dir, line, RUNTIME_HEALTH_MAX_BYTES, || {})
 }
 
+#[cfg(feature = "desktop")]
 /// The bounded runtime-health path is compiled and tested on every platform,
 /// even though production Unix builds use dated files. The callback exists so
 /// the deterministic concurrency regression can pause one writer only after it
@@ -1379,6 +1625,7 @@ where
 /// Newest `days` runtime-health day files concatenated oldest-first, falling
 /// back to the legacy plain file when no dated file exists yet (pre-rotation
 /// installs and the non-unix single-file mode).
+#[cfg(feature = "desktop")]
 fn read_runtime_health_recent_days(data_dir: &Path, days: usize) -> String {
     let mut dated = list_runtime_health_dated_files(data_dir);
     dated.sort_by(|a, b| b.0.cmp(&a.0));
@@ -1405,6 +1652,7 @@ fn read_runtime_health_recent_days(data_dir: &Path, days: usize) -> String {
 }
 
 /// Tail of `raw` within `max_bytes`, cut at a line boundary.
+#[cfg(feature = "desktop")]
 fn tail_lines_within_bytes(raw: &str, max_bytes: u64) -> &str {
     if raw.len() as u64 <= max_bytes {
         return raw;
@@ -1415,10 +1663,12 @@ fn tail_lines_within_bytes(raw: &str, max_bytes: u64) -> &str {
         .unwrap_or("")
 }
 
+#[cfg(feature = "desktop")]
 fn runtime_diagnostics_path(data_dir: &Path) -> PathBuf {
     data_dir.join(RUNTIME_DIAGNOSTICS_FILE)
 }
 
+#[cfg(feature = "desktop")]
 fn append_bounded_jsonl(path: &Path, line: &str, max_bytes: u64) -> std::io::Result<()> {
     if let Ok(metadata) = std::fs::metadata(path) {
         if metadata.len() > max_bytes {
@@ -1440,6 +1690,7 @@ fn append_bounded_jsonl(path: &Path, line: &str, max_bytes: u64) -> std::io::Res
     writeln!(file, "{}", line)
 }
 
+#[cfg(feature = "desktop")]
 fn append_runtime_health(app: &tauri::AppHandle, mut payload: serde_json::Value) {
     let Ok(data_dir) = app.path().app_data_dir() else {
         return;
@@ -1473,8 +1724,9 @@ fn append_runtime_health(app: &tauri::AppHandle, mut payload: serde_json::Value)
 //
 // A passive monitor over the runtime-health event stream. It consumes the same
 // events append_runtime_health writes -- P0-02 window_destroyed, P0-03
-// cloud_upload_attempt / scrape_outcome, and renderer_recovery_attempt -- and,
-// when a verified pathology signature trips, appends a one-line
+// cloud_upload_attempt / scrape_outcome, renderer_recovery_attempt, and the
+// backup scheduler's backup_completed / backup_failed -- and, when a verified
+// pathology signature trips, appends a one-line
 // `invariant_alarm` record carrying a short runbook string so the app degrades
 // loudly instead of looping silently. Post-fix, each alarm is the permanent
 // regression tripwire.
@@ -1490,17 +1742,28 @@ fn append_runtime_health(app: &tauri::AppHandle, mut payload: serde_json::Value)
 // inbound path), so an alarm for it today could never fire.
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "desktop")]
 const ALARM_CLOUD_LOOP_WINDOW_MS: u64 = 15 * 60 * 1000;
+#[cfg(feature = "desktop")]
 const ALARM_CLOUD_LOOP_THRESHOLD: usize = 5;
+#[cfg(feature = "desktop")]
 const ALARM_WATCHDOG_THRASH_WINDOW_MS: u64 = 6 * 60 * 60 * 1000;
+#[cfg(feature = "desktop")]
 const ALARM_WATCHDOG_THRASH_THRESHOLD: usize = 3;
+#[cfg(feature = "desktop")]
 const ALARM_SCRAPE_ZERO_PERSIST_MIN_EXTRACTED: u64 = 5;
+#[cfg(feature = "desktop")]
 const ALARM_AUTH_ZOMBIE_RECHECK_STREAK: u32 = 3;
+#[cfg(feature = "desktop")]
 const ALARM_AUTH_ZOMBIE_RECONNECT_STREAK: u32 = 6;
+#[cfg(feature = "desktop")]
+const ALARM_BACKUP_FAILING_STREAK: u32 = 3;
 // One alarm of a given key per cooldown; the unfixed loops would otherwise emit
 // an alarm on every event once over threshold.
+#[cfg(feature = "desktop")]
 const ALARM_REFIRE_COOLDOWN_MS: u64 = 15 * 60 * 1000;
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Default)]
 struct InvariantAlarmState {
     /// tsMs of recent cloud uploads whose heads were unchanged.
@@ -1509,11 +1772,14 @@ struct InvariantAlarmState {
     main_recoveries: VecDeque<u64>,
     /// provider -> consecutive "ok but empty" scrape outcomes.
     auth_empty_streak: HashMap<String, u32>,
+    /// consecutive `backup_failed` events since the last `backup_completed`.
+    backup_failure_streak: u32,
     /// alarm key -> tsMs it last fired, for refire cooldown.
     last_fired_ms: HashMap<String, u64>,
 }
 
 /// A tripped invariant, ready to serialize as an `invariant_alarm` record.
+#[cfg(feature = "desktop")]
 struct InvariantAlarm {
     name: &'static str,
     provider: Option<String>,
@@ -1521,15 +1787,18 @@ struct InvariantAlarm {
     runbook: &'static str,
 }
 
+#[cfg(feature = "desktop")]
 static INVARIANT_ALARM_STATE: std::sync::LazyLock<StdMutex<InvariantAlarmState>> =
     std::sync::LazyLock::new(|| StdMutex::new(InvariantAlarmState::default()));
 
+#[cfg(feature = "desktop")]
 fn prune_before(times: &mut VecDeque<u64>, cutoff: u64) {
     while times.front().is_some_and(|&front| front < cutoff) {
         times.pop_front();
     }
 }
 
+#[cfg(feature = "desktop")]
 impl InvariantAlarmState {
     /// True if `key` has not fired within the cooldown; records `now` when it
     /// returns true so the caller can emit exactly once per window.
@@ -1669,6 +1938,32 @@ impl InvariantAlarmState {
         }
     }
 
+    fn observe_backup(
+        &mut self,
+        payload: &serde_json::Value,
+        ts: u64,
+        out: &mut Vec<InvariantAlarm>,
+    ) {
+        if payload.get("event").and_then(|v| v.as_str()) == Some("backup_completed") {
+            self.backup_failure_streak = 0;
+            return;
+        }
+        self.backup_failure_streak += 1;
+        let streak = self.backup_failure_streak;
+        if streak >= ALARM_BACKUP_FAILING_STREAK && self.take_refire_slot("backup_failing", ts) {
+            let error = payload
+                .get("error")
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown");
+            out.push(InvariantAlarm {
+                name: "backup_failing",
+                provider: None,
+                detail: format!("{streak} scheduled backups failed in a row; last error: {error}"),
+                runbook: "Off-disk backups are not being written. Check the backup folder is mounted and writable, and that archive backups still have a passphrase.",
+            });
+        }
+    }
+
     fn observe_main_recovery(&mut self, ts: u64, out: &mut Vec<InvariantAlarm>) {
         self.main_recoveries.push_back(ts);
         prune_before(
@@ -1695,6 +1990,7 @@ impl InvariantAlarmState {
 /// Passive observer over the runtime-health stream. Called from
 /// append_runtime_health after the event is written. Never recurses: alarms are
 /// appended with the low-level writer.
+#[cfg(feature = "desktop")]
 fn observe_invariant_alarm_event(data_dir: &Path, payload: &serde_json::Value) {
     let Some(event) = payload.get("event").and_then(|v| v.as_str()) else {
         return;
@@ -1717,6 +2013,7 @@ fn observe_invariant_alarm_event(data_dir: &Path, payload: &serde_json::Value) {
             "scrape_outcome" => state.observe_scrape_outcome(payload, ts, &mut alarms),
             "window_destroyed" => state.observe_window_destroyed(payload, ts, &mut alarms),
             "renderer_recovery_attempt" => state.observe_main_recovery(ts, &mut alarms),
+            "backup_completed" | "backup_failed" => state.observe_backup(payload, ts, &mut alarms),
             _ => {}
         }
     }
@@ -1745,7 +2042,7 @@ fn observe_invariant_alarm_event(data_dir: &Path, payload: &serde_json::Value) {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "desktop"))]
 mod invariant_alarm_tests {
     use super::*;
 
@@ -1901,8 +2198,26 @@ mod invariant_alarm_tests {
         state.observe_main_recovery(later, &mut out);
         assert!(out.is_empty(), "only one recovery inside the window");
     }
+
+    #[test]
+    fn backup_failing_fires_on_a_failure_streak_and_resets_on_success() {
+        let failed = serde_json::json!({ "event": "backup_failed", "error": "folder missing" });
+        let completed = serde_json::json!({ "event": "backup_completed" });
+        let mut state = InvariantAlarmState::default();
+        let mut out = Vec::new();
+        state.observe_backup(&failed, 10, &mut out);
+        state.observe_backup(&failed, 20, &mut out);
+        state.observe_backup(&completed, 30, &mut out);
+        state.observe_backup(&failed, 40, &mut out);
+        state.observe_backup(&failed, 50, &mut out);
+        assert!(out.is_empty(), "a success breaks the streak");
+        state.observe_backup(&failed, 60, &mut out);
+        assert_eq!(names(&out), vec!["backup_failing"]);
+        assert!(out[0].detail.contains("folder missing"));
+    }
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct DevSyncTriggerRequest {
@@ -1912,6 +2227,7 @@ struct DevSyncTriggerRequest {
     created_at: Option<u64>,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct DevSyncTriggerResult {
@@ -1922,6 +2238,7 @@ struct DevSyncTriggerResult {
     updated_at: u64,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct RuntimeHealthHeartbeat {
@@ -1929,6 +2246,7 @@ struct RuntimeHealthHeartbeat {
     ts_ms: Option<u64>,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct DesktopReleaseChannelState {
@@ -1937,14 +2255,17 @@ struct DesktopReleaseChannelState {
     installed_channel: Option<String>,
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_path(data_dir: &Path) -> PathBuf {
     data_dir.join(DEV_SYNC_TRIGGER_FILE)
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_result_path(data_dir: &Path) -> PathBuf {
     data_dir.join(DEV_SYNC_TRIGGER_RESULT_FILE)
 }
 
+#[cfg(feature = "desktop")]
 fn load_dev_sync_trigger_request(data_dir: &Path) -> Option<DevSyncTriggerRequest> {
     let raw = std::fs::read_to_string(dev_sync_trigger_path(data_dir)).ok()?;
     match serde_json::from_str::<DevSyncTriggerRequest>(&raw) {
@@ -1956,6 +2277,7 @@ fn load_dev_sync_trigger_request(data_dir: &Path) -> Option<DevSyncTriggerReques
     }
 }
 
+#[cfg(feature = "desktop")]
 fn write_dev_sync_trigger_result(
     data_dir: &Path,
     id: &str,
@@ -1982,16 +2304,19 @@ fn write_dev_sync_trigger_result(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn load_dev_sync_trigger_result(data_dir: &Path, id: &str) -> Option<DevSyncTriggerResult> {
     let raw = std::fs::read_to_string(dev_sync_trigger_result_path(data_dir)).ok()?;
     let result = serde_json::from_str::<DevSyncTriggerResult>(&raw).ok()?;
     (result.id == id).then_some(result)
 }
 
+#[cfg(feature = "desktop")]
 fn load_dev_sync_trigger_result_status(data_dir: &Path, id: &str) -> Option<String> {
     load_dev_sync_trigger_result(data_dir, id).map(|result| result.status)
 }
 
+#[cfg(feature = "desktop")]
 fn latest_runtime_health_background_job_active(data_dir: &Path, now_ms: u64) -> Option<bool> {
     // Two days so a heartbeat written just before the daily rollover is
     // still visible in the minutes after it (F-series trigger findings —
@@ -2020,6 +2345,7 @@ fn latest_runtime_health_background_job_active(data_dir: &Path, now_ms: u64) ->
     None
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_started_result_recoverable(data_dir: &Path, id: &str, now_ms: u64) -> bool {
     let Some(result) = load_dev_sync_trigger_result(data_dir, id) else {
         return false;
@@ -2033,6 +2359,7 @@ fn dev_sync_trigger_started_result_recoverable(data_dir: &Path, id: &str, now_ms
     latest_runtime_health_background_job_active(data_dir, now_ms) == Some(false)
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_waiting_result_recoverable(data_dir: &Path, id: &str, now_ms: u64) -> bool {
     let Some(result) = load_dev_sync_trigger_result(data_dir, id) else {
         return false;
@@ -2049,6 +2376,7 @@ fn dev_sync_trigger_waiting_result_recoverable(data_dir: &Path, id: &str, now_ms
     latest_runtime_health_background_job_active(data_dir, now_ms) == Some(false)
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_request_should_dispatch(
     last_handled_id: Option<&str>,
     request_id: &str,
@@ -2061,6 +2389,7 @@ fn dev_sync_trigger_request_should_dispatch(
     matches!(current_status, Some("waiting")) && waiting_recoverable
 }
 
+#[cfg(feature = "desktop")]
 fn is_current_dev_sync_trigger_request(data_dir: &Path, id: &str) -> bool {
     load_dev_sync_trigger_request(data_dir)
         .and_then(|request| request.id)
@@ -2068,6 +2397,7 @@ fn is_current_dev_sync_trigger_request(data_dir: &Path, id: &str) -> bool {
         == Some(id)
 }
 
+#[cfg(feature = "desktop")]
 fn write_current_dev_sync_trigger_result(
     data_dir: &Path,
     id: &str,
@@ -2086,14 +2416,17 @@ fn write_current_dev_sync_trigger_result(
     true
 }
 
+#[cfg(feature = "desktop")]
 fn is_dev_sync_trigger_terminal_status(status: &str) -> bool {
     matches!(status, "completed" | "error" | "ignored")
 }
 
+#[cfg(feature = "desktop")]
 fn is_supported_dev_sync_provider(provider: &str) -> bool {
     matches!(provider, "facebook" | "instagram" | "linkedin" | "youtube")
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_request_expiration_detail(
     request: &DevSyncTriggerRequest,
     now_ms: u64,
@@ -2107,6 +2440,7 @@ fn dev_sync_trigger_request_expiration_detail(
     None
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_triggers_enabled(data_dir: &Path) -> bool {
     if std::env::var("FREED_ENABLE_DEV_SYNC_TRIGGERS")
         .ok()
@@ -2132,10 +2466,12 @@ fn dev_sync_triggers_enabled(data_dir: &Path) -> bool {
         || state.installed_channel.as_deref() == Some("dev")
 }
 
+#[cfg(feature = "desktop")]
 fn escape_js_string(value: &str) -> String {
     serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string())
 }
 
+#[cfg(feature = "desktop")]
 fn dispatch_dev_sync_trigger_to_renderer(
     app: &tauri::AppHandle,
     request_id: &str,
@@ -2197,6 +2533,7 @@ fn dispatch_dev_sync_trigger_to_renderer(
     window.eval(&script).map_err(|error| error.to_string())
 }
 
+#[cfg(feature = "desktop")]
 fn dev_sync_trigger_keepalive_script(request_id: &str) -> String {
     let request_id = escape_js_string(request_id);
     let event_name = escape_js_string("dev-sync-trigger-native-result");
@@ -2228,6 +2565,7 @@ fn dev_sync_trigger_keepalive_script(request_id: &str) -> String {
     )
 }
 
+#[cfg(feature = "desktop")]
 fn start_dev_sync_trigger_keepalive(app: tauri::AppHandle, data_dir: PathBuf, request_id: String) {
     tauri::async_runtime::spawn(async move {
         let started_at = Instant::now();
@@ -2304,6 +2642,7 @@ fn start_dev_sync_trigger_keepalive(app: tauri::AppHandle, data_dir: PathBuf, re
     });
 }
 
+#[cfg(feature = "desktop")]
 fn handle_dev_sync_trigger_result_event(data_dir: &Path, payload: &str) {
     let Ok(result) = serde_json::from_str::<DevSyncTriggerResult>(payload) else {
         warn!("[dev-sync-trigger] failed to parse renderer result payload");
@@ -2318,6 +2657,7 @@ fn handle_dev_sync_trigger_result_event(data_dir: &Path, payload: &str) {
     );
 }
 
+#[cfg(feature = "desktop")]
 fn start_dev_sync_trigger_watcher(app: tauri::AppHandle, data_dir: PathBuf) {
     if !dev_sync_triggers_enabled(&data_dir) {
         info!("[dev-sync-trigger] native trigger watcher disabled");
@@ -2434,8 +2774,10 @@ fn start_dev_sync_trigger_watcher(app: tauri::AppHandle, data_dir: PathBuf) {
     });
 }
 
+#[cfg(feature = "desktop")]
 static LAST_DEEP_DIAGNOSTIC_AT: StdMutex<Option<Instant>> = StdMutex::new(None);
 
+#[cfg(feature = "desktop")]
 fn command_output_truncated(command: &str, args: &[String], max_chars: usize) -> Option<String> {
     let output = Command::new(command).args(args).output().ok()?;
     let combined = if output.status.success() {
@@ -2447,6 +2789,7 @@ fn command_output_truncated(command: &str, args: &[String], max_chars: usize) ->
     Some(truncate_for_log(&raw, max_chars))
 }
 
+#[cfg(feature = "desktop")]
 fn capture_deep_runtime_diagnostic(
     app: &tauri::AppHandle,
     trigger: &str,
@@ -2551,36 +2894,42 @@ fn capture_deep_runtime_diagnostic(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn reconcile_startup_recovery_state(data_dir: &Path) -> StartupRecoveryState {
     let mut state = load_startup_recovery_state(data_dir);
 
     if state.pending_boot_started_at_ms.take().is_some() {
         state.consecutive_failed_boots = state.consecutive_failed_boots.saturating_add(1);
         state.last_failed_boot_at_ms = Some(now_unix_ms());
+        state.recovery_tier = state.recovery_tier.escalate();
         save_startup_recovery_state(data_dir, &state);
         warn!(
-            "[recovery] detected unfinished startup, consecutive_failed_boots={}",
-            state.consecutive_failed_boots
+            "[recovery] detected unfinished startup, consecutive_failed_boots={} recovery_tier={:?}",
+            state.consecutive_failed_boots, state.recovery_tier
         );
     }
 
     state
 }
 
+#[cfg(feature = "desktop")]
 fn mark_startup_failed(data_dir: &Path) {
     let mut state = load_startup_recovery_state(data_dir);
     state.pending_boot_started_at_ms = None;
     state.consecutive_failed_boots = state.consecutive_failed_boots.saturating_add(1);
     state.last_failed_boot_at_ms = Some(now_unix_ms());
+    state.recovery_tier = state.recovery_tier.escalate();
     save_startup_recovery_state(data_dir, &state);
 }
 
+#[cfg(feature = "desktop")]
 fn mark_startup_pending(data_dir: &Path) {
     let mut state = load_startup_recovery_state(data_dir);
     state.pending_boot_started_at_ms = Some(now_unix_ms());
     save_startup_recovery_state(data_dir, &state);
 }
 
+#[cfg(feature = "desktop")]
 fn prepare_startup_recovery_retry(data_dir: &Path) {
     let mut state = load_startup_recovery_state(data_dir);
     state.pending_boot_started_at_ms = Some(now_unix_ms());
@@ -2588,23 +2937,183 @@ fn prepare_startup_recovery_retry(data_dir: &Path) {
     save_startup_recovery_state(data_dir, &state);
 }
 
+#[cfg(feature = "desktop")]
 fn mark_startup_success(data_dir: &Path) {
     let mut state = load_startup_recovery_state(data_dir);
-    if state.pending_boot_started_at_ms.is_none() && state.consecutive_failed_boots == 0 {
+    if state.pending_boot_started_at_ms.is_none()
+        && state.consecutive_failed_boots == 0
+        && state.recovery_tier == StartupRecoveryTier::None
+    {
         return;
     }
 
     state.pending_boot_started_at_ms = None;
     state.consecutive_failed_boots = 0;
     state.last_successful_boot_at_ms = Some(now_unix_ms());
+    state.recovery_tier = StartupRecoveryTier::None;
+    state.document_check = None;
     save_startup_recovery_state(data_dir, &state);
     info!("[recovery] renderer reached healthy startup state");
 }
 
+#[cfg(feature = "desktop")]
 fn startup_requires_recovery(state: &StartupRecoveryState) -> bool {
     state.consecutive_failed_boots > 0
 }
 
+#[cfg(feature = "desktop")]
+fn record_startup_recovery_action(
+    state: &mut StartupRecoveryState,
+    tier: StartupRecoveryTier,
+    detail: String,
+) -> StartupRecoveryAction {
+    let action = StartupRecoveryAction {
+        tier,
+        at_ms: now_unix_ms(),
+        detail,
+    };
+    state.recovery_actions.push(action.clone());
+    let excess = state
+        .recovery_actions
+        .len()
+        .saturating_sub(STARTUP_RECOVERY_ACTION_LIMIT);
+    state.recovery_actions.drain(..excess);
+    action
+}
+
+/// Whether the held relay document decodes, opens under the sync key, and
+/// loads as an Automerge document.
+#[cfg(feature = "desktop")]
+fn validate_held_relay_doc(data_dir: &Path, bytes: &[u8]) -> Result<(), String> {
+    let (doc, _) = decode_relay_doc_file(bytes)?;
+    let doc = match RelayPayloadKey::load(data_dir) {
+        Some(key) if sealed_payload_key_id(&doc).is_ok() => key.open(&doc)?,
+        _ => doc,
+    };
+    automerge::Automerge::load(&doc)
+        .map(|_| ())
+        .map_err(|error| format!("held document does not load: {error}"))
+}
+
+#[cfg(feature = "desktop")]
+fn check_startup_document(data_dir: &Path) -> StartupDocumentCheck {
+    let held_document_error = match std::fs::read(data_dir.join(RELAY_DOC_FILE)) {
+        Ok(bytes) => validate_held_relay_doc(data_dir, &bytes).err(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => Some(format!("failed to read held document: {error}")),
+    };
+    let snapshot_dir = snapshot_dir(data_dir);
+    let last_good = list_snapshots_in(&snapshot_dir)
+        .into_iter()
+        .find(
+            |snapshot| match load_snapshot_doc(&snapshot_dir, &snapshot.name) {
+                Ok(_) => true,
+                Err(error) => {
+                    warn!(
+                        "[recovery] snapshot {} is unusable: {}",
+                        snapshot.name, error
+                    );
+                    false
+                }
+            },
+        );
+    StartupDocumentCheck {
+        checked_at_ms: now_unix_ms(),
+        held_document_error,
+        last_good_snapshot_timestamp: last_good.as_ref().map(|snapshot| snapshot.timestamp),
+        last_good_snapshot: last_good.map(|snapshot| snapshot.name),
+    }
+}
+
+/// Applies the disk-side work of `state.recovery_tier` and records every tier
+/// it covers. Disabling scrapers is left to the caller, which owns the
+/// background runtime. Returns the actions recorded.
+#[cfg(feature = "desktop")]
+fn apply_startup_recovery_tier(
+    data_dir: &Path,
+    webkit_cache_root: Option<&Path>,
+    state: &mut StartupRecoveryState,
+) -> Vec<StartupRecoveryAction> {
+    let tier = state.recovery_tier;
+    let mut actions = Vec::new();
+    if tier >= StartupRecoveryTier::ScrapersDisabled {
+        actions.push(record_startup_recovery_action(
+            state,
+            StartupRecoveryTier::ScrapersDisabled,
+            "scrapers disabled for this launch".to_string(),
+        ));
+    }
+    if tier >= StartupRecoveryTier::WebkitCachesCleared {
+        let detail = match webkit_cache_root {
+            Some(root) => {
+                let result = clear_webkit_network_cache_root(root);
+                format!(
+                    "WebKit caches cleared before_bytes={} after_bytes={}",
+                    result.before_bytes, result.after_bytes
+                )
+            }
+            None => "WebKit cache directory unavailable".to_string(),
+        };
+        actions.push(record_startup_recovery_action(
+            state,
+            StartupRecoveryTier::WebkitCachesCleared,
+            detail,
+        ));
+    }
+    if tier >= StartupRecoveryTier::DocumentRollback {
+        let check = check_startup_document(data_dir);
+        let held = match &check.held_document_error {
+            Some(error) => format!("held document invalid: {error}"),
+            None => "held document ok".to_string(),
+        };
+        let snapshot = check
+            .last_good_snapshot
+            .as_deref()
+            .unwrap_or("no loadable snapshot");
+        actions.push(record_startup_recovery_action(
+            state,
+            StartupRecoveryTier::DocumentRollback,
+            format!("{held}; rollback candidate: {snapshot}"),
+        ));
+        state.document_check = Some(check);
+    }
+    save_startup_recovery_state(data_dir, state);
+    actions
+}
+
+/// Applies the recovery tier at launch: disables scrapers for tier 1 and up,
+/// does the disk-side work, and records each action in runtime health.
+#[cfg(feature = "desktop")]
+fn apply_startup_recovery_tier_for_launch(
+    app: &tauri::AppHandle,
+    data_dir: &Path,
+    state: &mut StartupRecoveryState,
+) {
+    if state.recovery_tier >= StartupRecoveryTier::ScrapersDisabled {
+        app.state::<CaptureState>()
+            .background_runtime
+            .disable_for_startup_recovery();
+    }
+    let webkit_cache_root = app
+        .path()
+        .app_cache_dir()
+        .ok()
+        .map(|cache_root| cache_root.join("WebKit"));
+    for action in apply_startup_recovery_tier(data_dir, webkit_cache_root.as_deref(), state) {
+        warn!("[recovery] tier {:?}: {}", action.tier, action.detail);
+        append_runtime_health(
+            app,
+            serde_json::json!({
+                "event": "startup_recovery_tier_applied",
+                "tier": action.tier,
+                "detail": action.detail,
+                "consecutiveFailedBoots": state.consecutive_failed_boots,
+            }),
+        );
+    }
+}
+
+#[cfg(feature = "desktop")]
 fn open_or_focus_recovery_window(
     app: &tauri::AppHandle,
 ) -> Result<tauri::WebviewWindow, tauri::Error> {
@@ -2635,6 +3144,7 @@ fn open_or_focus_recovery_window(
     Ok(window)
 }
 
+#[cfg(feature = "desktop")]
 fn read_recent_text_file(path: &Path, max_bytes: u64) -> Option<String> {
     let metadata = std::fs::metadata(path).ok()?;
     let raw = std::fs::read(path).ok()?;
@@ -2652,6 +3162,7 @@ fn read_recent_text_file(path: &Path, max_bytes: u64) -> Option<String> {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn write_startup_diagnostics_bundle(
     data_dir: &Path,
     downloads_dir: &Path,
@@ -2682,6 +3193,7 @@ fn write_startup_diagnostics_bundle(
             &data_dir.join("sync-health.json"),
             STARTUP_DIAGNOSTICS_MAX_FILE_BYTES,
         ),
+        "recentPanics": recent_rust_panics(data_dir, RUST_PANIC_DIAGNOSTICS_LIMIT),
     });
     let serialized = serde_json::to_vec_pretty(&diagnostics)
         .map_err(|error| format!("failed to serialize diagnostics: {}", error))?;
@@ -2691,232 +3203,159 @@ fn write_startup_diagnostics_bundle(
 }
 
 // ---------------------------------------------------------------------------
+// Panic hook
+//
+// A panic on a tokio task (relay, OAuth server, dev sync watcher) only unwinds
+// that task, so without a hook it left no trace outside stderr. The hook writes
+// a bounded `rust_panic` runtime-health record and, while a boot is still
+// pending, marks that boot failed so the next launch opens startup recovery.
 // ---------------------------------------------------------------------------
-// mDNS advertisement
-// ---------------------------------------------------------------------------
-
-/// Keeps the mDNS daemon alive for the application lifetime.
-/// The `Drop` impl shuts the daemon down cleanly on exit.
-struct MdnsState(Option<mdns_sd::ServiceDaemon>);
-
-impl Drop for MdnsState {
-    fn drop(&mut self) {
-        if let Some(daemon) = self.0.take() {
-            let _ = daemon.shutdown();
-        }
-    }
-}
-
-/// Register `_freed-sync._tcp.local` so future native clients can discover
-/// the relay without a QR scan.  The pairing token is intentionally absent
-/// from TXT records — discovery reveals the host/port, not the secret.
-fn advertise_mdns(port: u16) -> Option<mdns_sd::ServiceDaemon> {
-    use mdns_sd::{ServiceDaemon, ServiceInfo};
-
-    let daemon = ServiceDaemon::new()
-        .map_err(|e| error!("[mDNS] Failed to create daemon: {}", e))
-        .ok()?;
-
-    let hostname = hostname::get()
-        .ok()
-        .and_then(|h| h.into_string().ok())
-        .unwrap_or_else(|| "freed-desktop".to_string());
-
-    let fqdn = format!("{}.local.", hostname);
-
-    let mut properties = std::collections::HashMap::new();
-    properties.insert("v".to_string(), "1".to_string());
-    properties.insert("app".to_string(), "freed".to_string());
-
-    // Pass `()` so mdns-sd auto-discovers all local interfaces — avoids
-    // hard-coding the LAN IP and handles multi-homed machines gracefully.
-    let service = ServiceInfo::new(
-        "_freed-sync._tcp.local.",
-        "Freed Desktop",
-        &fqdn,
-        (),
-        port,
-        Some(properties),
-    )
-    .map_err(|e| error!("[mDNS] Failed to build ServiceInfo: {}", e))
-    .ok()?;
-
-    daemon
-        .register(service)
-        .map_err(|e| error!("[mDNS] Failed to register service: {}", e))
-        .ok()?;
 
-    info!("[mDNS] Advertising _freed-sync._tcp.local on port {}", port);
-    Some(daemon)
-}
+/// Set once setup resolves the app data directory. Panics before that only
+/// reach the default hook.
+#[cfg(feature = "desktop")]
+static PANIC_DATA_DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-struct SyncRelayBindRetryPolicy {
-    max_retries: usize,
-    retry_delay: Duration,
+thread_local! {
+    static IN_PANIC_HOOK: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
 }
 
-const DEFAULT_SYNC_RELAY_BIND_RETRY_POLICY: SyncRelayBindRetryPolicy = SyncRelayBindRetryPolicy {
-    max_retries: SYNC_RELAY_BIND_MAX_RETRIES,
-    retry_delay: SYNC_RELAY_BIND_RETRY_DELAY,
-};
-
-fn sync_relay_bind_retry_delay(
-    policy: SyncRelayBindRetryPolicy,
-    error: &std::io::Error,
-    retries_used: usize,
-) -> Option<Duration> {
-    if error.kind() != std::io::ErrorKind::AddrInUse || retries_used >= policy.max_retries {
-        return None;
-    }
-
-    Some(policy.retry_delay)
+/// Chains a recording hook after the default one, which still prints to stderr.
+#[cfg(feature = "desktop")]
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        // A panic while recording must not recurse into the hook.
+        if IN_PANIC_HOOK.with(|active| active.replace(true)) {
+            return;
+        }
+        if let Some(data_dir) = PANIC_DATA_DIR.get() {
+            let message = info
+                .payload()
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("non-string panic payload");
+            let location = info.location().map(|location| {
+                format!(
+                    "{}:{}:{}",
+                    location.file(),
+                    location.line(),
+                    location.column()
+                )
+            });
+            let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+            let record = rust_panic_record(
+                std::thread::current().name(),
+                location.as_deref(),
+                message,
+                &backtrace,
+            );
+            record_rust_panic(data_dir, record);
+        }
+        IN_PANIC_HOOK.with(|active| active.set(false));
+    }));
+}
+
+/// The backtrace is reduced to a hash so repeats of one panic group together
+/// without putting symbol paths in the record.
+#[cfg(feature = "desktop")]
+fn rust_panic_record(
+    thread: Option<&str>,
+    location: Option<&str>,
+    message: &str,
+    backtrace: &str,
+) -> serde_json::Value {
+    let truncated = message.chars().count() > RUST_PANIC_MESSAGE_MAX_CHARS;
+    let message: String = message.chars().take(RUST_PANIC_MESSAGE_MAX_CHARS).collect();
+    let backtrace_hash = sha256_hex(&Sha256::digest(backtrace.as_bytes())[..8]);
+    serde_json::json!({
+        "event": "rust_panic",
+        "tsMs": now_unix_ms(),
+        "thread": thread.unwrap_or("<unnamed>"),
+        "location": location,
+        "message": message,
+        "messageTruncated": truncated,
+        "backtraceHash": backtrace_hash,
+    })
 }
 
-async fn bind_sync_relay_listener_with_policy(
-    addr: &str,
-    policy: SyncRelayBindRetryPolicy,
-) -> std::io::Result<(TcpListener, usize)> {
-    let mut retries_used = 0;
-    loop {
-        match TcpListener::bind(addr).await {
-            Ok(listener) => return Ok((listener, retries_used)),
-            Err(error) => {
-                let Some(retry_delay) = sync_relay_bind_retry_delay(policy, &error, retries_used)
-                else {
-                    return Err(error);
-                };
-
-                retries_used += 1;
-                warn!(
-                    "[Sync] Relay port is still busy on {}. retry_attempt={}/{} retry_delay_ms={}",
-                    addr,
-                    retries_used,
-                    policy.max_retries,
-                    retry_delay.as_millis()
-                );
-                tokio::time::sleep(retry_delay).await;
-            }
+#[cfg(feature = "desktop")]
+fn record_rust_panic(data_dir: &Path, record: serde_json::Value) {
+    error!(
+        "[panic] thread {} panicked at {}: {}",
+        record["thread"], record["location"], record["message"]
+    );
+    if let Ok(line) = serde_json::to_string(&record) {
+        if let Err(error) = append_runtime_health_line(data_dir, &line) {
+            warn!("[panic] failed to record panic: {}", error);
         }
     }
+    if load_startup_recovery_state(data_dir)
+        .pending_boot_started_at_ms
+        .is_some()
+    {
+        mark_startup_failed(data_dir);
+        warn!("[recovery] panic before a healthy startup; marked the boot failed");
+    }
 }
 
-// ---------------------------------------------------------------------------
-// Local snapshot rotation (grandfather-father-son)
-// ---------------------------------------------------------------------------
+/// The newest `limit` `rust_panic` records in retained runtime health,
+/// oldest first.
+#[cfg(feature = "desktop")]
+fn recent_rust_panics(data_dir: &Path, limit: usize) -> Vec<serde_json::Value> {
+    let raw = read_runtime_health_recent_days(data_dir, RUNTIME_HEALTH_RETAIN_DAYS);
+    let mut panics: Vec<serde_json::Value> = raw
+        .lines()
+        .filter(|line| line.contains("\"rust_panic\""))
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|record| record["event"] == "rust_panic")
+        .collect();
+    panics.split_off(panics.len().saturating_sub(limit))
+}
 
-/// Write a timestamped Automerge snapshot to `{app_data}/snapshots/` and
-/// prune old files using a GFS scheme:
-///   - last 60 minutely  (≤ 1 hour old)
-///   - last 24 hourly    (1–24 hours old)
-///   - last 30 daily     (> 24 hours old)
-#[cfg_attr(feature = "perf", tracing::instrument(skip(doc_bytes), fields(bytes = doc_bytes.len())))]
-fn write_snapshot(snapshot_dir: &std::path::Path, doc_bytes: &[u8]) {
-    use std::time::{SystemTime, UNIX_EPOCH};
+/// Replaces `path` via a synced temp file so a crash never leaves it torn.
+fn write_file_atomically(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    use std::io::Write;
 
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()
+    };
+    write().map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    std::fs::rename(&tmp_path, path)
+        .map_err(|e| format!("failed to replace {}: {}", path.display(), e))
+}
 
-    if let Err(e) = std::fs::create_dir_all(snapshot_dir) {
-        error!("[Snapshot] Failed to create dir: {}", e);
-        return;
+#[cfg(feature = "desktop")]
+impl RelayEventSink for tauri::AppHandle {
+    fn client_count_changed(&self, count: usize) {
+        let _ = self.emit("sync-client-count", count);
     }
 
-    let path = snapshot_dir.join(format!("freed-{}.automerge", ts));
-    if let Err(e) = std::fs::write(&path, doc_bytes) {
-        error!("[Snapshot] Failed to write: {}", e);
-        return;
+    fn record_health(&self, event: serde_json::Value) {
+        append_runtime_health(self, event);
     }
 
-    prune_snapshots(snapshot_dir, ts);
-}
-
-#[cfg_attr(feature = "perf", tracing::instrument(skip(snapshot_dir)))]
-fn prune_snapshots(snapshot_dir: &std::path::Path, now_secs: u64) {
-    use std::cmp::Reverse;
-
-    let mut entries: Vec<(u64, std::path::PathBuf)> = std::fs::read_dir(snapshot_dir)
-        .into_iter()
-        .flatten()
-        .flatten()
-        .filter_map(|e| {
-            let name = e.file_name().into_string().ok()?;
-            let ts: u64 = name
-                .strip_prefix("freed-")?
-                .strip_suffix(".automerge")?
-                .parse()
-                .ok()?;
-            Some((ts, e.path()))
-        })
-        .collect();
+    fn sync_peers_changed(&self, peers: &[SyncPeerSummary]) {
+        let _ = self.emit("sync-peers-changed", peers.to_vec());
+    }
 
-    entries.sort_by_key(|(ts, _)| Reverse(*ts));
-
-    let mut kept: HashSet<std::path::PathBuf> = Default::default();
-    let (mut minutely, mut hourly, mut daily) = (0usize, 0usize, 0usize);
-    let mut last_hour_bucket = u64::MAX;
-    let mut last_day_bucket = u64::MAX;
-
-    for (ts, path) in &entries {
-        let age = now_secs.saturating_sub(*ts);
-        if age < 3_600 && minutely < 60 {
-            kept.insert(path.clone());
-            minutely += 1;
-        } else if age < 86_400 {
-            let bucket = age / 3_600;
-            if bucket != last_hour_bucket && hourly < 24 {
-                kept.insert(path.clone());
-                last_hour_bucket = bucket;
-                hourly += 1;
-            }
-        } else {
-            let bucket = age / 86_400;
-            if bucket != last_day_bucket && daily < 30 {
-                kept.insert(path.clone());
-                last_day_bucket = bucket;
-                daily += 1;
-            }
-        }
+    fn sync_peer_doc_merged(&self, fingerprint: &str) {
+        let _ = self.emit("sync-peer-doc", fingerprint.to_string());
     }
 
-    for (_, path) in &entries {
-        if !kept.contains(path) {
-            let _ = std::fs::remove_file(path);
-        }
+    fn held_doc_unreadable(&self) {
+        let _ = self.emit("sync-held-doc-unreadable", ());
     }
 }
 
-// Relay state
-// ---------------------------------------------------------------------------
-
-struct SyncRelayState {
-    port: u16,
-    /// Serializes token snapshots and document exchange against relay reset.
-    epoch_gate: RwLock<()>,
-    /// Broadcast channel — sends doc bytes to all connected clients.
-    broadcast_tx: broadcast::Sender<Arc<Vec<u8>>>,
-    /// Latest doc binary, served to new joiners immediately on connect.
-    current_doc: RwLock<Option<Arc<Vec<u8>>>>,
-    /// Disconnect signal for every connection authenticated before a factory reset.
-    disconnect_tx: broadcast::Sender<u64>,
-    /// Incremented before factory-reset relay state is cleared.
-    generation: std::sync::atomic::AtomicU64,
-    /// Blocks renderer and mobile writes until local document deletion completes.
-    accepting_doc_updates: std::sync::atomic::AtomicBool,
-    /// Live connection count (displayed in tray / sync indicator).
-    client_count: RwLock<usize>,
-    /// Pairing token — must appear as `?t=<token>` in the WS upgrade URI.
-    ///
-    /// Uses `std::sync::RwLock` (not Tokio's) because it is never held
-    /// across an `.await` point; it is read/written synchronously and the
-    /// guard is dropped before any async work begins.
-    pairing_token: StdRwLock<String>,
-}
-
-type RelayState = Arc<SyncRelayState>;
-
+#[cfg(feature = "desktop")]
 #[derive(Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RuntimeMemoryStats {
@@ -2952,6 +3391,7 @@ struct RuntimeMemoryStats {
     relay_client_count: u64,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct WebkitProcessRuntimeStats {
@@ -2964,12 +3404,14 @@ struct WebkitProcessRuntimeStats {
     role: String,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy)]
 struct RuntimeMemoryStatsOptions {
     include_storage_sizes: bool,
     precise_webkit_attribution: bool,
 }
 
+#[cfg(feature = "desktop")]
 impl RuntimeMemoryStatsOptions {
     fn full() -> Self {
         Self {
@@ -2979,6 +3421,7 @@ impl RuntimeMemoryStatsOptions {
     }
 }
 
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 struct ScraperRecycleVerification {
@@ -2993,6 +3436,7 @@ struct ScraperRecycleVerification {
     webkit_resident_delta_bytes: i64,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct AIHardwareProfile {
@@ -3004,6 +3448,7 @@ struct AIHardwareProfile {
     web_gpu_available: bool,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct ScrapeMemoryPreparation {
@@ -3016,6 +3461,7 @@ struct ScrapeMemoryPreparation {
     may_proceed: bool,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct SocialScrapePlan {
@@ -3025,6 +3471,7 @@ struct SocialScrapePlan {
     reason: &'static str,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct WebkitCacheTrimResult {
@@ -3033,6 +3480,7 @@ struct WebkitCacheTrimResult {
     cache_trimmed: bool,
 }
 
+#[cfg(feature = "desktop")]
 struct WebkitMemoryStats {
     total_resident_bytes: u64,
     total_footprint_bytes: Option<u64>,
@@ -3047,12 +3495,14 @@ struct WebkitMemoryStats {
     processes: Vec<WebkitProcessRuntimeStats>,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone)]
 struct ActiveBackgroundJob {
     operation: &'static str,
     started_at: Instant,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug)]
 struct BackgroundRuntimeState {
     healthy_heartbeats: u64,
@@ -3060,12 +3510,15 @@ struct BackgroundRuntimeState {
     cooldown_until: Option<Instant>,
     memory_cooldown_until: Option<Instant>,
     safe_mode_until: Option<Instant>,
+    /// Set for the whole launch by startup recovery tier 1.
+    disabled_for_startup_recovery: bool,
     recovery_history: VecDeque<Instant>,
     active_job: Option<ActiveBackgroundJob>,
     last_recovery_reason: Option<String>,
     last_memory_pressure_reason: Option<String>,
 }
 
+#[cfg(feature = "desktop")]
 impl BackgroundRuntimeState {
     fn new() -> Self {
         Self {
@@ -3074,6 +3527,7 @@ impl BackgroundRuntimeState {
             cooldown_until: None,
             memory_cooldown_until: None,
             safe_mode_until: None,
+            disabled_for_startup_recovery: false,
             recovery_history: VecDeque::new(),
             active_job: None,
             last_recovery_reason: None,
@@ -3082,11 +3536,13 @@ impl BackgroundRuntimeState {
     }
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug)]
 struct BackgroundRuntimeCoordinator {
     state: StdRwLock<BackgroundRuntimeState>,
 }
 
+#[cfg(feature = "desktop")]
 impl BackgroundRuntimeCoordinator {
     fn new() -> Self {
         Self {
@@ -3187,10 +3643,20 @@ impl BackgroundRuntimeCoordinator {
         Some(remaining_ms)
     }
 
+    fn disable_for_startup_recovery(&self) {
+        self.state.write().unwrap().disabled_for_startup_recovery = true;
+    }
+
     fn begin_job(&self, operation: &'static str) -> Result<(), String> {
         let now = Instant::now();
         let mut state = self.state.write().unwrap();
 
+        if state.disabled_for_startup_recovery {
+            return Err(
+                "background work is off while Freed recovers from a failed startup".to_string(),
+            );
+        }
+
         if state.healthy_heartbeats < BACKGROUND_REQUIRED_HEALTHY_HEARTBEATS {
             return Err(format!(
                 "background work is waiting for {} healthy renderer heartbeats",
@@ -3366,6 +3832,7 @@ impl BackgroundRuntimeCoordinator {
 
 /// Per-session user agent strings set by TypeScript at platform connect time,
 /// plus a shared rquest HTTP client with persistent connection pooling.
+#[cfg(feature = "desktop")]
 struct CaptureState {
     fb_user_agent: std::sync::Mutex<String>,
     ig_user_agent: std::sync::Mutex<String>,
@@ -3377,6 +3844,7 @@ struct CaptureState {
     x_client: rquest::Client,
 }
 
+#[cfg(feature = "desktop")]
 impl CaptureState {
     fn new() -> Self {
         // rquest 5.x uses rquest_util::Emulation for Chrome TLS fingerprinting.
@@ -3400,6 +3868,7 @@ impl CaptureState {
     }
 }
 
+#[cfg(feature = "desktop")]
 struct ActiveScraperSession {
     _guard: tokio::sync::OwnedMutexGuard<()>,
     background_runtime: Arc<BackgroundRuntimeCoordinator>,
@@ -3407,6 +3876,7 @@ struct ActiveScraperSession {
     acquired_at: std::time::Instant,
 }
 
+#[cfg(feature = "desktop")]
 impl Drop for ActiveScraperSession {
     fn drop(&mut self) {
         let runtime_held_ms = self
@@ -3429,6 +3899,7 @@ impl Drop for ActiveScraperSession {
     }
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct RendererHeartbeatPayload {
@@ -3450,6 +3921,7 @@ struct RendererHeartbeatPayload {
     dialog_open: Option<bool>,
 }
 
+#[cfg(feature = "desktop")]
 struct RendererHeartbeatStatus {
     started_at: std::time::Instant,
     last_seen_at: Option<std::time::Instant>,
@@ -3476,6 +3948,7 @@ struct RendererHeartbeatStatus {
     recovery_history: VecDeque<std::time::Instant>,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone)]
 struct RendererMemorySample {
     sampled_at: std::time::Instant,
@@ -3497,6 +3970,7 @@ struct RendererMemorySample {
     memory_critical_bytes: u64,
 }
 
+#[cfg(feature = "desktop")]
 impl RendererMemorySample {
     fn from_stats(sampled_at: std::time::Instant, stats: RuntimeMemoryStats) -> Self {
         Self {
@@ -3521,6 +3995,7 @@ impl RendererMemorySample {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_memory_sample_due(
     last_sampled_at: Option<std::time::Instant>,
     now: std::time::Instant,
@@ -3530,6 +4005,7 @@ fn renderer_memory_sample_due(
         .unwrap_or(true)
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_memory_health_fields(
     sample: Option<&RendererMemorySample>,
     now: std::time::Instant,
@@ -3615,6 +4091,7 @@ fn renderer_memory_health_fields(
     fields
 }
 
+#[cfg(feature = "desktop")]
 impl RendererHeartbeatStatus {
     fn new() -> Self {
         Self {
@@ -3723,11 +4200,12 @@ impl RendererHeartbeatStatus {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "desktop"))]
 fn renderer_recovery_threshold(is_visible: bool, last_visibility: &str) -> Duration {
     renderer_recovery_threshold_for_count(is_visible, last_visibility, 0)
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_recovery_threshold_for_count(
     is_visible: bool,
     last_visibility: &str,
@@ -3744,10 +4222,12 @@ fn renderer_recovery_threshold_for_count(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_is_effectively_visible(is_visible: bool, last_visibility: &str) -> bool {
     is_visible && last_visibility != "hidden"
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_watchdog_treats_as_visible(
     is_visible: bool,
     is_focused: bool,
@@ -3759,6 +4239,7 @@ fn renderer_watchdog_treats_as_visible(
     )
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_watchdog_last_visibility_for_policy<'a>(
     is_visible: bool,
     is_focused: bool,
@@ -3771,6 +4252,7 @@ fn renderer_watchdog_last_visibility_for_policy<'a>(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_stale_log_after(is_visible: bool, last_visibility: &str) -> Duration {
     if renderer_is_effectively_visible(is_visible, last_visibility) {
         RENDERER_STALE_LOG_AFTER
@@ -3779,10 +4261,12 @@ fn renderer_stale_log_after(is_visible: bool, last_visibility: &str) -> Duration
     }
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_stale_log_should_pause_background(is_visible: bool, last_visibility: &str) -> bool {
     renderer_is_effectively_visible(is_visible, last_visibility)
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_stale_log_should_capture_deep_diagnostic(
     is_visible: bool,
     last_visibility: &str,
@@ -3790,6 +4274,7 @@ fn renderer_stale_log_should_capture_deep_diagnostic(
     renderer_is_effectively_visible(is_visible, last_visibility)
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_gap_is_expected_hidden_throttle(
     is_visible: bool,
     last_visibility: &str,
@@ -3802,6 +4287,7 @@ fn renderer_gap_is_expected_hidden_throttle(
         && age >= WEBKIT_HIDDEN_TIMER_THROTTLE_AFTER
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_health_hidden_timer_throttled(
     expected_hidden_throttle: bool,
     last_hidden_timer_throttled: Option<bool>,
@@ -3813,10 +4299,12 @@ fn renderer_health_hidden_timer_throttled(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_stale_should_recover(is_visible: bool, last_visibility: &str) -> bool {
     renderer_is_effectively_visible(is_visible, last_visibility)
 }
 
+#[cfg(feature = "desktop")]
 fn renderer_event_loop_lag_should_recover(
     is_visible: bool,
     last_visibility: &str,
@@ -3828,7 +4316,7 @@ fn renderer_event_loop_lag_should_recover(
             .unwrap_or(false)
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "desktop"))]
 fn main_renderer_memory_should_recover(
     is_visible: bool,
     last_visibility: &str,
@@ -3839,6 +4327,7 @@ fn main_renderer_memory_should_recover(
         .is_some()
 }
 
+#[cfg(feature = "desktop")]
 fn main_renderer_memory_recovery_reason(
     is_visible: bool,
     last_visibility: &str,
@@ -3906,6 +4395,7 @@ fn main_renderer_memory_recovery_reason(
     None
 }
 
+#[cfg(feature = "desktop")]
 fn main_renderer_idle_webkit_resident_tail_should_recover(stats: &RuntimeMemoryStats) -> bool {
     if !webkit_resident_tail_is_probably_reclaimable(stats) {
         return false;
@@ -3921,6 +4411,7 @@ fn main_renderer_idle_webkit_resident_tail_should_recover(stats: &RuntimeMemoryS
     resident_hot && cpu_idle
 }
 
+#[cfg(feature = "desktop")]
 fn main_renderer_visible_webkit_resident_tail_should_recover(stats: &RuntimeMemoryStats) -> bool {
     if !webkit_resident_tail_is_probably_reclaimable(stats) {
         return false;
@@ -3936,6 +4427,7 @@ fn main_renderer_visible_webkit_resident_tail_should_recover(stats: &RuntimeMemo
     over_high_memory && cpu_idle
 }
 
+#[cfg(feature = "desktop")]
 fn main_renderer_hot_webkit_activity_should_recover(stats: &RuntimeMemoryStats) -> bool {
     let resident_hot = stats
         .webkit_largest_resident_bytes
@@ -3958,6 +4450,7 @@ fn main_renderer_hot_webkit_activity_should_recover(stats: &RuntimeMemoryStats)
     resident_hot && footprint_hot && cpu_hot && below_high_memory_limit
 }
 
+#[cfg(feature = "desktop")]
 fn main_renderer_webkit_role_can_recover(role: Option<&str>) -> bool {
     matches!(
         role,
@@ -3965,6 +4458,7 @@ fn main_renderer_webkit_role_can_recover(role: Option<&str>) -> bool {
     )
 }
 
+#[cfg(feature = "desktop")]
 fn main_renderer_recovery_verification_should_restart(
     before: &RuntimeMemoryStats,
     after: &RuntimeMemoryStats,
@@ -4000,6 +4494,7 @@ fn main_renderer_recovery_verification_should_restart(
     still_high && barely_reclaimed
 }
 
+#[cfg(feature = "desktop")]
 fn format_bytes_for_log(bytes: u64) -> String {
     const KIB: f64 = 1024.0;
     const MIB: f64 = KIB * 1024.0;
@@ -4017,6 +4512,7 @@ fn format_bytes_for_log(bytes: u64) -> String {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn format_duration_for_user(duration: Duration) -> String {
     let total_seconds = duration.as_secs().max(1);
     if total_seconds < 60 {
@@ -4035,7 +4531,7 @@ fn format_duration_for_user(duration: Duration) -> String {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "desktop"))]
 mod renderer_watchdog_tests {
     use super::*;
 
@@ -4961,6 +5457,7 @@ mod renderer_watchdog_tests {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn truncate_for_log(value: &str, max_chars: usize) -> String {
     let mut chars = value.chars();
     let truncated: String = chars.by_ref().take(max_chars).collect();
@@ -4971,6 +5468,7 @@ fn truncate_for_log(value: &str, max_chars: usize) -> String {
     }
 }
 
+#[cfg(feature = "desktop")]
 async fn acquire_background_scraper_session(
     capture: &CaptureState,
     operation: &'static str,
@@ -5012,16 +5510,19 @@ async fn acquire_background_scraper_session(
 // Tauri commands — meta
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_platform() -> String {
     std::env::consts::OS.to_string()
 }
 
+#[cfg(feature = "desktop")]
 fn hash_desktop_installation_witness(machine_id: &str, user_id: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(b"freed-desktop-installation-witness-v1\0");
@@ -5076,7 +5577,7 @@ fn platform_machine_identifier() -> Result<String, String> {
         .ok_or_else(|| "Windows returned an invalid machine identifier".to_string())
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(all(feature = "desktop", target_os = "linux"))]
 fn platform_machine_identifier() -> Result<String, String> {
     ["/etc/machine-id", "/var/lib/dbus/machine-id"]
         .iter()
@@ -5097,7 +5598,7 @@ fn platform_machine_identifier() -> Result<String, String> {
         .map_err(|_| "the machine hostname is not valid UTF-8".to_string())
 }
 
-#[cfg(unix)]
+#[cfg(all(feature = "desktop", unix))]
 fn platform_user_identifier() -> String {
     format!("uid:{}", unsafe { libc::geteuid() })
 }
@@ -5114,6 +5615,7 @@ fn platform_user_identifier() -> String {
     std::env::var("USER").unwrap_or_else(|_| "unknown-user".to_string())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_desktop_installation_witness() -> Result<String, String> {
     let machine_id = platform_machine_identifier()?;
@@ -5123,6 +5625,7 @@ fn get_desktop_installation_witness() -> Result<String, String> {
     ))
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_updater_target() -> String {
     let os = match std::env::consts::OS {
@@ -5138,6 +5641,7 @@ fn get_updater_target() -> String {
 // ---------------------------------------------------------------------------
 
 /// Result type returned to the frontend for contact picking.
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct ContactResult {
     name: String,
@@ -5162,6 +5666,7 @@ struct ContactResult {
 /// TODO: implement macOS CNContactStore picker using objc2-contacts.
 /// Until then this command always returns an authorization-pending error so
 /// callers can display a helpful message rather than silently failing.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn pick_contact() -> Result<Option<ContactResult>, String> {
     #[cfg(target_os = "macos")]
@@ -5182,6 +5687,7 @@ async fn pick_contact() -> Result<Option<ContactResult>, String> {
 // ---------------------------------------------------------------------------
 
 /// Fetch any URL and return its body as text (bypasses browser CORS).
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fetch_url(url: String, max_bytes: Option<usize>) -> Result<String, String> {
     let client = reqwest::Client::builder()
@@ -5228,6 +5734,7 @@ async fn fetch_url(url: String, max_bytes: Option<usize>) -> Result<String, Stri
     Ok(String::from_utf8_lossy(&body).into_owned())
 }
 
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize)]
 struct NativeHttpResponse {
     status: u16,
@@ -5235,6 +5742,7 @@ struct NativeHttpResponse {
     body: Vec<u8>,
 }
 
+#[cfg(feature = "desktop")]
 fn response_headers(response: &reqwest::Response) -> Vec<(String, String)> {
     response
         .headers()
@@ -5249,6 +5757,7 @@ fn response_headers(response: &reqwest::Response) -> Vec<(String, String)> {
 }
 
 /// Fetch a Google People API URL with a bearer token.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn google_api_request(
     url: String,
@@ -5296,6 +5805,7 @@ async fn google_api_request(
 }
 
 /// Make a Google Drive API request through the native networking stack.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn google_drive_request(
     url: String,
@@ -5357,6 +5867,7 @@ async fn google_drive_request(
 }
 
 /// POST to Google OAuth endpoints through native networking.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn google_oauth_proxy_request(
     url: String,
@@ -5420,6 +5931,7 @@ async fn google_oauth_proxy_request(
 }
 
 /// Fetch any URL and return its body as bytes for permanent local media archive.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fetch_binary_url(url: String) -> Result<Vec<u8>, String> {
     let client = reqwest::Client::builder()
@@ -5451,6 +5963,7 @@ async fn fetch_binary_url(url: String) -> Result<Vec<u8>, String> {
 /// directly to x.com. This matters in dev where the shell may export an
 /// HTTPS_PROXY (e.g. Cursor's safe-chain) whose TLS cert is not trusted by
 /// the Rust native-tls stack, causing a silent connection failure.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn x_api_request(
     capture: tauri::State<'_, CaptureState>,
@@ -5491,6 +6004,7 @@ async fn x_api_request(
 // Tauri commands — sync
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_local_ip() -> Result<String, String> {
     local_ip_address::local_ip()
@@ -5498,97 +6012,336 @@ fn get_local_ip() -> Result<String, String> {
         .map_err(|e| e.to_string())
 }
 
-/// Get all non-loopback IPv4 addresses with their interface names.
+/// Get the non-loopback addresses the relay serves, with their interface
+/// names. IPv6 addresses are included only when the relay accepts IPv6.
 /// Useful for diagnosing cases where the primary IP is a VPN tunnel
 /// rather than the Wi-Fi interface the phone is connected to.
+#[cfg(feature = "desktop")]
 #[tauri::command]
-fn get_all_local_ips() -> Vec<serde_json::Value> {
-    let port = sync_relay_port();
-    match local_ip_address::list_afinet_netifas() {
-        Ok(ifaces) => ifaces
-            .into_iter()
-            .filter(|(_, ip)| {
-                // IPv4 only, skip loopback
-                matches!(ip, std::net::IpAddr::V4(v4) if !v4.is_loopback())
-            })
-            .map(|(name, ip)| {
-                serde_json::json!({
-                    "interface": name,
-                    "ip": ip.to_string(),
-                    "url": format!("ws://{}:{}", ip, port),
-                })
+fn get_all_local_ips(state: tauri::State<'_, RelayState>) -> Vec<serde_json::Value> {
+    let port = state.listen_port();
+    let settings = RelayNetworkSettings {
+        interface: None,
+        ..state.network_tx.borrow().clone()
+    };
+    let interfaces = local_ip_address::list_afinet_netifas().unwrap_or_default();
+    relay_interface_addresses(&settings, &interfaces)
+        .into_iter()
+        .map(|(name, ip)| {
+            let host = match ip {
+                std::net::IpAddr::V4(v4) => v4.to_string(),
+                std::net::IpAddr::V6(v6) => format!("[{}]", v6),
+            };
+            serde_json::json!({
+                "interface": name,
+                "ip": ip.to_string(),
+                "url": format!("{}://{}:{}", state.pairing_scheme(), host, port),
             })
-            .collect(),
-        Err(_) => vec![],
-    }
+        })
+        .collect()
 }
 
-/// Returns the full WebSocket pairing URL including the auth token.
+/// Returns the full WebSocket pairing URL including a fresh pairing code.
+///
+/// Format: `ws://<lan-ip>:<port>?pc=<base64url-code>&fp=<base64url-sha256>&a=<host>,<host>#k=<base64url-key>`
 ///
-/// Format: `ws://<lan-ip>:<port>?t=<base64url-token>`
+/// This URL is encoded into the QR code shown in the Mobile Sync tab. The code
+/// pairs exactly one device and expires after `PAIRING_CODE_TTL`; the device
+/// derives its own credential from it. `fp` lets clients that can pin the
+/// relay's self-signed certificate use TLS, and the `k` fragment carries the
+/// sync key, which is
+/// never part of the upgrade request. The host is the likeliest LAN address
+/// under the relay network settings; `a` lists the other candidates.
 ///
-/// This URL is encoded into the QR code shown in the Mobile Sync tab.
-/// Only devices that scan the QR code (i.e. know the token) can connect.
+/// With a rendezvous server configured the URL points at the relay's room on
+/// that server instead: `wss://<server>/client/<room>?pc=<code>#k=<key>`.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn get_sync_url(state: tauri::State<'_, RelayState>) -> Result<String, String> {
     let _epoch = state.epoch_gate.read().await;
-    let port = state.port;
-    let token = state.pairing_token.read().unwrap().clone();
-    let ip = local_ip_address::local_ip()
-        .map(|ip| ip.to_string())
-        .unwrap_or_else(|_| "localhost".to_string());
-    Ok(format!("ws://{}:{}?t={}", ip, port, token))
+    let code = state
+        .devices
+        .lock()
+        .unwrap()
+        .issue_pairing_code(now_unix_ms());
+    let rendezvous = state.rendezvous_tx.borrow().clone();
+    if let Some(url) = rendezvous_pairing_url(&state, &rendezvous, &code) {
+        return Ok(url);
+    }
+    let mut hosts = local_relay_candidate_hosts(&state.network_tx.borrow());
+    if hosts.is_empty() {
+        hosts.extend(local_ip_address::local_ip().map(|ip| ip.to_string()));
+    }
+    Ok(sync_pairing_url(&state, &hosts, &code))
 }
 
-/// Rotates the pairing token and persists the new value to disk.
-///
-/// In-flight connections are unaffected (they already authenticated).
-/// New connection attempts with the old token will be rejected — devices
-/// must rescan the QR code to reconnect.
+/// Returns the persisted relay network settings.
+#[cfg(feature = "desktop")]
 #[tauri::command]
-async fn reset_pairing_token(
+fn get_relay_network_settings(state: tauri::State<'_, RelayState>) -> RelayNetworkSettings {
+    state.network_tx.borrow().clone()
+}
+
+/// Validates and persists relay network settings. The relay rebinds
+/// immediately; connected clients stay connected.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn set_relay_network_settings(
     app: tauri::AppHandle,
     state: tauri::State<'_, RelayState>,
-) -> Result<String, String> {
+    settings: RelayNetworkSettings,
+) -> Result<RelayNetworkSettings, String> {
+    settings.validate()?;
     let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-    let new_token = generate_token();
-    std::fs::write(data_dir.join("pairing-token"), &new_token).map_err(|e| e.to_string())?;
-    let _epoch = state.epoch_gate.write().await;
-    *state.pairing_token.write().unwrap() = new_token.clone();
-    info!("[Sync] Pairing token rotated");
-    Ok(new_token)
+    settings.persist(&data_dir)?;
+    state.network_tx.send_replace(settings.clone());
+    info!("[Sync] Relay network settings updated");
+    Ok(settings)
 }
 
-async fn factory_reset_sync_relay_in(
-    data_dir: &Path,
-    state: &RelayState,
-) -> Result<String, String> {
-    let new_token = generate_token();
-    std::fs::write(data_dir.join("pairing-token"), &new_token).map_err(|e| e.to_string())?;
+/// Returns the rendezvous server the relay tunnels through, if any.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn get_relay_rendezvous(state: tauri::State<'_, RelayState>) -> RelayRendezvousStatus {
+    relay_rendezvous_status(&state)
+}
 
-    let _epoch = state.epoch_gate.write().await;
-    state
-        .accepting_doc_updates
-        .store(false, std::sync::atomic::Ordering::SeqCst);
-    let generation = state
-        .generation
-        .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
-        + 1;
-    *state.current_doc.write().await = None;
-    *state.pairing_token.write().unwrap() = new_token.clone();
-    let _ = state.disconnect_tx.send(generation);
-    Ok(new_token)
+/// Sets, or with `None` clears, the rendezvous server. The tunnel reconnects
+/// immediately; LAN clients stay connected.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn set_relay_rendezvous(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    url: Option<String>,
+) -> Result<RelayRendezvousStatus, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let status = set_relay_rendezvous_in(&state, &data_dir, url)?;
+    info!("[Sync] Relay rendezvous settings updated");
+    Ok(status)
 }
 
-async fn wait_for_relay_clients_to_disconnect(
-    state: &RelayState,
-    drain_timeout: Duration,
+/// Lists devices paired with their own credential.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn list_sync_devices(state: tauri::State<'_, RelayState>) -> Vec<PairedDeviceSummary> {
+    state.devices.lock().unwrap().summaries()
+}
+
+/// Revokes a paired device and retires the shared pairing token with it.
+///
+/// Every paired device has seen the shared token in the QR code, so leaving it
+/// valid would let a revoked device reconnect as a legacy client. Clients still
+/// on the shared token are closed with `pairing_rotated` and must rescan.
+#[cfg(feature = "desktop")]
+fn revoke_sync_device_in(
+    state: &SyncRelayState,
+    data_dir: &Path,
+    device_id: &str,
 ) -> Result<(), String> {
-    let deadline = Instant::now() + drain_timeout;
-    loop {
-        let client_count = *state.client_count.read().await;
-        if client_count == 0 {
-            return Ok(());
+    {
+        let mut devices = state.devices.lock().unwrap();
+        if !devices.contains(device_id) {
+            return Err(format!("unknown sync device: {}", device_id));
+        }
+        let new_token = generate_token();
+        write_private_file(&data_dir.join("pairing-token"), new_token.as_bytes())
+            .map_err(|e| format!("Failed to rotate pairing token: {}", e))?;
+        *state.pairing_token.write().unwrap() = new_token;
+        devices.revoke(device_id)?;
+    }
+    let _ = state
+        .disconnect_tx
+        .send(RelayDisconnect::Device(device_id.to_string()));
+    let _ = state.disconnect_tx.send(RelayDisconnect::SharedToken);
+    info!(
+        "[Sync] Revoked paired device {} and rotated the pairing token",
+        device_id
+    );
+    Ok(())
+}
+
+/// Forgets one paired device and closes its live connections. Other paired
+/// devices keep syncing; clients on the shared token must rescan the QR code.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn revoke_sync_device(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    device_id: String,
+) -> Result<(), String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    revoke_sync_device_in(&state, &data_dir, &device_id)
+}
+
+/// Lists trusted peer desktops and the untrusted relays mDNS currently sees.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn list_sync_peers(state: tauri::State<'_, RelayState>) -> Vec<SyncPeerSummary> {
+    state.peers.lock().unwrap().summaries()
+}
+
+#[cfg(feature = "desktop")]
+fn trust_sync_peer_in(
+    state: &RelayState,
+    events: &RelayEvents,
+    pairing_link: &str,
+) -> Result<SyncPeerSummary, String> {
+    let peer = parse_peer_pairing_link(pairing_link)?;
+    let own_fingerprint = state
+        .tls
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|tls| tls.fingerprint.clone());
+    if own_fingerprint.as_deref() == Some(peer.fingerprint.as_str()) {
+        return Err("this is this desktop's own pairing link".to_string());
+    }
+    let fingerprint = peer.fingerprint.clone();
+    let mut trusted = Ok(());
+    update_sync_peers(state, &**events, |peers| trusted = peers.trust(peer));
+    trusted?;
+    start_sync_peer(state, events, &fingerprint);
+    info!("[Sync] Trusted peer {}", fingerprint);
+    state
+        .peers
+        .lock()
+        .unwrap()
+        .summaries()
+        .into_iter()
+        .find(|summary| summary.fingerprint.as_deref() == Some(fingerprint.as_str()))
+        .ok_or_else(|| "peer was forgotten while it was being trusted".to_string())
+}
+
+/// Trusts another desktop from the pairing link in its Sync tab and starts
+/// replicating the held document with it.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn trust_sync_peer(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    pairing_link: String,
+) -> Result<SyncPeerSummary, String> {
+    let events: RelayEvents = Arc::new(app);
+    trust_sync_peer_in(&state, &events, &pairing_link)
+}
+
+/// Stops replicating with a peer and forgets its credentials.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn forget_sync_peer(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    fingerprint: String,
+) -> Result<(), String> {
+    let mut forgotten = Ok(false);
+    update_sync_peers(&state, &app, |peers| forgotten = peers.forget(&fingerprint));
+    if !forgotten? {
+        return Err(format!("unknown sync peer: {}", fingerprint));
+    }
+    info!("[Sync] Forgot peer {}", fingerprint);
+    Ok(())
+}
+
+/// Returns the opened held document so the renderer can merge what peers
+/// sent. Empty when the relay holds nothing.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn get_relay_doc(state: tauri::State<'_, RelayState>) -> Result<Vec<u8>, String> {
+    let _epoch = state.epoch_gate.read().await;
+    let held = state.current_doc.read().await.clone();
+    match held {
+        Some(doc) => open_relay_payload(&state, &doc),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Rotates the pairing token, relay certificate, and sync key, forgets every
+/// paired device, and persists all of it to disk.
+///
+/// In-flight connections stay open (they already authenticated), but they
+/// cannot open documents sealed under the new key and their pushes are
+/// ignored. New connection attempts with the old token or a device credential
+/// will be rejected — devices must rescan the QR code to reconnect.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn reset_pairing_token(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+) -> Result<String, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let identity = RelayTlsIdentity::generate()?;
+    let tls = identity.to_relay_tls()?;
+    let payload_key = RelayPayloadKey::generate();
+    let new_token = generate_token();
+    std::fs::write(data_dir.join("pairing-token"), &new_token).map_err(|e| e.to_string())?;
+    identity.persist(&data_dir)?;
+    payload_key.persist(&data_dir)?;
+    let _epoch = state.epoch_gate.write().await;
+    state.devices.lock().unwrap().clear()?;
+    *state.pairing_token.write().unwrap() = new_token.clone();
+    *state.tls.write().unwrap() = Some(tls);
+    rotate_relay_payload_key(&state, payload_key).await;
+    info!("[Sync] Pairing token, relay certificate, and sync key rotated");
+    Ok(new_token)
+}
+
+/// Stops the relay taking documents and drops the one it holds, including a
+/// pending snapshot, so nothing stale is written back to disk. Call with the
+/// epoch gate held for writing; older connections must then be sent
+/// `RelayDisconnect::FactoryReset` with the returned generation.
+#[cfg(feature = "desktop")]
+async fn quiesce_sync_relay(state: &SyncRelayState) -> u64 {
+    state
+        .accepting_doc_updates
+        .store(false, std::sync::atomic::Ordering::SeqCst);
+    let generation = state
+        .generation
+        .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+        + 1;
+    *state.current_doc.write().await = None;
+    *state.current_heads.write().unwrap() = Arc::default();
+    state.snapshot_tx.send_replace(None);
+    generation
+}
+
+#[cfg(feature = "desktop")]
+async fn factory_reset_sync_relay_in(
+    data_dir: &Path,
+    state: &RelayState,
+) -> Result<String, String> {
+    let identity = RelayTlsIdentity::generate()?;
+    let tls = identity.to_relay_tls()?;
+    let payload_key = RelayPayloadKey::generate();
+    let new_token = generate_token();
+    std::fs::write(data_dir.join("pairing-token"), &new_token).map_err(|e| e.to_string())?;
+    identity.persist(data_dir)?;
+    payload_key.persist(data_dir)?;
+
+    let _epoch = state.epoch_gate.write().await;
+    remove_factory_reset_file(&data_dir.join(RELAY_DOC_FILE))?;
+    state.devices.lock().unwrap().clear()?;
+    state.peers.lock().unwrap().clear()?;
+    let generation = quiesce_sync_relay(state).await;
+    *state.pairing_token.write().unwrap() = new_token.clone();
+    *state.tls.write().unwrap() = Some(tls);
+    *state.payload_key.write().unwrap() = Some(Arc::new(payload_key));
+    let _ = state
+        .disconnect_tx
+        .send(RelayDisconnect::FactoryReset(generation));
+    Ok(new_token)
+}
+
+#[cfg(feature = "desktop")]
+async fn wait_for_relay_clients_to_disconnect(
+    state: &RelayState,
+    drain_timeout: Duration,
+) -> Result<(), String> {
+    let deadline = Instant::now() + drain_timeout;
+    loop {
+        let client_count = *state.client_count.read().await;
+        if client_count == 0 {
+            return Ok(());
         }
         if Instant::now() >= deadline {
             return Err(format!(
@@ -5601,6 +6354,7 @@ async fn wait_for_relay_clients_to_disconnect(
 }
 
 /// Revoke existing mobile sessions, clear held bytes, and drain old connections.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn factory_reset_sync_relay(
     app: tauri::AppHandle,
@@ -5614,6 +6368,7 @@ async fn factory_reset_sync_relay(
 }
 
 /// Resume relay document updates only after the local Automerge document is cleared.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn resume_sync_relay_after_factory_reset(
     state: tauri::State<'_, RelayState>,
@@ -5625,6 +6380,7 @@ async fn resume_sync_relay_after_factory_reset(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 fn remove_factory_reset_file(path: &Path) -> Result<(), String> {
     match std::fs::remove_file(path) {
         Ok(()) => Ok(()),
@@ -5633,6 +6389,7 @@ fn remove_factory_reset_file(path: &Path) -> Result<(), String> {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn clear_factory_reset_runtime_artifacts_in(data_dir: &Path) -> Result<(), String> {
     let mut runtime_health_write_guard = runtime_health_write_guard(data_dir)
         .map_err(|error| format!("failed to lock runtime-health state: {error}"))?;
@@ -5668,6 +6425,7 @@ fn clear_factory_reset_runtime_artifacts_in(data_dir: &Path) -> Result<(), Strin
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn clear_factory_reset_runtime_artifacts(app: tauri::AppHandle) -> Result<(), String> {
     let data_dir = app
@@ -5677,6 +6435,7 @@ fn clear_factory_reset_runtime_artifacts(app: tauri::AppHandle) -> Result<(), St
     clear_factory_reset_runtime_artifacts_in(&data_dir)
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn sha256_file(app: tauri::AppHandle, path: String) -> Result<String, String> {
     let model_root = app
@@ -5709,6 +6468,7 @@ async fn sha256_file(app: tauri::AppHandle, path: String) -> Result<String, Stri
     Ok(format!("{:x}", hasher.finalize()))
 }
 
+#[cfg(feature = "desktop")]
 fn validate_local_ai_download_url(raw: &str) -> Result<(), String> {
     let url = url::Url::parse(raw).map_err(|error| format!("invalid model file URL: {error}"))?;
     if url.scheme() != "https" || url.host_str() != Some("huggingface.co") {
@@ -5717,6 +6477,7 @@ fn validate_local_ai_download_url(raw: &str) -> Result<(), String> {
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 fn validate_local_ai_model_path(root: &Path, path: &Path, field: &str) -> Result<(), String> {
     if !path.is_absolute() {
         return Err(format!("{field} must be an absolute path"));
@@ -5740,6 +6501,7 @@ fn validate_local_ai_model_path(root: &Path, path: &Path, field: &str) -> Result
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 fn local_ai_model_download_cancelled(
     state: &tauri::State<'_, LocalAIModelDownloadState>,
     download_id: &str,
@@ -5751,6 +6513,7 @@ fn local_ai_model_download_cancelled(
         .unwrap_or(false)
 }
 
+#[cfg(feature = "desktop")]
 fn emit_local_ai_download_progress(
     app: &tauri::AppHandle,
     event: &str,
@@ -5766,6 +6529,7 @@ fn emit_local_ai_download_progress(
     );
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn cancel_local_ai_model_download(
     state: tauri::State<'_, LocalAIModelDownloadState>,
@@ -5779,6 +6543,7 @@ async fn cancel_local_ai_model_download(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn download_local_ai_model_file(
     app: tauri::AppHandle,
@@ -5955,11 +6720,42 @@ async fn download_local_ai_model_file(
     Ok(request.expected_size_bytes)
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn get_sync_client_count(state: tauri::State<'_, RelayState>) -> Result<usize, String> {
     Ok(*state.client_count.read().await)
 }
 
+/// Live relay connections with their traffic, for support.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn get_sync_clients(state: tauri::State<'_, RelayState>) -> Vec<SyncClientSummary> {
+    state.clients.lock().unwrap().summaries()
+}
+
+#[cfg(feature = "desktop")]
+fn disconnect_sync_client_in(state: &SyncRelayState, id: u64) -> bool {
+    if !state.clients.lock().unwrap().contains(id) {
+        return false;
+    }
+    let _ = state.disconnect_tx.send(RelayDisconnect::Client(id));
+    true
+}
+
+/// Closes one relay connection. The client is free to reconnect, which is
+/// usually the point: a stuck phone starts over with a fresh session.
+/// Returns `false` when the connection had already closed.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn disconnect_sync_client(state: tauri::State<'_, RelayState>, id: u64) -> bool {
+    let disconnected = disconnect_sync_client_in(&state, id);
+    if disconnected {
+        info!("[Sync] Disconnecting relay client {}", id);
+    }
+    disconnected
+}
+
+#[cfg(feature = "desktop")]
 fn dir_size_bytes(path: &Path) -> Option<u64> {
     let metadata = std::fs::metadata(path).ok()?;
     if metadata.is_file() {
@@ -5979,6 +6775,7 @@ fn dir_size_bytes(path: &Path) -> Option<u64> {
     Some(total)
 }
 
+#[cfg(feature = "desktop")]
 fn memory_pressure_limits(total_physical_memory_bytes: u64) -> (u64, u64) {
     let proportional = total_physical_memory_bytes.saturating_mul(12) / 100;
     let critical = proportional.clamp(MIN_CRITICAL_MEMORY_BYTES, MAX_CRITICAL_MEMORY_BYTES);
@@ -5986,6 +6783,7 @@ fn memory_pressure_limits(total_physical_memory_bytes: u64) -> (u64, u64) {
     (high, critical)
 }
 
+#[cfg(feature = "desktop")]
 fn social_feed_scroll_script(delta_px: i64) -> String {
     const SCRIPT_TEMPLATE: &str = r#"
         (function() {
@@ -6240,16 +7038,19 @@ fn social_feed_scroll_script(delta_px: i64) -> String {
     SCRIPT_TEMPLATE.replace("__FREED_SCROLL_DELTA__", &delta_px.to_string())
 }
 
+#[cfg(feature = "desktop")]
 fn path_is_under_any_root(path: &Path, roots: &[PathBuf]) -> bool {
     roots.iter().any(|root| path.starts_with(root))
 }
 
+#[cfg(feature = "desktop")]
 fn push_unique_path(paths: &mut Vec<PathBuf>, path: PathBuf) {
     if !paths.iter().any(|existing| existing == &path) {
         paths.push(path);
     }
 }
 
+#[cfg(feature = "desktop")]
 fn app_storage_roots(app: &tauri::AppHandle) -> Vec<PathBuf> {
     let mut roots = Vec::new();
     if let Ok(path) = app.path().app_cache_dir() {
@@ -6294,14 +7095,17 @@ fn macos_process_has_open_file_under_roots(pid: u32, roots: &[PathBuf]) -> bool
         .any(|path| path_is_under_any_root(Path::new(path), roots))
 }
 
+#[cfg(feature = "desktop")]
 fn webkit_process_started_after_app_start(webkit_age_seconds: u64, app_age_seconds: u64) -> bool {
     webkit_age_seconds <= app_age_seconds.saturating_add(WEBKIT_PROCESS_START_GRACE_SECONDS)
 }
 
+#[cfg(feature = "desktop")]
 fn webkit_process_started_with_app(webkit_age_seconds: u64, app_age_seconds: u64) -> bool {
     webkit_age_seconds.abs_diff(app_age_seconds) <= WEBKIT_PROCESS_START_GRACE_SECONDS
 }
 
+#[cfg(feature = "desktop")]
 fn webkit_process_matches_renderer_uptime(
     webkit_age_seconds: Option<u64>,
     renderer_uptime_ms: Option<u64>,
@@ -6316,6 +7120,7 @@ fn webkit_process_matches_renderer_uptime(
     webkit_process_started_with_app(webkit_age_seconds, renderer_age_seconds)
 }
 
+#[cfg(feature = "desktop")]
 fn freed_webkit_process_role(
     has_open_file_under_roots: bool,
     webkit_age_seconds: u64,
@@ -6350,11 +7155,12 @@ fn macos_process_physical_footprint_bytes(pid: u32) -> Option<u64> {
     Some(unsafe { usage.assume_init() }.ri_phys_footprint)
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn macos_process_physical_footprint_bytes(_pid: u32) -> Option<u64> {
     None
 }
 
+#[cfg(feature = "desktop")]
 fn freed_webkit_memory_stats(
     system: &System,
     roots: &[PathBuf],
@@ -6430,6 +7236,7 @@ fn freed_webkit_memory_stats(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn collect_runtime_memory_stats(
     app: &tauri::AppHandle,
     relay_doc_bytes: u64,
@@ -6443,6 +7250,7 @@ fn collect_runtime_memory_stats(
     )
 }
 
+#[cfg(feature = "desktop")]
 fn collect_runtime_memory_stats_with_options(
     app: &tauri::AppHandle,
     relay_doc_bytes: u64,
@@ -6548,6 +7356,7 @@ fn collect_runtime_memory_stats_with_options(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn webkit_resident_delta_bytes(before: u64, after: u64) -> i64 {
     if before >= after {
         before.saturating_sub(after).min(i64::MAX as u64) as i64
@@ -6556,6 +7365,7 @@ fn webkit_resident_delta_bytes(before: u64, after: u64) -> i64 {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn scraper_recycle_verification_from_processes(
     before_processes: &[WebkitProcessRuntimeStats],
     before_webkit_resident_bytes: u64,
@@ -6605,6 +7415,7 @@ fn scraper_recycle_verification_from_processes(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn build_scraper_recycle_verification(
     recycled_scraper_windows: bool,
     before: &RuntimeMemoryStats,
@@ -6622,6 +7433,7 @@ fn build_scraper_recycle_verification(
     })
 }
 
+#[cfg(feature = "desktop")]
 fn collect_webkit_network_cache_files(root: &Path, files: &mut Vec<(PathBuf, u64, SystemTime)>) {
     let Ok(entries) = std::fs::read_dir(root) else {
         return;
@@ -6648,9 +7460,29 @@ fn collect_webkit_network_cache_files(root: &Path, files: &mut Vec<(PathBuf, u64
     }
 }
 
+#[cfg(feature = "desktop")]
 fn trim_webkit_network_cache_root_with_result(webkit_root: &Path) -> WebkitCacheTrimResult {
-    let webkit_bytes = dir_size_bytes(&webkit_root).unwrap_or(0);
-    if webkit_bytes <= WEBKIT_CACHE_TRIM_AT_BYTES {
+    trim_webkit_network_cache_root_to(
+        webkit_root,
+        WEBKIT_CACHE_TRIM_AT_BYTES,
+        WEBKIT_CACHE_TRIM_TARGET_BYTES,
+    )
+}
+
+/// Removes every NetworkCache file, for startup recovery.
+#[cfg(feature = "desktop")]
+fn clear_webkit_network_cache_root(webkit_root: &Path) -> WebkitCacheTrimResult {
+    trim_webkit_network_cache_root_to(webkit_root, 0, 0)
+}
+
+#[cfg(feature = "desktop")]
+fn trim_webkit_network_cache_root_to(
+    webkit_root: &Path,
+    trim_at_bytes: u64,
+    target_bytes: u64,
+) -> WebkitCacheTrimResult {
+    let webkit_bytes = dir_size_bytes(webkit_root).unwrap_or(0);
+    if webkit_bytes <= trim_at_bytes {
         return WebkitCacheTrimResult {
             before_bytes: webkit_bytes,
             after_bytes: webkit_bytes,
@@ -6666,7 +7498,7 @@ fn trim_webkit_network_cache_root_with_result(webkit_root: &Path) -> WebkitCache
     let mut current_bytes = webkit_bytes;
     let mut trimmed = false;
     for (path, bytes, _) in files {
-        if current_bytes <= WEBKIT_CACHE_TRIM_TARGET_BYTES {
+        if current_bytes <= target_bytes {
             break;
         }
         if std::fs::remove_file(&path).is_ok() {
@@ -6682,6 +7514,7 @@ fn trim_webkit_network_cache_root_with_result(webkit_root: &Path) -> WebkitCache
     }
 }
 
+#[cfg(feature = "desktop")]
 fn trim_webkit_network_cache(app: &tauri::AppHandle) -> WebkitCacheTrimResult {
     let Ok(cache_root) = app.path().app_cache_dir() else {
         return WebkitCacheTrimResult {
@@ -6693,12 +7526,14 @@ fn trim_webkit_network_cache(app: &tauri::AppHandle) -> WebkitCacheTrimResult {
     trim_webkit_network_cache_root_with_result(&cache_root.join("WebKit"))
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_memory_start_budget_bytes(stats: &RuntimeMemoryStats) -> u64 {
     stats
         .memory_high_bytes
         .saturating_sub(SCRAPE_MEMORY_HEADROOM_BYTES)
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_resident_start_budget_bytes(stats: &RuntimeMemoryStats) -> u64 {
     stats
         .memory_high_bytes
@@ -6706,14 +7541,17 @@ fn scrape_resident_start_budget_bytes(stats: &RuntimeMemoryStats) -> u64 {
         .saturating_sub(SCRAPE_MEMORY_HEADROOM_BYTES)
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_webkit_resident_start_budget_bytes(stats: &RuntimeMemoryStats) -> u64 {
     SCRAPE_WEBKIT_RESIDENT_START_BUDGET_BYTES.min(scrape_resident_start_budget_bytes(stats))
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_webkit_resident_may_start(stats: &RuntimeMemoryStats) -> bool {
     stats.webkit_total_resident_bytes < scrape_webkit_resident_start_budget_bytes(stats)
 }
 
+#[cfg(feature = "desktop")]
 fn webkit_resident_tail_is_probably_reclaimable(stats: &RuntimeMemoryStats) -> bool {
     if !stats.webkit_telemetry_available {
         return false;
@@ -6735,6 +7573,7 @@ fn webkit_resident_tail_is_probably_reclaimable(stats: &RuntimeMemoryStats) -> b
         && largest_webkit_cpu_usage <= 10.0
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_effective_resident_bytes(stats: &RuntimeMemoryStats) -> u64 {
     if webkit_resident_tail_is_probably_reclaimable(stats) {
         return stats
@@ -6744,12 +7583,14 @@ fn scrape_effective_resident_bytes(stats: &RuntimeMemoryStats) -> u64 {
     stats.app_resident_bytes
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_memory_may_proceed(stats: &RuntimeMemoryStats) -> bool {
     stats.app_memory_pressure_bytes < scrape_memory_start_budget_bytes(stats)
         && scrape_effective_resident_bytes(stats) < scrape_resident_start_budget_bytes(stats)
         && scrape_webkit_resident_may_start(stats)
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_memory_pressure_level(stats: &RuntimeMemoryStats) -> &'static str {
     let effective_resident_bytes = scrape_effective_resident_bytes(stats);
     if stats.app_memory_pressure_bytes >= stats.memory_critical_bytes
@@ -6767,11 +7608,13 @@ fn scrape_memory_pressure_level(stats: &RuntimeMemoryStats) -> &'static str {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn blocked_social_scrape_should_recover_main_renderer(stats: &RuntimeMemoryStats) -> bool {
     !scrape_webkit_resident_may_start(stats)
         && stats.webkit_total_resident_bytes >= MAIN_RENDERER_HOT_WEBKIT_RESIDENT_RECOVERY_BYTES
 }
 
+#[cfg(feature = "desktop")]
 fn optional_story_memory_budget_bytes(stats: &RuntimeMemoryStats) -> u64 {
     stats
         .memory_high_bytes
@@ -6779,12 +7622,14 @@ fn optional_story_memory_budget_bytes(stats: &RuntimeMemoryStats) -> u64 {
         / 100
 }
 
+#[cfg(feature = "desktop")]
 fn optional_story_scrape_may_proceed(stats: &RuntimeMemoryStats) -> bool {
     let story_budget_bytes = optional_story_memory_budget_bytes(stats);
     stats.app_memory_pressure_bytes < story_budget_bytes
         && stats.app_resident_bytes < story_budget_bytes
 }
 
+#[cfg(feature = "desktop")]
 fn scrape_memory_available_margin_bytes(stats: &RuntimeMemoryStats) -> u64 {
     let pressure_margin =
         scrape_memory_start_budget_bytes(stats).saturating_sub(stats.app_memory_pressure_bytes);
@@ -6797,6 +7642,7 @@ fn scrape_memory_available_margin_bytes(stats: &RuntimeMemoryStats) -> u64 {
         .min(webkit_resident_margin)
 }
 
+#[cfg(feature = "desktop")]
 fn capped_scrape_passes(
     default_max_passes: usize,
     target_min: usize,
@@ -6807,6 +7653,7 @@ fn capped_scrape_passes(
     (min_passes, max_passes)
 }
 
+#[cfg(feature = "desktop")]
 fn social_scrape_plan_for_memory(
     stats: &RuntimeMemoryStats,
     default_min_passes: usize,
@@ -6860,6 +7707,7 @@ fn social_scrape_plan_for_memory(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn emit_social_scrape_plan(
     app: &tauri::AppHandle,
     provider: &str,
@@ -6906,6 +7754,7 @@ fn emit_social_scrape_plan(
     );
 }
 
+#[cfg(feature = "desktop")]
 fn social_scrape_may_continue(
     app: &tauri::AppHandle,
     provider: &str,
@@ -6958,6 +7807,7 @@ fn social_scrape_may_continue(
     may_continue
 }
 
+#[cfg(feature = "desktop")]
 fn optional_story_scrape_may_continue(
     app: &tauri::AppHandle,
     provider: &str,
@@ -6998,6 +7848,7 @@ fn optional_story_scrape_may_continue(
     may_continue
 }
 
+#[cfg(feature = "desktop")]
 fn post_social_scrape_memory_recovery_reason(
     before: &RuntimeMemoryStats,
     after: &RuntimeMemoryStats,
@@ -7050,6 +7901,7 @@ fn post_social_scrape_memory_recovery_reason(
     None
 }
 
+#[cfg(feature = "desktop")]
 async fn maybe_recover_after_social_feed_scrape(
     app: &tauri::AppHandle,
     background_runtime: &BackgroundRuntimeCoordinator,
@@ -7163,6 +8015,7 @@ async fn maybe_recover_after_social_feed_scrape(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn recycle_social_scraper_windows_except(
     app: &tauri::AppHandle,
     preserve_label: Option<&str>,
@@ -7182,6 +8035,7 @@ fn recycle_social_scraper_windows_except(
     recycled
 }
 
+#[cfg(feature = "desktop")]
 fn blocked_preflight_preserved_scraper_label<'a>(
     preserve_label: Option<&'a str>,
     critical: bool,
@@ -7193,6 +8047,7 @@ fn blocked_preflight_preserved_scraper_label<'a>(
     }
 }
 
+#[cfg(feature = "desktop")]
 async fn recover_main_renderer_after_blocked_social_scrape(
     app: &tauri::AppHandle,
     background_runtime: &BackgroundRuntimeCoordinator,
@@ -7271,6 +8126,7 @@ async fn recover_main_renderer_after_blocked_social_scrape(
     }
 }
 
+#[cfg(feature = "desktop")]
 async fn prepare_social_scrape_memory_internal(
     app: &tauri::AppHandle,
     background_runtime: Option<&BackgroundRuntimeCoordinator>,
@@ -7424,6 +8280,7 @@ async fn prepare_social_scrape_memory_internal(
     }
 }
 
+#[cfg(feature = "desktop")]
 async fn ensure_social_scrape_memory(
     app: &tauri::AppHandle,
     background_runtime: &BackgroundRuntimeCoordinator,
@@ -7501,6 +8358,7 @@ async fn ensure_social_scrape_memory(
     ))
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn get_runtime_memory_stats(
     app: tauri::AppHandle,
@@ -7528,6 +8386,7 @@ async fn get_runtime_memory_stats(
     ))
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn trim_webkit_network_cache_now(
     app: tauri::AppHandle,
@@ -7546,6 +8405,7 @@ async fn trim_webkit_network_cache_now(
 /// cloud_upload_attempt, scrape_outcome, worker_init/worker_spawn. Accepts
 /// only small fixed-shape JSON objects with an `event` string so the
 /// renderer cannot flood or malform the health log.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn record_runtime_health_event(
     app: tauri::AppHandle,
@@ -7577,6 +8437,7 @@ fn record_runtime_health_event(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn get_recent_runtime_health(
     app: tauri::AppHandle,
@@ -7595,6 +8456,7 @@ async fn get_recent_runtime_health(
 
 /// Full current + previous day of runtime-health for bug-report bundles
 /// (stability P0-04), tail-capped so the IPC payload stays bounded.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn get_runtime_health_history(
     app: tauri::AppHandle,
@@ -7610,6 +8472,7 @@ async fn get_runtime_health_history(
     Ok(raw.lines().map(|line| line.to_string()).collect())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn prepare_social_scrape_memory(
     app: tauri::AppHandle,
@@ -7639,6 +8502,7 @@ async fn prepare_social_scrape_memory(
     .await)
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn get_ai_hardware_profile(
     app: tauri::AppHandle,
@@ -7670,19 +8534,23 @@ async fn get_ai_hardware_profile(
 /// Relay broadcast volume counters (stability program P0-03, F07/F10).
 /// Aggregated over ~60 s windows so the counter itself cannot bloat
 /// runtime-health.jsonl at full-doc-per-mutation broadcast rates.
+#[cfg(feature = "desktop")]
 struct RelayBroadcastAggregate {
     window_started_at: Instant,
     count: u64,
     total_bytes: u64,
 }
 
+#[cfg(feature = "desktop")]
 static RELAY_BROADCAST_AGGREGATE: StdMutex<Option<RelayBroadcastAggregate>> = StdMutex::new(None);
+#[cfg(feature = "desktop")]
 const RELAY_BROADCAST_AGGREGATE_WINDOW: Duration = Duration::from_secs(60);
 
 /// Fold one broadcast into the current window. Returns the finished window
 /// to flush when this broadcast starts a new one. The trailing window is
 /// flushed by the first broadcast after it closes; a final partial window
 /// with no successor is dropped (acceptable for a rate counter).
+#[cfg(feature = "desktop")]
 fn relay_broadcast_aggregate_update(
     slot: &mut Option<RelayBroadcastAggregate>,
     now: Instant,
@@ -7709,6 +8577,7 @@ fn relay_broadcast_aggregate_update(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn note_relay_broadcast(app: &tauri::AppHandle, doc_bytes: u64, client_count: u64) {
     let now = Instant::now();
     let finished = {
@@ -7729,13 +8598,11 @@ fn note_relay_broadcast(app: &tauri::AppHandle, doc_bytes: u64, client_count: u6
     }
 }
 
-/// Push a document update to all connected clients.
-#[cfg_attr(feature = "perf", tracing::instrument(skip(app, state, doc_bytes), fields(bytes = doc_bytes.len())))]
-#[tauri::command]
-async fn broadcast_doc(
-    app: tauri::AppHandle,
-    state: tauri::State<'_, RelayState>,
-    doc_bytes: Vec<u8>,
+#[cfg(feature = "desktop")]
+async fn broadcast_renderer_update(
+    app: &tauri::AppHandle,
+    state: &RelayState,
+    update: RelayUpdate,
 ) -> Result<(), String> {
     let _epoch = state.epoch_gate.read().await;
     if !state
@@ -7744,9 +8611,9 @@ async fn broadcast_doc(
     {
         return Err("sync relay is being factory reset".to_string());
     }
-    let byte_len = doc_bytes.len() as u64;
-    let doc_bytes = Arc::new(doc_bytes);
-    {
+    let update = seal_relay_update(state, update)?;
+    let byte_len = update.payload().len() as u64;
+    let doc_bytes = {
         let mut current_doc = state.current_doc.write().await;
         if !state
             .accepting_doc_updates
@@ -7754,48 +8621,403 @@ async fn broadcast_doc(
         {
             return Err("sync relay is being factory reset".to_string());
         }
-        *current_doc = Some(doc_bytes.clone());
-    }
-    let _ = state.broadcast_tx.send(doc_bytes);
+        apply_relay_update(state, &mut current_doc, &update)
+    };
+    state.snapshot_tx.send_replace(Some(doc_bytes));
+    let _ = state.broadcast_tx.send(update);
     let client_count = *state.client_count.read().await as u64;
-    note_relay_broadcast(&app, byte_len, client_count);
+    note_relay_broadcast(app, byte_len, client_count);
     Ok(())
 }
 
+/// Push a document update to all connected clients.
+///
+/// `heads` are the hex change hashes of the saved document. When present,
+/// framed clients that already hold those heads skip the download on connect.
+#[cfg(feature = "desktop")]
+#[cfg_attr(feature = "perf", tracing::instrument(skip(app, state, doc_bytes), fields(bytes = doc_bytes.len())))]
+#[tauri::command]
+async fn broadcast_doc(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    doc_bytes: Vec<u8>,
+    heads: Option<Vec<String>>,
+) -> Result<(), String> {
+    let heads = parse_relay_heads_hex(&heads.unwrap_or_default())?;
+    broadcast_renderer_update(
+        &app,
+        &state,
+        RelayUpdate::full_doc(&doc_bytes, Arc::new(heads)),
+    )
+    .await
+}
+
+/// Push incremental Automerge change chunks (`saveIncremental` /
+/// `saveSince`) instead of the whole document. Framed clients receive only
+/// the chunks; legacy clients still receive the full held document.
+#[cfg(feature = "desktop")]
+#[cfg_attr(feature = "perf", tracing::instrument(skip(app, state, change_bytes), fields(bytes = change_bytes.len())))]
+#[tauri::command]
+async fn broadcast_changes(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    change_bytes: Vec<u8>,
+    heads: Vec<String>,
+) -> Result<(), String> {
+    if change_bytes.is_empty() {
+        return Ok(());
+    }
+    let heads = parse_relay_heads_hex(&heads)?;
+    broadcast_renderer_update(
+        &app,
+        &state,
+        RelayUpdate::changes(&change_bytes, Arc::new(heads)),
+    )
+    .await
+}
+
 // ---------------------------------------------------------------------------
 // Tauri commands — mDNS + snapshots
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_mdns_active(mdns: tauri::State<'_, MdnsState>) -> bool {
     mdns.0.is_some()
 }
 
+/// Stored snapshots, newest first, with their logical and on-disk sizes.
+#[cfg(feature = "desktop")]
 #[tauri::command]
-fn list_snapshots(app: tauri::AppHandle) -> Vec<String> {
+fn list_snapshots(app: tauri::AppHandle) -> Vec<SnapshotSummary> {
     let Ok(data_dir) = app.path().app_data_dir() else {
         return vec![];
     };
-    let dir = data_dir.join("snapshots");
+    list_snapshots_in(&snapshot_dir(&data_dir))
+}
 
-    let mut entries: Vec<String> = std::fs::read_dir(&dir)
-        .into_iter()
-        .flatten()
-        .flatten()
-        .filter_map(|e| {
-            let name = e.file_name().into_string().ok()?;
-            if name.starts_with("freed-") && name.ends_with(".automerge") {
-                Some(name)
-            } else {
-                None
-            }
-        })
-        .collect();
+/// Summarizes a stored snapshot without loading it into the renderer.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn inspect_snapshot(
+    app: tauri::AppHandle,
+    name: String,
+) -> Result<SnapshotInspection, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || inspect_snapshot_in(&snapshot_dir(&data_dir), &name))
+        .await
+        .map_err(|e| e.to_string())?
+}
 
-    entries.sort_unstable_by(|a, b| b.cmp(a)); // newest first
-    entries
+/// Compares two stored snapshots, so a user can see what restoring `target`
+/// over `base` would add and drop.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn diff_snapshots(
+    app: tauri::AppHandle,
+    base: String,
+    target: String,
+) -> Result<SnapshotDiff, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || diff_snapshots_in(&snapshot_dir(&data_dir), &base, &target))
+        .await
+        .map_err(|e| e.to_string())?
 }
 
+/// Returns the snapshot retention counts and disk quota.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn get_snapshot_retention(state: tauri::State<'_, RelayState>) -> SnapshotRetention {
+    *state.snapshot_retention_tx.borrow()
+}
+
+/// Validates and persists snapshot retention, then prunes existing snapshots
+/// with it right away so a smaller quota frees space immediately.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn set_snapshot_retention(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    retention: SnapshotRetention,
+) -> Result<SnapshotRetention, String> {
+    retention.validate()?;
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    retention.persist(&data_dir)?;
+    state.snapshot_retention_tx.send_replace(retention);
+    info!("[Snapshot] Retention settings updated");
+
+    let dir = snapshot_dir(&data_dir);
+    let summary = tokio::task::spawn_blocking(move || {
+        prune_snapshots(&dir, now_unix_ms() / 1000, &retention)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    report_snapshot_prune(&app, summary);
+    Ok(retention)
+}
+
+#[cfg(feature = "desktop")]
+async fn restore_snapshot_in(
+    snapshot_dir: &Path,
+    state: &RelayState,
+    name: &str,
+) -> Result<u64, String> {
+    let bytes = read_snapshot(snapshot_dir, name)?;
+    validate_snapshot_bytes(&bytes)?;
+    let byte_len = bytes.len() as u64;
+
+    let _epoch = state.epoch_gate.read().await;
+    let update = {
+        let mut current_doc = state.current_doc.write().await;
+        if !state
+            .accepting_doc_updates
+            .load(std::sync::atomic::Ordering::SeqCst)
+        {
+            return Err("sync relay is being factory reset".to_string());
+        }
+        let update = seal_relay_update(state, RelayUpdate::full_doc(&bytes, Arc::default()))?;
+        apply_relay_update(state, &mut current_doc, &update);
+        update
+    };
+    let _ = state.broadcast_tx.send(update);
+    Ok(byte_len)
+}
+
+/// Swap a stored snapshot into the relay and re-broadcast it, so a corrupted
+/// library can be rolled back without reinstalling.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn restore_snapshot(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    name: String,
+) -> Result<(), String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let byte_len = restore_snapshot_in(&snapshot_dir(&data_dir), &state, &name).await?;
+    info!("[Snapshot] Restored {} ({} bytes)", name, byte_len);
+    append_runtime_health(
+        &app,
+        serde_json::json!({
+            "event": "snapshot_restored",
+            "name": name,
+            "bytes": byte_len,
+        }),
+    );
+    let _ = app.emit("sync-snapshot-restored", &name);
+    Ok(())
+}
+
+#[cfg(feature = "desktop")]
+async fn compact_document_in(
+    state: &RelayState,
+    drop_history_before: Option<u64>,
+) -> Result<DocumentCompaction, String> {
+    let _epoch = state.epoch_gate.read().await;
+    let held = state
+        .current_doc
+        .read()
+        .await
+        .clone()
+        .ok_or_else(|| "the sync relay holds no document to compact".to_string())?;
+    let plaintext = open_relay_payload(state, &held)?;
+    let (compacted, heads, compaction) = tokio::task::spawn_blocking(move || {
+        compact_automerge_doc(&plaintext, drop_history_before, now_unix_ms() / 1000)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    let update = {
+        let mut current_doc = state.current_doc.write().await;
+        if !state
+            .accepting_doc_updates
+            .load(std::sync::atomic::Ordering::SeqCst)
+        {
+            return Err("sync relay is being factory reset".to_string());
+        }
+        if current_doc.as_ref() != Some(&held) {
+            return Err("the document changed while compacting; try again".to_string());
+        }
+        let update = seal_relay_update(state, RelayUpdate::full_doc(&compacted, Arc::new(heads)))?;
+        let doc = apply_relay_update(state, &mut current_doc, &update);
+        state.snapshot_tx.send_replace(Some(doc));
+        update
+    };
+    let _ = state.broadcast_tx.send(update);
+    Ok(compaction)
+}
+
+/// Rewrites the held document in compacted form and re-broadcasts it.
+/// `drop_history_before` (Unix seconds) also drops the change history when it
+/// reaches back past that time; see `compact_automerge_doc`.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn compact_document(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    drop_history_before: Option<u64>,
+) -> Result<DocumentCompaction, String> {
+    let compaction = compact_document_in(&state, drop_history_before).await?;
+    info!(
+        "[Sync] Compacted document from {} to {} bytes ({} to {} changes)",
+        compaction.bytes_before,
+        compaction.bytes_after,
+        compaction.changes_before,
+        compaction.changes_after
+    );
+    if compaction.history_dropped {
+        warn!(
+            "[Sync] Dropped document history; devices that still hold it will merge it back on their next push"
+        );
+    }
+    append_runtime_health(
+        &app,
+        serde_json::json!({
+            "event": "document_compacted",
+            "bytesBefore": compaction.bytes_before,
+            "bytesAfter": compaction.bytes_after,
+            "changesBefore": compaction.changes_before,
+            "changesAfter": compaction.changes_after,
+            "historyDropped": compaction.history_dropped,
+        }),
+    );
+    let _ = app.emit("sync-document-compacted", &compaction);
+    Ok(compaction)
+}
+
+#[cfg(feature = "desktop")]
+fn backup_roots(app: &tauri::AppHandle, include_scraper_data: bool) -> Result<BackupRoots, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    #[cfg(target_os = "macos")]
+    let scraper_data = match include_scraper_data {
+        true => Some(scraper_data_store_root(app)?),
+        false => None,
+    };
+    #[cfg(not(target_os = "macos"))]
+    let scraper_data = {
+        let _ = include_scraper_data;
+        None
+    };
+    Ok(BackupRoots {
+        data_dir,
+        scraper_data,
+    })
+}
+
+/// Writes an encrypted archive of the app data to `path`. Scraper WebKit data
+/// stores (logged-in social sessions) are only included when asked for.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn export_backup(
+    app: tauri::AppHandle,
+    path: String,
+    passphrase: String,
+    include_scraper_data: Option<bool>,
+) -> Result<BackupSummary, String> {
+    let roots = backup_roots(&app, include_scraper_data.unwrap_or(false))?;
+    let summary = tokio::task::spawn_blocking(move || {
+        export_backup_in(
+            &roots,
+            Path::new(&path),
+            &passphrase,
+            BACKUP_PBKDF2_ITERATIONS,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    info!(
+        "[Backup] Exported {} files ({} bytes)",
+        summary.files, summary.bytes
+    );
+    Ok(summary)
+}
+
+/// Replaces the app data with the contents of an encrypted archive. Old relay
+/// connections are drained as for a factory reset; the renderer must reload
+/// its document and then call `resume_sync_relay_after_factory_reset`.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn import_backup(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    path: String,
+    passphrase: String,
+) -> Result<BackupSummary, String> {
+    let roots = backup_roots(&app, true)?;
+    let summary = import_backup_in(&roots, Path::new(&path), &passphrase, &state).await?;
+    wait_for_relay_clients_to_disconnect(&state, FACTORY_RESET_RELAY_DRAIN_TIMEOUT).await?;
+    info!(
+        "[Backup] Imported {} files ({} bytes)",
+        summary.files, summary.bytes
+    );
+    let _ = app.emit("sync-backup-imported", summary);
+    Ok(summary)
+}
+
+/// Returns the scheduled backup folder, interval, kind, and retention.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn get_backup_schedule(state: tauri::State<'_, RelayState>) -> BackupSchedule {
+    state.backup_schedule_tx.borrow().clone()
+}
+
+/// Validates and persists the backup schedule. Archive backups need a
+/// passphrase, given here once and kept in `backup-passphrase`.
+///
+/// The passphrase sits beside the sync key and pairing token with the same
+/// owner-only permissions, so anyone who can read it can already read the data
+/// the archives hold. It protects the copies in the backup folder, not this
+/// machine. Any other schedule kind deletes it.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn set_backup_schedule(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+    schedule: BackupSchedule,
+    passphrase: Option<String>,
+) -> Result<BackupSchedule, String> {
+    schedule.validate()?;
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let passphrase_path = data_dir.join(BACKUP_PASSPHRASE_FILE);
+    if schedule.kind != ScheduledBackupKind::Archive {
+        match std::fs::remove_file(&passphrase_path) {
+            Ok(()) => info!("[Backup] Removed the archive passphrase"),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => return Err(format!("failed to remove backup passphrase: {}", error)),
+        }
+    } else if let Some(passphrase) = passphrase {
+        if passphrase.chars().count() < BACKUP_MIN_PASSPHRASE_CHARS {
+            return Err(format!(
+                "passphrase must be at least {} characters",
+                BACKUP_MIN_PASSPHRASE_CHARS
+            ));
+        }
+        write_private_file(&passphrase_path, passphrase.as_bytes())
+            .map_err(|e| format!("failed to persist backup passphrase: {}", e))?;
+    } else if !passphrase_path.exists() {
+        return Err("archive backups need a passphrase".to_string());
+    }
+    schedule.persist(&data_dir)?;
+    state.backup_schedule_tx.send_replace(schedule.clone());
+    info!("[Backup] Schedule updated");
+    Ok(schedule)
+}
+
+/// Runs a scheduled backup now, whether or not one is due.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn run_backup_now(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+) -> Result<Option<ScheduledBackup>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let schedule = state.backup_schedule_tx.borrow().clone();
+    if schedule.directory.is_none() {
+        return Err("choose a backup folder".to_string());
+    }
+    run_scheduled_backup_and_report(data_dir, schedule, &app).await
+}
+
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn get_recent_logs(app: tauri::AppHandle, limit: Option<usize>) -> Result<Vec<String>, String> {
     let limit = limit.unwrap_or(120).clamp(1, 1_000);
@@ -7847,6 +9069,7 @@ fn get_recent_logs(app: tauri::AppHandle, limit: Option<usize>) -> Result<Vec<St
 /// Parse the OAuth callback request from the browser stream, emit the code to
 /// the frontend, and respond with a success page so the user knows to close
 /// the tab.
+#[cfg(feature = "desktop")]
 async fn handle_oauth_stream(stream: TcpStream, app: tauri::AppHandle) {
     let (reader_half, mut writer_half) = stream.into_split();
     let mut buf_reader = BufReader::new(reader_half);
@@ -7903,6 +9126,7 @@ async fn handle_oauth_stream(stream: TcpStream, app: tauri::AppHandle) {
 /// *before* launching the browser. Emits `cloud-oauth-code` with
 /// `{ code: String, state: String }` when the callback arrives, then shuts
 /// the server down. Times out after 5 minutes of waiting.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn start_oauth_server(app: tauri::AppHandle) -> Result<u16, String> {
     // Bind to IPv4 loopback first to get a port, then also bind IPv6 loopback
@@ -7965,11 +9189,13 @@ async fn start_oauth_server(app: tauri::AppHandle) -> Result<u16, String> {
 // Tauri commands — window
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn show_window(app: tauri::AppHandle) {
     let _ = start_main_window(&app);
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn retry_startup_after_crash(app: tauri::AppHandle) -> Result<(), String> {
     let data_dir = app
@@ -7998,6 +9224,56 @@ fn retry_startup_after_crash(app: tauri::AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
+#[tauri::command]
+fn get_startup_recovery_state(app: tauri::AppHandle) -> Result<StartupRecoveryState, String> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|error| error.to_string())?;
+    Ok(load_startup_recovery_state(&data_dir))
+}
+
+/// Restores the snapshot the document-rollback tier found, then records the
+/// rollback in the startup recovery file. Returns the snapshot name.
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn rollback_to_last_good_snapshot(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, RelayState>,
+) -> Result<String, String> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|error| error.to_string())?;
+    let mut recovery = load_startup_recovery_state(&data_dir);
+    let name = recovery
+        .document_check
+        .as_ref()
+        .and_then(|check| check.last_good_snapshot.clone())
+        .ok_or_else(|| "no loadable snapshot to roll back to".to_string())?;
+    let byte_len = restore_snapshot_in(&snapshot_dir(&data_dir), &state, &name).await?;
+    let action = record_startup_recovery_action(
+        &mut recovery,
+        StartupRecoveryTier::DocumentRollback,
+        format!("rolled back to {name}"),
+    );
+    save_startup_recovery_state(&data_dir, &recovery);
+    warn!("[recovery] {} ({} bytes)", action.detail, byte_len);
+    append_runtime_health(
+        &app,
+        serde_json::json!({
+            "event": "snapshot_restored",
+            "name": name,
+            "bytes": byte_len,
+            "reason": "startup_recovery",
+        }),
+    );
+    let _ = app.emit("sync-snapshot-restored", &name);
+    Ok(name)
+}
+
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn export_startup_diagnostics(app: tauri::AppHandle) -> Result<String, String> {
     let data_dir = app
@@ -8021,6 +9297,7 @@ fn export_startup_diagnostics(app: tauri::AppHandle) -> Result<String, String> {
 // Tauri commands — X login window
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "desktop")]
 #[derive(serde::Serialize)]
 #[serde(tag = "status")]
 enum XLoginCheckResult {
@@ -8037,6 +9314,7 @@ enum XLoginCheckResult {
 
 /// Open a secondary WebView window pointing to X's login page.
 /// If the window already exists, focus it instead of creating a duplicate.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn open_x_login_window(app: tauri::AppHandle) -> Result<(), String> {
     if let Some(existing) = app.get_webview_window("x-login") {
@@ -8063,6 +9341,7 @@ async fn open_x_login_window(app: tauri::AppHandle) -> Result<(), String> {
 /// Check whether the X login webview has session cookies available.
 /// Returns a tagged result so the frontend can distinguish "window gone"
 /// from "window open, still waiting for login."
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn check_x_login_cookies(app: tauri::AppHandle) -> Result<XLoginCheckResult, String> {
     let Some(window) = app.get_webview_window("x-login") else {
@@ -8088,6 +9367,7 @@ async fn check_x_login_cookies(app: tauri::AppHandle) -> Result<XLoginCheckResul
 }
 
 /// Close and destroy the X login window.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn close_x_login_window(app: tauri::AppHandle) -> Result<(), String> {
     if let Some(window) = app.get_webview_window("x-login") {
@@ -8112,6 +9392,7 @@ async fn close_x_login_window(app: tauri::AppHandle) -> Result<(), String> {
 ///
 /// Uses the Box-Muller transform to convert two uniform samples to a normal
 /// variate, which is fast and requires no external crate.
+#[cfg(feature = "desktop")]
 fn gaussian_ms(mean: f64, std_dev: f64) -> u64 {
     use rand::Rng;
     let mut rng = rand::thread_rng();
@@ -8130,11 +9411,16 @@ fn gaussian_ms(mean: f64, std_dev: f64) -> u64 {
 ///
 /// This is a self-contained script with no external dependencies.
 /// It runs inside facebook.com's execution context.
+#[cfg(feature = "desktop")]
 const FB_EXTRACT_SCRIPT: &str = include_str!("fb-extract.js");
+#[cfg(feature = "desktop")]
 const FB_GROUPS_EXTRACT_SCRIPT: &str = include_str!("fb-groups-extract.js");
+#[cfg(feature = "desktop")]
 const FB_STORIES_EXTRACT_SCRIPT: &str = include_str!("fb-stories-extract.js");
+#[cfg(feature = "desktop")]
 const FB_COMMENTS_EXTRACT_SCRIPT: &str = include_str!("fb-comments-extract.js");
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 struct FbGroupInfoPayload {
     id: String,
@@ -8142,12 +9428,14 @@ struct FbGroupInfoPayload {
     url: String,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Deserialize)]
 struct FbGroupsDataPayload {
     groups: Vec<FbGroupInfoPayload>,
     error: Option<String>,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct FbGroupMembershipPayload {
@@ -8159,6 +9447,7 @@ struct FbGroupMembershipPayload {
     checked_at: u64,
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct FbPageStatePayload {
@@ -8172,12 +9461,14 @@ struct FbPageStatePayload {
     title: String,
 }
 
+#[cfg(feature = "desktop")]
 impl FbPageStatePayload {
     fn feed_like(&self) -> bool {
         self.logged_in_cookie || self.feed_posts_heading_count > 0 || self.feed_unit_count > 0
     }
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct IgFeedStatePayload {
@@ -8196,6 +9487,7 @@ struct IgFeedStatePayload {
     title: String,
 }
 
+#[cfg(feature = "desktop")]
 impl IgFeedStatePayload {
     fn placeholders_only(&self) -> bool {
         self.article_count > 0 && self.ready_article_count == 0 && self.tiny_article_count > 0
@@ -8224,6 +9516,7 @@ impl IgFeedStatePayload {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn fb_page_state_probe_script() -> &'static str {
     r#"
     (function() {
@@ -8263,6 +9556,7 @@ fn fb_page_state_probe_script() -> &'static str {
     "#
 }
 
+#[cfg(feature = "desktop")]
 fn ig_feed_state_probe_script() -> &'static str {
     r#"
     (function() {
@@ -8331,6 +9625,7 @@ fn ig_feed_state_probe_script() -> &'static str {
     "#
 }
 
+#[cfg(feature = "desktop")]
 fn fb_auth_result_script() -> &'static str {
     r#"
     (function() {
@@ -8367,6 +9662,7 @@ fn fb_auth_result_script() -> &'static str {
     "#
 }
 
+#[cfg(feature = "desktop")]
 async fn probe_fb_page_state(
     app: &tauri::AppHandle,
     wv: &tauri::WebviewWindow,
@@ -8399,6 +9695,7 @@ async fn probe_fb_page_state(
     result?
 }
 
+#[cfg(feature = "desktop")]
 async fn probe_ig_feed_state(
     app: &tauri::AppHandle,
     wv: &tauri::WebviewWindow,
@@ -8431,6 +9728,7 @@ async fn probe_ig_feed_state(
     result?
 }
 
+#[cfg(feature = "desktop")]
 async fn wait_for_ig_feed_state(
     app: &tauri::AppHandle,
     wv: &tauri::WebviewWindow,
@@ -8474,6 +9772,7 @@ async fn wait_for_ig_feed_state(
 ///
 /// An `on_navigation` handler detects when the user completes login
 /// (URL leaves /login) and emits `fb-auth-result`.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_show_login(
     app: tauri::AppHandle,
@@ -8569,6 +9868,7 @@ async fn fb_show_login(
 }
 
 /// Hide the Facebook login window after successful authentication.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_hide_login(app: tauri::AppHandle) -> Result<(), String> {
     let _ = app.emit(
@@ -8589,6 +9889,7 @@ async fn fb_hide_login(app: tauri::AppHandle) -> Result<(), String> {
 /// Creates a hidden WebView if none exists, navigates to facebook.com,
 /// waits for the page to settle, then checks for logged-in indicators
 /// (USER_ID != "0" in the page source).
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_check_auth(
     app: tauri::AppHandle,
@@ -8653,6 +9954,7 @@ async fn fb_check_auth(
 ///
 /// Bails early if the story viewer closes (overlay no longer present) or
 /// if `max_frames` have been viewed.
+#[cfg(feature = "desktop")]
 async fn scrape_fb_stories(wv: &tauri::WebviewWindow, max_frames: usize) {
     use rand::Rng;
 
@@ -8765,6 +10067,7 @@ async fn scrape_fb_stories(wv: &tauri::WebviewWindow, max_frames: usize) {
 /// Clicks the first story avatar in the Instagram stories tray (the horizontal
 /// row of circular avatars at the top of the Following feed), then advances
 /// through frames using the right-side click area.
+#[cfg(feature = "desktop")]
 async fn scrape_ig_stories(wv: &tauri::WebviewWindow, max_frames: usize) {
     use rand::Rng;
 
@@ -8873,6 +10176,7 @@ async fn scrape_ig_stories(wv: &tauri::WebviewWindow, max_frames: usize) {
 /// - `shown`: window is centered and visible during sync.
 /// - `cloaked`: window stays visible for WebKit but is transparent and click-through.
 /// - `hidden`: window is fully hidden, which is quieter but can be less reliable.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_scrape_feed(
     app: tauri::AppHandle,
@@ -9198,6 +10502,7 @@ async fn fb_scrape_feed(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_scrape_groups(
     app: tauri::AppHandle,
@@ -9353,6 +10658,7 @@ async fn fb_scrape_groups(
     Ok(groups_by_id.into_values().collect())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_check_group_membership(
     app: tauri::AppHandle,
@@ -9539,6 +10845,7 @@ async fn fb_check_group_membership(
     Ok(result)
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_scrape_comments(
     app: tauri::AppHandle,
@@ -9586,6 +10893,7 @@ async fn fb_scrape_comments(
 }
 
 /// Disconnect Facebook by clearing all browsing data in the scraper WebView.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_disconnect(app: tauri::AppHandle) -> Result<(), String> {
     if let Some(wv) = app.get_webview_window("fb-scraper") {
@@ -9608,8 +10916,11 @@ async fn fb_disconnect(app: tauri::AppHandle) -> Result<(), String> {
 
 /// The extraction script injected into the Instagram WebView after page load.
 /// Reads posts from the rendered DOM and emits them via Tauri event IPC.
+#[cfg(feature = "desktop")]
 const IG_EXTRACT_SCRIPT: &str = include_str!("ig-extract.js");
+#[cfg(feature = "desktop")]
 const IG_STORIES_EXTRACT_SCRIPT: &str = include_str!("ig-stories-extract.js");
+#[cfg(feature = "desktop")]
 const IG_COMMENTS_EXTRACT_SCRIPT: &str = include_str!("ig-comments-extract.js");
 
 /// Show a visible WebView window navigated to instagram.com/accounts/login
@@ -9617,6 +10928,7 @@ const IG_COMMENTS_EXTRACT_SCRIPT: &str = include_str!("ig-comments-extract.js");
 ///
 /// An `on_navigation` handler detects when the user completes login
 /// (URL leaves /accounts/login) and emits `ig-auth-result`.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_show_login(
     app: tauri::AppHandle,
@@ -9692,6 +11004,7 @@ async fn ig_show_login(
 }
 
 /// Hide the Instagram login window after successful authentication.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_hide_login(app: tauri::AppHandle) -> Result<(), String> {
     let _ = app.emit(
@@ -9711,6 +11024,7 @@ async fn ig_hide_login(app: tauri::AppHandle) -> Result<(), String> {
 ///
 /// Creates a hidden WebView if none exists, navigates to instagram.com,
 /// waits for the page to settle, then checks for the sessionid cookie.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_check_auth(
     app: tauri::AppHandle,
@@ -9780,6 +11094,7 @@ async fn ig_check_auth(
 /// - `shown`: window is centered and visible during sync.
 /// - `cloaked`: window stays visible for WebKit but is transparent and click-through.
 /// - `hidden`: window is fully hidden, which is quieter but can be less reliable.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_scrape_feed(
     app: tauri::AppHandle,
@@ -10079,6 +11394,7 @@ async fn ig_scrape_feed(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_scrape_comments(
     app: tauri::AppHandle,
@@ -10126,6 +11442,7 @@ async fn ig_scrape_comments(
 }
 
 /// Disconnect Instagram by clearing all browsing data in the scraper WebView.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_disconnect(app: tauri::AppHandle) -> Result<(), String> {
     if let Some(wv) = app.get_webview_window("ig-scraper") {
@@ -10151,6 +11468,7 @@ async fn ig_disconnect(app: tauri::AppHandle) -> Result<(), String> {
 ///
 /// Returns Ok(()) on navigation success, Err if the window doesn't exist or
 /// navigation fails. The caller should treat Err as a retriable failure.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_visit_url(
     app: tauri::AppHandle,
@@ -10184,6 +11502,7 @@ async fn fb_visit_url(
 
 /// Navigate the Instagram scraper WebView to a URL and wait for it to load.
 /// Used by the outbox processor to mark posts as seen.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_visit_url(
     app: tauri::AppHandle,
@@ -10229,6 +11548,7 @@ async fn ig_visit_url(
 /// Ok(()) means the script was injected, not that the click succeeded.
 /// The outbox processor treats this as a success; if the DOM selector missed,
 /// the item stays "liked locally" which is an acceptable degradation.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn fb_like_post(
     app: tauri::AppHandle,
@@ -10280,6 +11600,7 @@ async fn fb_like_post(
 ///
 /// Same best-effort semantics as `fb_like_post`. `wv.eval()` injects the
 /// click script but cannot confirm whether the selector matched.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn ig_like_post(
     app: tauri::AppHandle,
@@ -10334,8 +11655,11 @@ async fn ig_like_post(
 
 /// The extraction script injected into the LinkedIn WebView after page load.
 /// Reads posts from the rendered DOM and emits them via Tauri event IPC.
+#[cfg(feature = "desktop")]
 const LI_EXTRACT_SCRIPT: &str = include_str!("li-extract.js");
+#[cfg(feature = "desktop")]
 const SUBSTACK_EXTRACT_SCRIPT: &str = include_str!("substack-extract.js");
+#[cfg(feature = "desktop")]
 const MEDIUM_EXTRACT_SCRIPT: &str = include_str!("medium-extract.js");
 
 /// Show a visible WebView window navigated to linkedin.com/login so the
@@ -10343,6 +11667,7 @@ const MEDIUM_EXTRACT_SCRIPT: &str = include_str!("medium-extract.js");
 ///
 /// An `on_navigation` handler detects when the user completes login
 /// (URL leaves /login) and emits `li-auth-result`.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn li_show_login(
     app: tauri::AppHandle,
@@ -10419,6 +11744,7 @@ async fn li_show_login(
 }
 
 /// Hide the LinkedIn login window after successful authentication.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn li_hide_login(app: tauri::AppHandle) -> Result<(), String> {
     let _ = app.emit(
@@ -10438,6 +11764,7 @@ async fn li_hide_login(app: tauri::AppHandle) -> Result<(), String> {
 ///
 /// Creates a hidden WebView if none exists, navigates to linkedin.com/feed,
 /// waits for the page to settle, then checks for the li_at session cookie.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn li_check_auth(
     app: tauri::AppHandle,
@@ -10519,6 +11846,7 @@ async fn li_check_auth(
 /// - `shown`: window is centered and visible during sync.
 /// - `cloaked`: window stays visible for WebKit but is transparent and click-through.
 /// - `hidden`: window is fully hidden, which is quieter but can be less reliable.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn li_scrape_feed(
     app: tauri::AppHandle,
@@ -10734,6 +12062,7 @@ async fn li_scrape_feed(
 }
 
 /// Disconnect LinkedIn by clearing all browsing data in the scraper WebView.
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn li_disconnect(app: tauri::AppHandle) -> Result<(), String> {
     if let Some(wv) = app.get_webview_window("li-scraper") {
@@ -10750,6 +12079,7 @@ async fn li_disconnect(app: tauri::AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy)]
 struct EssayScrapePage {
     url: &'static str,
@@ -10758,11 +12088,16 @@ struct EssayScrapePage {
     substack_profile_tab: Option<&'static str>,
 }
 
+#[cfg(feature = "desktop")]
 const ESSAY_ROSTER_MAX_EXTRACTION_PASSES: usize = 20;
+#[cfg(feature = "desktop")]
 const ESSAY_ROSTER_MAX_SURFACE_DURATION: Duration = Duration::from_secs(60);
+#[cfg(feature = "desktop")]
 const ESSAY_ROSTER_SCROLL_EVENT: &str = "essay-roster-scroll-state";
+#[cfg(feature = "desktop")]
 const SUBSTACK_PROFILE_URL_EVENT: &str = "substack-profile-url";
 
+#[cfg(feature = "desktop")]
 const SUBSTACK_GRAPH_PAGES: [EssayScrapePage; 4] = [
     EssayScrapePage {
         url: "https://substack.com/home",
@@ -10789,18 +12124,21 @@ const SUBSTACK_GRAPH_PAGES: [EssayScrapePage; 4] = [
         substack_profile_tab: Some("reads"),
     },
 ];
+#[cfg(feature = "desktop")]
 const SUBSTACK_ACTIVITY_PAGES: [EssayScrapePage; 1] = [EssayScrapePage {
     url: "https://substack.com/notes",
     scope: "activity",
     relation: None,
     substack_profile_tab: None,
 }];
+#[cfg(feature = "desktop")]
 const SUBSTACK_ESSAY_PAGES: [EssayScrapePage; 1] = [EssayScrapePage {
     url: "https://substack.com/home",
     scope: "essays",
     relation: None,
     substack_profile_tab: None,
 }];
+#[cfg(feature = "desktop")]
 const MEDIUM_GRAPH_PAGES: [EssayScrapePage; 2] = [
     EssayScrapePage {
         url: "https://medium.com/me/following",
@@ -10815,12 +12153,14 @@ const MEDIUM_GRAPH_PAGES: [EssayScrapePage; 2] = [
         substack_profile_tab: None,
     },
 ];
+#[cfg(feature = "desktop")]
 const MEDIUM_ACTIVITY_PAGES: [EssayScrapePage; 1] = [EssayScrapePage {
     url: "https://medium.com/",
     scope: "activity",
     relation: None,
     substack_profile_tab: None,
 }];
+#[cfg(feature = "desktop")]
 const MEDIUM_ESSAY_PAGES: [EssayScrapePage; 1] = [EssayScrapePage {
     url: "https://medium.com/me/stories/public",
     scope: "essays",
@@ -10828,6 +12168,7 @@ const MEDIUM_ESSAY_PAGES: [EssayScrapePage; 1] = [EssayScrapePage {
     substack_profile_tab: None,
 }];
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy)]
 struct EssayProviderConfig {
     label: &'static str,
@@ -10848,6 +12189,7 @@ struct EssayProviderConfig {
     extract_script: &'static str,
 }
 
+#[cfg(feature = "desktop")]
 const SUBSTACK_ESSAY_PROVIDER: EssayProviderConfig = EssayProviderConfig {
     label: "Substack",
     id: "substack",
@@ -10867,6 +12209,7 @@ const SUBSTACK_ESSAY_PROVIDER: EssayProviderConfig = EssayProviderConfig {
     extract_script: SUBSTACK_EXTRACT_SCRIPT,
 };
 
+#[cfg(feature = "desktop")]
 const MEDIUM_ESSAY_PROVIDER: EssayProviderConfig = EssayProviderConfig {
     label: "Medium",
     id: "medium",
@@ -10886,6 +12229,7 @@ const MEDIUM_ESSAY_PROVIDER: EssayProviderConfig = EssayProviderConfig {
     extract_script: MEDIUM_EXTRACT_SCRIPT,
 };
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy)]
 struct EssayScrapePlan {
     provider: EssayProviderConfig,
@@ -10893,41 +12237,49 @@ struct EssayScrapePlan {
     pages: &'static [EssayScrapePage],
 }
 
+#[cfg(feature = "desktop")]
 const SUBSTACK_GRAPH_SCRAPE_PLAN: EssayScrapePlan = EssayScrapePlan {
     provider: SUBSTACK_ESSAY_PROVIDER,
     operation: "substack_scrape_graph",
     pages: &SUBSTACK_GRAPH_PAGES,
 };
+#[cfg(feature = "desktop")]
 const SUBSTACK_ACTIVITY_SCRAPE_PLAN: EssayScrapePlan = EssayScrapePlan {
     provider: SUBSTACK_ESSAY_PROVIDER,
     operation: "substack_scrape_activity",
     pages: &SUBSTACK_ACTIVITY_PAGES,
 };
+#[cfg(feature = "desktop")]
 const SUBSTACK_ESSAY_SCRAPE_PLAN: EssayScrapePlan = EssayScrapePlan {
     provider: SUBSTACK_ESSAY_PROVIDER,
     operation: "substack_scrape_essays",
     pages: &SUBSTACK_ESSAY_PAGES,
 };
+#[cfg(feature = "desktop")]
 const MEDIUM_GRAPH_SCRAPE_PLAN: EssayScrapePlan = EssayScrapePlan {
     provider: MEDIUM_ESSAY_PROVIDER,
     operation: "medium_scrape_graph",
     pages: &MEDIUM_GRAPH_PAGES,
 };
+#[cfg(feature = "desktop")]
 const MEDIUM_ACTIVITY_SCRAPE_PLAN: EssayScrapePlan = EssayScrapePlan {
     provider: MEDIUM_ESSAY_PROVIDER,
     operation: "medium_scrape_activity",
     pages: &MEDIUM_ACTIVITY_PAGES,
 };
+#[cfg(feature = "desktop")]
 const MEDIUM_ESSAY_SCRAPE_PLAN: EssayScrapePlan = EssayScrapePlan {
     provider: MEDIUM_ESSAY_PROVIDER,
     operation: "medium_scrape_essays",
     pages: &MEDIUM_ESSAY_PAGES,
 };
 
+#[cfg(feature = "desktop")]
 fn provider_host_matches(host: &str, provider_host: &str) -> bool {
     host == provider_host || host.ends_with(&format!(".{}", provider_host))
 }
 
+#[cfg(feature = "desktop")]
 fn provider_page_requires_login(url: &url::Url, provider_host: &str) -> bool {
     if !provider_host_matches(url.host_str().unwrap_or_default(), provider_host) {
         return true;
@@ -10936,6 +12288,7 @@ fn provider_page_requires_login(url: &url::Url, provider_host: &str) -> bool {
     path.contains("sign-in") || path.contains("signin") || path.contains("login")
 }
 
+#[cfg(feature = "desktop")]
 fn essay_capture_surface_is_sensitive(url: &url::Url, provider: EssayProviderConfig) -> bool {
     if provider.id != "substack" {
         return false;
@@ -10946,6 +12299,7 @@ fn essay_capture_surface_is_sensitive(url: &url::Url, provider: EssayProviderCon
         .any(|blocked| path.contains(blocked))
 }
 
+#[cfg(feature = "desktop")]
 fn store_essay_provider_user_agent(
     user_agent_store: &std::sync::Mutex<String>,
     user_agent: String,
@@ -10959,6 +12313,7 @@ fn store_essay_provider_user_agent(
     Ok(normalized)
 }
 
+#[cfg(feature = "desktop")]
 fn canonical_substack_profile_root(value: &str) -> Option<url::Url> {
     let parsed = url::Url::parse(value).ok()?;
     if parsed.scheme() != "https"
@@ -10981,6 +12336,7 @@ fn canonical_substack_profile_root(value: &str) -> Option<url::Url> {
     url::Url::parse(&format!("https://substack.com/@{}", handle)).ok()
 }
 
+#[cfg(feature = "desktop")]
 fn substack_profile_tab_url(root: &url::Url, tab: &str) -> Result<url::Url, String> {
     if !matches!(tab, "followers" | "following" | "reads") {
         return Err("Unsupported Substack profile surface".to_string());
@@ -10990,6 +12346,7 @@ fn substack_profile_tab_url(root: &url::Url, tab: &str) -> Result<url::Url, Stri
     Ok(result)
 }
 
+#[cfg(feature = "desktop")]
 fn normalized_surface_path(url: &url::Url) -> &str {
     let path = url.path().trim_end_matches('/');
     if path.is_empty() {
@@ -10999,11 +12356,13 @@ fn normalized_surface_path(url: &url::Url) -> &str {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn normalized_surface_host(url: &url::Url) -> Option<&str> {
     url.host_str()
         .map(|host| host.strip_prefix("www.").unwrap_or(host))
 }
 
+#[cfg(feature = "desktop")]
 fn essay_surface_origin_matches(current_url: &url::Url, target_url: &url::Url) -> bool {
     current_url.scheme() == target_url.scheme()
         && current_url.scheme() == "https"
@@ -11011,6 +12370,7 @@ fn essay_surface_origin_matches(current_url: &url::Url, target_url: &url::Url) -
         && current_url.port_or_known_default() == target_url.port_or_known_default()
 }
 
+#[cfg(feature = "desktop")]
 fn essay_provider_auth_surface_matches(
     current_url: &url::Url,
     provider: EssayProviderConfig,
@@ -11022,6 +12382,7 @@ fn essay_provider_auth_surface_matches(
         && normalized_surface_path(current_url) == normalized_surface_path(&target_url)
 }
 
+#[cfg(feature = "desktop")]
 fn essay_login_auth_probe_script(provider: EssayProviderConfig) -> Result<String, String> {
     Ok(format!(
         r#"
@@ -11048,6 +12409,7 @@ fn essay_login_auth_probe_script(provider: EssayProviderConfig) -> Result<String
     ))
 }
 
+#[cfg(feature = "desktop")]
 fn essay_capture_surface_matches(
     current_url: &url::Url,
     target_url: &url::Url,
@@ -11057,6 +12419,7 @@ fn essay_capture_surface_matches(
         && normalized_surface_path(current_url) == normalized_surface_path(target_url)
 }
 
+#[cfg(feature = "desktop")]
 async fn resolve_substack_profile_root(
     app: &tauri::AppHandle,
     window: &tauri::WebviewWindow,
@@ -11136,6 +12499,7 @@ async fn resolve_substack_profile_root(
         .ok_or_else(|| "Substack returned an invalid profile link".to_string())
 }
 
+#[cfg(feature = "desktop")]
 async fn resolve_substack_profile_root_with_fallback(
     app: &tauri::AppHandle,
     window: &tauri::WebviewWindow,
@@ -11158,6 +12522,7 @@ async fn resolve_substack_profile_root_with_fallback(
     }
 }
 
+#[cfg(feature = "desktop")]
 async fn emit_essay_extraction_pass(
     window: &tauri::WebviewWindow,
     provider: EssayProviderConfig,
@@ -11202,6 +12567,7 @@ async fn emit_essay_extraction_pass(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 async fn scroll_essay_roster_surface(
     app: &tauri::AppHandle,
     window: &tauri::WebviewWindow,
@@ -11290,6 +12656,7 @@ async fn scroll_essay_roster_surface(
     Ok(can_continue)
 }
 
+#[cfg(feature = "desktop")]
 async fn show_essay_provider_login(
     app: tauri::AppHandle,
     user_agent_store: &std::sync::Mutex<String>,
@@ -11350,6 +12717,7 @@ async fn show_essay_provider_login(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 async fn hide_essay_provider_login(
     app: tauri::AppHandle,
     provider: EssayProviderConfig,
@@ -11367,6 +12735,7 @@ async fn hide_essay_provider_login(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 async fn check_essay_provider_auth(
     app: tauri::AppHandle,
     capture: &CaptureState,
@@ -11475,6 +12844,7 @@ async fn check_essay_provider_auth(
     Ok(logged_in)
 }
 
+#[cfg(feature = "desktop")]
 fn build_essay_scraper_window(
     app: &tauri::AppHandle,
     window_label: &'static str,
@@ -11531,6 +12901,7 @@ fn build_essay_scraper_window(
     Ok(window)
 }
 
+#[cfg(feature = "desktop")]
 async fn scrape_essay_provider(
     app: tauri::AppHandle,
     capture: &CaptureState,
@@ -11734,6 +13105,7 @@ async fn scrape_essay_provider(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 async fn disconnect_essay_provider(
     app: tauri::AppHandle,
     provider: EssayProviderConfig,
@@ -11790,6 +13162,7 @@ async fn disconnect_essay_provider(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn substack_show_login(
     app: tauri::AppHandle,
@@ -11805,11 +13178,13 @@ async fn substack_show_login(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn substack_hide_login(app: tauri::AppHandle) -> Result<(), String> {
     hide_essay_provider_login(app, SUBSTACK_ESSAY_PROVIDER).await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn substack_check_auth(
     app: tauri::AppHandle,
@@ -11826,6 +13201,7 @@ async fn substack_check_auth(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn substack_disconnect(
     app: tauri::AppHandle,
@@ -11837,6 +13213,7 @@ async fn substack_disconnect(
     result
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn substack_scrape_graph(
     app: tauri::AppHandle,
@@ -11855,6 +13232,7 @@ async fn substack_scrape_graph(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn substack_scrape_activity(
     app: tauri::AppHandle,
@@ -11873,6 +13251,7 @@ async fn substack_scrape_activity(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn substack_scrape_essays(
     app: tauri::AppHandle,
@@ -11891,6 +13270,7 @@ async fn substack_scrape_essays(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn medium_show_login(
     app: tauri::AppHandle,
@@ -11906,11 +13286,13 @@ async fn medium_show_login(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn medium_hide_login(app: tauri::AppHandle) -> Result<(), String> {
     hide_essay_provider_login(app, MEDIUM_ESSAY_PROVIDER).await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn medium_check_auth(
     app: tauri::AppHandle,
@@ -11927,6 +13309,7 @@ async fn medium_check_auth(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn medium_disconnect(
     app: tauri::AppHandle,
@@ -11937,6 +13320,7 @@ async fn medium_disconnect(
     result
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn medium_scrape_graph(
     app: tauri::AppHandle,
@@ -11955,289 +13339,49 @@ async fn medium_scrape_graph(
     .await
 }
 
+#[cfg(feature = "desktop")]
 #[tauri::command]
 async fn medium_scrape_activity(
     app: tauri::AppHandle,
     capture: tauri::State<'_, CaptureState>,
     window_mode: ScraperWindowMode,
     user_agent: String,
-) -> Result<(), String> {
-    scrape_essay_provider(
-        app,
-        &capture,
-        &capture.medium_user_agent,
-        user_agent,
-        MEDIUM_ACTIVITY_SCRAPE_PLAN,
-        window_mode,
-    )
-    .await
-}
-
-#[tauri::command]
-async fn medium_scrape_essays(
-    app: tauri::AppHandle,
-    capture: tauri::State<'_, CaptureState>,
-    window_mode: ScraperWindowMode,
-    user_agent: String,
-) -> Result<(), String> {
-    scrape_essay_provider(
-        app,
-        &capture,
-        &capture.medium_user_agent,
-        user_agent,
-        MEDIUM_ESSAY_SCRAPE_PLAN,
-        window_mode,
-    )
-    .await
-}
-
-// ---------------------------------------------------------------------------
-// WebSocket relay
-// ---------------------------------------------------------------------------
-
-fn relay_connection_can_exchange_docs(state: &RelayState, connection_generation: u64) -> bool {
-    state
-        .accepting_doc_updates
-        .load(std::sync::atomic::Ordering::SeqCst)
-        && connection_generation == state.generation.load(std::sync::atomic::Ordering::SeqCst)
-}
-
-fn relay_request_token_matches(query: Option<&str>, expected_token: &str) -> bool {
-    query
-        .and_then(|query| {
-            query.split('&').find_map(|pair| {
-                let mut fields = pair.splitn(2, '=');
-                (fields.next() == Some("t"))
-                    .then(|| fields.next())
-                    .flatten()
-            })
-        })
-        .map(|token| token == expected_token)
-        .unwrap_or(false)
-}
-
-async fn store_relay_client_doc_if_current(
-    state: &RelayState,
-    connection_generation: u64,
-    bytes: Arc<Vec<u8>>,
-) -> bool {
-    let _epoch = state.epoch_gate.read().await;
-    let mut current_doc = state.current_doc.write().await;
-    if !relay_connection_can_exchange_docs(state, connection_generation) {
-        return false;
-    }
-    *current_doc = Some(bytes.clone());
-    let _ = state.broadcast_tx.send(bytes);
-    true
-}
-
-/// Authenticate and handle a single WebSocket connection.
-///
-/// The client must include `?t=<token>` in the upgrade URI.  Any connection
-/// that omits the token or presents an incorrect value is rejected with HTTP
-/// 401 before the WebSocket handshake completes. No data is exchanged.
-#[cfg_attr(feature = "perf", tracing::instrument(skip(stream, state, app), fields(addr = %addr)))]
-async fn handle_connection(
-    stream: TcpStream,
-    addr: SocketAddr,
-    state: RelayState,
-    app: tauri::AppHandle,
-) {
-    info!("[Sync] New connection from: {}", addr);
-
-    let (expected_token, connection_generation) = {
-        let _epoch = state.epoch_gate.read().await;
-        (
-            state.pairing_token.read().unwrap().clone(),
-            state.generation.load(std::sync::atomic::Ordering::SeqCst),
-        )
-    };
-    let handshake_token = expected_token.clone();
-    let mut disconnect_rx = state.disconnect_tx.subscribe();
-
-    let ws_stream = match accept_hdr_async(
-        stream,
-        move |req: &WsRequest, resp: WsResponse| -> Result<WsResponse, ErrorResponse> {
-            let token_ok = relay_request_token_matches(req.uri().query(), &handshake_token);
-
-            if token_ok {
-                Ok(resp)
-            } else {
-                error!("[Sync] Rejected unauthorized connection from {}", addr);
-                Err(tokio_tungstenite::tungstenite::http::Response::builder()
-                    .status(401)
-                    .body(Some("Unauthorized: rescan the QR code to pair".to_owned()))
-                    .unwrap())
-            }
-        },
-    )
-    .await
-    {
-        Ok(ws) => ws,
-        Err(e) => {
-            // 401 rejections are normal; log everything else
-            if !e.to_string().contains("HTTP error") {
-                error!("[Sync] WebSocket handshake failed: {}", e);
-            }
-            return;
-        }
-    };
-
-    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-    let mut broadcast_rx = state.broadcast_tx.subscribe();
-
-    let connected_count = {
-        let _epoch = state.epoch_gate.read().await;
-        let token_is_current =
-            state.pairing_token.read().unwrap().as_str() == expected_token.as_str();
-        if !token_is_current || !relay_connection_can_exchange_docs(&state, connection_generation) {
-            let _ = timeout(
-                SYNC_RELAY_DOC_SEND_TIMEOUT,
-                ws_sender.send(Message::Close(None)),
-            )
-            .await;
-            info!("[Sync] Client {} rejected during relay reset", addr);
-            return;
-        }
-
-        if let Some(doc) = state.current_doc.read().await.clone() {
-            match timeout(
-                SYNC_RELAY_DOC_SEND_TIMEOUT,
-                ws_sender.send(Message::Binary(doc.as_ref().clone().into())),
-            )
-            .await
-            {
-                Ok(Ok(())) => {}
-                Ok(Err(error)) => {
-                    error!("[Sync] Failed to send initial doc: {}", error);
-                    return;
-                }
-                Err(_) => {
-                    error!("[Sync] Timed out sending initial doc to {}", addr);
-                    return;
-                }
-            }
-        }
-
-        let mut count = state.client_count.write().await;
-        *count += 1;
-        *count
-    };
-    info!("[Sync] Client connected. Total: {}", connected_count);
-    let _ = app.emit("sync-client-count", connected_count);
-
-    loop {
-        tokio::select! {
-            msg = ws_receiver.next() => {
-                match msg {
-                    Some(Ok(Message::Binary(data))) => {
-                        // The client pushed a document update. Store and rebroadcast it.
-                        let bytes = Arc::new(data.to_vec());
-                        if !store_relay_client_doc_if_current(
-                            &state,
-                            connection_generation,
-                            bytes,
-                        ).await {
-                            info!("[Sync] Ignored stale client update after relay reset");
-                            break;
-                        }
-                    }
-                    Some(Ok(Message::Close(_))) | None => {
-                        info!("[Sync] Client {} disconnected", addr);
-                        break;
-                    }
-                    Some(Ok(Message::Ping(data))) => {
-                        let _ = ws_sender.send(Message::Pong(data)).await;
-                    }
-                    Some(Err(e)) => {
-                        error!("[Sync] Error from {}: {}", addr, e);
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-            broadcast = broadcast_rx.recv() => {
-                if let Ok(doc) = broadcast {
-                    let _epoch = state.epoch_gate.read().await;
-                    if !relay_connection_can_exchange_docs(&state, connection_generation) {
-                        let _ = timeout(
-                            SYNC_RELAY_DOC_SEND_TIMEOUT,
-                            ws_sender.send(Message::Close(None)),
-                        ).await;
-                        info!("[Sync] Client {} rejected a broadcast after relay reset", addr);
-                        break;
-                    }
-                    match timeout(
-                        SYNC_RELAY_DOC_SEND_TIMEOUT,
-                        ws_sender.send(Message::Binary(doc.as_ref().clone().into())),
-                    ).await {
-                        Ok(Ok(())) => {}
-                        Ok(Err(error)) => {
-                            error!("[Sync] Failed to send to {}: {}", addr, error);
-                            break;
-                        }
-                        Err(_) => {
-                            error!("[Sync] Timed out sending to {}", addr);
-                            break;
-                        }
-                    }
-                }
-            }
-            reset = disconnect_rx.recv() => {
-                if reset.is_ok() {
-                    let _ = ws_sender.send(Message::Close(None)).await;
-                    info!("[Sync] Client {} disconnected by factory reset", addr);
-                    break;
-                }
-            }
-        }
-    }
-
-    // Decrement client count and notify frontend
-    {
-        let mut count = state.client_count.write().await;
-        *count = count.saturating_sub(1);
-        let new_count = *count;
-        info!("[Sync] Client disconnected. Total: {}", new_count);
-        let _ = app.emit("sync-client-count", new_count);
-    }
-}
-
-async fn start_sync_relay(state: RelayState, app: tauri::AppHandle) {
-    let addr = format!("0.0.0.0:{}", state.port);
-
-    let (listener, retries_used) =
-        match bind_sync_relay_listener_with_policy(&addr, DEFAULT_SYNC_RELAY_BIND_RETRY_POLICY)
-            .await
-        {
-            Ok(listener) => listener,
-            Err(e) => {
-                error!("[Sync] Failed to bind to {}: {}", addr, e);
-                return;
-            }
-        };
-
-    if retries_used == 0 {
-        info!("[Sync] Relay server listening on {}", addr);
-    } else {
-        info!(
-            "[Sync] Relay server listening on {} after {} retry attempt(s)",
-            addr, retries_used
-        );
-    }
+) -> Result<(), String> {
+    scrape_essay_provider(
+        app,
+        &capture,
+        &capture.medium_user_agent,
+        user_agent,
+        MEDIUM_ACTIVITY_SCRAPE_PLAN,
+        window_mode,
+    )
+    .await
+}
 
-    while let Ok((stream, addr)) = listener.accept().await {
-        let state = state.clone();
-        let app = app.clone();
-        tokio::spawn(handle_connection(stream, addr, state, app));
-    }
+#[cfg(feature = "desktop")]
+#[tauri::command]
+async fn medium_scrape_essays(
+    app: tauri::AppHandle,
+    capture: tauri::State<'_, CaptureState>,
+    window_mode: ScraperWindowMode,
+    user_agent: String,
+) -> Result<(), String> {
+    scrape_essay_provider(
+        app,
+        &capture,
+        &capture.medium_user_agent,
+        user_agent,
+        MEDIUM_ESSAY_SCRAPE_PLAN,
+        window_mode,
+    )
+    .await
 }
 
 // ---------------------------------------------------------------------------
 // App entry point
 // ---------------------------------------------------------------------------
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn main_window_webview_configuration() -> Retained<WKWebViewConfiguration> {
     let mtm = MainThreadMarker::new()
         .expect("WKWebView configuration must be created on the main thread");
@@ -12256,7 +13400,7 @@ fn main_window_webview_configuration() -> Retained<WKWebViewConfiguration> {
     config
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn disable_window_restoration(window: &tauri::WebviewWindow) {
     let Ok(ns_window) = window.ns_window() else {
         return;
@@ -12269,16 +13413,17 @@ fn disable_window_restoration(window: &tauri::WebviewWindow) {
     }
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn main_window_handle_available(window: &tauri::WebviewWindow) -> bool {
     window.ns_window().is_ok()
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn main_window_handle_available(_window: &tauri::WebviewWindow) -> bool {
     true
 }
 
+#[cfg(feature = "desktop")]
 fn live_main_window(app: &tauri::AppHandle) -> Option<tauri::WebviewWindow> {
     let window = app.get_webview_window(MAIN_WINDOW_LABEL)?;
     if main_window_handle_available(&window) {
@@ -12288,6 +13433,7 @@ fn live_main_window(app: &tauri::AppHandle) -> Option<tauri::WebviewWindow> {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn wait_for_main_window_release(app: &tauri::AppHandle, reason: &str) -> Result<(), String> {
     for attempt in 0..MAIN_WINDOW_RELEASE_POLL_ATTEMPTS {
         match app.get_webview_window(MAIN_WINDOW_LABEL) {
@@ -12319,11 +13465,12 @@ fn wait_for_main_window_release(app: &tauri::AppHandle, reason: &str) -> Result<
     ))
 }
 
+#[cfg(feature = "desktop")]
 fn main_window_release_timeout_ms() -> u128 {
     MAIN_WINDOW_RELEASE_POLL_INTERVAL.as_millis() * MAIN_WINDOW_RELEASE_POLL_ATTEMPTS as u128
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn apply_main_window_vibrancy(window: &tauri::WebviewWindow, context: &str) -> bool {
     if std::env::var("FREED_ENABLE_MAIN_WINDOW_VIBRANCY")
         .ok()
@@ -12354,17 +13501,19 @@ fn apply_main_window_vibrancy(window: &tauri::WebviewWindow, context: &str) -> b
     }
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn apply_main_window_vibrancy(_window: &tauri::WebviewWindow, _context: &str) -> bool {
     false
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum MainWindowPresentation {
     Foreground,
     Quiet,
 }
 
+#[cfg(feature = "desktop")]
 impl MainWindowPresentation {
     fn should_focus(self) -> bool {
         matches!(self, MainWindowPresentation::Foreground)
@@ -12382,14 +13531,17 @@ impl MainWindowPresentation {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn show_webview_window(window: &tauri::WebviewWindow) {
     present_webview_window(window, MainWindowPresentation::Foreground, "show");
 }
 
+#[cfg(feature = "desktop")]
 fn quietly_show_webview_window(window: &tauri::WebviewWindow, context: &str) {
     quiet_show_webview_window(window, context);
 }
 
+#[cfg(feature = "desktop")]
 fn present_webview_window(
     window: &tauri::WebviewWindow,
     presentation: MainWindowPresentation,
@@ -12415,6 +13567,7 @@ fn present_webview_window(
     }
 }
 
+#[cfg(feature = "desktop")]
 fn set_main_window_focusable(window: &tauri::WebviewWindow, focusable: bool, context: &str) {
     match window.set_focusable(focusable) {
         Ok(()) => info!(
@@ -12428,7 +13581,7 @@ fn set_main_window_focusable(window: &tauri::WebviewWindow, focusable: bool, con
     }
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn show_app_for_main_window(
     window: &tauri::WebviewWindow,
     presentation: MainWindowPresentation,
@@ -12447,7 +13600,7 @@ fn show_app_for_main_window(
     }
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn show_app_for_main_window(
     _window: &tauri::WebviewWindow,
     _presentation: MainWindowPresentation,
@@ -12455,7 +13608,7 @@ fn show_app_for_main_window(
 ) {
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn force_activate_ns_app(context: &str) {
     let Some(mtm) = MainThreadMarker::new() else {
         warn!(
@@ -12491,10 +13644,10 @@ fn force_activate_ns_app(context: &str) {
     );
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn force_activate_ns_app(_context: &str) {}
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn main_window_native_occlusion_state(window: &tauri::WebviewWindow) -> Option<usize> {
     let Ok(ns_window) = window.ns_window() else {
         return None;
@@ -12504,17 +13657,18 @@ fn main_window_native_occlusion_state(window: &tauri::WebviewWindow) -> Option<u
     Some(unsafe { msg_send![ns_window, occlusionState] })
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn main_window_native_occlusion_state(_window: &tauri::WebviewWindow) -> Option<usize> {
     None
 }
 
+#[cfg(feature = "desktop")]
 fn main_window_native_is_occluded(window: &tauri::WebviewWindow) -> Option<bool> {
     main_window_native_occlusion_state(window)
         .map(|state| state & NS_WINDOW_OCCLUSION_STATE_VISIBLE == 0)
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn log_main_window_native_state(window: &tauri::WebviewWindow, context: &str) {
     let Ok(ns_window) = window.ns_window() else {
         warn!(
@@ -12564,10 +13718,10 @@ fn log_main_window_native_state(window: &tauri::WebviewWindow, context: &str) {
     }
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn log_main_window_native_state(_window: &tauri::WebviewWindow, _context: &str) {}
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn force_show_webview_window(window: &tauri::WebviewWindow, context: &str) {
     let Ok(ns_window) = window.ns_window() else {
         warn!(
@@ -12599,10 +13753,10 @@ fn force_show_webview_window(window: &tauri::WebviewWindow, context: &str) {
     );
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn force_show_webview_window(_window: &tauri::WebviewWindow, _context: &str) {}
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn quiet_show_webview_window(window: &tauri::WebviewWindow, context: &str) {
     let Ok(ns_window) = window.ns_window() else {
         warn!(
@@ -12633,9 +13787,10 @@ fn quiet_show_webview_window(window: &tauri::WebviewWindow, context: &str) {
     );
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(all(feature = "desktop", not(target_os = "macos")))]
 fn quiet_show_webview_window(_window: &tauri::WebviewWindow, _context: &str) {}
 
+#[cfg(feature = "desktop")]
 fn schedule_main_window_focusable_restore(
     app: &tauri::AppHandle,
     delay: Duration,
@@ -12659,6 +13814,7 @@ fn schedule_main_window_focusable_restore(
     });
 }
 
+#[cfg(feature = "desktop")]
 fn schedule_main_window_visibility_probe(
     app: &tauri::AppHandle,
     delay: Duration,
@@ -12693,6 +13849,7 @@ fn schedule_main_window_visibility_probe(
     });
 }
 
+#[cfg(feature = "desktop")]
 fn schedule_main_window_occlusion_recovery(
     app: &tauri::AppHandle,
     delay: Duration,
@@ -12730,10 +13887,12 @@ fn schedule_main_window_occlusion_recovery(
     });
 }
 
+#[cfg(feature = "desktop")]
 fn create_main_window(app: &tauri::AppHandle) -> Result<tauri::WebviewWindow, tauri::Error> {
     create_main_window_with_initial_visibility(app, true)
 }
 
+#[cfg(feature = "desktop")]
 fn create_main_window_with_initial_visibility(
     app: &tauri::AppHandle,
     initially_visible: bool,
@@ -12778,6 +13937,7 @@ fn create_main_window_with_initial_visibility(
     Ok(window)
 }
 
+#[cfg(feature = "desktop")]
 fn open_main_window_recovery_keepalive(
     app: &tauri::AppHandle,
     reason: &str,
@@ -12812,6 +13972,7 @@ fn open_main_window_recovery_keepalive(
     Ok(window)
 }
 
+#[cfg(feature = "desktop")]
 fn close_main_window_recovery_keepalive(app: &tauri::AppHandle, reason: &str) {
     let Some(window) = app.get_webview_window(MAIN_WINDOW_RECOVERY_KEEPALIVE_LABEL) else {
         return;
@@ -12829,6 +13990,7 @@ fn close_main_window_recovery_keepalive(app: &tauri::AppHandle, reason: &str) {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn start_main_window_with_presentation(
     app: &tauri::AppHandle,
     presentation: MainWindowPresentation,
@@ -12870,14 +14032,17 @@ fn start_main_window_with_presentation(
     Ok(window)
 }
 
+#[cfg(feature = "desktop")]
 fn start_main_window(app: &tauri::AppHandle) -> Result<tauri::WebviewWindow, tauri::Error> {
     start_main_window_with_presentation(app, MainWindowPresentation::Foreground)
 }
 
+#[cfg(feature = "desktop")]
 fn start_main_window_quietly(app: &tauri::AppHandle) -> Result<tauri::WebviewWindow, tauri::Error> {
     start_main_window_with_presentation(app, MainWindowPresentation::Quiet)
 }
 
+#[cfg(feature = "desktop")]
 fn run_main_window_step_on_main_thread<F, T>(
     app: &tauri::AppHandle,
     context: &'static str,
@@ -12902,6 +14067,7 @@ where
     }
 }
 
+#[cfg(feature = "desktop")]
 fn request_restart_after_recovery_failure(app: &tauri::AppHandle, reason: &str, error: &str) {
     if let Ok(data_dir) = app.path().app_data_dir() {
         mark_startup_failed(&data_dir);
@@ -12928,6 +14094,7 @@ fn request_restart_after_recovery_failure(app: &tauri::AppHandle, reason: &str,
     });
 }
 
+#[cfg(feature = "desktop")]
 fn request_restart_after_recovery_verification_failure(
     app: &tauri::AppHandle,
     reason: &str,
@@ -12968,6 +14135,7 @@ fn request_restart_after_recovery_verification_failure(
     });
 }
 
+#[cfg(feature = "desktop")]
 fn schedule_main_window_recovery_verification(
     app: &tauri::AppHandle,
     reason: &str,
@@ -13042,12 +14210,14 @@ fn schedule_main_window_recovery_verification(
     });
 }
 
+#[cfg(feature = "desktop")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum MainWindowRecoveryPresentation {
     RestoreIfVisible,
     KeepHidden,
 }
 
+#[cfg(feature = "desktop")]
 fn recover_main_window(
     app: &tauri::AppHandle,
     reason_enum: WindowDestroyedReason,
@@ -13061,6 +14231,7 @@ fn recover_main_window(
     )
 }
 
+#[cfg(feature = "desktop")]
 fn recover_main_window_hidden(
     app: &tauri::AppHandle,
     reason_enum: WindowDestroyedReason,
@@ -13074,6 +14245,7 @@ fn recover_main_window_hidden(
     )
 }
 
+#[cfg(feature = "desktop")]
 fn recover_main_window_with_presentation(
     app: &tauri::AppHandle,
     reason_enum: WindowDestroyedReason,
@@ -13129,6 +14301,7 @@ fn recover_main_window_with_presentation(
     outcome
 }
 
+#[cfg(feature = "desktop")]
 fn destroy_main_window_for_recovery(
     app: &tauri::AppHandle,
     reason_enum: WindowDestroyedReason,
@@ -13151,6 +14324,7 @@ fn destroy_main_window_for_recovery(
     Ok(was_visible)
 }
 
+#[cfg(feature = "desktop")]
 fn rebuild_main_window_after_recovery(
     app: &tauri::AppHandle,
     reason: &str,
@@ -13173,6 +14347,7 @@ fn rebuild_main_window_after_recovery(
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 fn show_primary_window(app: &tauri::AppHandle) {
     if let Some(window) = live_main_window(app) {
         show_webview_window(&window);
@@ -13197,6 +14372,7 @@ fn show_primary_window(app: &tauri::AppHandle) {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn handle_primary_menu_action(app: &tauri::AppHandle, id: &str) -> bool {
     match id {
         PRIMARY_MENU_ITEM_SHOW => {
@@ -13211,6 +14387,7 @@ fn handle_primary_menu_action(app: &tauri::AppHandle, id: &str) -> bool {
     }
 }
 
+#[cfg(feature = "desktop")]
 fn build_primary_action_items<R: tauri::Runtime, M: Manager<R>>(
     manager: &M,
 ) -> tauri::Result<(MenuItem<R>, MenuItem<R>)> {
@@ -13238,7 +14415,7 @@ fn should_show_primary_window_on_reopen(has_visible_windows: bool) -> bool {
     true
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn handle_macos_reopen(app: &tauri::AppHandle, has_visible_windows: bool) {
     info!(
         "[main-window] macOS reopen event has_visible_windows={} forcing_primary_window_show=true",
@@ -13250,7 +14427,7 @@ fn handle_macos_reopen(app: &tauri::AppHandle, has_visible_windows: bool) {
     }
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(feature = "desktop", target_os = "macos"))]
 fn build_macos_app_menu<R: tauri::Runtime, M: Manager<R>>(manager: &M) -> tauri::Result<Menu<R>> {
     let (show_item, quit_item) = build_primary_action_items(manager)?;
     let app_menu = Submenu::with_items(
@@ -13286,6 +14463,7 @@ fn build_macos_app_menu<R: tauri::Runtime, M: Manager<R>>(manager: &M) -> tauri:
     Menu::with_items(manager, &[&app_menu, &edit_menu])
 }
 
+#[cfg(feature = "desktop")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // When built with --features perf, initialise a JSON tracing subscriber so
@@ -13302,21 +14480,9 @@ pub fn run() {
             .init();
     }
 
-    let (broadcast_tx, _) = broadcast::channel::<Arc<Vec<u8>>>(16);
-    let (disconnect_tx, _) = broadcast::channel::<u64>(16);
-
-    let relay_state = Arc::new(SyncRelayState {
-        port: sync_relay_port(),
-        epoch_gate: RwLock::new(()),
-        broadcast_tx,
-        current_doc: RwLock::new(None),
-        disconnect_tx,
-        generation: std::sync::atomic::AtomicU64::new(0),
-        accepting_doc_updates: std::sync::atomic::AtomicBool::new(true),
-        client_count: RwLock::new(0),
-        // Populated from disk in .setup() before the relay starts accepting connections.
-        pairing_token: StdRwLock::new(String::new()),
-    });
+    install_panic_hook();
+
+    let relay_state = Arc::new(SyncRelayState::new(sync_relay_port()));
 
     let relay_state_clone = relay_state.clone();
     let log_plugin = {
@@ -13371,6 +14537,7 @@ pub fn run() {
                 .app_data_dir()
                 .expect("Failed to resolve app data directory");
             std::fs::create_dir_all(&data_dir).ok();
+            let _ = PANIC_DATA_DIR.set(data_dir.clone());
             let dev_sync_result_data_dir = data_dir.clone();
             app.listen("dev-sync-trigger-native-result", move |event| {
                 handle_dev_sync_trigger_result_event(&dev_sync_result_data_dir, event.payload());
@@ -13380,8 +14547,9 @@ pub fn run() {
             #[cfg(target_os = "macos")]
             clear_saved_window_state(&app_handle);
 
-            let startup_recovery_state = reconcile_startup_recovery_state(&data_dir);
+            let mut startup_recovery_state = reconcile_startup_recovery_state(&data_dir);
             if startup_requires_recovery(&startup_recovery_state) {
+                apply_startup_recovery_tier_for_launch(&app_handle, &data_dir, &mut startup_recovery_state);
                 warn!(
                     "[recovery] opening native recovery window after {} failed early startup attempt(s)",
                     startup_recovery_state.consecutive_failed_boots
@@ -13393,8 +14561,14 @@ pub fn run() {
 
             // Load (or generate) the persistent pairing token before the relay
             // starts accepting connections.
-            let token = load_or_create_token(&data_dir);
-            *relay_state_clone.pairing_token.write().unwrap() = token;
+            load_relay_credentials(&relay_state_clone, &data_dir);
+            match load_or_create_payload_key(&data_dir) {
+                Ok(key) => *relay_state_clone.payload_key.write().unwrap() = Some(Arc::new(key)),
+                Err(error) => error!(
+                    "[Sync] Sync key unavailable, relaying plaintext documents: {}",
+                    error
+                ),
+            }
 
             // Build system tray
             let (show_item, quit_item) = build_primary_action_items(app)?;
@@ -14445,16 +15619,77 @@ pub fn run() {
                 }
             });
 
-            // Start mDNS advertisement and keep the daemon alive.
-            let mdns_daemon = advertise_mdns(relay_state_clone.port);
+            // Start mDNS advertisement, browse for peer desktops on the same
+            // daemon, and keep it alive.
+            let mdns_instance = sync_peer_local_name();
+            let relay_fingerprint = relay_state_clone
+                .tls
+                .read()
+                .unwrap()
+                .as_ref()
+                .map(|tls| tls.fingerprint.clone());
+            let mdns_daemon = advertise_mdns(
+                relay_state_clone.listen_port(),
+                &mdns_instance,
+                relay_fingerprint.as_deref(),
+            );
+            if let Some(daemon) = &mdns_daemon {
+                browse_sync_peers(
+                    daemon,
+                    &mdns_instance,
+                    relay_state_clone.clone(),
+                    Arc::new(app.handle().clone()),
+                );
+            }
             app.manage(MdnsState(mdns_daemon));
 
+            let relay_doc_state = relay_state_clone.clone();
+            let relay_doc_dir = data_dir.clone();
+            tauri::async_runtime::spawn(run_debounced_doc_writer(
+                relay_state_clone.snapshot_tx.subscribe(),
+                DEFAULT_SNAPSHOT_WRITER_POLICY,
+                move |_| persist_held_relay_doc(&relay_doc_state, &relay_doc_dir),
+            ));
+
+            relay_state_clone
+                .snapshot_retention_tx
+                .send_replace(SnapshotRetention::load(&data_dir));
+            let snapshot_rx = relay_state_clone.snapshot_tx.subscribe();
+            let retention_rx = relay_state_clone.snapshot_retention_tx.subscribe();
+            let snapshot_writer_dir = snapshot_dir(&data_dir);
+            let snapshot_state = relay_state_clone.clone();
+            let snapshot_events: RelayEvents = Arc::new(app.handle().clone());
+            tauri::async_runtime::spawn(async move {
+                run_snapshot_writer(
+                    snapshot_writer_dir,
+                    snapshot_rx,
+                    DEFAULT_SNAPSHOT_WRITER_POLICY,
+                    retention_rx,
+                    snapshot_events,
+                    move |doc| open_relay_payload(&snapshot_state, doc),
+                )
+                .await;
+            });
+
+            relay_state_clone
+                .backup_schedule_tx
+                .send_replace(BackupSchedule::load(&data_dir));
+            tauri::async_runtime::spawn(run_backup_scheduler(
+                data_dir.clone(),
+                relay_state_clone.backup_schedule_tx.subscribe(),
+                Arc::new(app.handle().clone()),
+            ));
+
             // Start the relay — token is already set, so new connections are
-            // immediately subject to authentication.
+            // immediately subject to authentication, and the held document is
+            // loaded before the first connection is accepted.
             let state = relay_state_clone.clone();
-            let app_handle = app.handle().clone();
+            let events: RelayEvents = Arc::new(app.handle().clone());
+            let relay_doc_dir = data_dir.clone();
             tauri::async_runtime::spawn(async move {
-                start_sync_relay(state, app_handle).await;
+                restore_held_relay_doc(&state, &relay_doc_dir).await;
+                start_trusted_sync_peers(&state, &events);
+                start_sync_relay(state, events).await;
             });
 
             // Dev-only: auto-trigger a Facebook scrape on startup so we can
@@ -14516,6 +15751,8 @@ pub fn run() {
             get_desktop_installation_witness,
             get_updater_target,
             retry_startup_after_crash,
+            get_startup_recovery_state,
+            rollback_to_last_good_snapshot,
             export_startup_diagnostics,
             fetch_url,
             google_api_request,
@@ -14525,11 +15762,17 @@ pub fn run() {
             x_api_request,
             get_local_ip,
             get_all_local_ips,
+            get_relay_network_settings,
+            set_relay_network_settings,
+            get_relay_rendezvous,
+            set_relay_rendezvous,
             get_sync_url,
             sha256_file,
             download_local_ai_model_file,
             cancel_local_ai_model_download,
             get_sync_client_count,
+            get_sync_clients,
+            disconnect_sync_client,
             get_runtime_memory_stats,
             trim_webkit_network_cache_now,
             get_recent_runtime_health,
@@ -14540,8 +15783,15 @@ pub fn run() {
             get_social_provider_cookie_state,
             prepare_social_scrape_memory,
             broadcast_doc,
+            broadcast_changes,
             clear_factory_reset_runtime_artifacts,
             reset_pairing_token,
+            list_sync_devices,
+            revoke_sync_device,
+            list_sync_peers,
+            trust_sync_peer,
+            forget_sync_peer,
+            get_relay_doc,
             factory_reset_sync_relay,
             resume_sync_relay_after_factory_reset,
             show_window,
@@ -14550,6 +15800,17 @@ pub fn run() {
             close_x_login_window,
             get_mdns_active,
             list_snapshots,
+            inspect_snapshot,
+            diff_snapshots,
+            restore_snapshot,
+            compact_document,
+            get_snapshot_retention,
+            set_snapshot_retention,
+            export_backup,
+            import_backup,
+            get_backup_schedule,
+            set_backup_schedule,
+            run_backup_now,
             get_recent_logs,
             start_oauth_server,
             pick_contact,
@@ -14614,7 +15875,39 @@ pub fn run() {
 #[cfg(test)]
 mod tests {
     use super::*;
-
+    #[cfg(feature = "desktop")]
+    use crate::peers::SyncPeerRegistry;
+    #[cfg(feature = "desktop")]
+    use crate::relay::{
+        authorize_relay_request, load_or_create_token, relay_connection_can_exchange_docs,
+        relay_payload_is_acceptable, relay_request_token_matches,
+        store_relay_client_doc_if_current, sync_pairing_url,
+        tests::{
+            automerge_doc_bytes, automerge_doc_entries, automerge_like_bytes, loopback_test_relay,
+            loopback_test_upgrade, snapshot_test_relay_state,
+        },
+        RelayAuthLimiter, RelayClientAuth, RelayClientRegistry, RelayClientStore,
+        RelayDeviceRegistry, RelayHead, RelayTlsIdentity, DEFAULT_RELAY_ABUSE_POLICY,
+        RELAY_CLOSE_DISCONNECTED, SEALED_PAYLOAD_MAGIC,
+    };
+    use crate::relay::{
+        load_relay_credentials, restore_held_relay_doc, write_relay_doc, SyncRelayState,
+        DEFAULT_SYNC_RELAY_PORT, RELAY_DOC_FILE, RELAY_HEAD_BYTES,
+    };
+    #[cfg(feature = "desktop")]
+    use crate::rendezvous::RelayRendezvousSettings;
+    use crate::snapshots::{run_debounced_doc_writer, SnapshotWriterPolicy};
+    #[cfg(feature = "desktop")]
+    use crate::snapshots::{store_snapshot, tests::appended_history_doc};
+    use bytes::Bytes;
+    #[cfg(feature = "desktop")]
+    use futures_util::SinkExt;
+    #[cfg(feature = "desktop")]
+    use tokio::sync::{broadcast, RwLock};
+    #[cfg(feature = "desktop")]
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[cfg(feature = "desktop")]
     #[test]
     fn desktop_installation_witness_is_scoped_to_machine_and_user() {
         let first = hash_desktop_installation_witness("machine-a", "user-a");
@@ -14633,6 +15926,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn factory_reset_clears_runtime_artifacts_but_preserves_installation_state() {
         let data_dir = tempfile::tempdir().unwrap();
@@ -14676,28 +15970,63 @@ mod tests {
         clear_factory_reset_runtime_artifacts_in(data_dir.path()).unwrap();
     }
 
+    #[cfg(feature = "desktop")]
     #[tokio::test]
     async fn factory_reset_relay_rejects_old_clients_and_clears_held_document() {
         let data_dir = tempfile::tempdir().unwrap();
         std::fs::write(data_dir.path().join("pairing-token"), "old-token").unwrap();
-        let (broadcast_tx, _) = broadcast::channel::<Arc<Vec<u8>>>(16);
-        let (disconnect_tx, _) = broadcast::channel::<u64>(16);
+        let (broadcast_tx, _) = broadcast::channel::<RelayUpdate>(16);
+        let (disconnect_tx, _) = broadcast::channel::<RelayDisconnect>(16);
         let mut disconnect_rx = disconnect_tx.subscribe();
         let state = Arc::new(SyncRelayState {
             port: DEFAULT_SYNC_RELAY_PORT,
             epoch_gate: RwLock::new(()),
             broadcast_tx,
-            current_doc: RwLock::new(Some(Arc::new(vec![1, 2, 3]))),
+            current_doc: RwLock::new(None),
+            current_heads: StdRwLock::new(Arc::default()),
+            snapshot_tx: tokio::sync::watch::channel(None).0,
+            #[cfg(feature = "desktop")]
+            snapshot_retention_tx: tokio::sync::watch::channel(SnapshotRetention::default()).0,
+            #[cfg(feature = "desktop")]
+            backup_schedule_tx: tokio::sync::watch::channel(BackupSchedule::default()).0,
             disconnect_tx,
             generation: std::sync::atomic::AtomicU64::new(7),
             accepting_doc_updates: std::sync::atomic::AtomicBool::new(true),
             client_count: RwLock::new(1),
+            client_count_tx: tokio::sync::watch::channel(1).0,
             pairing_token: StdRwLock::new("old-token".to_string()),
+            tls: StdRwLock::new(None),
+            devices: StdMutex::new(RelayDeviceRegistry::default()),
+            payload_key: StdRwLock::new(None),
+            #[cfg(feature = "desktop")]
+            peers: StdMutex::new(SyncPeerRegistry::default()),
+            network_tx: tokio::sync::watch::channel(RelayNetworkSettings::default()).0,
+            abuse_policy: DEFAULT_RELAY_ABUSE_POLICY,
+            connection_slots: Arc::new(tokio::sync::Semaphore::new(
+                DEFAULT_RELAY_ABUSE_POLICY.max_connections,
+            )),
+            auth_limiter: StdMutex::new(RelayAuthLimiter::default()),
+            clients: StdMutex::new(RelayClientRegistry::default()),
+            rendezvous_tx: tokio::sync::watch::channel(RelayRendezvousSettings::default()).0,
+            rendezvous_connected: std::sync::atomic::AtomicBool::new(false),
         });
 
         let mut broadcast_rx = state.broadcast_tx.subscribe();
-        assert!(store_relay_client_doc_if_current(&state, 7, Arc::new(vec![4, 5, 6]),).await);
-        assert_eq!(broadcast_rx.recv().await.unwrap().as_slice(), &[4, 5, 6]);
+        let pushed = automerge_doc_bytes("title", "pushed");
+        assert_eq!(
+            store_relay_client_doc_if_current(&state, 7, &pushed).await,
+            RelayClientStore::Stored
+        );
+        assert_eq!(
+            automerge_doc_entries(broadcast_rx.recv().await.unwrap().payload()),
+            automerge_doc_entries(&pushed)
+        );
+        let persist_state = state.clone();
+        let persist_dir = data_dir.path().to_path_buf();
+        tokio::task::spawn_blocking(move || persist_held_relay_doc(&persist_state, &persist_dir))
+            .await
+            .unwrap();
+        assert!(data_dir.path().join(RELAY_DOC_FILE).exists());
 
         let epoch = state.epoch_gate.read().await;
         let reset_state = state.clone();
@@ -14711,10 +16040,25 @@ mod tests {
         let new_token = reset.await.unwrap().unwrap();
 
         assert_ne!(new_token, "old-token");
+        assert!(!data_dir.path().join(RELAY_DOC_FILE).exists());
         assert_eq!(
             std::fs::read_to_string(data_dir.path().join("pairing-token")).unwrap(),
             new_token
         );
+        let rotated_fingerprint = state
+            .tls
+            .read()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .fingerprint
+            .clone();
+        assert_eq!(
+            RelayTlsIdentity::load(data_dir.path())
+                .unwrap()
+                .fingerprint(),
+            rotated_fingerprint
+        );
         assert_eq!(state.pairing_token.read().unwrap().as_str(), new_token);
         assert!(state.current_doc.read().await.is_none());
         assert_eq!(
@@ -14724,10 +16068,19 @@ mod tests {
         assert!(!state
             .accepting_doc_updates
             .load(std::sync::atomic::Ordering::SeqCst));
-        assert_eq!(disconnect_rx.recv().await.unwrap(), 8);
+        assert_eq!(
+            disconnect_rx.recv().await.unwrap(),
+            RelayDisconnect::FactoryReset(8)
+        );
 
-        assert!(!store_relay_client_doc_if_current(&state, 7, Arc::new(vec![9, 9, 9])).await);
-        assert!(!store_relay_client_doc_if_current(&state, 8, Arc::new(vec![8, 8, 8])).await);
+        assert_eq!(
+            store_relay_client_doc_if_current(&state, 7, &pushed).await,
+            RelayClientStore::Stale
+        );
+        assert_eq!(
+            store_relay_client_doc_if_current(&state, 8, &pushed).await,
+            RelayClientStore::Stale
+        );
         assert!(!relay_connection_can_exchange_docs(&state, 7));
         assert!(!relay_connection_can_exchange_docs(&state, 8));
         assert!(state.current_doc.read().await.is_none());
@@ -14754,23 +16107,45 @@ mod tests {
         assert!(relay_request_token_matches(Some(&new_query), &new_token));
     }
 
+    #[cfg(feature = "desktop")]
     #[tokio::test]
     async fn factory_reset_relay_persistence_failure_preserves_state_and_sessions() {
         let data_dir = tempfile::tempdir().unwrap();
         std::fs::create_dir(data_dir.path().join("pairing-token")).unwrap();
-        let (broadcast_tx, _) = broadcast::channel::<Arc<Vec<u8>>>(16);
-        let (disconnect_tx, _) = broadcast::channel::<u64>(16);
+        let (broadcast_tx, _) = broadcast::channel::<RelayUpdate>(16);
+        let (disconnect_tx, _) = broadcast::channel::<RelayDisconnect>(16);
         let mut disconnect_rx = disconnect_tx.subscribe();
         let state = Arc::new(SyncRelayState {
             port: DEFAULT_SYNC_RELAY_PORT,
             epoch_gate: RwLock::new(()),
             broadcast_tx,
-            current_doc: RwLock::new(Some(Arc::new(vec![1, 2, 3]))),
+            current_doc: RwLock::new(Some(Bytes::from_static(&[1, 2, 3]))),
+            current_heads: StdRwLock::new(Arc::default()),
+            snapshot_tx: tokio::sync::watch::channel(None).0,
+            #[cfg(feature = "desktop")]
+            snapshot_retention_tx: tokio::sync::watch::channel(SnapshotRetention::default()).0,
+            #[cfg(feature = "desktop")]
+            backup_schedule_tx: tokio::sync::watch::channel(BackupSchedule::default()).0,
             disconnect_tx,
             generation: std::sync::atomic::AtomicU64::new(7),
             accepting_doc_updates: std::sync::atomic::AtomicBool::new(true),
             client_count: RwLock::new(1),
+            client_count_tx: tokio::sync::watch::channel(1).0,
             pairing_token: StdRwLock::new("old-token".to_string()),
+            tls: StdRwLock::new(None),
+            devices: StdMutex::new(RelayDeviceRegistry::default()),
+            payload_key: StdRwLock::new(None),
+            #[cfg(feature = "desktop")]
+            peers: StdMutex::new(SyncPeerRegistry::default()),
+            network_tx: tokio::sync::watch::channel(RelayNetworkSettings::default()).0,
+            abuse_policy: DEFAULT_RELAY_ABUSE_POLICY,
+            connection_slots: Arc::new(tokio::sync::Semaphore::new(
+                DEFAULT_RELAY_ABUSE_POLICY.max_connections,
+            )),
+            auth_limiter: StdMutex::new(RelayAuthLimiter::default()),
+            clients: StdMutex::new(RelayClientRegistry::default()),
+            rendezvous_tx: tokio::sync::watch::channel(RelayRendezvousSettings::default()).0,
+            rendezvous_connected: std::sync::atomic::AtomicBool::new(false),
         });
 
         assert!(factory_reset_sync_relay_in(data_dir.path(), &state)
@@ -14782,16 +16157,13 @@ mod tests {
             state.generation.load(std::sync::atomic::Ordering::SeqCst),
             7
         );
+        assert!(state.tls.read().unwrap().is_none());
+        assert!(RelayTlsIdentity::load(data_dir.path()).is_none());
         assert!(state
             .accepting_doc_updates
             .load(std::sync::atomic::Ordering::SeqCst));
         assert_eq!(
-            state
-                .current_doc
-                .read()
-                .await
-                .as_deref()
-                .map(|bytes| bytes.as_slice()),
+            state.current_doc.read().await.as_deref(),
             Some(&[1, 2, 3][..])
         );
         assert_eq!(*state.client_count.read().await, 1);
@@ -14802,7 +16174,198 @@ mod tests {
         ));
     }
 
-    #[cfg(unix)]
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn revoking_a_device_disconnects_only_that_device() {
+        let state = snapshot_test_relay_state(None);
+        let mut disconnect_rx = state.disconnect_tx.subscribe();
+        let (lost, kept) = {
+            let mut devices = state.devices.lock().unwrap();
+            let lost_code = devices.issue_pairing_code(0);
+            let kept_code = devices.issue_pairing_code(0);
+            (
+                devices
+                    .redeem_pairing_code(&lost_code, "Lost phone", 1)
+                    .unwrap(),
+                devices
+                    .redeem_pairing_code(&kept_code, "Tablet", 1)
+                    .unwrap(),
+            )
+        };
+
+        let data_dir = tempfile::tempdir().unwrap();
+        revoke_sync_device_in(&state, data_dir.path(), &lost).unwrap();
+        assert_eq!(
+            disconnect_rx.recv().await.unwrap(),
+            RelayDisconnect::Device(lost.clone())
+        );
+        assert_eq!(
+            disconnect_rx.recv().await.unwrap(),
+            RelayDisconnect::SharedToken
+        );
+        assert!(revoke_sync_device_in(&state, data_dir.path(), &lost).is_err());
+        let devices = state.devices.lock().unwrap();
+        assert!(!devices.contains(&lost));
+        assert!(devices.contains(&kept));
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn revoked_device_cannot_return_with_the_shared_token() {
+        let state = snapshot_test_relay_state(None);
+        let data_dir = tempfile::tempdir().unwrap();
+        let old_token = state.pairing_token.read().unwrap().clone();
+        let lost = {
+            let mut devices = state.devices.lock().unwrap();
+            let code = devices.issue_pairing_code(0);
+            devices.redeem_pairing_code(&code, "Lost phone", 1).unwrap()
+        };
+
+        revoke_sync_device_in(&state, data_dir.path(), &lost).unwrap();
+
+        let new_token = state.pairing_token.read().unwrap().clone();
+        assert_ne!(new_token, old_token);
+        assert_eq!(load_or_create_token(data_dir.path()), new_token);
+        let mut devices = state.devices.lock().unwrap();
+        let query = format!("t={}", old_token);
+        assert_eq!(
+            authorize_relay_request(Some(&query), &new_token, &mut devices, 2),
+            None
+        );
+        let query = format!("t={}", new_token);
+        assert_eq!(
+            authorize_relay_request(Some(&query), &new_token, &mut devices, 2),
+            Some(RelayClientAuth::SharedToken)
+        );
+    }
+
+    #[tokio::test]
+    async fn headless_relay_state_reloads_credentials_and_held_document() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let first = SyncRelayState::new(DEFAULT_SYNC_RELAY_PORT);
+        load_relay_credentials(&first, data_dir.path());
+        let token = first.pairing_token.read().unwrap().clone();
+        let fingerprint = first
+            .tls
+            .read()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .fingerprint
+            .clone();
+        assert!(first.payload_key.read().unwrap().is_none());
+
+        let (doc_tx, doc_rx) = tokio::sync::watch::channel::<Option<Bytes>>(None);
+        let doc_dir = data_dir.path().to_path_buf();
+        let writer = tokio::spawn(run_debounced_doc_writer(
+            doc_rx,
+            SnapshotWriterPolicy {
+                debounce: Duration::from_millis(20),
+                max_delay: Duration::from_secs(5),
+            },
+            move |doc| write_relay_doc(&doc_dir, doc, &[[7; RELAY_HEAD_BYTES]]).unwrap(),
+        ));
+        doc_tx.send_replace(Some(Bytes::from(vec![1, 2, 3])));
+        doc_tx.send_replace(Some(Bytes::from(vec![4, 5, 6])));
+        drop(doc_tx);
+        writer.await.unwrap();
+
+        let second = SyncRelayState::new(DEFAULT_SYNC_RELAY_PORT);
+        load_relay_credentials(&second, data_dir.path());
+        assert_eq!(*second.pairing_token.read().unwrap(), token);
+        assert_eq!(
+            second.tls.read().unwrap().as_ref().unwrap().fingerprint,
+            fingerprint
+        );
+        restore_held_relay_doc(&second, data_dir.path()).await;
+        assert_eq!(
+            second.current_doc.read().await.as_deref(),
+            Some(&[4, 5, 6][..])
+        );
+        assert_eq!(
+            second.current_heads.read().unwrap().as_slice(),
+            &[[7; RELAY_HEAD_BYTES]]
+        );
+        assert!(!data_dir
+            .path()
+            .join(format!("{RELAY_DOC_FILE}.tmp"))
+            .exists());
+    }
+
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn relay_holds_only_payloads_sealed_under_the_current_key() {
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let snapshot = automerge_like_bytes(4);
+        std::fs::write(
+            snapshot_dir.path().join("freed-1700000000.automerge"),
+            &snapshot,
+        )
+        .unwrap();
+        let state = snapshot_test_relay_state(None);
+        let key = RelayPayloadKey::generate();
+        let pairing_secret = key.secret_param();
+        *state.payload_key.write().unwrap() = Some(Arc::new(key));
+        assert!(sync_pairing_url(&state, &["192.168.1.20"], "code")
+            .ends_with(&format!("?pc=code#k={pairing_secret}")));
+
+        restore_snapshot_in(snapshot_dir.path(), &state, "freed-1700000000.automerge")
+            .await
+            .unwrap();
+        let held = state.current_doc.read().await.clone().unwrap();
+        assert!(held.starts_with(&SEALED_PAYLOAD_MAGIC));
+        assert_eq!(open_relay_payload(&state, &held).unwrap(), snapshot);
+
+        assert!(relay_payload_is_acceptable(&state, &held));
+        assert!(!relay_payload_is_acceptable(&state, &snapshot));
+        let stale = RelayPayloadKey::generate().seal(&snapshot).unwrap();
+        assert!(!relay_payload_is_acceptable(&state, &stale));
+
+        let mut snapshot_rx = state.snapshot_tx.subscribe();
+        let rotated = RelayPayloadKey::generate();
+        let rotated_id = rotated.key_id;
+        rotate_relay_payload_key(&state, rotated).await;
+        let resealed = state.current_doc.read().await.clone().unwrap();
+        assert_eq!(sealed_payload_key_id(&resealed).unwrap(), rotated_id);
+        assert_eq!(open_relay_payload(&state, &resealed).unwrap(), snapshot);
+        assert!(!relay_payload_is_acceptable(&state, &held));
+        assert_eq!(
+            snapshot_rx.borrow_and_update().as_deref(),
+            Some(resealed.as_ref())
+        );
+    }
+
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn compact_document_swaps_current_doc_and_rebroadcasts() {
+        let state = snapshot_test_relay_state(None);
+        assert!(compact_document_in(&state, None).await.is_err());
+
+        let bytes = appended_history_doc(100, 5);
+        *state.current_doc.write().await = Some(Bytes::from(bytes.clone()));
+        let mut broadcast_rx = state.broadcast_tx.subscribe();
+
+        let compaction = compact_document_in(&state, None).await.unwrap();
+        let held = state.current_doc.read().await.clone().unwrap();
+        assert_eq!(held.len() as u64, compaction.bytes_after);
+        let update = broadcast_rx.recv().await.unwrap();
+        assert_eq!(update.payload(), &held);
+        let expected: Vec<RelayHead> = automerge::Automerge::load(&bytes)
+            .unwrap()
+            .get_heads()
+            .into_iter()
+            .map(|hash| hash.0)
+            .collect();
+        assert_eq!(**update.heads(), expected);
+        assert_eq!(state.snapshot_tx.borrow().as_ref(), Some(&held));
+
+        state
+            .accepting_doc_updates
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        assert!(compact_document_in(&state, None).await.is_err());
+    }
+
+    #[cfg(all(unix, feature = "desktop"))]
     #[test]
     fn runtime_health_rotation_migrates_legacy_file_and_prunes_old_days() {
         let temp = tempfile::tempdir().unwrap();
@@ -14838,7 +16401,7 @@ mod tests {
         assert_eq!(remaining.last().map(String::as_str), Some("20260702"));
     }
 
-    #[cfg(unix)]
+    #[cfg(all(unix, feature = "desktop"))]
     #[test]
     fn runtime_health_rotation_repoints_symlink_on_next_day() {
         let temp = tempfile::tempdir().unwrap();
@@ -14866,6 +16429,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn runtime_health_concurrent_appends_preserve_every_jsonl_record() {
         const WRITER_COUNT: usize = 16;
@@ -14929,6 +16493,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn runtime_health_writer_lock_serializes_bounded_rewrite_and_append() {
         const MAX_BYTES: u64 = 1_024;
@@ -14992,6 +16557,7 @@ mod tests {
         assert!(records.iter().any(|entry| entry["writer"] == "second"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn runtime_health_file_lock_excludes_an_independent_handle() {
         let temp = tempfile::tempdir().unwrap();
@@ -15006,6 +16572,7 @@ mod tests {
         second.unlock().unwrap();
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn runtime_health_same_date_initializes_each_data_directory() {
         let first = tempfile::tempdir().unwrap();
@@ -15032,7 +16599,7 @@ mod tests {
         }
     }
 
-    #[cfg(unix)]
+    #[cfg(all(unix, feature = "desktop"))]
     #[test]
     fn runtime_health_roll_failure_leaves_target_uncommitted_and_retries() {
         let temp = tempfile::tempdir().unwrap();
@@ -15060,6 +16627,7 @@ mod tests {
         assert!(!raw.contains("blocked"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn runtime_health_recent_days_concatenates_newest_files_oldest_first() {
         let temp = tempfile::tempdir().unwrap();
@@ -15077,6 +16645,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn runtime_health_recent_days_falls_back_to_legacy_plain_file() {
         let temp = tempfile::tempdir().unwrap();
@@ -15088,6 +16657,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn runtime_health_dated_file_date_accepts_only_dated_names() {
         assert_eq!(
@@ -15105,6 +16675,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn tail_lines_within_bytes_cuts_at_line_boundaries() {
         let raw = "first\nsecond\nthird\n";
@@ -15113,6 +16684,7 @@ mod tests {
         assert_eq!(tail_lines_within_bytes(raw, 8), "third\n");
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn civil_from_days_matches_known_dates() {
         assert_eq!(civil_from_days(0), (1970, 1, 1));
@@ -15120,7 +16692,7 @@ mod tests {
         assert_eq!(civil_from_days(20_636), (2026, 7, 2));
     }
 
-    #[cfg(unix)]
+    #[cfg(all(unix, feature = "desktop"))]
     #[test]
     fn dev_sync_trigger_idle_check_reads_dated_runtime_health_files() {
         let temp = tempfile::tempdir().unwrap();
@@ -15136,6 +16708,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn window_destroyed_reason_serializes_snake_case() {
         let cases = [
@@ -15160,6 +16733,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn window_created_registry_preserves_age_and_clears_on_destroy() {
         let label = "window-created-registry-test";
@@ -15180,6 +16754,7 @@ mod tests {
         assert_eq!(take_window_age_seconds(label), None);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn relay_broadcast_aggregate_flushes_on_window_rollover() {
         let mut slot: Option<RelayBroadcastAggregate> = None;
@@ -15210,66 +16785,91 @@ mod tests {
         assert_eq!(successor.total_bytes, 50);
     }
 
-    #[test]
-    fn sync_relay_bind_retry_delay_only_retries_addr_in_use() {
-        let policy = SyncRelayBindRetryPolicy {
-            max_retries: 3,
-            retry_delay: Duration::from_millis(25),
-        };
-
-        let addr_in_use = std::io::Error::new(std::io::ErrorKind::AddrInUse, "busy");
-        assert_eq!(
-            sync_relay_bind_retry_delay(policy, &addr_in_use, 0),
-            Some(Duration::from_millis(25))
-        );
-        assert_eq!(sync_relay_bind_retry_delay(policy, &addr_in_use, 3), None);
-
-        let permission_denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "nope");
-        assert_eq!(
-            sync_relay_bind_retry_delay(policy, &permission_denied, 0),
-            None
-        );
-    }
-
-    #[tokio::test(flavor = "current_thread")]
-    async fn sync_relay_bind_listener_retries_until_the_port_is_released() {
-        let policy = SyncRelayBindRetryPolicy {
-            max_retries: 10,
-            retry_delay: Duration::from_millis(10),
-        };
-        let occupied = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = occupied.local_addr().unwrap();
-        let addr_string = addr.to_string();
-
-        let release_task = tauri::async_runtime::spawn(async move {
-            tokio::time::sleep(Duration::from_millis(35)).await;
-            drop(occupied);
-        });
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn relay_lists_live_clients_and_disconnects_one_on_request() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 
-        let (listener, retries_used) = bind_sync_relay_listener_with_policy(&addr_string, policy)
+        let (state, _recorded, addr) = loopback_test_relay(DEFAULT_RELAY_ABUSE_POLICY).await;
+        let mut request = format!("ws://{}/?t=token", addr)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("User-Agent", "Mozilla/5.0 (iPhone)".parse().unwrap());
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut phone, _) = tokio_tungstenite::client_async(request, stream)
             .await
             .unwrap();
-        assert!(retries_used > 0);
-        drop(listener);
-        release_task.await.unwrap();
-    }
+        let mut laptop = loopback_test_upgrade(addr, "token").await.unwrap();
 
-    #[tokio::test(flavor = "current_thread")]
-    async fn sync_relay_bind_listener_stops_after_retry_budget() {
-        let policy = SyncRelayBindRetryPolicy {
-            max_retries: 2,
-            retry_delay: Duration::from_millis(5),
-        };
-        let occupied = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr_string = occupied.local_addr().unwrap().to_string();
-
-        let error = bind_sync_relay_listener_with_policy(&addr_string, policy)
+        let doc = automerge_doc_bytes("title", "pushed");
+        phone
+            .send(Message::Binary(doc.clone().into()))
             .await
-            .unwrap_err();
-        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
-        drop(occupied);
+            .unwrap();
+        // The laptop pulls the phone's push.
+        timeout(Duration::from_secs(5), async {
+            while !matches!(laptop.next().await, Some(Ok(Message::Binary(_)))) {}
+        })
+        .await
+        .unwrap();
+        let clients = timeout(Duration::from_secs(5), async {
+            loop {
+                let clients = state.clients.lock().unwrap().summaries();
+                if clients.len() == 2 && clients[0].docs_pushed == 1 && clients[1].docs_pulled == 1
+                {
+                    return clients;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("both connections are listed with their traffic");
+        let phone_summary = &clients[0];
+        assert_eq!(
+            phone_summary.user_agent.as_deref(),
+            Some("Mozilla/5.0 (iPhone)")
+        );
+        assert_eq!(phone_summary.bytes_received, doc.len() as u64);
+        assert!(phone_summary.last_message_at_ms.is_some());
+        assert!(phone_summary.address.starts_with("127.0.0.1:"));
+        assert_eq!(phone_summary.device_id, None);
+        assert_eq!(clients[1].user_agent, None);
+        assert!(clients[1].bytes_sent > 0);
+
+        assert!(disconnect_sync_client_in(&state, phone_summary.id));
+        let close = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(Ok(Message::Close(frame))) = phone.next().await {
+                    return frame;
+                }
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(u16::from(close.code), RELAY_CLOSE_DISCONNECTED);
+        timeout(Duration::from_secs(5), async {
+            while state.clients.lock().unwrap().contains(phone_summary.id) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the closed connection leaves the list");
+        let remaining: Vec<u64> = state
+            .clients
+            .lock()
+            .unwrap()
+            .summaries()
+            .iter()
+            .map(|client| client.id)
+            .collect();
+        assert_eq!(remaining, [clients[1].id]);
+        assert!(!disconnect_sync_client_in(&state, phone_summary.id));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn main_window_presentation_focus_contract_is_explicit() {
         assert!(MainWindowPresentation::Foreground.should_focus());
@@ -15286,6 +16886,7 @@ mod tests {
         assert!(!MainWindowPresentation::Quiet.should_recover_startup_occlusion());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn linux_background_scraper_cursor_ignore_waits_for_cloaked_show() {
         assert_eq!(
@@ -15302,6 +16903,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn non_linux_background_scraper_cursor_ignore_stays_before_visibility_change() {
         assert_eq!(
@@ -15318,6 +16920,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     fn make_runtime_memory_stats_for_test(
         app_resident_bytes: u64,
         app_memory_pressure_bytes: u64,
@@ -15356,6 +16959,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn memory_pressure_limits_are_adaptive() {
         assert_eq!(
@@ -15381,6 +16985,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_feed_scroll_script_uses_measured_container_scrolls() {
         let script = social_feed_scroll_script(420);
@@ -15402,6 +17007,7 @@ mod tests {
         assert!(!script.contains("dispatchEvent(new Event('scroll'"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_feed_scroll_script_supports_backscroll() {
         let script = social_feed_scroll_script(-180);
@@ -15411,6 +17017,7 @@ mod tests {
         assert!(script.contains("clamp(after + requestedDelta"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn instagram_feed_ready_does_not_require_readable_session_cookie() {
         let state = IgFeedStatePayload {
@@ -15450,6 +17057,7 @@ mod tests {
         .placeholders_only());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn facebook_page_state_requires_real_feed_evidence() {
         let tall_logged_out_shell = FbPageStatePayload {
@@ -15507,6 +17115,7 @@ mod tests {
         assert_eq!(parse_screen_locked_from_ioreg_plist("<dict></dict>"), None);
     }
 
+    #[cfg(feature = "desktop")]
     fn binary_cookie_record(name: &str) -> Vec<u8> {
         let domain = b".facebook.com\0";
         let mut name_bytes = name.as_bytes().to_vec();
@@ -15532,6 +17141,7 @@ mod tests {
         record
     }
 
+    #[cfg(feature = "desktop")]
     fn binary_cookie_store(names: &[&str]) -> Vec<u8> {
         let mut page = vec![0u8; 8 + names.len() * 4];
         page[4..8].copy_from_slice(&(names.len() as u32).to_le_bytes());
@@ -15550,6 +17160,7 @@ mod tests {
         data
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_cookie_parser_reads_names_without_values() {
         let parsed = parse_webkit_binary_cookie_names(&binary_cookie_store(&["datr", "c_user"]))
@@ -15558,6 +17169,7 @@ mod tests {
         assert_eq!(parsed, vec!["c_user".to_string(), "datr".to_string()]);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn data_store_identifier_folder_matches_webkit_directory_names() {
         assert_eq!(
@@ -15582,6 +17194,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_request_parses_and_recognizes_supported_providers() {
         let temp = tempfile::tempdir().unwrap();
@@ -15604,6 +17217,7 @@ mod tests {
         assert!(!is_supported_dev_sync_provider("medium"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_request_expiration_blocks_stale_or_malformed_requests() {
         let fresh = DevSyncTriggerRequest {
@@ -15643,6 +17257,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_defers_locked_sessions_before_renderer_dispatch() {
         let locked = DesktopSessionState {
@@ -15672,6 +17287,7 @@ mod tests {
         assert_eq!(dev_sync_trigger_lock_deferral_detail(&unavailable), None);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_retries_same_request_only_after_renderer_waiting_state() {
         assert!(dev_sync_trigger_request_should_dispatch(
@@ -15712,6 +17328,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_keepalive_checks_active_renderer_request() {
         let script = dev_sync_trigger_keepalive_script("instagram-123");
@@ -15721,6 +17338,7 @@ mod tests {
         assert!(script.contains("Renderer was rebuilt before the sync trigger finished"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_result_uses_terminal_helper_shape() {
         let temp = tempfile::tempdir().unwrap();
@@ -15743,6 +17361,7 @@ mod tests {
         assert!(!is_dev_sync_trigger_terminal_status("started"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_stale_results_do_not_replace_current_result() {
         let temp = tempfile::tempdir().unwrap();
@@ -15791,6 +17410,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_recovers_stale_started_result_only_when_work_is_idle() {
         let temp = tempfile::tempdir().unwrap();
@@ -15824,6 +17444,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_stale_started_recovery_requires_fresh_runtime_health() {
         let temp = tempfile::tempdir().unwrap();
@@ -15845,6 +17466,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_stale_started_recovery_skips_malformed_health_lines() {
         let temp = tempfile::tempdir().unwrap();
@@ -15866,6 +17488,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_waiting_recovery_waits_for_idle_background_work() {
         let temp = tempfile::tempdir().unwrap();
@@ -15899,6 +17522,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn dev_sync_trigger_waiting_recovery_does_not_replay_finished_provider_work() {
         let temp = tempfile::tempdir().unwrap();
@@ -15920,6 +17544,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn renderer_memory_sample_due_respects_throttle() {
         let now = std::time::Instant::now();
@@ -15935,6 +17560,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn renderer_memory_health_fields_include_app_scoped_webkit_stats() {
         let now = std::time::Instant::now();
@@ -15996,6 +17622,7 @@ mod tests {
         assert_eq!(fields["memoryCriticalBytes"], serde_json::json!(602));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn background_runtime_requires_stable_renderer_before_jobs() {
         let runtime = BackgroundRuntimeCoordinator::new();
@@ -16019,6 +17646,7 @@ mod tests {
         assert!(runtime.finish_job("fb_scrape_feed").is_some());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn active_social_scraper_jobs_defer_recovery_recycling() {
         assert!(active_job_uses_social_scraper(Some("fb_scrape_feed")));
@@ -16029,6 +17657,7 @@ mod tests {
         assert!(!active_job_uses_social_scraper(None));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn background_runtime_resumes_after_healthy_recovery_heartbeats() {
         let runtime = BackgroundRuntimeCoordinator::new();
@@ -16055,6 +17684,7 @@ mod tests {
         assert!(runtime.begin_job("ig_scrape_feed").is_ok());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn background_runtime_blocks_after_memory_pressure() {
         let runtime = BackgroundRuntimeCoordinator::new();
@@ -16081,6 +17711,7 @@ mod tests {
         assert!(critical_err.contains("memory pressure critical"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn background_runtime_clears_memory_cooldown_after_recovery_sample() {
         let runtime = BackgroundRuntimeCoordinator::new();
@@ -16100,6 +17731,7 @@ mod tests {
         assert!(runtime.begin_job("ig_scrape_feed").is_ok());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn mark_startup_failed_forces_recovery_on_next_launch() {
         let temp = tempfile::tempdir().unwrap();
@@ -16112,6 +17744,7 @@ mod tests {
         assert!(state.last_failed_boot_at_ms.is_some());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn startup_recovery_retry_rearms_pending_boot_without_preserving_failure_count() {
         let temp = tempfile::tempdir().unwrap();
@@ -16122,6 +17755,7 @@ mod tests {
                 pending_boot_started_at_ms: None,
                 last_failed_boot_at_ms: Some(123),
                 last_successful_boot_at_ms: Some(100),
+                ..StartupRecoveryState::default()
             },
         );
 
@@ -16133,6 +17767,7 @@ mod tests {
         assert_eq!(state.last_failed_boot_at_ms, Some(123));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn startup_diagnostics_bundle_includes_recovery_and_runtime_tail() {
         let data_dir = tempfile::tempdir().unwrap();
@@ -16144,6 +17779,13 @@ mod tests {
                 pending_boot_started_at_ms: None,
                 last_failed_boot_at_ms: Some(123),
                 last_successful_boot_at_ms: Some(100),
+                recovery_tier: StartupRecoveryTier::ScrapersDisabled,
+                recovery_actions: vec![StartupRecoveryAction {
+                    tier: StartupRecoveryTier::ScrapersDisabled,
+                    at_ms: 124,
+                    detail: "scrapers disabled for this launch".to_string(),
+                }],
+                document_check: None,
             },
         );
         std::fs::write(runtime_health_path(data_dir.path()), "old\nnew\n").unwrap();
@@ -16171,14 +17813,100 @@ mod tests {
         assert_eq!(json["version"], "26.6.901");
         assert_eq!(json["platform"], "macos");
         assert_eq!(json["startupRecovery"]["consecutive_failed_boots"], 1);
+        assert_eq!(
+            json["startupRecovery"]["recovery_actions"][0]["tier"],
+            "scrapers_disabled"
+        );
         assert!(json["runtimeHealth"].as_str().unwrap().contains("new"));
         assert!(json["runtimeDiagnostics"]
             .as_str()
             .unwrap()
             .contains("deep"));
         assert!(json["syncHealth"].as_str().unwrap().contains("healthy"));
+        assert_eq!(json["recentPanics"], serde_json::json!([]));
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn rust_panic_record_bounds_the_message_and_hashes_the_backtrace() {
+        let long = "x".repeat(RUST_PANIC_MESSAGE_MAX_CHARS + 10);
+        let record = rust_panic_record(
+            Some("tokio-runtime-worker"),
+            Some("src/lib.rs:1:2"),
+            &long,
+            "frames",
+        );
+        assert_eq!(record["event"], "rust_panic");
+        assert_eq!(record["thread"], "tokio-runtime-worker");
+        assert_eq!(record["location"], "src/lib.rs:1:2");
+        assert_eq!(
+            record["message"].as_str().unwrap().len(),
+            RUST_PANIC_MESSAGE_MAX_CHARS
+        );
+        assert_eq!(record["messageTruncated"], true);
+        assert_eq!(record["backtraceHash"].as_str().unwrap().len(), 16);
+
+        let again = rust_panic_record(None, None, "boom", "frames");
+        assert_eq!(again["thread"], "<unnamed>");
+        assert_eq!(again["location"], serde_json::Value::Null);
+        assert_eq!(again["messageTruncated"], false);
+        assert_eq!(again["backtraceHash"], record["backtraceHash"]);
+        assert_ne!(
+            rust_panic_record(None, None, "boom", "other frames")["backtraceHash"],
+            record["backtraceHash"]
+        );
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn recorded_panics_fail_a_pending_boot_and_reach_diagnostics() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let downloads_dir = tempfile::tempdir().unwrap();
+
+        record_rust_panic(
+            data_dir.path(),
+            rust_panic_record(Some("main"), None, "after startup", "a"),
+        );
+        assert_eq!(
+            load_startup_recovery_state(data_dir.path()).consecutive_failed_boots,
+            0
+        );
+
+        mark_startup_pending(data_dir.path());
+        for index in 0..RUST_PANIC_DIAGNOSTICS_LIMIT + 2 {
+            record_rust_panic(
+                data_dir.path(),
+                rust_panic_record(Some("main"), None, &format!("panic {index}"), "a"),
+            );
+        }
+        let recovery = load_startup_recovery_state(data_dir.path());
+        assert_eq!(recovery.consecutive_failed_boots, 1);
+        assert!(recovery.pending_boot_started_at_ms.is_none());
+        assert!(startup_requires_recovery(&recovery));
+
+        let panics = recent_rust_panics(data_dir.path(), RUST_PANIC_DIAGNOSTICS_LIMIT);
+        assert_eq!(panics.len(), RUST_PANIC_DIAGNOSTICS_LIMIT);
+        assert_eq!(
+            panics.last().unwrap()["message"],
+            format!("panic {}", RUST_PANIC_DIAGNOSTICS_LIMIT + 1)
+        );
+
+        let output_path = write_startup_diagnostics_bundle(
+            data_dir.path(),
+            downloads_dir.path(),
+            "26.6.901",
+            "macos",
+        )
+        .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(output_path).unwrap()).unwrap();
+        assert_eq!(
+            json["recentPanics"].as_array().unwrap().len(),
+            RUST_PANIC_DIAGNOSTICS_LIMIT
+        );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn bounded_jsonl_retains_tail_when_file_exceeds_budget() {
         let temp = tempfile::tempdir().unwrap();
@@ -16201,6 +17929,7 @@ mod tests {
         assert!(retained.ends_with("fresh\n"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn app_storage_path_filter_excludes_safari_cache() {
         let roots = vec![
@@ -16226,6 +17955,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_scraper_data_stores_are_provider_specific() {
         assert_eq!(
@@ -16272,6 +18002,7 @@ mod tests {
         assert_eq!(unique.len(), 5);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn essay_provider_login_detection_rejects_auth_and_foreign_pages() {
         let substack_home = url::Url::parse("https://substack.com/home").unwrap();
@@ -16296,6 +18027,7 @@ mod tests {
         assert!(provider_page_requires_login(&foreign, "substack.com"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn essay_login_auth_requires_the_configured_canonical_surface() {
         let substack_home = url::Url::parse("https://substack.com/home?redirected=1").unwrap();
@@ -16336,6 +18068,7 @@ mod tests {
         assert!(!script.contains("loggedIn: false"));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn essay_provider_configurations_keep_sessions_and_events_isolated() {
         for provider in [SUBSTACK_ESSAY_PROVIDER, MEDIUM_ESSAY_PROVIDER] {
@@ -16364,6 +18097,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn authenticated_essay_browser_identity_is_validated_and_stored() {
         let store = std::sync::Mutex::new(String::new());
@@ -16378,6 +18112,7 @@ mod tests {
         assert_eq!(stored_or_default_user_agent(&store), user_agent);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn substack_profile_surfaces_accept_only_public_profile_roots() {
         let root = canonical_substack_profile_root(
@@ -16400,6 +18135,7 @@ mod tests {
         assert!(substack_profile_tab_url(&root, "subscribers").is_err());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn essay_roster_capture_has_hard_traversal_limits() {
         assert_eq!(ESSAY_ROSTER_MAX_EXTRACTION_PASSES, 20);
@@ -16416,6 +18152,7 @@ mod tests {
             .all(|page| page.scope == "graph" && page.relation.is_some()));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn essay_capture_rejects_redirects_to_the_wrong_provider_surface() {
         let medium_followers = url::Url::parse("https://medium.com/me/followers").unwrap();
@@ -16466,6 +18203,7 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn essay_capture_plan_excludes_subscriber_management_surfaces() {
         for page in SUBSTACK_GRAPH_PAGES
@@ -16503,6 +18241,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn webkit_process_filter_excludes_prior_launches() {
         let app_age = 60;
@@ -16525,6 +18264,7 @@ mod tests {
         assert!(!webkit_process_started_with_app(0, app_age));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn webkit_process_role_counts_rooted_current_launches() {
         let app_age = 60;
@@ -16547,6 +18287,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn webkit_process_role_keeps_only_app_start_rootless_processes() {
         let app_age = 60;
@@ -16574,6 +18315,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     fn webkit_process_stats(process_id: u32, resident_bytes: u64) -> WebkitProcessRuntimeStats {
         WebkitProcessRuntimeStats {
             process_id,
@@ -16586,6 +18328,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scraper_recycle_verification_records_exited_retained_and_new_webkit_pids() {
         let before = vec![
@@ -16607,6 +18350,7 @@ mod tests {
         assert_eq!(verification.elapsed_ms, 742);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn webkit_network_cache_collection_includes_record_resources() {
         let temp = tempfile::tempdir().unwrap();
@@ -16624,6 +18368,7 @@ mod tests {
         assert_eq!(files[0].0, record_file);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn webkit_network_cache_trimmer_removes_large_records_cache() {
         let temp = tempfile::tempdir().unwrap();
@@ -16647,6 +18392,7 @@ mod tests {
         assert!(!record_file.exists());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn local_ai_download_urls_are_restricted_to_hugging_face() {
         assert!(validate_local_ai_download_url(
@@ -16657,6 +18403,7 @@ mod tests {
         assert!(validate_local_ai_download_url("https://example.com/model").is_err());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn local_ai_model_paths_must_stay_under_model_root() {
         let temp = tempfile::tempdir().unwrap();
@@ -16669,6 +18416,7 @@ mod tests {
         assert!(validate_local_ai_model_path(&root, &denied, "targetPath").is_err());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scrape_memory_blocks_when_after_cleanup_is_still_high() {
         let stats = RuntimeMemoryStats {
@@ -16716,6 +18464,7 @@ mod tests {
         }));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scrape_memory_allows_reclaimable_webkit_rss_tail_below_critical_pressure() {
         let budget =
@@ -16775,6 +18524,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scrape_memory_blocks_large_reclaimable_webkit_rss_before_social_scrape() {
         let stats = RuntimeMemoryStats {
@@ -16803,6 +18553,7 @@ mod tests {
         assert!(blocked_social_scrape_should_recover_main_renderer(&stats));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scrape_memory_blocks_high_resident_bytes_without_webkit_footprint_telemetry() {
         let budget =
@@ -16844,6 +18595,7 @@ mod tests {
         assert_eq!(scrape_memory_pressure_level(&stats), "high");
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scrape_memory_blocks_high_resident_bytes_even_when_footprint_is_low() {
         let stats = RuntimeMemoryStats {
@@ -16891,6 +18643,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scrape_memory_reserves_startup_headroom_below_high_pressure() {
         let stats = RuntimeMemoryStats {
@@ -16938,6 +18691,7 @@ mod tests {
         }));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn scrape_memory_recycles_preserved_window_under_high_pressure() {
         assert_eq!(
@@ -16951,6 +18705,7 @@ mod tests {
         assert_eq!(blocked_preflight_preserved_scraper_label(None, false), None);
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn optional_story_scrape_uses_stricter_memory_budget() {
         let make_stats = |app_resident_bytes, app_memory_pressure_bytes| RuntimeMemoryStats {
@@ -17002,6 +18757,7 @@ mod tests {
         )));
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_scrape_plan_keeps_full_passes_when_memory_has_room() {
         let stats = make_runtime_memory_stats_for_test(512 * 1024 * 1024, 512 * 1024 * 1024);
@@ -17018,6 +18774,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_scrape_plan_skips_stories_when_story_budget_is_tight() {
         let mut stats = make_runtime_memory_stats_for_test(0, 0);
@@ -17038,6 +18795,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_scrape_plan_reduces_passes_near_start_budget() {
         let mut stats = make_runtime_memory_stats_for_test(0, 0);
@@ -17059,6 +18817,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn social_scrape_plan_uses_minimal_passes_at_low_margin() {
         let mut stats = make_runtime_memory_stats_for_test(0, 0);
@@ -17080,6 +18839,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     fn runtime_stats_with_webkit(
         app_resident_bytes: u64,
         app_memory_pressure_bytes: u64,
@@ -17106,6 +18866,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn post_social_scrape_recovery_triggers_on_large_webkit_footprint_growth() {
         let before = runtime_stats_with_webkit(
@@ -17127,6 +18888,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn post_social_scrape_recovery_triggers_on_sustained_webkit_pressure() {
         let before = runtime_stats_with_webkit(
@@ -17148,6 +18910,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn post_social_scrape_recovery_ignores_reclaimable_resident_tail() {
         let before = runtime_stats_with_webkit(
@@ -17170,6 +18933,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn post_social_scrape_recovery_ignores_reclaimable_resident_tail_below_high_memory() {
         let before = runtime_stats_with_webkit(
@@ -17192,6 +18956,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn post_social_scrape_recovery_triggers_on_reclaimable_tail_with_large_footprint() {
         let before = runtime_stats_with_webkit(
@@ -17214,6 +18979,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn post_social_scrape_recovery_triggers_on_high_reclaimable_resident_tail() {
         let before = runtime_stats_with_webkit(
@@ -17237,6 +19003,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn post_social_scrape_recovery_waits_below_threshold() {
         let before = runtime_stats_with_webkit(
@@ -17258,6 +19025,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn reconcile_marks_unfinished_boot_as_failed() {
         let temp = tempfile::tempdir().unwrap();
@@ -17269,6 +19037,7 @@ mod tests {
                 pending_boot_started_at_ms: Some(123),
                 last_failed_boot_at_ms: None,
                 last_successful_boot_at_ms: None,
+                ..StartupRecoveryState::default()
             },
         );
 
@@ -17279,6 +19048,7 @@ mod tests {
         assert!(state.last_failed_boot_at_ms.is_some());
     }
 
+    #[cfg(feature = "desktop")]
     #[test]
     fn mark_startup_success_clears_recovery_state() {
         let temp = tempfile::tempdir().unwrap();
@@ -17290,6 +19060,7 @@ mod tests {
                 pending_boot_started_at_ms: Some(456),
                 last_failed_boot_at_ms: Some(789),
                 last_successful_boot_at_ms: None,
+                ..StartupRecoveryState::default()
             },
         );
 
@@ -17301,6 +19072,93 @@ mod tests {
         assert!(state.last_successful_boot_at_ms.is_some());
     }
 
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn startup_recovery_tiers_escalate_until_a_healthy_boot() {
+        let temp = tempfile::tempdir().unwrap();
+        mark_startup_pending(temp.path());
+        let state = reconcile_startup_recovery_state(temp.path());
+        assert_eq!(state.recovery_tier, StartupRecoveryTier::ScrapersDisabled);
+
+        prepare_startup_recovery_retry(temp.path());
+        let state = reconcile_startup_recovery_state(temp.path());
+        assert_eq!(state.consecutive_failed_boots, 1);
+        assert_eq!(
+            state.recovery_tier,
+            StartupRecoveryTier::WebkitCachesCleared
+        );
+
+        mark_startup_failed(temp.path());
+        mark_startup_failed(temp.path());
+        let state = load_startup_recovery_state(temp.path());
+        assert_eq!(state.recovery_tier, StartupRecoveryTier::DocumentRollback);
+
+        mark_startup_success(temp.path());
+        let state = load_startup_recovery_state(temp.path());
+        assert_eq!(state.recovery_tier, StartupRecoveryTier::None);
+        assert!(state.document_check.is_none());
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn document_rollback_tier_clears_caches_and_finds_the_newest_loadable_snapshot() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path().join("data");
+        let webkit_root = temp.path().join("WebKit");
+        let record = webkit_root.join("NetworkCache/Version 17/Records/hash/Resource/record");
+        std::fs::create_dir_all(record.parent().unwrap()).unwrap();
+        std::fs::write(&record, [0; 1024]).unwrap();
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join(RELAY_DOC_FILE), b"torn").unwrap();
+        let snapshots = snapshot_dir(&data_dir);
+        store_snapshot(&snapshots, 100, &automerge_doc_bytes("title", "good")).unwrap();
+        store_snapshot(&snapshots, 200, &automerge_like_bytes(2)).unwrap();
+
+        let mut state = StartupRecoveryState {
+            consecutive_failed_boots: 1,
+            recovery_tier: StartupRecoveryTier::DocumentRollback,
+            ..StartupRecoveryState::default()
+        };
+        let actions = apply_startup_recovery_tier(&data_dir, Some(&webkit_root), &mut state);
+        assert_eq!(
+            actions.iter().map(|action| action.tier).collect::<Vec<_>>(),
+            [
+                StartupRecoveryTier::ScrapersDisabled,
+                StartupRecoveryTier::WebkitCachesCleared,
+                StartupRecoveryTier::DocumentRollback,
+            ]
+        );
+        assert!(!record.exists());
+        let check = state.document_check.clone().unwrap();
+        assert!(check.held_document_error.is_some());
+        assert_eq!(check.last_good_snapshot.as_deref(), Some("freed-100.json"));
+        assert_eq!(check.last_good_snapshot_timestamp, Some(100));
+        let saved = load_startup_recovery_state(&data_dir);
+        assert_eq!(saved.recovery_actions, state.recovery_actions);
+        assert_eq!(saved.document_check, Some(check));
+
+        let key = load_or_create_payload_key(&data_dir).unwrap();
+        let sealed = key.seal(&automerge_doc_bytes("title", "held")).unwrap();
+        write_relay_doc(&data_dir, &sealed, &[]).unwrap();
+        assert_eq!(check_startup_document(&data_dir).held_document_error, None);
+
+        for _ in 0..6 {
+            apply_startup_recovery_tier(&data_dir, None, &mut state);
+        }
+        assert_eq!(state.recovery_actions.len(), STARTUP_RECOVERY_ACTION_LIMIT);
+        assert_eq!(
+            state.recovery_actions.last().unwrap().tier,
+            StartupRecoveryTier::DocumentRollback
+        );
+
+        let runtime = BackgroundRuntimeCoordinator::new();
+        runtime.disable_for_startup_recovery();
+        assert!(runtime
+            .begin_job("fb_scrape_feed")
+            .unwrap_err()
+            .contains("failed startup"));
+    }
+
     #[cfg(target_os = "macos")]
     #[test]
     fn macos_reopen_always_reasserts_primary_window() {
@@ -0,0 +1,1044 @@
+//! Desktop peers: other Freed desktops found over mDNS or paired by link, and
+//! the loops that replicate the held relay document with them.
+//!
+//! Every desktop relay advertises `_freed-sync._tcp.local` with its certificate
+//! fingerprint in the `fp` TXT record and browses for the others. Discovery
+//! grants nothing: the user trusts a peer by pasting the pairing link from that
+//! desktop's Sync tab, which carries a one-time pairing code, the certificate
+//! fingerprint to pin, and the peer's sync key. This desktop then connects to
+//! the peer's relay as an ordinary framed client. Documents from the peer are
+//! opened with the peer's key and merged into the held document; local updates
+//! are resealed under the peer's key and pushed back. Only the side that
+//! trusted the other dials out, so one trust step is enough to replicate both
+//! ways.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use crate::relay::{
+    format_relay_heads_hex, pairing_code_device_credential, pairing_code_device_id,
+    parse_relay_client_message, parse_relay_heads_hex, recv_relay_delivery, relay_heads_match,
+    relay_query_param, relay_websocket_config, report_unreadable_held_doc,
+    store_relay_client_update_if_current, write_private_file, RelayClientMessage,
+    RelayClientMetrics, RelayClientStore, RelayDelivery, RelayDisconnect, RelayEventSink,
+    RelayEvents, RelayHead, RelayPayloadKey, RelayServerControl, RelayState, RelayUpdate,
+    SyncRelayState, DEFAULT_SYNC_RELAY_PORT, RELAY_CAPABILITIES, RELAY_CLOSE_DEVICE_REVOKED,
+    RELAY_CLOSE_FACTORY_RESET, RELAY_CONTROL_VERSION, RELAY_PROTOCOL_VERSION,
+    SYNC_RELAY_DOC_SEND_TIMEOUT, SYNC_RELAY_TLS_HANDSHAKE_TIMEOUT,
+};
+use crate::sync_cli::pinned_relay_connector;
+use crate::{
+    now_unix_ms, SYNC_PEER_CONNECT_TIMEOUT, SYNC_PEER_RETRY_MAX_DELAY, SYNC_PEER_RETRY_MIN_DELAY,
+    SYNC_PEER_SERVICE_TYPE,
+};
+
+pub(crate) const SYNC_PEERS_FILE: &str = "sync-peers.json";
+
+/// A peer relay this desktop replicates with. Persisted with its secrets, so
+/// the file is written owner-only like the relay's own credentials.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TrustedPeer {
+    /// base64url SHA-256 of the peer's relay certificate; identifies the peer.
+    pub(crate) fingerprint: String,
+    name: String,
+    host: String,
+    port: u16,
+    /// Code from the pairing link, kept until the first connection redeems it.
+    pairing_code: Option<String>,
+    device_id: String,
+    credential: String,
+    /// The peer's `#k=` sync key; `None` when the peer relays plaintext.
+    sync_key: Option<String>,
+}
+
+/// Builds a trusted peer from another desktop's pairing link. The link must
+/// carry `fp`, since the fingerprint is the peer's identity; peers always
+/// connect over TLS pinned to it, even from a `ws://` link.
+pub(crate) fn parse_peer_pairing_link(link: &str) -> Result<TrustedPeer, String> {
+    let url = url::Url::parse(link.trim()).map_err(|e| format!("invalid pairing link: {}", e))?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        return Err(
+            "pairing link must be a ws:// or wss:// link with a certificate fingerprint"
+                .to_string(),
+        );
+    }
+    let host = url
+        .host_str()
+        .ok_or("pairing link has no host")?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let code = relay_query_param(url.query(), "pc")
+        .filter(|code| !code.is_empty())
+        .ok_or("pairing link has no pairing code")?;
+    let fingerprint = relay_query_param(url.query(), "fp")
+        .filter(|fp| {
+            URL_SAFE_NO_PAD
+                .decode(fp)
+                .is_ok_and(|digest| digest.len() == 32)
+        })
+        .ok_or("pairing link has no valid certificate fingerprint")?;
+    let sync_key = match url
+        .fragment()
+        .and_then(|fragment| fragment.strip_prefix("k="))
+    {
+        Some(param) => {
+            RelayPayloadKey::from_secret_param(param)
+                .ok_or("pairing link has an invalid sync key")?;
+            Some(param.to_string())
+        }
+        None => None,
+    };
+    Ok(TrustedPeer {
+        fingerprint: fingerprint.to_string(),
+        name: host.clone(),
+        host,
+        port: url.port().unwrap_or(DEFAULT_SYNC_RELAY_PORT),
+        pairing_code: Some(code.to_string()),
+        device_id: pairing_code_device_id(code),
+        credential: pairing_code_device_credential(code),
+        sync_key,
+    })
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+enum SyncPeerStatus {
+    /// Seen over mDNS but not trusted.
+    Discovered,
+    Connecting,
+    Connected,
+    /// Trusted but unreachable; retried with backoff.
+    #[default]
+    Offline,
+    /// The peer rejected our credentials or was reset; paste a new link.
+    NeedsTrust,
+}
+
+/// A relay resolved over mDNS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct DiscoveredPeer {
+    name: String,
+    host: String,
+    port: u16,
+    fingerprint: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct SyncPeerLink {
+    status: SyncPeerStatus,
+    last_error: Option<String>,
+    last_synced_at_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SyncPeerSummary {
+    pub(crate) fingerprint: Option<String>,
+    name: String,
+    host: String,
+    port: u16,
+    trusted: bool,
+    /// Currently answering mDNS queries on this network.
+    discovered: bool,
+    status: SyncPeerStatus,
+    last_error: Option<String>,
+    last_synced_at_ms: Option<u64>,
+}
+
+/// Trusted peers plus what mDNS currently sees. Only the trusted list is
+/// persisted; discovery and link status live in memory.
+#[derive(Default)]
+pub(crate) struct SyncPeerRegistry {
+    /// `None` keeps the registry in memory only.
+    path: Option<PathBuf>,
+    trusted: Vec<TrustedPeer>,
+    /// Resolved records by mDNS full name.
+    discovered: HashMap<String, DiscoveredPeer>,
+    /// Link status by fingerprint.
+    links: HashMap<String, SyncPeerLink>,
+    /// Replication tasks by fingerprint.
+    tasks: HashMap<String, tokio::task::AbortHandle>,
+}
+
+impl SyncPeerRegistry {
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let trusted = match std::fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).unwrap_or_else(|error| {
+                warn!("[Sync] Ignoring unreadable peer list: {}", error);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path: Some(path),
+            trusted,
+            ..Self::default()
+        }
+    }
+
+    fn persist(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec_pretty(&self.trusted).map_err(|e| e.to_string())?;
+        write_private_file(path, &json).map_err(|e| format!("failed to persist peer list: {}", e))
+    }
+
+    /// Trusts `peer`, replacing any earlier trust of the same relay. A name
+    /// already learned over mDNS beats the host from the link.
+    pub(crate) fn trust(&mut self, mut peer: TrustedPeer) -> Result<(), String> {
+        if let Some(found) = self.discovered_by_fingerprint(&peer.fingerprint) {
+            peer.name = found.name.clone();
+        }
+        self.trusted
+            .retain(|trusted| trusted.fingerprint != peer.fingerprint);
+        self.links.remove(&peer.fingerprint);
+        self.trusted.push(peer);
+        self.persist()
+    }
+
+    pub(crate) fn forget(&mut self, fingerprint: &str) -> Result<bool, String> {
+        if let Some(task) = self.tasks.remove(fingerprint) {
+            task.abort();
+        }
+        self.links.remove(fingerprint);
+        let before = self.trusted.len();
+        self.trusted
+            .retain(|trusted| trusted.fingerprint != fingerprint);
+        if self.trusted.len() == before {
+            return Ok(false);
+        }
+        self.persist()?;
+        Ok(true)
+    }
+
+    /// Stops replicating and forgets every trusted peer.
+    pub(crate) fn clear(&mut self) -> Result<(), String> {
+        for (_, task) in self.tasks.drain() {
+            task.abort();
+        }
+        self.trusted.clear();
+        self.links.clear();
+        self.persist()
+    }
+
+    /// The trusted peer, dialed at the address mDNS last resolved for it.
+    fn trusted(&self, fingerprint: &str) -> Option<TrustedPeer> {
+        let mut peer = self
+            .trusted
+            .iter()
+            .find(|trusted| trusted.fingerprint == fingerprint)?
+            .clone();
+        if let Some(found) = self.discovered_by_fingerprint(fingerprint) {
+            peer.host = found.host.clone();
+            peer.port = found.port;
+        }
+        Some(peer)
+    }
+
+    fn fingerprints(&self) -> Vec<String> {
+        self.trusted
+            .iter()
+            .map(|trusted| trusted.fingerprint.clone())
+            .collect()
+    }
+
+    /// The peer redeemed its pairing code; later connections use the
+    /// derived device credential.
+    fn note_redeemed(&mut self, fingerprint: &str) {
+        let Some(peer) = self
+            .trusted
+            .iter_mut()
+            .find(|trusted| trusted.fingerprint == fingerprint && trusted.pairing_code.is_some())
+        else {
+            return;
+        };
+        peer.pairing_code = None;
+        if let Err(error) = self.persist() {
+            warn!("[Sync] {}", error);
+        }
+    }
+
+    fn set_status(&mut self, fingerprint: &str, status: SyncPeerStatus, error: Option<String>) {
+        let link = self.links.entry(fingerprint.to_string()).or_default();
+        link.status = status;
+        link.last_error = error;
+    }
+
+    fn note_synced(&mut self, fingerprint: &str, now_ms: u64) {
+        self.links
+            .entry(fingerprint.to_string())
+            .or_default()
+            .last_synced_at_ms = Some(now_ms);
+    }
+
+    fn discover(&mut self, fullname: &str, peer: DiscoveredPeer) {
+        self.discovered.insert(fullname.to_string(), peer);
+    }
+
+    fn lose(&mut self, fullname: &str) {
+        self.discovered.remove(fullname);
+    }
+
+    fn discovered_by_fingerprint(&self, fingerprint: &str) -> Option<&DiscoveredPeer> {
+        self.discovered
+            .values()
+            .find(|found| found.fingerprint.as_deref() == Some(fingerprint))
+    }
+
+    /// Trusted peers first, then untrusted relays currently on the network.
+    pub(crate) fn summaries(&self) -> Vec<SyncPeerSummary> {
+        let mut summaries: Vec<SyncPeerSummary> = self
+            .trusted
+            .iter()
+            .map(|trusted| {
+                let found = self.discovered_by_fingerprint(&trusted.fingerprint);
+                let link = self
+                    .links
+                    .get(&trusted.fingerprint)
+                    .cloned()
+                    .unwrap_or_default();
+                SyncPeerSummary {
+                    fingerprint: Some(trusted.fingerprint.clone()),
+                    name: trusted.name.clone(),
+                    host: found.map_or(trusted.host.clone(), |found| found.host.clone()),
+                    port: found.map_or(trusted.port, |found| found.port),
+                    trusted: true,
+                    discovered: found.is_some(),
+                    status: link.status,
+                    last_error: link.last_error,
+                    last_synced_at_ms: link.last_synced_at_ms,
+                }
+            })
+            .collect();
+        let mut untrusted: Vec<SyncPeerSummary> = self
+            .discovered
+            .values()
+            .filter(|found| {
+                !found.fingerprint.as_ref().is_some_and(|fingerprint| {
+                    self.trusted
+                        .iter()
+                        .any(|trusted| &trusted.fingerprint == fingerprint)
+                })
+            })
+            .map(|found| SyncPeerSummary {
+                fingerprint: found.fingerprint.clone(),
+                name: found.name.clone(),
+                host: found.host.clone(),
+                port: found.port,
+                trusted: false,
+                discovered: true,
+                status: SyncPeerStatus::Discovered,
+                last_error: None,
+                last_synced_at_ms: None,
+            })
+            .collect();
+        untrusted.sort_by(|a, b| a.name.cmp(&b.name));
+        summaries.extend(untrusted);
+        summaries
+    }
+}
+
+/// Applies `change` to the peer registry and publishes the new peer list.
+pub(crate) fn update_sync_peers(
+    state: &SyncRelayState,
+    events: &dyn RelayEventSink,
+    change: impl FnOnce(&mut SyncPeerRegistry),
+) {
+    let summaries = {
+        let mut peers = state.peers.lock().unwrap();
+        change(&mut peers);
+        peers.summaries()
+    };
+    events.sync_peers_changed(&summaries);
+}
+
+/// Name this desktop registers with peers and advertises over mDNS.
+pub(crate) fn sync_peer_local_name() -> String {
+    match hostname::get().ok().and_then(|h| h.into_string().ok()) {
+        Some(hostname) => format!("Freed Desktop ({})", hostname),
+        None => "Freed Desktop".to_string(),
+    }
+}
+
+/// Upgrade URL for a peer: the pairing code on first contact, the derived
+/// device credential afterwards.
+fn sync_peer_upgrade_url(peer: &TrustedPeer, local_name: &str) -> String {
+    let host = if peer.host.contains(':') {
+        format!("[{}]", peer.host)
+    } else {
+        peer.host.clone()
+    };
+    let credentials = match &peer.pairing_code {
+        Some(code) => format!(
+            "pc={}&n={}",
+            code,
+            url::form_urlencoded::byte_serialize(local_name.as_bytes()).collect::<String>()
+        ),
+        None => format!("d={}&t={}", peer.device_id, peer.credential),
+    };
+    format!(
+        "wss://{}:{}/?v={}&{}",
+        host, peer.port, RELAY_PROTOCOL_VERSION, credentials
+    )
+}
+
+/// Re-seals an update's payload from one sync key to another. `None` on
+/// either side is plaintext.
+fn reseal_relay_update(
+    update: &RelayUpdate,
+    from: Option<&RelayPayloadKey>,
+    to: Option<&RelayPayloadKey>,
+) -> Result<RelayUpdate, String> {
+    let plaintext = match from {
+        Some(key) => key.open(update.payload())?,
+        None => update.payload().to_vec(),
+    };
+    let payload = match to {
+        Some(key) => key.seal(&plaintext)?,
+        None => plaintext,
+    };
+    Ok(match update {
+        RelayUpdate::FullDoc { heads, .. } => RelayUpdate::full_doc(&payload, heads.clone()),
+        RelayUpdate::Changes { heads, .. } => RelayUpdate::changes(&payload, heads.clone()),
+    })
+}
+
+/// Why one replication session with a peer ended.
+#[derive(Debug, PartialEq, Eq)]
+enum SyncPeerExit {
+    /// Network trouble; reconnect after a backoff.
+    Retry(String),
+    /// The peer no longer accepts this desktop, or was factory reset.
+    NeedsTrust(String),
+    /// This relay was factory reset or shut down.
+    Stop,
+}
+
+/// Sends one update to a peer, resealed under the peer's key.
+async fn send_sync_peer_update<S>(
+    state: &SyncRelayState,
+    peer_key: Option<&RelayPayloadKey>,
+    ws_sender: &mut S,
+    update: &RelayUpdate,
+) -> Result<(), String>
+where
+    S: futures_util::Sink<Message> + Unpin,
+    S::Error: std::fmt::Display,
+{
+    let local_key = state.payload_key.read().unwrap().clone();
+    let update = reseal_relay_update(update, local_key.as_deref(), peer_key)?;
+    match timeout(
+        SYNC_RELAY_DOC_SEND_TIMEOUT,
+        ws_sender.send(Message::Binary(update.frame().clone())),
+    )
+    .await
+    {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(error)) => Err(format!("send failed: {}", error)),
+        Err(_) => Err("send timed out".to_string()),
+    }
+}
+
+/// Held document as an update, for the opening push and lag catch-up.
+async fn held_relay_update(state: &SyncRelayState) -> Option<RelayUpdate> {
+    let current_doc = state.current_doc.read().await;
+    let heads = state.current_heads.read().unwrap().clone();
+    current_doc
+        .as_ref()
+        .map(|doc| RelayUpdate::full_doc(doc, heads))
+}
+
+/// Runs one replication session with a trusted peer until it ends.
+async fn replicate_with_sync_peer(
+    state: &RelayState,
+    events: &RelayEvents,
+    peer: &TrustedPeer,
+    local_name: &str,
+) -> SyncPeerExit {
+    use rustls::pki_types::ServerName;
+
+    let generation = state.generation.load(std::sync::atomic::Ordering::SeqCst);
+    let peer_key = match &peer.sync_key {
+        Some(param) => match RelayPayloadKey::from_secret_param(param) {
+            Some(key) => Some(key),
+            None => return SyncPeerExit::NeedsTrust("stored sync key is invalid".to_string()),
+        },
+        None => None,
+    };
+    let connector = match pinned_relay_connector(&peer.fingerprint) {
+        Ok(connector) => connector,
+        Err(error) => return SyncPeerExit::Retry(error),
+    };
+    let server_name = match ServerName::try_from(peer.host.clone()) {
+        Ok(name) => name,
+        Err(error) => return SyncPeerExit::NeedsTrust(format!("invalid peer host: {}", error)),
+    };
+    let stream = match timeout(
+        SYNC_PEER_CONNECT_TIMEOUT,
+        TcpStream::connect((peer.host.as_str(), peer.port)),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(error)) => return SyncPeerExit::Retry(format!("connect failed: {}", error)),
+        Err(_) => return SyncPeerExit::Retry("connect timed out".to_string()),
+    };
+    let stream = match timeout(
+        SYNC_RELAY_TLS_HANDSHAKE_TIMEOUT,
+        connector.connect(server_name, stream),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(error)) => return SyncPeerExit::Retry(format!("TLS handshake failed: {}", error)),
+        Err(_) => return SyncPeerExit::Retry("TLS handshake timed out".to_string()),
+    };
+    let upgrade = tokio_tungstenite::client_async_with_config(
+        sync_peer_upgrade_url(peer, local_name),
+        stream,
+        Some(relay_websocket_config(&state.abuse_policy)),
+    );
+    let ws_stream = match timeout(SYNC_PEER_CONNECT_TIMEOUT, upgrade).await {
+        Ok(Ok((ws_stream, _))) => ws_stream,
+        Ok(Err(WsError::Http(response))) if response.status() == 401 => {
+            return SyncPeerExit::NeedsTrust("peer rejected this desktop's credentials".to_string())
+        }
+        Ok(Err(error)) => return SyncPeerExit::Retry(format!("upgrade failed: {}", error)),
+        Err(_) => return SyncPeerExit::Retry("upgrade timed out".to_string()),
+    };
+
+    let fingerprint = peer.fingerprint.as_str();
+    update_sync_peers(state, &**events, |peers| {
+        peers.note_redeemed(fingerprint);
+        peers.set_status(fingerprint, SyncPeerStatus::Connected, None);
+    });
+    info!("[Sync] Replicating with peer {} ({})", peer.name, peer.host);
+
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let mut broadcast_rx = state.broadcast_tx.subscribe();
+    let mut disconnect_rx = state.disconnect_tx.subscribe();
+    let mut metrics = RelayClientMetrics::default();
+    // Heads the peer last told us it holds, so its own updates are not echoed.
+    let mut peer_heads: Vec<RelayHead> = Vec::new();
+
+    let local_heads = state.current_heads.read().unwrap().clone();
+    let client_id = match state.tls.read().unwrap().as_ref() {
+        Some(tls) => format!("peer-{}", tls.fingerprint),
+        None => "peer".to_string(),
+    };
+    let hello = serde_json::json!({
+        "type": "hello",
+        "version": RELAY_CONTROL_VERSION,
+        "clientId": client_id,
+        "heads": format_relay_heads_hex(&local_heads),
+        "capabilities": RELAY_CAPABILITIES,
+    });
+    if let Err(error) = ws_sender
+        .send(Message::Text(hello.to_string().into()))
+        .await
+    {
+        return SyncPeerExit::Retry(format!("hello failed: {}", error));
+    }
+    if let Some(held) = held_relay_update(state).await {
+        if let Err(error) =
+            send_sync_peer_update(state, peer_key.as_ref(), &mut ws_sender, &held).await
+        {
+            return SyncPeerExit::Retry(error);
+        }
+    }
+
+    loop {
+        tokio::select! {
+            msg = ws_receiver.next() => {
+                match msg {
+                    Some(Ok(Message::Binary(data))) => {
+                        let update = match parse_relay_client_message(true, &data) {
+                            RelayClientMessage::Update(update) => update,
+                            RelayClientMessage::FullDocRequest => continue,
+                            RelayClientMessage::Malformed(error) => {
+                                warn!("[Sync] Ignored malformed frame from peer {}: {}", peer.name, error);
+                                continue;
+                            }
+                        };
+                        peer_heads = update.heads().to_vec();
+                        let local_key = state.payload_key.read().unwrap().clone();
+                        let update = match reseal_relay_update(&update, peer_key.as_ref(), local_key.as_deref()) {
+                            Ok(update) => update,
+                            Err(error) => {
+                                warn!("[Sync] Could not open document from peer {}: {}", peer.name, error);
+                                continue;
+                            }
+                        };
+                        match store_relay_client_update_if_current(state, generation, update).await {
+                            RelayClientStore::Stored => {
+                                update_sync_peers(state, &**events, |peers| {
+                                    peers.note_synced(fingerprint, now_unix_ms());
+                                });
+                                events.sync_peer_doc_merged(fingerprint);
+                            }
+                            RelayClientStore::Unchanged => {}
+                            RelayClientStore::Stale => return SyncPeerExit::Stop,
+                            RelayClientStore::Rejected(reason) => {
+                                warn!("[Sync] Rejected document from peer {}: {}", peer.name, reason);
+                            }
+                            RelayClientStore::HeldUnreadable(reason) => {
+                                report_unreadable_held_doc(&**events, &peer.name, &reason);
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<RelayServerControl>(&text) {
+                            Ok(RelayServerControl::Ack { heads, .. })
+                            | Ok(RelayServerControl::HeadsAck { heads }) => {
+                                peer_heads = parse_relay_heads_hex(&heads).unwrap_or_default();
+                            }
+                            Ok(RelayServerControl::FactoryReset { .. }) => {
+                                return SyncPeerExit::NeedsTrust("peer was factory reset".to_string());
+                            }
+                            Ok(RelayServerControl::DeviceRevoked) => {
+                                return SyncPeerExit::NeedsTrust("peer revoked this desktop".to_string());
+                            }
+                            Ok(RelayServerControl::Error { reason }) => {
+                                warn!("[Sync] Peer {} reported: {}", peer.name, reason);
+                            }
+                            Ok(RelayServerControl::ClientCount { .. }) => {}
+                            Err(error) => {
+                                warn!("[Sync] Ignored control message from peer {}: {}", peer.name, error);
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        let code = frame.map(|frame| u16::from(frame.code));
+                        return match code {
+                            Some(RELAY_CLOSE_FACTORY_RESET) => {
+                                SyncPeerExit::NeedsTrust("peer was factory reset".to_string())
+                            }
+                            Some(RELAY_CLOSE_DEVICE_REVOKED) => {
+                                SyncPeerExit::NeedsTrust("peer revoked this desktop".to_string())
+                            }
+                            _ => SyncPeerExit::Retry("peer closed the connection".to_string()),
+                        };
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => return SyncPeerExit::Retry(error.to_string()),
+                    None => return SyncPeerExit::Retry("peer closed the connection".to_string()),
+                }
+            }
+            delivery = recv_relay_delivery(&mut broadcast_rx, &mut metrics) => {
+                let update = match delivery {
+                    RelayDelivery::Update(update) => update,
+                    RelayDelivery::CatchUp => match held_relay_update(state).await {
+                        Some(held) => held,
+                        None => continue,
+                    },
+                    RelayDelivery::Closed => return SyncPeerExit::Stop,
+                };
+                if relay_heads_match(update.heads(), &peer_heads) {
+                    continue;
+                }
+                if let Err(error) =
+                    send_sync_peer_update(state, peer_key.as_ref(), &mut ws_sender, &update).await
+                {
+                    return SyncPeerExit::Retry(error);
+                }
+            }
+            reset = disconnect_rx.recv() => {
+                if let Ok(RelayDisconnect::FactoryReset(_)) = reset {
+                    let _ = ws_sender.send(Message::Close(None)).await;
+                    return SyncPeerExit::Stop;
+                }
+            }
+        }
+    }
+}
+
+/// Replicates with one trusted peer until it is forgotten, needs to be
+/// trusted again, or this relay is reset. Reconnects with backoff.
+async fn run_sync_peer(state: RelayState, events: RelayEvents, fingerprint: String) {
+    let local_name = sync_peer_local_name();
+    let mut delay = SYNC_PEER_RETRY_MIN_DELAY;
+    loop {
+        let Some(peer) = state.peers.lock().unwrap().trusted(&fingerprint) else {
+            return;
+        };
+        update_sync_peers(&state, &*events, |peers| {
+            peers.set_status(&fingerprint, SyncPeerStatus::Connecting, None);
+        });
+        let started = Instant::now();
+        match replicate_with_sync_peer(&state, &events, &peer, &local_name).await {
+            SyncPeerExit::Retry(reason) => {
+                warn!("[Sync] Peer {} unreachable: {}", peer.name, reason);
+                update_sync_peers(&state, &*events, |peers| {
+                    peers.set_status(&fingerprint, SyncPeerStatus::Offline, Some(reason));
+                });
+            }
+            SyncPeerExit::NeedsTrust(reason) => {
+                warn!(
+                    "[Sync] Peer {} needs to be trusted again: {}",
+                    peer.name, reason
+                );
+                update_sync_peers(&state, &*events, |peers| {
+                    peers.set_status(&fingerprint, SyncPeerStatus::NeedsTrust, Some(reason));
+                });
+                return;
+            }
+            SyncPeerExit::Stop => {
+                update_sync_peers(&state, &*events, |peers| {
+                    peers.set_status(&fingerprint, SyncPeerStatus::Offline, None);
+                });
+                return;
+            }
+        }
+        if started.elapsed() >= SYNC_PEER_RETRY_MAX_DELAY {
+            delay = SYNC_PEER_RETRY_MIN_DELAY;
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(SYNC_PEER_RETRY_MAX_DELAY);
+    }
+}
+
+/// Starts (or restarts) replication with one trusted peer.
+pub(crate) fn start_sync_peer(state: &RelayState, events: &RelayEvents, fingerprint: &str) {
+    let task = tokio::spawn(run_sync_peer(
+        state.clone(),
+        events.clone(),
+        fingerprint.to_string(),
+    ));
+    let previous = state
+        .peers
+        .lock()
+        .unwrap()
+        .tasks
+        .insert(fingerprint.to_string(), task.abort_handle());
+    if let Some(previous) = previous {
+        previous.abort();
+    }
+}
+
+pub(crate) fn start_trusted_sync_peers(state: &RelayState, events: &RelayEvents) {
+    let fingerprints = state.peers.lock().unwrap().fingerprints();
+    for fingerprint in fingerprints {
+        start_sync_peer(state, events, &fingerprint);
+    }
+}
+
+/// Browses for other Freed relays on the daemon that advertises this one.
+/// Resolved records only feed the peer list; nothing connects until the user
+/// trusts a peer.
+pub(crate) fn browse_sync_peers(
+    daemon: &mdns_sd::ServiceDaemon,
+    instance_name: &str,
+    state: RelayState,
+    events: RelayEvents,
+) {
+    use mdns_sd::ServiceEvent;
+
+    let receiver = match daemon.browse(SYNC_PEER_SERVICE_TYPE) {
+        Ok(receiver) => receiver,
+        Err(error) => {
+            error!("[mDNS] Failed to browse for peers: {}", error);
+            return;
+        }
+    };
+    let own_fullname = format!("{}.{}", instance_name, SYNC_PEER_SERVICE_TYPE);
+    std::thread::spawn(move || {
+        while let Ok(event) = receiver.recv() {
+            match event {
+                ServiceEvent::ServiceResolved(info) => {
+                    let fullname = info.get_fullname().to_string();
+                    let fingerprint = info.get_property_val_str("fp").map(str::to_string);
+                    let own_fingerprint = state
+                        .tls
+                        .read()
+                        .unwrap()
+                        .as_ref()
+                        .map(|tls| tls.fingerprint.clone());
+                    if fullname == own_fullname
+                        || (fingerprint.is_some() && fingerprint == own_fingerprint)
+                    {
+                        continue;
+                    }
+                    let addresses = info.get_addresses();
+                    let Some(host) = addresses
+                        .iter()
+                        .find(|ip| ip.is_ipv4())
+                        .or_else(|| addresses.iter().next())
+                    else {
+                        continue;
+                    };
+                    let peer = DiscoveredPeer {
+                        name: fullname
+                            .strip_suffix(SYNC_PEER_SERVICE_TYPE)
+                            .unwrap_or(&fullname)
+                            .trim_end_matches('.')
+                            .to_string(),
+                        host: host.to_string(),
+                        port: info.get_port(),
+                        fingerprint,
+                    };
+                    info!(
+                        "[mDNS] Found peer {} at {}:{}",
+                        peer.name, peer.host, peer.port
+                    );
+                    update_sync_peers(&state, &*events, |peers| peers.discover(&fullname, peer));
+                }
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    update_sync_peers(&state, &*events, |peers| peers.lose(&fullname));
+                }
+                _ => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::{
+        handle_connection, open_relay_payload, store_relay_client_doc_if_current, sync_pairing_url,
+        tests::{automerge_doc_bytes, automerge_doc_entries},
+        LoggedRelayEvents, RelayTlsIdentity,
+    };
+    use crate::trust_sync_peer_in;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::time::Duration;
+
+    #[test]
+    fn peer_pairing_links_carry_code_fingerprint_and_sync_key() {
+        let state = SyncRelayState::new(9001);
+        let tls = RelayTlsIdentity::generate()
+            .unwrap()
+            .to_relay_tls()
+            .unwrap();
+        let fingerprint = tls.fingerprint.clone();
+        *state.tls.write().unwrap() = Some(tls);
+        let key = RelayPayloadKey::generate();
+        let secret = key.secret_param();
+        *state.payload_key.write().unwrap() = Some(Arc::new(key));
+
+        let link = sync_pairing_url(&state, &["192.168.1.20"], "code-1");
+        let peer = parse_peer_pairing_link(&link).unwrap();
+        assert_eq!(peer.host, "192.168.1.20");
+        assert_eq!(peer.port, 9001);
+        assert_eq!(peer.fingerprint, fingerprint);
+        assert_eq!(peer.pairing_code.as_deref(), Some("code-1"));
+        assert_eq!(peer.device_id, pairing_code_device_id("code-1"));
+        assert_eq!(peer.credential, pairing_code_device_credential("code-1"));
+        assert_eq!(peer.sync_key, Some(secret));
+        assert_eq!(
+            sync_peer_upgrade_url(&peer, "Freed Desktop (den)"),
+            "wss://192.168.1.20:9001/?v=1&pc=code-1&n=Freed+Desktop+%28den%29"
+        );
+
+        let redeemed = TrustedPeer {
+            host: "fe80::1".to_string(),
+            pairing_code: None,
+            ..peer
+        };
+        assert_eq!(
+            sync_peer_upgrade_url(&redeemed, "Freed Desktop (den)"),
+            format!(
+                "wss://[fe80::1]:9001/?v=1&d={}&t={}",
+                redeemed.device_id, redeemed.credential
+            )
+        );
+
+        // Relays that allow plaintext show ws:// links; both schemes pin `fp`.
+        let plain_link = link.replacen("wss://", "ws://", 1);
+        assert_eq!(
+            parse_peer_pairing_link(&plain_link).unwrap().fingerprint,
+            fingerprint
+        );
+
+        for invalid in [
+            link.replacen("wss://", "https://", 1),
+            link.replace("pc=code-1&", ""),
+            link.replace("fp=", "fq="),
+            format!("{}x", link),
+        ] {
+            assert!(parse_peer_pairing_link(&invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sync_peer_registry_persists_trust_and_follows_discovery() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let path = data_dir.path().join(SYNC_PEERS_FILE);
+        let fingerprint = URL_SAFE_NO_PAD.encode([7u8; 32]);
+        let studio = format!("Freed Desktop (studio).{}", SYNC_PEER_SERVICE_TYPE);
+        let den = format!("Freed Desktop (den).{}", SYNC_PEER_SERVICE_TYPE);
+
+        let mut peers = SyncPeerRegistry::load(path.clone());
+        peers.discover(
+            &studio,
+            DiscoveredPeer {
+                name: "Freed Desktop (studio)".to_string(),
+                host: "192.168.1.30".to_string(),
+                port: 8765,
+                fingerprint: Some(fingerprint.clone()),
+            },
+        );
+        peers.discover(
+            &den,
+            DiscoveredPeer {
+                name: "Freed Desktop (den)".to_string(),
+                host: "192.168.1.40".to_string(),
+                port: 8765,
+                fingerprint: Some(URL_SAFE_NO_PAD.encode([9u8; 32])),
+            },
+        );
+        let link = format!("wss://192.168.1.20:8765?pc=code-1&fp={}", fingerprint);
+        peers
+            .trust(parse_peer_pairing_link(&link).unwrap())
+            .unwrap();
+
+        let summaries = peers.summaries();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].name, "Freed Desktop (studio)");
+        assert_eq!(summaries[0].host, "192.168.1.30");
+        assert!(summaries[0].trusted && summaries[0].discovered);
+        assert_eq!(summaries[0].status, SyncPeerStatus::Offline);
+        assert_eq!(summaries[1].name, "Freed Desktop (den)");
+        assert!(!summaries[1].trusted);
+        assert_eq!(summaries[1].status, SyncPeerStatus::Discovered);
+        assert_eq!(peers.trusted(&fingerprint).unwrap().host, "192.168.1.30");
+
+        peers.note_redeemed(&fingerprint);
+        peers.set_status(&fingerprint, SyncPeerStatus::Connected, None);
+        let reloaded = SyncPeerRegistry::load(path.clone());
+        let stored = reloaded.trusted(&fingerprint).unwrap();
+        assert_eq!(stored.pairing_code, None);
+        assert_eq!(stored.name, "Freed Desktop (studio)");
+        assert_eq!(stored.host, "192.168.1.20");
+        assert_eq!(reloaded.summaries()[0].status, SyncPeerStatus::Offline);
+
+        peers.lose(&studio);
+        assert!(!peers.summaries()[0].discovered);
+        assert!(peers.forget(&fingerprint).unwrap());
+        assert!(!peers.forget(&fingerprint).unwrap());
+        assert!(SyncPeerRegistry::load(path).trusted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn trusted_peer_desktops_replicate_held_documents_both_ways() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let remote: RelayState =
+            Arc::new(SyncRelayState::new(listener.local_addr().unwrap().port()));
+        let local: RelayState = Arc::new(SyncRelayState::new(DEFAULT_SYNC_RELAY_PORT));
+        for (state, key, value) in [(&remote, "remote", "a"), (&local, "local", "b")] {
+            *state.tls.write().unwrap() = Some(
+                RelayTlsIdentity::generate()
+                    .unwrap()
+                    .to_relay_tls()
+                    .unwrap(),
+            );
+            let sync_key = Arc::new(RelayPayloadKey::generate());
+            let doc = sync_key.seal(&automerge_doc_bytes(key, value)).unwrap();
+            *state.payload_key.write().unwrap() = Some(sync_key);
+            assert_eq!(
+                store_relay_client_doc_if_current(state, 0, &doc).await,
+                RelayClientStore::Stored
+            );
+        }
+
+        let events: RelayEvents = Arc::new(LoggedRelayEvents);
+        let server_state = remote.clone();
+        let server_events = events.clone();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, addr)) = listener.accept().await {
+                tokio::spawn(handle_connection(
+                    stream,
+                    addr,
+                    server_state.clone(),
+                    server_events.clone(),
+                ));
+            }
+        });
+        let code = remote
+            .devices
+            .lock()
+            .unwrap()
+            .issue_pairing_code(now_unix_ms());
+        let link = sync_pairing_url(&remote, &["127.0.0.1"], &code);
+        let fingerprint = trust_sync_peer_in(&local, &events, &link)
+            .unwrap()
+            .fingerprint
+            .unwrap();
+
+        let held_entries = |state: RelayState| async move {
+            let held = state.current_doc.read().await.clone().unwrap();
+            automerge_doc_entries(&open_relay_payload(&state, &held).unwrap())
+        };
+        let converged = |expected: Vec<(&'static str, &'static str)>| {
+            let expected: Vec<(String, String)> = expected
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            let (local, remote) = (local.clone(), remote.clone());
+            async move {
+                loop {
+                    if held_entries(local.clone()).await == expected
+                        && held_entries(remote.clone()).await == expected
+                    {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            }
+        };
+        timeout(
+            Duration::from_secs(10),
+            converged(vec![("local", "b"), ("remote", "a")]),
+        )
+        .await
+        .expect("peers exchange their held documents");
+
+        let devices = remote.devices.lock().unwrap().summaries();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, sync_peer_local_name());
+        let summary = local.peers.lock().unwrap().summaries().remove(0);
+        assert_eq!(summary.status, SyncPeerStatus::Connected);
+        assert!(local
+            .peers
+            .lock()
+            .unwrap()
+            .trusted(&fingerprint)
+            .unwrap()
+            .pairing_code
+            .is_none());
+
+        // A later local edit is forwarded over the open session.
+        let local_key = local.payload_key.read().unwrap().clone().unwrap();
+        let held = local.current_doc.read().await.clone().unwrap();
+        let mut edited = automerge::AutoCommit::load(&local_key.open(&held).unwrap()).unwrap();
+        {
+            use automerge::transaction::Transactable;
+            edited.put(automerge::ROOT, "later", "c").unwrap();
+        }
+        let sealed = local_key.seal(&edited.save()).unwrap();
+        assert_eq!(
+            store_relay_client_doc_if_current(&local, 0, &sealed).await,
+            RelayClientStore::Stored
+        );
+        timeout(
+            Duration::from_secs(10),
+            converged(vec![("later", "c"), ("local", "b"), ("remote", "a")]),
+        )
+        .await
+        .expect("local edits reach the peer");
+
+        assert!(local.peers.lock().unwrap().forget(&fingerprint).unwrap());
+        server.abort();
+    }
+}
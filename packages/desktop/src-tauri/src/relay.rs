@@ -0,0 +1,5180 @@
+//! Sync relay: the WebSocket server every device pairs with, its TLS identity,
+//! paired-device registry, sealed payloads, and mDNS advertisement.
+//!
+//! Everything here builds without the `desktop` feature so `freed-relay` can
+//! run it headless.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::{
+    accept_hdr_async_with_config,
+    tungstenite::{
+        error::CapacityError,
+        handshake::server::{ErrorResponse, Request as WsRequest, Response as WsResponse},
+        protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig},
+        Error as WsError, Message,
+    },
+};
+
+#[cfg(feature = "desktop")]
+use crate::backups::BackupSchedule;
+#[cfg(feature = "desktop")]
+use crate::peers::{SyncPeerRegistry, SyncPeerSummary, SYNC_PEERS_FILE};
+use crate::rendezvous::{run_relay_rendezvous, RelayRendezvousSettings};
+#[cfg(feature = "desktop")]
+use crate::snapshots::SnapshotRetention;
+use crate::snapshots::{run_debounced_doc_writer, DEFAULT_SNAPSHOT_WRITER_POLICY};
+use crate::{now_unix_ms, write_file_atomically, SYNC_PEER_SERVICE_TYPE};
+
+pub(crate) const DEFAULT_SYNC_RELAY_PORT: u16 = 8765;
+pub(crate) const SYNC_RELAY_DOC_SEND_TIMEOUT: Duration = Duration::from_secs(2);
+pub(crate) const SYNC_RELAY_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// First byte of a TLS ClientHello record; anything else is served as ws://.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+const RELAY_TLS_CERT_FILE: &str = "relay-tls-cert.der";
+const RELAY_TLS_KEY_FILE: &str = "relay-tls-key.der";
+const PAIRED_DEVICES_FILE: &str = "paired-devices.json";
+const RELAY_NETWORK_FILE: &str = "relay-network.json";
+/// How long a pairing code shown in the QR stays redeemable.
+const PAIRING_CODE_TTL: Duration = Duration::from_secs(10 * 60);
+/// Oldest unredeemed codes are dropped beyond this many.
+const MAX_PENDING_PAIRING_CODES: usize = 8;
+#[cfg(feature = "desktop")]
+const SYNC_KEY_FILE: &str = "sync-key";
+/// Held relay document, persisted so it survives restarts.
+pub(crate) const RELAY_DOC_FILE: &str = "relay-doc.bin";
+/// Leading bytes of `relay-doc.bin`; see `write_relay_doc`.
+const RELAY_DOC_MAGIC: [u8; 4] = *b"FRD\x01";
+/// Leading bytes of every sealed relay payload envelope; see `RelayPayloadKey`.
+pub(crate) const SEALED_PAYLOAD_MAGIC: [u8; 4] = *b"FRS\x01";
+/// Magic, key id, and big-endian u32 body length.
+const SEALED_PAYLOAD_HEADER_BYTES: usize = 16;
+const SEALED_PAYLOAD_KEY_ID_BYTES: usize = 8;
+const SEALED_PAYLOAD_NONCE_BYTES: usize = 12;
+/// Leading bytes of every saved Automerge document chunk.
+pub(crate) const AUTOMERGE_MAGIC_BYTES: [u8; 4] = [0x85, 0x6f, 0x4a, 0x83];
+/// First byte of every framed relay message; see `RelayFrameKind`.
+pub(crate) const RELAY_PROTOCOL_VERSION: u8 = 1;
+/// Automerge change hashes are SHA-256 digests.
+pub(crate) const RELAY_HEAD_BYTES: usize = 32;
+/// Version of the JSON control channel negotiated by `hello` / `ack`.
+pub(crate) const RELAY_CONTROL_VERSION: u8 = 1;
+/// Features the relay offers in its `ack`.
+pub(crate) const RELAY_CAPABILITIES: [&str; 4] = ["frames", "changes", "merge", "control"];
+const RELAY_MAX_CLIENT_ID_LEN: usize = 64;
+/// WebSocket close codes, so clients can tell a relay decision from a
+/// dropped network even without the control channel. 4001 means the client
+/// must re-pair: the relay was factory reset or its shared token rotated.
+pub(crate) const RELAY_CLOSE_FACTORY_RESET: u16 = 4001;
+/// Sent only to a paired device that was revoked.
+pub(crate) const RELAY_CLOSE_DEVICE_REVOKED: u16 = 4003;
+/// Closed with `disconnect_sync_client`; the client may reconnect at once.
+pub(crate) const RELAY_CLOSE_DISCONNECTED: u16 = 4004;
+/// Times a push is re-merged when the held document changes underneath it.
+const RELAY_MERGE_ATTEMPTS: usize = 4;
+/// Standard "message too big" close code, sent when a frame exceeds the cap.
+const RELAY_CLOSE_FRAME_TOO_LARGE: u16 = 1009;
+
+pub(crate) fn sync_relay_port() -> u16 {
+    std::env::var("FREED_SYNC_PORT")
+        .ok()
+        .and_then(|raw| raw.parse::<u16>().ok())
+        .filter(|port| *port > 0)
+        .unwrap_or(DEFAULT_SYNC_RELAY_PORT)
+}
+
+const SYNC_RELAY_BIND_RETRY_DELAY: Duration = Duration::from_secs(5);
+const SYNC_RELAY_BIND_MAX_RETRIES: usize = 60;
+
+// ---------------------------------------------------------------------------
+// Token management
+// ---------------------------------------------------------------------------
+
+/// Generates a cryptographically random 256-bit pairing token encoded as
+/// base64url without padding (43 ASCII characters).
+pub(crate) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Loads the pairing token from `data_dir/pairing-token`, or creates and
+/// persists a fresh one if the file is missing or malformed.
+pub(crate) fn load_or_create_token(data_dir: &std::path::Path) -> String {
+    let path = data_dir.join("pairing-token");
+    if let Ok(raw) = std::fs::read_to_string(&path) {
+        let token = raw.trim().to_string();
+        // 32 bytes base64url-no-pad → exactly 43 chars, all URL-safe
+        let looks_valid = token.len() == 43
+            && token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if looks_valid {
+            return token;
+        }
+    }
+    let token = generate_token();
+    let _ = std::fs::write(&path, &token);
+    token
+}
+
+// ---------------------------------------------------------------------------
+// Relay TLS identity
+// ---------------------------------------------------------------------------
+
+/// Self-signed certificate the relay serves TLS with. Clients cannot validate
+/// it against a CA, so they pin the SHA-256 fingerprint carried in the pairing
+/// URL instead. Browsers cannot pin at all and stay on plain `ws://`.
+pub(crate) struct RelayTlsIdentity {
+    cert_der: Vec<u8>,
+    key_der: Vec<u8>,
+}
+
+impl RelayTlsIdentity {
+    pub(crate) fn generate() -> Result<Self, String> {
+        let hostname = hostname::get()
+            .ok()
+            .and_then(|h| h.into_string().ok())
+            .unwrap_or_else(|| "freed-desktop".to_string());
+        let certified = rcgen::generate_simple_self_signed(vec![
+            format!("{}.local", hostname),
+            "localhost".to_string(),
+        ])
+        .map_err(|e| format!("failed to generate relay certificate: {}", e))?;
+        Ok(Self {
+            cert_der: certified.cert.der().to_vec(),
+            key_der: certified.key_pair.serialize_der(),
+        })
+    }
+
+    pub(crate) fn load(data_dir: &Path) -> Option<Self> {
+        Some(Self {
+            cert_der: std::fs::read(data_dir.join(RELAY_TLS_CERT_FILE)).ok()?,
+            key_der: std::fs::read(data_dir.join(RELAY_TLS_KEY_FILE)).ok()?,
+        })
+    }
+
+    pub(crate) fn persist(&self, data_dir: &Path) -> Result<(), String> {
+        write_file_atomically(&data_dir.join(RELAY_TLS_CERT_FILE), &self.cert_der)
+            .map_err(|e| format!("failed to persist relay certificate: {}", e))?;
+        write_private_file(&data_dir.join(RELAY_TLS_KEY_FILE), &self.key_der)
+            .map_err(|e| format!("failed to persist relay certificate key: {}", e))
+    }
+
+    pub(crate) fn fingerprint(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(&self.cert_der))
+    }
+
+    pub(crate) fn to_relay_tls(&self) -> Result<RelayTls, String> {
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from(self.cert_der.clone())],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.key_der.clone())),
+        )
+        .map_err(|e| format!("invalid relay certificate: {}", e))?;
+        Ok(RelayTls {
+            acceptor: tokio_rustls::TlsAcceptor::from(Arc::new(config)),
+            fingerprint: self.fingerprint(),
+        })
+    }
+}
+
+/// Writes a secret readable only by the current user where the OS supports it.
+/// The bytes go to a synced temp file that is renamed over `path`, so a crash
+/// leaves either the old secret or the new one.
+pub(crate) fn write_private_file(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    // The mode only applies to a file the open creates.
+    let _ = std::fs::remove_file(&tmp_path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Loads the pairing token, paired devices, trusted peers, network and
+/// rendezvous settings, and TLS identity from `data_dir`.
+pub(crate) fn load_relay_credentials(state: &SyncRelayState, data_dir: &Path) {
+    *state.pairing_token.write().unwrap() = load_or_create_token(data_dir);
+    *state.devices.lock().unwrap() = RelayDeviceRegistry::load(data_dir.join(PAIRED_DEVICES_FILE));
+    #[cfg(feature = "desktop")]
+    {
+        *state.peers.lock().unwrap() = SyncPeerRegistry::load(data_dir.join(SYNC_PEERS_FILE));
+    }
+    state
+        .network_tx
+        .send_replace(RelayNetworkSettings::load(data_dir));
+    state
+        .rendezvous_tx
+        .send_replace(RelayRendezvousSettings::load(data_dir));
+    match load_or_create_relay_tls(data_dir) {
+        Ok(tls) => *state.tls.write().unwrap() = Some(tls),
+        Err(error) => error!(
+            "[Sync] Relay certificate unavailable, only plaintext can be served: {}",
+            error
+        ),
+    }
+}
+
+/// Loads the persisted relay certificate, or creates and persists a fresh one
+/// if the files are missing or no longer form a usable key pair.
+fn load_or_create_relay_tls(data_dir: &Path) -> Result<RelayTls, String> {
+    if let Some(identity) = RelayTlsIdentity::load(data_dir) {
+        match identity.to_relay_tls() {
+            Ok(tls) => return Ok(tls),
+            Err(error) => warn!("[Sync] Regenerating relay certificate: {}", error),
+        }
+    }
+    let identity = RelayTlsIdentity::generate()?;
+    identity.persist(data_dir)?;
+    identity.to_relay_tls()
+}
+
+// ---------------------------------------------------------------------------
+// Paired devices
+// ---------------------------------------------------------------------------
+//
+// The QR carries a one-time pairing code (`pc=`) instead of the shared token.
+// Phone and relay both derive the device id and credential from that code, so
+// the credential never crosses the wire during pairing and the relay only keeps
+// a SHA-256 verifier of it. After the first connection redeems the code, the
+// device reconnects with `?d=<device id>&t=<credential>` and can be revoked on
+// its own. The shared `pairing-token` is still accepted from devices paired
+// before per-device credentials existed.
+
+pub(crate) fn pairing_code_device_id(code: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"freed-device-id:v1:")
+        .chain_update(code.as_bytes())
+        .finalize();
+    URL_SAFE_NO_PAD.encode(&digest[..16])
+}
+
+pub(crate) fn pairing_code_device_credential(code: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"freed-device-credential:v1:")
+        .chain_update(code.as_bytes())
+        .finalize();
+    URL_SAFE_NO_PAD.encode(digest)
+}
+
+fn device_credential_verifier(credential: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(credential.as_bytes()))
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PairedDevice {
+    id: String,
+    name: String,
+    /// base64url SHA-256 of the device credential.
+    credential_verifier: String,
+    first_seen_at_ms: u64,
+    last_seen_at_ms: u64,
+    last_address: Option<String>,
+    bytes_received: u64,
+    bytes_sent: u64,
+}
+
+#[cfg(feature = "desktop")]
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PairedDeviceSummary {
+    id: String,
+    pub(crate) name: String,
+    first_seen_at_ms: u64,
+    last_seen_at_ms: u64,
+    last_address: Option<String>,
+    bytes_received: u64,
+    bytes_sent: u64,
+    connected: bool,
+}
+
+struct PendingPairingCode {
+    code: String,
+    expires_at_ms: u64,
+}
+
+/// Paired devices plus the pairing codes that have not been redeemed yet.
+/// Only the device list is persisted; pending codes live in memory.
+#[derive(Default)]
+pub(crate) struct RelayDeviceRegistry {
+    /// `None` keeps the registry in memory only.
+    path: Option<PathBuf>,
+    devices: Vec<PairedDevice>,
+    pending_codes: Vec<PendingPairingCode>,
+    /// Open connections per device id.
+    live_sessions: HashMap<String, usize>,
+}
+
+impl RelayDeviceRegistry {
+    fn load(path: PathBuf) -> Self {
+        let devices = match std::fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).unwrap_or_else(|error| {
+                warn!("[Sync] Ignoring unreadable device registry: {}", error);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path: Some(path),
+            devices,
+            ..Self::default()
+        }
+    }
+
+    fn persist(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec_pretty(&self.devices).map_err(|e| e.to_string())?;
+        write_private_file(path, &json)
+            .map_err(|e| format!("failed to persist device registry: {}", e))
+    }
+
+    pub(crate) fn issue_pairing_code(&mut self, now_ms: u64) -> String {
+        self.pending_codes
+            .retain(|pending| pending.expires_at_ms > now_ms);
+        if self.pending_codes.len() >= MAX_PENDING_PAIRING_CODES {
+            let excess = self.pending_codes.len() + 1 - MAX_PENDING_PAIRING_CODES;
+            self.pending_codes.drain(..excess);
+        }
+        let code = generate_token();
+        self.pending_codes.push(PendingPairingCode {
+            code: code.clone(),
+            expires_at_ms: now_ms + PAIRING_CODE_TTL.as_millis() as u64,
+        });
+        code
+    }
+
+    /// Consumes an unexpired pairing code and registers the device it pairs.
+    pub(crate) fn redeem_pairing_code(
+        &mut self,
+        code: &str,
+        name: &str,
+        now_ms: u64,
+    ) -> Option<String> {
+        let index = self
+            .pending_codes
+            .iter()
+            .position(|pending| pending.code == code && pending.expires_at_ms > now_ms)?;
+        self.pending_codes.remove(index);
+
+        let id = pairing_code_device_id(code);
+        self.devices.retain(|device| device.id != id);
+        self.devices.push(PairedDevice {
+            id: id.clone(),
+            name: name.to_string(),
+            credential_verifier: device_credential_verifier(&pairing_code_device_credential(code)),
+            first_seen_at_ms: now_ms,
+            last_seen_at_ms: now_ms,
+            last_address: None,
+            bytes_received: 0,
+            bytes_sent: 0,
+        });
+        if let Err(error) = self.persist() {
+            warn!("[Sync] {}", error);
+        }
+        Some(id)
+    }
+
+    fn verify_device(&self, id: &str, credential: &str) -> bool {
+        let verifier = device_credential_verifier(credential);
+        self.devices
+            .iter()
+            .any(|device| device.id == id && device.credential_verifier == verifier)
+    }
+
+    pub(crate) fn contains(&self, id: &str) -> bool {
+        self.devices.iter().any(|device| device.id == id)
+    }
+
+    fn name(&self, id: &str) -> Option<&str> {
+        self.devices
+            .iter()
+            .find(|device| device.id == id)
+            .map(|device| device.name.as_str())
+    }
+
+    fn note_connected(&mut self, id: &str, addr: SocketAddr, now_ms: u64) {
+        let Some(device) = self.devices.iter_mut().find(|device| device.id == id) else {
+            return;
+        };
+        device.last_seen_at_ms = now_ms;
+        device.last_address = Some(addr.ip().to_string());
+        *self.live_sessions.entry(id.to_string()).or_default() += 1;
+        if let Err(error) = self.persist() {
+            warn!("[Sync] {}", error);
+        }
+    }
+
+    /// Records a one-shot HTTP request, which opens no session.
+    fn note_request(
+        &mut self,
+        id: &str,
+        addr: SocketAddr,
+        bytes_received: u64,
+        bytes_sent: u64,
+        now_ms: u64,
+    ) {
+        let Some(device) = self.devices.iter_mut().find(|device| device.id == id) else {
+            return;
+        };
+        device.last_seen_at_ms = now_ms;
+        device.last_address = Some(addr.ip().to_string());
+        device.bytes_received += bytes_received;
+        device.bytes_sent += bytes_sent;
+        if let Err(error) = self.persist() {
+            warn!("[Sync] {}", error);
+        }
+    }
+
+    fn note_disconnected(&mut self, id: &str, bytes_received: u64, bytes_sent: u64, now_ms: u64) {
+        if let Some(sessions) = self.live_sessions.get_mut(id) {
+            *sessions = sessions.saturating_sub(1);
+            if *sessions == 0 {
+                self.live_sessions.remove(id);
+            }
+        }
+        let Some(device) = self.devices.iter_mut().find(|device| device.id == id) else {
+            return;
+        };
+        device.last_seen_at_ms = now_ms;
+        device.bytes_received += bytes_received;
+        device.bytes_sent += bytes_sent;
+        if let Err(error) = self.persist() {
+            warn!("[Sync] {}", error);
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn revoke(&mut self, id: &str) -> Result<bool, String> {
+        let before = self.devices.len();
+        self.devices.retain(|device| device.id != id);
+        if self.devices.len() == before {
+            return Ok(false);
+        }
+        self.persist()?;
+        Ok(true)
+    }
+
+    /// Forgets every paired device and outstanding pairing code.
+    #[cfg(feature = "desktop")]
+    pub(crate) fn clear(&mut self) -> Result<(), String> {
+        self.devices.clear();
+        self.pending_codes.clear();
+        self.persist()
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn summaries(&self) -> Vec<PairedDeviceSummary> {
+        self.devices
+            .iter()
+            .map(|device| PairedDeviceSummary {
+                id: device.id.clone(),
+                name: device.name.clone(),
+                first_seen_at_ms: device.first_seen_at_ms,
+                last_seen_at_ms: device.last_seen_at_ms,
+                last_address: device.last_address.clone(),
+                bytes_received: device.bytes_received,
+                bytes_sent: device.bytes_sent,
+                connected: self.live_sessions.contains_key(&device.id),
+            })
+            .collect()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Relay payload sealing
+// ---------------------------------------------------------------------------
+//
+// Documents cross the relay sealed with a key only the paired devices hold.
+// The 256-bit secret reaches a phone solely inside the QR code, as the URL
+// fragment `#k=` that browsers never send to the server. Both ends expand it
+// with HKDF-SHA256 into an AES-256-GCM key, so the relay routes and caches
+// ciphertext it cannot read.
+//
+// A sealed payload is one or more self-delimiting envelopes:
+//
+//   [0..4]       SEALED_PAYLOAD_MAGIC
+//   [4..12]      key id (public; lets the relay drop payloads from a stale key)
+//   [12..16]     body length L (u32, big endian)
+//   [16..16+L]   12-byte nonce, then ciphertext and tag; the header is the AAD
+//
+// Appending a sealed change envelope to a sealed document therefore still
+// yields a sealed payload, and opening it yields the loadable concatenation.
+
+struct SealedEnvelope<'a> {
+    /// Magic, key id, and length; authenticated as the AAD.
+    header: &'a [u8],
+    /// Nonce, ciphertext, and tag.
+    body: &'a [u8],
+}
+
+fn sealed_payload_envelopes(mut bytes: &[u8]) -> Result<Vec<SealedEnvelope<'_>>, String> {
+    if bytes.is_empty() {
+        return Err("sealed payload is empty".to_string());
+    }
+    let mut envelopes = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < SEALED_PAYLOAD_HEADER_BYTES
+            || bytes[..SEALED_PAYLOAD_MAGIC.len()] != SEALED_PAYLOAD_MAGIC
+        {
+            return Err("payload is not sealed".to_string());
+        }
+        let (header, rest) = bytes.split_at(SEALED_PAYLOAD_HEADER_BYTES);
+        let body_len = u32::from_be_bytes(header[12..16].try_into().unwrap()) as usize;
+        if body_len < SEALED_PAYLOAD_NONCE_BYTES || rest.len() < body_len {
+            return Err("sealed payload is truncated".to_string());
+        }
+        let (body, rest) = rest.split_at(body_len);
+        envelopes.push(SealedEnvelope { header, body });
+        bytes = rest;
+    }
+    Ok(envelopes)
+}
+
+/// Key id shared by every envelope in a sealed payload.
+pub(crate) fn sealed_payload_key_id(
+    bytes: &[u8],
+) -> Result<[u8; SEALED_PAYLOAD_KEY_ID_BYTES], String> {
+    let mut key_id = None;
+    for envelope in sealed_payload_envelopes(bytes)? {
+        let id: [u8; SEALED_PAYLOAD_KEY_ID_BYTES] = envelope.header[4..12].try_into().unwrap();
+        if key_id.is_some_and(|seen| seen != id) {
+            return Err("sealed payload mixes keys".to_string());
+        }
+        key_id = Some(id);
+    }
+    key_id.ok_or_else(|| "sealed payload is empty".to_string())
+}
+
+pub(crate) struct RelayPayloadKey {
+    secret: [u8; 32],
+    pub(crate) key_id: [u8; SEALED_PAYLOAD_KEY_ID_BYTES],
+    key: ring::aead::LessSafeKey,
+}
+
+impl RelayPayloadKey {
+    fn from_secret(secret: [u8; 32]) -> Self {
+        let prk = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, &[]).extract(&secret);
+        let okm = prk
+            .expand(&[b"freed-relay-payload:v1"], &ring::aead::AES_256_GCM)
+            .expect("HKDF output fits one AES-256 key");
+        let digest = Sha256::new()
+            .chain_update(b"freed-relay-key-id:v1:")
+            .chain_update(secret)
+            .finalize();
+        Self {
+            secret,
+            key_id: digest[..SEALED_PAYLOAD_KEY_ID_BYTES].try_into().unwrap(),
+            key: ring::aead::LessSafeKey::new(ring::aead::UnboundKey::from(okm)),
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self::from_secret(secret)
+    }
+
+    /// Parses the base64url secret from a pairing link's `#k=` fragment.
+    pub(crate) fn from_secret_param(param: &str) -> Option<Self> {
+        let secret = URL_SAFE_NO_PAD.decode(param.trim()).ok()?;
+        Some(Self::from_secret(secret.try_into().ok()?))
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn load(data_dir: &Path) -> Option<Self> {
+        Self::from_secret_param(&std::fs::read_to_string(data_dir.join(SYNC_KEY_FILE)).ok()?)
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn persist(&self, data_dir: &Path) -> Result<(), String> {
+        write_private_file(
+            &data_dir.join(SYNC_KEY_FILE),
+            self.secret_param().as_bytes(),
+        )
+        .map_err(|e| format!("failed to persist sync key: {}", e))
+    }
+
+    /// base64url secret published as the QR fragment `#k=`.
+    pub(crate) fn secret_param(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.secret)
+    }
+
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        use ring::aead::{Aad, Nonce, MAX_TAG_LEN};
+
+        let body_len = u32::try_from(SEALED_PAYLOAD_NONCE_BYTES + plaintext.len() + MAX_TAG_LEN)
+            .map_err(|_| "payload is too large to seal".to_string())?;
+        let mut nonce = [0u8; SEALED_PAYLOAD_NONCE_BYTES];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut sealed = Vec::with_capacity(SEALED_PAYLOAD_HEADER_BYTES + body_len as usize);
+        sealed.extend_from_slice(&SEALED_PAYLOAD_MAGIC);
+        sealed.extend_from_slice(&self.key_id);
+        sealed.extend_from_slice(&body_len.to_be_bytes());
+        sealed.extend_from_slice(&nonce);
+        let mut in_out = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&sealed[..SEALED_PAYLOAD_HEADER_BYTES]),
+                &mut in_out,
+            )
+            .map_err(|_| "failed to seal payload".to_string())?;
+        sealed.extend_from_slice(&in_out);
+        Ok(sealed)
+    }
+
+    /// Opens every envelope and returns the concatenated plaintext.
+    pub(crate) fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        use ring::aead::{Aad, Nonce};
+
+        let mut plaintext = Vec::with_capacity(sealed.len());
+        for SealedEnvelope { header, body } in sealed_payload_envelopes(sealed)? {
+            if header[4..12] != self.key_id {
+                return Err("payload was sealed with a different sync key".to_string());
+            }
+            let (nonce, ciphertext) = body.split_at(SEALED_PAYLOAD_NONCE_BYTES);
+            let nonce = Nonce::try_assume_unique_for_key(nonce)
+                .map_err(|_| "sealed payload nonce is invalid".to_string())?;
+            let mut in_out = ciphertext.to_vec();
+            let opened = self
+                .key
+                .open_in_place(nonce, Aad::from(header), &mut in_out)
+                .map_err(|_| "sealed payload failed authentication".to_string())?;
+            plaintext.extend_from_slice(opened);
+        }
+        Ok(plaintext)
+    }
+}
+
+/// Loads the persisted sync key, or creates and persists a fresh one if the
+/// file is missing or malformed.
+#[cfg(feature = "desktop")]
+pub(crate) fn load_or_create_payload_key(data_dir: &Path) -> Result<RelayPayloadKey, String> {
+    if let Some(key) = RelayPayloadKey::load(data_dir) {
+        return Ok(key);
+    }
+    let key = RelayPayloadKey::generate();
+    key.persist(data_dir)?;
+    Ok(key)
+}
+
+// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// mDNS advertisement
+// ---------------------------------------------------------------------------
+
+/// Keeps the mDNS daemon alive for the application lifetime.
+/// The `Drop` impl shuts the daemon down cleanly on exit.
+#[cfg(feature = "desktop")]
+pub(crate) struct MdnsState(pub(crate) Option<mdns_sd::ServiceDaemon>);
+
+#[cfg(feature = "desktop")]
+impl Drop for MdnsState {
+    fn drop(&mut self) {
+        if let Some(daemon) = self.0.take() {
+            let _ = daemon.shutdown();
+        }
+    }
+}
+
+/// Register `_freed-sync._tcp.local` so other desktops and future native
+/// clients can discover the relay without a QR scan.  The pairing token is
+/// intentionally absent from TXT records — discovery reveals the host/port and
+/// the certificate fingerprint (`fp`), not the secret.
+pub(crate) fn advertise_mdns(
+    port: u16,
+    instance_name: &str,
+    fingerprint: Option<&str>,
+) -> Option<mdns_sd::ServiceDaemon> {
+    use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+    let daemon = ServiceDaemon::new()
+        .map_err(|e| error!("[mDNS] Failed to create daemon: {}", e))
+        .ok()?;
+
+    let hostname = hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "freed-desktop".to_string());
+
+    let fqdn = format!("{}.local.", hostname);
+
+    let mut properties = std::collections::HashMap::new();
+    properties.insert("v".to_string(), "1".to_string());
+    properties.insert("app".to_string(), "freed".to_string());
+    if let Some(fingerprint) = fingerprint {
+        properties.insert("fp".to_string(), fingerprint.to_string());
+    }
+
+    // Pass `()` so mdns-sd auto-discovers all local interfaces — avoids
+    // hard-coding the LAN IP and handles multi-homed machines gracefully.
+    let service = ServiceInfo::new(
+        SYNC_PEER_SERVICE_TYPE,
+        instance_name,
+        &fqdn,
+        (),
+        port,
+        Some(properties),
+    )
+    .map_err(|e| error!("[mDNS] Failed to build ServiceInfo: {}", e))
+    .ok()?;
+
+    daemon
+        .register(service)
+        .map_err(|e| error!("[mDNS] Failed to register service: {}", e))
+        .ok()?;
+
+    info!("[mDNS] Advertising _freed-sync._tcp.local on port {}", port);
+    Some(daemon)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SyncRelayBindRetryPolicy {
+    max_retries: usize,
+    retry_delay: Duration,
+}
+
+const DEFAULT_SYNC_RELAY_BIND_RETRY_POLICY: SyncRelayBindRetryPolicy = SyncRelayBindRetryPolicy {
+    max_retries: SYNC_RELAY_BIND_MAX_RETRIES,
+    retry_delay: SYNC_RELAY_BIND_RETRY_DELAY,
+};
+
+fn sync_relay_bind_retry_delay(
+    policy: SyncRelayBindRetryPolicy,
+    error: &std::io::Error,
+    retries_used: usize,
+) -> Option<Duration> {
+    if error.kind() != std::io::ErrorKind::AddrInUse || retries_used >= policy.max_retries {
+        return None;
+    }
+
+    Some(policy.retry_delay)
+}
+
+async fn bind_sync_relay_listener_with_policy(
+    addr: SocketAddr,
+    policy: SyncRelayBindRetryPolicy,
+) -> std::io::Result<(TcpListener, usize)> {
+    let mut retries_used = 0;
+    loop {
+        match bind_relay_socket(addr) {
+            Ok(listener) => return Ok((listener, retries_used)),
+            Err(error) => {
+                let Some(retry_delay) = sync_relay_bind_retry_delay(policy, &error, retries_used)
+                else {
+                    return Err(error);
+                };
+
+                retries_used += 1;
+                warn!(
+                    "[Sync] Relay port is still busy on {}. retry_attempt={}/{} retry_delay_ms={}",
+                    addr,
+                    retries_used,
+                    policy.max_retries,
+                    retry_delay.as_millis()
+                );
+                tokio::time::sleep(retry_delay).await;
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Relay network settings
+// ---------------------------------------------------------------------------
+//
+// By default the relay binds every IPv4 interface on `sync_relay_port()`. The
+// user can instead pin it to one address or one interface, accept IPv6 as
+// well, override the port, and only admit clients from listed subnets. The
+// settings live in `relay-network.json`; changing them rebinds the listener
+// without restarting the app.
+
+/// Persisted relay network settings. Every field is optional in the file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct RelayNetworkSettings {
+    /// Bind this address only. Mutually exclusive with `interface`.
+    pub(crate) bind_address: Option<std::net::IpAddr>,
+    /// Bind every usable address of this interface, e.g. `en0`.
+    pub(crate) interface: Option<String>,
+    /// Also accept IPv6. Binding every interface then uses one dual-stack socket.
+    pub(crate) ipv6: bool,
+    /// Overrides `FREED_SYNC_PORT` and the default port.
+    pub(crate) port: Option<u16>,
+    /// CIDR blocks such as `192.168.1.0/24` allowed to connect. Empty admits
+    /// every address.
+    pub(crate) allowed_subnets: Vec<String>,
+    /// Also serve plain `ws://` and HTTP next to TLS. Browsers cannot pin the
+    /// relay's self-signed certificate, so a PWA on the LAN needs this, but
+    /// pairing codes and credentials then cross the network unencrypted.
+    pub(crate) allow_plaintext: bool,
+}
+
+impl RelayNetworkSettings {
+    fn load(data_dir: &Path) -> Self {
+        let Ok(raw) = std::fs::read(data_dir.join(RELAY_NETWORK_FILE)) else {
+            return Self::default();
+        };
+        match serde_json::from_slice::<Self>(&raw)
+            .map_err(|e| e.to_string())
+            .and_then(|settings| settings.validate().map(|()| settings))
+        {
+            Ok(settings) => settings,
+            Err(error) => {
+                warn!("[Sync] Ignoring unusable relay network settings: {}", error);
+                Self::default()
+            }
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn persist(&self, data_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_file_atomically(&data_dir.join(RELAY_NETWORK_FILE), &json)
+            .map_err(|e| format!("failed to persist relay network settings: {}", e))
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.bind_address.is_some() && self.interface.is_some() {
+            return Err("choose a bind address or an interface, not both".to_string());
+        }
+        if self
+            .interface
+            .as_deref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            return Err("interface name is empty".to_string());
+        }
+        if self.port == Some(0) {
+            return Err("port must be between 1 and 65535".to_string());
+        }
+        for subnet in &self.allowed_subnets {
+            RelaySubnet::parse(subnet)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a client at `ip` may connect.
+    fn admits(&self, ip: std::net::IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.allowed_subnets.is_empty()
+            || self
+                .allowed_subnets
+                .iter()
+                .filter_map(|subnet| RelaySubnet::parse(subnet).ok())
+                .any(|subnet| subnet.contains(ip))
+    }
+}
+
+/// One allowlist entry. A bare address is a single-host subnet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RelaySubnet {
+    network: std::net::IpAddr,
+    prefix_len: u8,
+}
+
+impl RelaySubnet {
+    fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let (address, prefix_len) = match raw.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (raw, None),
+        };
+        let network: std::net::IpAddr = address
+            .parse()
+            .map_err(|_| format!("invalid subnet address: {}", raw))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid subnet prefix length: {}", raw))?,
+            None => max_len,
+        };
+        Ok(Self {
+            network: network.to_canonical(),
+            prefix_len,
+        })
+    }
+
+    fn contains(&self, ip: std::net::IpAddr) -> bool {
+        use std::net::IpAddr;
+
+        let mask = |bits: u32, len: u8| -> u128 {
+            if len == 0 {
+                0
+            } else {
+                (u128::MAX << (bits - len as u32)) & (u128::MAX >> (128 - bits))
+            }
+        };
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask(32, self.prefix_len);
+                u32::from(network) as u128 & mask == u32::from(ip) as u128 & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask(128, self.prefix_len);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_ipv6_link_local(ip: &std::net::IpAddr) -> bool {
+    matches!(ip, std::net::IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfe80)
+}
+
+/// Addresses usable for binding and pairing: no loopback, no IPv6 link-local
+/// addresses (they need a scope id), and IPv6 only when enabled.
+pub(crate) fn relay_interface_addresses(
+    settings: &RelayNetworkSettings,
+    interfaces: &[(String, std::net::IpAddr)],
+) -> Vec<(String, std::net::IpAddr)> {
+    interfaces
+        .iter()
+        .filter(|(name, ip)| {
+            settings
+                .interface
+                .as_ref()
+                .is_none_or(|interface| interface == name)
+                && !ip.is_loopback()
+                && !is_ipv6_link_local(ip)
+                && (ip.is_ipv4() || settings.ipv6)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Socket addresses the relay listens on for `settings`.
+fn relay_bind_addresses(
+    settings: &RelayNetworkSettings,
+    port: u16,
+    interfaces: &[(String, std::net::IpAddr)],
+) -> Result<Vec<SocketAddr>, String> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    if let Some(interface) = &settings.interface {
+        let addresses: Vec<SocketAddr> = relay_interface_addresses(settings, interfaces)
+            .into_iter()
+            .map(|(_, ip)| SocketAddr::new(ip, port))
+            .collect();
+        if addresses.is_empty() {
+            return Err(format!("interface {} has no usable address", interface));
+        }
+        return Ok(addresses);
+    }
+    let ip = match settings.bind_address {
+        Some(ip) => ip,
+        None if settings.ipv6 => Ipv6Addr::UNSPECIFIED.into(),
+        None => Ipv4Addr::UNSPECIFIED.into(),
+    };
+    Ok(vec![SocketAddr::new(ip, port)])
+}
+
+/// Tunnel interfaces (VPNs, overlay networks) rarely reach a phone on the LAN.
+fn looks_like_tunnel_interface(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [
+        "utun",
+        "tun",
+        "tap",
+        "wg",
+        "tailscale",
+        "zt",
+        "ppp",
+        "ipsec",
+    ]
+    .iter()
+    .any(|prefix| name.starts_with(prefix))
+}
+
+/// Hosts a phone can try, most likely to work first: private IPv4 on a
+/// physical interface, then other IPv4, then IPv6, with tunnels last in each.
+fn relay_candidate_hosts(
+    settings: &RelayNetworkSettings,
+    interfaces: &[(String, std::net::IpAddr)],
+) -> Vec<String> {
+    let mut addresses = match settings.bind_address {
+        Some(ip) if !ip.is_unspecified() => vec![(String::new(), ip)],
+        _ => relay_interface_addresses(settings, interfaces),
+    };
+    addresses.sort_by_key(|(name, ip)| {
+        let private = matches!(ip, std::net::IpAddr::V4(v4) if v4.is_private());
+        (ip.is_ipv6(), looks_like_tunnel_interface(name), !private)
+    });
+    let mut hosts: Vec<String> = Vec::new();
+    for (_, ip) in addresses {
+        let host = match ip {
+            std::net::IpAddr::V4(v4) => v4.to_string(),
+            std::net::IpAddr::V6(v6) => format!("[{}]", v6),
+        };
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    hosts
+}
+
+/// Candidate hosts for this machine's current interfaces.
+pub(crate) fn local_relay_candidate_hosts(settings: &RelayNetworkSettings) -> Vec<String> {
+    let interfaces = local_ip_address::list_afinet_netifas().unwrap_or_default();
+    relay_candidate_hosts(settings, &interfaces)
+}
+
+/// Binds one listener. A wildcard IPv6 address is bound dual-stack so it also
+/// accepts IPv4 clients on every platform.
+fn bind_relay_socket(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!addr.ip().is_unspecified())?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+// ---------------------------------------------------------------------------
+// Relay abuse limits
+// ---------------------------------------------------------------------------
+//
+// The relay can listen on every interface of a laptop that moves between
+// public networks, so it limits what any peer can cost it. Repeated failed
+// upgrades from one address lock that address out with exponential backoff.
+// A WebSocket message is capped at `max_frame_bytes`, and at most
+// `max_connections` sockets are open at once. Each limit records a `relay_*`
+// runtime-health event when it trips.
+
+/// Limits the relay enforces against misbehaving peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RelayAbusePolicy {
+    /// Largest WebSocket message, and frame, read from a peer.
+    max_frame_bytes: usize,
+    /// Open sockets, counted from TCP accept until close.
+    pub(crate) max_connections: usize,
+    /// Failed upgrades an address may make before it is locked out.
+    auth_failures_before_lockout: u32,
+    /// First lockout. Each further failure doubles it, up to `auth_lockout_max`.
+    auth_lockout_base: Duration,
+    auth_lockout_max: Duration,
+}
+
+pub(crate) const DEFAULT_RELAY_ABUSE_POLICY: RelayAbusePolicy = RelayAbusePolicy {
+    max_frame_bytes: 64 * 1024 * 1024,
+    max_connections: 64,
+    auth_failures_before_lockout: 5,
+    auth_lockout_base: Duration::from_secs(30),
+    auth_lockout_max: Duration::from_secs(60 * 60),
+};
+
+/// Addresses with failed upgrades tracked at once. The stalest is evicted
+/// first, so a flood of spoofed sources cannot grow the table.
+const RELAY_AUTH_TRACKED_ADDRESSES: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct RelayAuthFailures {
+    count: u32,
+    last_failure_ms: u64,
+    locked_until_ms: u64,
+}
+
+/// Failed-upgrade counters per client address.
+#[derive(Debug, Default)]
+pub(crate) struct RelayAuthLimiter {
+    failures: HashMap<std::net::IpAddr, RelayAuthFailures>,
+}
+
+impl RelayAuthLimiter {
+    /// When `ip` may try again, if it is locked out at `now_ms`.
+    fn locked_until(&self, ip: std::net::IpAddr, now_ms: u64) -> Option<u64> {
+        self.failures
+            .get(&ip.to_canonical())
+            .map(|failures| failures.locked_until_ms)
+            .filter(|until| *until > now_ms)
+    }
+
+    /// Counts a failed upgrade from `ip`. Returns the lockout it starts, if any.
+    /// Failures are forgotten after a quiet `auth_lockout_max`.
+    fn note_failure(
+        &mut self,
+        ip: std::net::IpAddr,
+        now_ms: u64,
+        policy: &RelayAbusePolicy,
+    ) -> Option<Duration> {
+        let ip = ip.to_canonical();
+        if !self.failures.contains_key(&ip) && self.failures.len() >= RELAY_AUTH_TRACKED_ADDRESSES {
+            let stalest = self
+                .failures
+                .iter()
+                .min_by_key(|(_, failures)| failures.last_failure_ms)
+                .map(|(ip, _)| *ip);
+            if let Some(stalest) = stalest {
+                self.failures.remove(&stalest);
+            }
+        }
+        let failures = self.failures.entry(ip).or_default();
+        if now_ms.saturating_sub(failures.last_failure_ms)
+            > policy.auth_lockout_max.as_millis() as u64
+        {
+            *failures = RelayAuthFailures::default();
+        }
+        failures.count += 1;
+        failures.last_failure_ms = now_ms;
+        let excess = failures
+            .count
+            .checked_sub(policy.auth_failures_before_lockout)?;
+        let lockout = policy
+            .auth_lockout_base
+            .saturating_mul(1u32.checked_shl(excess).unwrap_or(u32::MAX))
+            .min(policy.auth_lockout_max);
+        failures.locked_until_ms = now_ms + lockout.as_millis() as u64;
+        Some(lockout)
+    }
+
+    fn note_success(&mut self, ip: std::net::IpAddr) {
+        self.failures.remove(&ip.to_canonical());
+    }
+}
+
+/// Feeds one upgrade or HTTP auth result into the per-address lockout.
+fn note_relay_auth_attempt(
+    state: &SyncRelayState,
+    events: &dyn RelayEventSink,
+    addr: SocketAddr,
+    authorized: bool,
+    now_ms: u64,
+) {
+    let mut limiter = state.auth_limiter.lock().unwrap();
+    if authorized {
+        limiter.note_success(addr.ip());
+        return;
+    }
+    if let Some(lockout) = limiter.note_failure(addr.ip(), now_ms, &state.abuse_policy) {
+        warn!(
+            "[Sync] Locked out {} for {}s after repeated failed pairing attempts",
+            addr.ip(),
+            lockout.as_secs()
+        );
+        events.record_health(serde_json::json!({
+            "event": "relay_auth_lockout",
+            "peer": addr.ip().to_string(),
+            "lockoutMs": lockout.as_millis() as u64,
+        }));
+    }
+}
+
+/// WebSocket settings for every relay connection, inbound or to a peer.
+pub(crate) fn relay_websocket_config(policy: &RelayAbusePolicy) -> WebSocketConfig {
+    WebSocketConfig::default()
+        .max_message_size(Some(policy.max_frame_bytes))
+        .max_frame_size(Some(policy.max_frame_bytes))
+}
+
+/// Replaces `relay-doc.bin` via a synced temp file so a crash never leaves it
+/// torn. Layout:
+///
+///   [0..4]       RELAY_DOC_MAGIC
+///   [4..12]      document length (u64, big endian)
+///   [12..44]     SHA-256 over the heads and document
+///   [44..46]     head count N (u16, big endian)
+///   [46..46+32N] heads
+///   [46+32N..]   document
+pub(crate) fn write_relay_doc(
+    data_dir: &Path,
+    doc: &[u8],
+    heads: &[RelayHead],
+) -> Result<(), String> {
+    use std::io::Write;
+
+    let head_count =
+        u16::try_from(heads.len()).map_err(|_| "too many relay heads to persist".to_string())?;
+    let mut digest = Sha256::new();
+    for head in heads {
+        digest.update(head);
+    }
+    digest.update(doc);
+
+    let mut header = Vec::with_capacity(46 + heads.len() * RELAY_HEAD_BYTES);
+    header.extend_from_slice(&RELAY_DOC_MAGIC);
+    header.extend_from_slice(&(doc.len() as u64).to_be_bytes());
+    header.extend_from_slice(&digest.finalize());
+    header.extend_from_slice(&head_count.to_be_bytes());
+    for head in heads {
+        header.extend_from_slice(head);
+    }
+
+    let path = data_dir.join(RELAY_DOC_FILE);
+    let tmp_path = data_dir.join(format!("{RELAY_DOC_FILE}.tmp"));
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&header)?;
+        file.write_all(doc)?;
+        file.sync_all()
+    };
+    write().map_err(|e| format!("failed to write relay doc: {}", e))?;
+    std::fs::rename(&tmp_path, &path).map_err(|e| format!("failed to replace relay doc: {}", e))
+}
+
+pub(crate) fn decode_relay_doc_file(bytes: &[u8]) -> Result<(Vec<u8>, Vec<RelayHead>), String> {
+    if bytes.len() < 46 || bytes[..4] != RELAY_DOC_MAGIC {
+        return Err("relay doc header is missing".to_string());
+    }
+    let doc_len = u64::from_be_bytes(bytes[4..12].try_into().unwrap());
+    let expected_digest = &bytes[12..44];
+    let head_count = u16::from_be_bytes([bytes[44], bytes[45]]) as usize;
+    let doc_start = 46 + head_count * RELAY_HEAD_BYTES;
+    if bytes.len() < doc_start || (bytes.len() - doc_start) as u64 != doc_len {
+        return Err("relay doc length does not match its header".to_string());
+    }
+    if Sha256::digest(&bytes[46..]).as_slice() != expected_digest {
+        return Err("relay doc failed its integrity check".to_string());
+    }
+    let heads = bytes[46..doc_start]
+        .chunks_exact(RELAY_HEAD_BYTES)
+        .map(|head| head.try_into().unwrap())
+        .collect();
+    Ok((bytes[doc_start..].to_vec(), heads))
+}
+
+fn load_relay_doc(data_dir: &Path) -> Option<(Vec<u8>, Vec<RelayHead>)> {
+    let bytes = std::fs::read(data_dir.join(RELAY_DOC_FILE)).ok()?;
+    match decode_relay_doc_file(&bytes) {
+        Ok((doc, heads)) if !doc.is_empty() => Some((doc, heads)),
+        Ok(_) => None,
+        Err(error) => {
+            warn!("[Sync] Ignoring persisted relay doc: {}", error);
+            None
+        }
+    }
+}
+
+/// Persists the held document and heads. Runs on a blocking thread; the epoch
+/// guard keeps a factory reset from deleting the file underneath the write,
+/// and a document the reset already cleared is not written back.
+pub(crate) fn persist_held_relay_doc(state: &SyncRelayState, data_dir: &Path) {
+    let _epoch = state.epoch_gate.blocking_read();
+    let current_doc = state.current_doc.blocking_read();
+    let Some(doc) = current_doc.as_ref() else {
+        return;
+    };
+    let heads = state.current_heads.read().unwrap().clone();
+    if let Err(error) = write_relay_doc(data_dir, doc, &heads) {
+        error!("[Sync] {}", error);
+    }
+}
+
+/// Loads the persisted document into the relay before it accepts connections,
+/// so a phone that connects first receives it instead of pushing a stale copy.
+pub(crate) async fn restore_held_relay_doc(state: &SyncRelayState, data_dir: &Path) {
+    let Some((doc, heads)) = load_relay_doc(data_dir) else {
+        return;
+    };
+    if !relay_payload_is_acceptable(state, &doc) {
+        warn!("[Sync] Ignoring persisted relay doc sealed under another sync key");
+        return;
+    }
+    let mut current_doc = state.current_doc.write().await;
+    if current_doc.is_some() {
+        // The renderer already broadcast a newer document.
+        return;
+    }
+    info!("[Sync] Loaded held document ({} bytes)", doc.len());
+    *current_doc = Some(Bytes::from(doc));
+    *state.current_heads.write().unwrap() = Arc::new(heads);
+}
+
+// ---------------------------------------------------------------------------
+// Relay state
+// ---------------------------------------------------------------------------
+
+pub(crate) type RelayHead = [u8; RELAY_HEAD_BYTES];
+
+#[derive(Clone)]
+pub(crate) struct RelayTls {
+    pub(crate) acceptor: tokio_rustls::TlsAcceptor,
+    /// base64url SHA-256 of the certificate DER, published as `fp=`.
+    pub(crate) fingerprint: String,
+}
+
+/// One accepted relay mutation, fanned out to every live connection.
+///
+/// `frame` is the v1 wire encoding, built once per update, and the payload is
+/// a slice of it, so each connection sends the shared buffer without copying.
+#[derive(Clone, Debug)]
+pub(crate) enum RelayUpdate {
+    /// A complete saved document that replaces the held bytes.
+    FullDoc {
+        frame: Bytes,
+        doc: Bytes,
+        heads: Arc<Vec<RelayHead>>,
+    },
+    /// Automerge change chunks appended to the held document.
+    Changes {
+        frame: Bytes,
+        changes: Bytes,
+        heads: Arc<Vec<RelayHead>>,
+    },
+}
+
+impl RelayUpdate {
+    pub(crate) fn full_doc(doc: &[u8], heads: Arc<Vec<RelayHead>>) -> Self {
+        let frame = Bytes::from(encode_relay_frame(RelayFrameKind::FullDoc, &heads, doc));
+        let doc = frame.slice(frame.len() - doc.len()..);
+        RelayUpdate::FullDoc { frame, doc, heads }
+    }
+
+    pub(crate) fn changes(changes: &[u8], heads: Arc<Vec<RelayHead>>) -> Self {
+        let frame = Bytes::from(encode_relay_frame(RelayFrameKind::Changes, &heads, changes));
+        let changes = frame.slice(frame.len() - changes.len()..);
+        RelayUpdate::Changes {
+            frame,
+            changes,
+            heads,
+        }
+    }
+
+    /// Wraps a frame a client sent without copying its payload.
+    fn from_client_frame(data: &Bytes, frame: RelayFrame<'_>) -> Option<Self> {
+        let payload = data.slice(data.len() - frame.payload.len()..);
+        let heads = Arc::new(frame.heads);
+        match frame.kind {
+            RelayFrameKind::FullDoc => Some(RelayUpdate::FullDoc {
+                frame: data.clone(),
+                doc: payload,
+                heads,
+            }),
+            RelayFrameKind::Changes => Some(RelayUpdate::Changes {
+                frame: data.clone(),
+                changes: payload,
+                heads,
+            }),
+            RelayFrameKind::FullDocRequest => None,
+        }
+    }
+
+    pub(crate) fn payload(&self) -> &Bytes {
+        match self {
+            RelayUpdate::FullDoc { doc, .. } => doc,
+            RelayUpdate::Changes { changes, .. } => changes,
+        }
+    }
+
+    pub(crate) fn frame(&self) -> &Bytes {
+        match self {
+            RelayUpdate::FullDoc { frame, .. } | RelayUpdate::Changes { frame, .. } => frame,
+        }
+    }
+
+    pub(crate) fn heads(&self) -> &Arc<Vec<RelayHead>> {
+        match self {
+            RelayUpdate::FullDoc { heads, .. } | RelayUpdate::Changes { heads, .. } => heads,
+        }
+    }
+}
+
+pub(crate) struct SyncRelayState {
+    pub(crate) port: u16,
+    /// Serializes token snapshots and document exchange against relay reset.
+    pub(crate) epoch_gate: RwLock<()>,
+    /// Broadcast channel — sends doc updates to all connected clients.
+    pub(crate) broadcast_tx: broadcast::Sender<RelayUpdate>,
+    /// Latest doc binary, served to new joiners immediately on connect.
+    pub(crate) current_doc: RwLock<Option<Bytes>>,
+    /// Heads the last sender reported for `current_doc`; empty when unknown.
+    /// Only written while the `current_doc` write guard is held.
+    pub(crate) current_heads: StdRwLock<Arc<Vec<RelayHead>>>,
+    /// Latest accepted doc, consumed by the debounced snapshot writer.
+    pub(crate) snapshot_tx: tokio::sync::watch::Sender<Option<Bytes>>,
+    /// How many snapshots the writer keeps and how much disk they may use.
+    #[cfg(feature = "desktop")]
+    pub(crate) snapshot_retention_tx: tokio::sync::watch::Sender<SnapshotRetention>,
+    #[cfg(feature = "desktop")]
+    pub(crate) backup_schedule_tx: tokio::sync::watch::Sender<BackupSchedule>,
+    /// Closes live connections after a factory reset or device revocation.
+    pub(crate) disconnect_tx: broadcast::Sender<RelayDisconnect>,
+    /// Incremented before factory-reset relay state is cleared.
+    pub(crate) generation: std::sync::atomic::AtomicU64,
+    /// Blocks renderer and mobile writes until local document deletion completes.
+    pub(crate) accepting_doc_updates: std::sync::atomic::AtomicBool,
+    /// Live connection count (displayed in tray / sync indicator).
+    pub(crate) client_count: RwLock<usize>,
+    /// Mirrors `client_count` for connections that asked for control messages.
+    pub(crate) client_count_tx: tokio::sync::watch::Sender<usize>,
+    /// Pairing token — must appear as `?t=<token>` in the WS upgrade URI.
+    ///
+    /// Uses `std::sync::RwLock` (not Tokio's) because it is never held
+    /// across an `.await` point; it is read/written synchronously and the
+    /// guard is dropped before any async work begins.
+    pub(crate) pairing_token: StdRwLock<String>,
+    /// Certificate for TLS connections. Plain `ws://` is served on the same
+    /// port either way; `None` only happens when no certificate could be
+    /// loaded or created.
+    pub(crate) tls: StdRwLock<Option<RelayTls>>,
+    /// Devices holding their own credential, and unredeemed pairing codes.
+    pub(crate) devices: StdMutex<RelayDeviceRegistry>,
+    /// Seals renderer payloads and opens held bytes for local snapshots.
+    /// `None` relays plaintext, which only happens when no sync key could be
+    /// loaded or created.
+    pub(crate) payload_key: StdRwLock<Option<Arc<RelayPayloadKey>>>,
+    /// Other desktops' relays: trusted, discovered over mDNS, or both.
+    #[cfg(feature = "desktop")]
+    pub(crate) peers: StdMutex<SyncPeerRegistry>,
+    /// Bind address, port override, and subnet allowlist. The relay rebinds
+    /// whenever a new value is sent.
+    pub(crate) network_tx: tokio::sync::watch::Sender<RelayNetworkSettings>,
+    /// Frame, connection, and failed-auth limits.
+    pub(crate) abuse_policy: RelayAbusePolicy,
+    /// One permit per open socket, `abuse_policy.max_connections` in total.
+    pub(crate) connection_slots: Arc<tokio::sync::Semaphore>,
+    /// Failed upgrades per client address.
+    pub(crate) auth_limiter: StdMutex<RelayAuthLimiter>,
+    /// Live WebSocket connections for `get_sync_clients`.
+    pub(crate) clients: StdMutex<RelayClientRegistry>,
+    /// Rendezvous server the relay tunnels through. The tunnel reconnects
+    /// whenever a new value is sent.
+    pub(crate) rendezvous_tx: tokio::sync::watch::Sender<RelayRendezvousSettings>,
+    /// Whether the rendezvous tunnel is open.
+    pub(crate) rendezvous_connected: std::sync::atomic::AtomicBool,
+}
+
+impl SyncRelayState {
+    /// Empty relay state. Credentials are loaded from disk with
+    /// `load_relay_credentials` before the relay starts accepting connections.
+    pub(crate) fn new(port: u16) -> Self {
+        Self::with_abuse_policy(port, DEFAULT_RELAY_ABUSE_POLICY)
+    }
+
+    fn with_abuse_policy(port: u16, abuse_policy: RelayAbusePolicy) -> Self {
+        let (broadcast_tx, _) = broadcast::channel::<RelayUpdate>(16);
+        let (disconnect_tx, _) = broadcast::channel::<RelayDisconnect>(16);
+        let (snapshot_tx, _) = tokio::sync::watch::channel::<Option<Bytes>>(None);
+        Self {
+            port,
+            epoch_gate: RwLock::new(()),
+            broadcast_tx,
+            current_doc: RwLock::new(None),
+            current_heads: StdRwLock::new(Arc::default()),
+            snapshot_tx,
+            #[cfg(feature = "desktop")]
+            snapshot_retention_tx: tokio::sync::watch::channel(SnapshotRetention::default()).0,
+            #[cfg(feature = "desktop")]
+            backup_schedule_tx: tokio::sync::watch::channel(BackupSchedule::default()).0,
+            disconnect_tx,
+            generation: std::sync::atomic::AtomicU64::new(0),
+            accepting_doc_updates: std::sync::atomic::AtomicBool::new(true),
+            client_count: RwLock::new(0),
+            client_count_tx: tokio::sync::watch::channel(0).0,
+            pairing_token: StdRwLock::new(String::new()),
+            tls: StdRwLock::new(None),
+            devices: StdMutex::new(RelayDeviceRegistry::default()),
+            payload_key: StdRwLock::new(None),
+            #[cfg(feature = "desktop")]
+            peers: StdMutex::new(SyncPeerRegistry::default()),
+            network_tx: tokio::sync::watch::channel(RelayNetworkSettings::default()).0,
+            abuse_policy,
+            connection_slots: Arc::new(tokio::sync::Semaphore::new(abuse_policy.max_connections)),
+            auth_limiter: StdMutex::new(RelayAuthLimiter::default()),
+            clients: StdMutex::new(RelayClientRegistry::default()),
+            rendezvous_tx: tokio::sync::watch::channel(RelayRendezvousSettings::default()).0,
+            rendezvous_connected: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    /// The configured port override, else `port`.
+    pub(crate) fn listen_port(&self) -> u16 {
+        self.network_tx.borrow().port.unwrap_or(self.port)
+    }
+
+    /// `wss` once the relay has a certificate, unless plaintext is allowed.
+    pub(crate) fn pairing_scheme(&self) -> &'static str {
+        if self.tls.read().unwrap().is_some() && !self.network_tx.borrow().allow_plaintext {
+            "wss"
+        } else {
+            "ws"
+        }
+    }
+}
+
+pub(crate) type RelayState = Arc<SyncRelayState>;
+
+/// Receives relay state changes. The desktop forwards them to the webview;
+/// the headless `freed-relay` binary logs them.
+pub(crate) trait RelayEventSink: Send + Sync {
+    fn client_count_changed(&self, count: usize);
+    /// Records a runtime-health event such as `relay_rejected_payload`.
+    fn record_health(&self, event: serde_json::Value);
+    /// Publishes the peer list after discovery, trust, or link status changes.
+    #[cfg(feature = "desktop")]
+    fn sync_peers_changed(&self, peers: &[SyncPeerSummary]);
+    /// A trusted peer's document was merged into the held document.
+    #[cfg(feature = "desktop")]
+    fn sync_peer_doc_merged(&self, fingerprint: &str);
+    /// The held document can no longer be opened or loaded and needs to be
+    /// replaced before pushes can merge into it again.
+    fn held_doc_unreadable(&self);
+}
+
+pub(crate) type RelayEvents = Arc<dyn RelayEventSink>;
+
+/// Why live connections must close. Only desktop commands send these; the
+/// headless relay never closes a connection on its own.
+#[cfg_attr(not(feature = "desktop"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum RelayDisconnect {
+    /// Factory reset moved the relay to this generation; every older connection closes.
+    FactoryReset(u64),
+    /// A paired device was revoked; only its connections close.
+    Device(String),
+    /// Support closed one connection, by `RelayClientRegistry` id.
+    Client(u64),
+    /// The shared pairing token rotated; connections that used it close.
+    SharedToken,
+}
+
+/// How a connection proved it may use the relay.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum RelayClientAuth {
+    /// Legacy holder of the shared `pairing-token`.
+    SharedToken,
+    /// A paired device, by id.
+    Device(String),
+}
+
+/// One live relay connection, as reported by `get_sync_clients`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SyncClientSummary {
+    /// Connection id for `disconnect_sync_client`; unique until restart.
+    pub(crate) id: u64,
+    pub(crate) address: String,
+    /// Paired device the connection authenticated as, if any.
+    pub(crate) device_id: Option<String>,
+    device_name: Option<String>,
+    /// `clientId` from the client's hello.
+    client_id: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    connected_at_ms: u64,
+    pub(crate) last_message_at_ms: Option<u64>,
+    pub(crate) bytes_received: u64,
+    pub(crate) bytes_sent: u64,
+    /// Updates the client sent that the relay stored or already held.
+    pub(crate) docs_pushed: u64,
+    /// Documents and updates the relay sent the client.
+    pub(crate) docs_pulled: u64,
+}
+
+/// Live WebSocket connections, in connection order.
+#[derive(Debug, Default)]
+pub(crate) struct RelayClientRegistry {
+    next_id: u64,
+    clients: std::collections::BTreeMap<u64, SyncClientSummary>,
+}
+
+impl RelayClientRegistry {
+    fn open(
+        &mut self,
+        addr: SocketAddr,
+        device: Option<(String, String)>,
+        user_agent: Option<String>,
+        now_ms: u64,
+    ) -> u64 {
+        self.next_id += 1;
+        let (device_id, device_name) = device.unzip();
+        self.clients.insert(
+            self.next_id,
+            SyncClientSummary {
+                id: self.next_id,
+                address: addr.to_string(),
+                device_id,
+                device_name,
+                client_id: None,
+                user_agent,
+                connected_at_ms: now_ms,
+                last_message_at_ms: None,
+                bytes_received: 0,
+                bytes_sent: 0,
+                docs_pushed: 0,
+                docs_pulled: 0,
+            },
+        );
+        self.next_id
+    }
+
+    fn update(&mut self, id: u64, update: impl FnOnce(&mut SyncClientSummary)) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            update(client);
+        }
+    }
+
+    fn close(&mut self, id: u64) {
+        self.clients.remove(&id);
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn contains(&self, id: u64) -> bool {
+        self.clients.contains_key(&id)
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn summaries(&self) -> Vec<SyncClientSummary> {
+        self.clients.values().cloned().collect()
+    }
+}
+
+/// Pairing URL for the first host, listing the rest as `a=` alternates a
+/// phone tries when the first does not answer.
+///
+/// LAN URLs are `wss://` unless plaintext is allowed: a browser cannot pin a
+/// self-signed certificate, so a PWA needs `ws://` on the shared port. `fp`
+/// is included either way, and clients that can pin it (`freed-sync`, peer
+/// relays) use TLS on the same port.
+pub(crate) fn sync_pairing_url<H: AsRef<str>>(
+    state: &SyncRelayState,
+    hosts: &[H],
+    pairing_code: &str,
+) -> String {
+    let host = hosts.first().map_or("localhost", |host| host.as_ref());
+    let port = state.listen_port();
+    let mut url = format!(
+        "{}://{}:{}?pc={}",
+        state.pairing_scheme(),
+        host,
+        port,
+        pairing_code
+    );
+    if let Some(tls) = state.tls.read().unwrap().as_ref() {
+        url.push_str("&fp=");
+        url.push_str(&tls.fingerprint);
+    }
+    if hosts.len() > 1 {
+        let alternates: Vec<&str> = hosts[1..].iter().map(|host| host.as_ref()).collect();
+        url.push_str("&a=");
+        url.push_str(&alternates.join(","));
+    }
+    if let Some(key) = state.payload_key.read().unwrap().as_ref() {
+        url.push_str("#k=");
+        url.push_str(&key.secret_param());
+    }
+    url
+}
+
+// ---------------------------------------------------------------------------
+// WebSocket relay
+// ---------------------------------------------------------------------------
+
+pub(crate) fn relay_connection_can_exchange_docs(
+    state: &RelayState,
+    connection_generation: u64,
+) -> bool {
+    state
+        .accepting_doc_updates
+        .load(std::sync::atomic::Ordering::SeqCst)
+        && connection_generation == state.generation.load(std::sync::atomic::Ordering::SeqCst)
+}
+
+/// Seals a renderer or snapshot payload before the relay holds it. Callers
+/// hold the epoch read guard so the key cannot rotate in between.
+#[cfg(feature = "desktop")]
+pub(crate) fn seal_relay_update(
+    state: &SyncRelayState,
+    update: RelayUpdate,
+) -> Result<RelayUpdate, String> {
+    let Some(key) = state.payload_key.read().unwrap().clone() else {
+        return Ok(update);
+    };
+    Ok(match update {
+        RelayUpdate::FullDoc { doc, heads, .. } => RelayUpdate::full_doc(&key.seal(&doc)?, heads),
+        RelayUpdate::Changes { changes, heads, .. } => {
+            RelayUpdate::changes(&key.seal(&changes)?, heads)
+        }
+    })
+}
+
+/// Opens relay-held bytes for local use. Without a sync key they are plaintext.
+#[cfg(feature = "desktop")]
+pub(crate) fn open_relay_payload(state: &SyncRelayState, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    match state.payload_key.read().unwrap().clone() {
+        Some(key) => key.open(bytes),
+        None => Ok(bytes.to_vec()),
+    }
+}
+
+/// With a sync key, the relay only holds client payloads sealed under it, so
+/// a plaintext push or one from a device paired before a key rotation cannot
+/// reach other devices.
+pub(crate) fn relay_payload_is_acceptable(state: &SyncRelayState, payload: &[u8]) -> bool {
+    match state.payload_key.read().unwrap().as_ref() {
+        Some(key) => sealed_payload_key_id(payload).is_ok_and(|id| id == key.key_id),
+        None => true,
+    }
+}
+
+/// Re-seals the held document under `key` and makes it current. Callers hold
+/// the epoch write guard.
+#[cfg(feature = "desktop")]
+pub(crate) async fn rotate_relay_payload_key(state: &SyncRelayState, key: RelayPayloadKey) {
+    let mut current_doc = state.current_doc.write().await;
+    if let Some(doc) = current_doc.clone() {
+        match open_relay_payload(state, &doc).and_then(|plaintext| key.seal(&plaintext)) {
+            Ok(resealed) => {
+                let resealed = Bytes::from(resealed);
+                *current_doc = Some(resealed.clone());
+                state.snapshot_tx.send_replace(Some(resealed));
+            }
+            Err(error) => {
+                warn!("[Sync] Dropping held document on key rotation: {}", error);
+                *current_doc = None;
+                *state.current_heads.write().unwrap() = Arc::default();
+            }
+        }
+    }
+    *state.payload_key.write().unwrap() = Some(Arc::new(key));
+}
+
+pub(crate) fn relay_query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?.split('&').find_map(|pair| {
+        let mut fields = pair.splitn(2, '=');
+        (fields.next() == Some(key))
+            .then(|| fields.next())
+            .flatten()
+    })
+}
+
+pub(crate) fn relay_request_token_matches(query: Option<&str>, expected_token: &str) -> bool {
+    relay_query_param(query, "t")
+        .map(|token| token == expected_token)
+        .unwrap_or(false)
+}
+
+/// Authorizes an upgrade request. A device id (`d=`) must come with that
+/// device's credential in `t=`; a pairing code (`pc=`) is consumed and registers
+/// the device named by `n=`; otherwise `t=` must be the shared token.
+pub(crate) fn authorize_relay_request(
+    query: Option<&str>,
+    expected_token: &str,
+    devices: &mut RelayDeviceRegistry,
+    now_ms: u64,
+) -> Option<RelayClientAuth> {
+    if let Some(device_id) = relay_query_param(query, "d") {
+        let credential = relay_query_param(query, "t")?;
+        return devices
+            .verify_device(device_id, credential)
+            .then(|| RelayClientAuth::Device(device_id.to_string()));
+    }
+    if let Some(code) = relay_query_param(query, "pc") {
+        let name = url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .find_map(|(key, value)| (key == "n").then(|| value.trim().to_string()))
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "Unnamed device".to_string());
+        return devices
+            .redeem_pairing_code(code, &name, now_ms)
+            .map(RelayClientAuth::Device);
+    }
+    relay_request_token_matches(query, expected_token).then_some(RelayClientAuth::SharedToken)
+}
+
+// Framed relay messages (protocol v1)
+//
+// Legacy clients exchange bare Automerge documents. Clients that connect with
+// `?v=1` exchange framed messages instead:
+//
+//   [0]          RELAY_PROTOCOL_VERSION
+//   [1]          RelayFrameKind
+//   [2..4]       head count N (u16, big endian)
+//   [4..4+32N]   change hashes the frame leaves the sender at
+//   [4+32N..]    payload
+//
+// `Changes` payloads are Automerge change chunks. Automerge loads a document
+// chunk followed by change chunks, so the relay keeps `current_doc` loadable by
+// appending each payload and new joiners still get a single full document.
+// A client may also pass its heads as `?h=<base64url>` (concatenated 32-byte
+// hashes) to skip the initial download when nothing changed.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RelayFrameKind {
+    FullDoc = 0,
+    Changes = 1,
+    /// Client asks for the held document, e.g. after missing a change.
+    FullDocRequest = 2,
+}
+
+impl RelayFrameKind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(RelayFrameKind::FullDoc),
+            1 => Some(RelayFrameKind::Changes),
+            2 => Some(RelayFrameKind::FullDocRequest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct RelayFrame<'a> {
+    kind: RelayFrameKind,
+    heads: Vec<RelayHead>,
+    payload: &'a [u8],
+}
+
+fn encode_relay_frame(kind: RelayFrameKind, heads: &[RelayHead], payload: &[u8]) -> Vec<u8> {
+    let head_count = heads.len().min(u16::MAX as usize);
+    let mut frame = Vec::with_capacity(4 + head_count * RELAY_HEAD_BYTES + payload.len());
+    frame.push(RELAY_PROTOCOL_VERSION);
+    frame.push(kind as u8);
+    frame.extend_from_slice(&(head_count as u16).to_be_bytes());
+    for head in &heads[..head_count] {
+        frame.extend_from_slice(head);
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn decode_relay_frame(bytes: &[u8]) -> Result<RelayFrame<'_>, String> {
+    let [version, kind, count_hi, count_lo, rest @ ..] = bytes else {
+        return Err(format!("frame too short ({} bytes)", bytes.len()));
+    };
+    if *version != RELAY_PROTOCOL_VERSION {
+        return Err(format!("unsupported relay protocol version {}", version));
+    }
+    let kind =
+        RelayFrameKind::from_byte(*kind).ok_or_else(|| format!("unknown frame kind {}", kind))?;
+    let head_count = u16::from_be_bytes([*count_hi, *count_lo]) as usize;
+    let heads_len = head_count * RELAY_HEAD_BYTES;
+    if rest.len() < heads_len {
+        return Err(format!(
+            "frame declares {} heads but carries {} bytes",
+            head_count,
+            rest.len()
+        ));
+    }
+    let (head_bytes, payload) = rest.split_at(heads_len);
+    let heads = head_bytes
+        .chunks_exact(RELAY_HEAD_BYTES)
+        .map(|chunk| {
+            let mut head = [0u8; RELAY_HEAD_BYTES];
+            head.copy_from_slice(chunk);
+            head
+        })
+        .collect();
+    Ok(RelayFrame {
+        kind,
+        heads,
+        payload,
+    })
+}
+
+/// Parses the hex change hashes Automerge JS returns from `getHeads()`.
+pub(crate) fn parse_relay_heads_hex(heads: &[String]) -> Result<Vec<RelayHead>, String> {
+    heads
+        .iter()
+        .map(|hex| {
+            let hex = hex.as_bytes();
+            if hex.len() != RELAY_HEAD_BYTES * 2 {
+                return Err(format!("invalid head length {}", hex.len()));
+            }
+            let mut head = [0u8; RELAY_HEAD_BYTES];
+            for (byte, pair) in head.iter_mut().zip(hex.chunks_exact(2)) {
+                let pair = std::str::from_utf8(pair).map_err(|e| e.to_string())?;
+                *byte = u8::from_str_radix(pair, 16)
+                    .map_err(|_| format!("invalid head hex {:?}", pair))?;
+            }
+            Ok(head)
+        })
+        .collect()
+}
+
+fn decode_relay_heads_param(raw: &str) -> Option<Vec<RelayHead>> {
+    let bytes = URL_SAFE_NO_PAD.decode(raw).ok()?;
+    if bytes.len() % RELAY_HEAD_BYTES != 0 {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(RELAY_HEAD_BYTES)
+            .map(|chunk| {
+                let mut head = [0u8; RELAY_HEAD_BYTES];
+                head.copy_from_slice(chunk);
+                head
+            })
+            .collect(),
+    )
+}
+
+pub(crate) fn relay_heads_match(held: &[RelayHead], client: &[RelayHead]) -> bool {
+    if held.is_empty() || held.len() != client.len() {
+        return false;
+    }
+    let mut held = held.to_vec();
+    let mut client = client.to_vec();
+    held.sort_unstable();
+    client.sort_unstable();
+    held == client
+}
+
+/// Encodes the held document for one connection: framed for v1 clients, the
+/// bare Automerge bytes for legacy clients.
+fn relay_full_doc_message(framed: bool, doc: &Bytes, heads: &[RelayHead]) -> Message {
+    if framed {
+        Message::Binary(encode_relay_frame(RelayFrameKind::FullDoc, heads, doc).into())
+    } else {
+        Message::Binary(doc.clone())
+    }
+}
+
+/// Folds an accepted update into the held document and returns the new bytes.
+/// Callers hold the `current_doc` write guard so doc and heads move together.
+pub(crate) fn apply_relay_update(
+    state: &SyncRelayState,
+    current_doc: &mut Option<Bytes>,
+    update: &RelayUpdate,
+) -> Bytes {
+    let doc = match update {
+        RelayUpdate::FullDoc { doc, .. } => doc.clone(),
+        RelayUpdate::Changes { changes, .. } => {
+            let held = current_doc.as_deref().unwrap_or_default();
+            let mut appended = Vec::with_capacity(held.len() + changes.len());
+            appended.extend_from_slice(held);
+            appended.extend_from_slice(changes);
+            Bytes::from(appended)
+        }
+    };
+    *current_doc = Some(doc.clone());
+    *state.current_heads.write().unwrap() = update.heads().clone();
+    doc
+}
+
+pub(crate) enum RelayClientMessage {
+    Update(RelayUpdate),
+    FullDocRequest,
+    Malformed(String),
+}
+
+/// Classifies one binary message from a client. Legacy connections, and bare
+/// Automerge documents or sealed payloads on framed connections, are always
+/// whole documents.
+pub(crate) fn parse_relay_client_message(framed: bool, data: &Bytes) -> RelayClientMessage {
+    if !framed
+        || data.starts_with(&AUTOMERGE_MAGIC_BYTES)
+        || data.starts_with(&SEALED_PAYLOAD_MAGIC)
+    {
+        return RelayClientMessage::Update(RelayUpdate::full_doc(data, Arc::default()));
+    }
+    match decode_relay_frame(data) {
+        Ok(frame) => match RelayUpdate::from_client_frame(data, frame) {
+            Some(update) => RelayClientMessage::Update(update),
+            None => RelayClientMessage::FullDocRequest,
+        },
+        Err(error) => RelayClientMessage::Malformed(error),
+    }
+}
+
+/// A client's opening control message. Clients that never send one keep the
+/// behavior chosen by their upgrade query.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RelayHello {
+    /// Highest control version the client speaks.
+    version: u8,
+    client_id: String,
+    /// Hex change hashes of the client's document.
+    #[serde(default)]
+    heads: Vec<String>,
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+/// JSON text frames a client may send.
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum RelayClientControl {
+    Hello(RelayHello),
+}
+
+/// JSON text frames the relay sends to clients that asked for `control`.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum RelayServerControl {
+    #[serde(rename_all = "camelCase")]
+    Ack {
+        version: u8,
+        client_id: String,
+        /// Heads of the held document, so the client can skip or request it.
+        heads: Vec<String>,
+        capabilities: Vec<String>,
+        client_count: usize,
+    },
+    ClientCount {
+        count: usize,
+    },
+    /// Sent before the relay closes a connection after a factory reset.
+    FactoryReset {
+        generation: u64,
+    },
+    /// Sent before the relay closes a connection whose device was revoked.
+    DeviceRevoked,
+    /// Heads of the held document after the client's push was stored.
+    HeadsAck {
+        heads: Vec<String>,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+/// Options a connection settled on through its `hello`.
+#[derive(Debug, PartialEq, Eq)]
+struct RelayHandshake {
+    client_id: String,
+    heads: Vec<RelayHead>,
+    control: bool,
+    frames: bool,
+}
+
+pub(crate) fn format_relay_heads_hex(heads: &[RelayHead]) -> Vec<String> {
+    heads
+        .iter()
+        .map(|head| head.iter().map(|byte| format!("{:02x}", byte)).collect())
+        .collect()
+}
+
+/// Negotiates a client's `hello` and builds the `ack` for it.
+fn answer_relay_hello(
+    hello: RelayHello,
+    held_heads: &[RelayHead],
+    client_count: usize,
+) -> Result<(RelayHandshake, RelayServerControl), String> {
+    if hello.version == 0 {
+        return Err("unsupported control version 0".to_string());
+    }
+    if hello.client_id.is_empty() || hello.client_id.len() > RELAY_MAX_CLIENT_ID_LEN {
+        return Err("client id must be 1 to 64 bytes".to_string());
+    }
+    let heads = parse_relay_heads_hex(&hello.heads)?;
+    let capabilities: Vec<String> = RELAY_CAPABILITIES
+        .iter()
+        .filter(|capability| hello.capabilities.iter().any(|c| c == *capability))
+        .map(|capability| capability.to_string())
+        .collect();
+    let handshake = RelayHandshake {
+        client_id: hello.client_id.clone(),
+        heads,
+        control: capabilities.iter().any(|c| c == "control"),
+        frames: capabilities.iter().any(|c| c == "frames"),
+    };
+    let ack = RelayServerControl::Ack {
+        version: hello.version.min(RELAY_CONTROL_VERSION),
+        client_id: hello.client_id,
+        heads: format_relay_heads_hex(held_heads),
+        capabilities,
+        client_count,
+    };
+    Ok((handshake, ack))
+}
+
+fn relay_control_message(control: &RelayServerControl) -> Message {
+    Message::Text(serde_json::to_string(control).unwrap_or_default().into())
+}
+
+fn relay_close_message(code: u16, reason: &str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code: CloseCode::from(code),
+        reason: reason.to_string().into(),
+    }))
+}
+
+/// Close frame for a connection refused after the handshake. A stale shared
+/// token means the pairing rotated, so the client re-pairs; only a device
+/// that was actually revoked hears `device_revoked`.
+fn relay_rejection_close(auth: &RelayClientAuth, auth_is_current: bool) -> Message {
+    match auth {
+        _ if auth_is_current => relay_close_message(RELAY_CLOSE_FACTORY_RESET, "factory_reset"),
+        RelayClientAuth::SharedToken => {
+            relay_close_message(RELAY_CLOSE_FACTORY_RESET, "pairing_rotated")
+        }
+        RelayClientAuth::Device(_) => {
+            relay_close_message(RELAY_CLOSE_DEVICE_REVOKED, "device_revoked")
+        }
+    }
+}
+
+/// Sends a control message when the connection asked for them. Returns
+/// `false` when the connection should close.
+async fn send_relay_control<S>(
+    ws_sender: &mut S,
+    addr: SocketAddr,
+    enabled: bool,
+    control: &RelayServerControl,
+) -> bool
+where
+    S: futures_util::Sink<Message> + Unpin,
+    S::Error: std::fmt::Display,
+{
+    if !enabled {
+        return true;
+    }
+    match timeout(
+        SYNC_RELAY_DOC_SEND_TIMEOUT,
+        ws_sender.send(relay_control_message(control)),
+    )
+    .await
+    {
+        Ok(Ok(())) => true,
+        Ok(Err(error)) => {
+            error!(
+                "[Sync] Failed to send control message to {}: {}",
+                addr, error
+            );
+            false
+        }
+        Err(_) => {
+            error!("[Sync] Timed out sending control message to {}", addr);
+            false
+        }
+    }
+}
+
+/// Sends the held document, for a framed client's `FullDocRequest` or to
+/// catch up a client that lagged behind the broadcast channel. Returns the
+/// bytes sent, or `None` when the connection should close.
+async fn send_relay_held_doc<S>(
+    state: &RelayState,
+    connection_generation: u64,
+    framed: bool,
+    ws_sender: &mut S,
+    addr: SocketAddr,
+) -> Option<u64>
+where
+    S: futures_util::Sink<Message> + Unpin,
+    S::Error: std::fmt::Display,
+{
+    let _epoch = state.epoch_gate.read().await;
+    if !relay_connection_can_exchange_docs(state, connection_generation) {
+        return None;
+    }
+    let held = {
+        let current_doc = state.current_doc.read().await;
+        let heads = state.current_heads.read().unwrap().clone();
+        current_doc.clone().map(|doc| (doc, heads))
+    };
+    let Some((doc, heads)) = held else {
+        return Some(0);
+    };
+    let message = relay_full_doc_message(framed, &doc, &heads);
+    let sent = message.len() as u64;
+    match timeout(SYNC_RELAY_DOC_SEND_TIMEOUT, ws_sender.send(message)).await {
+        Ok(Ok(())) => Some(sent),
+        Ok(Err(error)) => {
+            error!("[Sync] Failed to send requested doc to {}: {}", addr, error);
+            None
+        }
+        Err(_) => {
+            error!("[Sync] Timed out sending requested doc to {}", addr);
+            None
+        }
+    }
+}
+
+/// Why a push could not be folded into the held document.
+#[derive(Debug)]
+enum RelayFoldError {
+    /// The pushed payload could not be opened or merged.
+    Payload(String),
+    /// The held document could not be opened or loaded. It is kept as it is
+    /// until the desktop replaces it; merging onto an empty document instead
+    /// would throw away everything the relay holds.
+    Held(String),
+}
+
+/// Merges plain Automerge `incoming` bytes into `held` and returns the saved
+/// result with its heads.
+fn merge_relay_automerge(
+    held: Option<&[u8]>,
+    incoming: &RelayUpdate,
+) -> Result<(Vec<u8>, Vec<RelayHead>), RelayFoldError> {
+    use automerge::AutoCommit;
+
+    let mut doc = match held.map(AutoCommit::load) {
+        Some(Ok(doc)) => doc,
+        Some(Err(error)) => {
+            return Err(RelayFoldError::Held(format!(
+                "held document is not an Automerge document: {}",
+                error
+            )))
+        }
+        None => AutoCommit::new(),
+    };
+    match incoming {
+        RelayUpdate::FullDoc { doc: bytes, .. } => {
+            let mut other = AutoCommit::load(bytes).map_err(|e| {
+                RelayFoldError::Payload(format!("payload is not an Automerge document: {}", e))
+            })?;
+            doc.merge(&mut other)
+                .map_err(|e| RelayFoldError::Payload(format!("failed to merge payload: {}", e)))?;
+        }
+        RelayUpdate::Changes { changes, .. } => {
+            doc.load_incremental(changes).map_err(|e| {
+                RelayFoldError::Payload(format!("payload is not Automerge changes: {}", e))
+            })?;
+        }
+    }
+    let heads = doc.get_heads().into_iter().map(|hash| hash.0).collect();
+    Ok((doc.save(), heads))
+}
+
+/// Folds a client push into the held document. Returns the update that
+/// becomes `current_doc` and the update fanned out to other clients.
+///
+/// Whenever the relay can read payloads (plaintext, or sealed under the
+/// desktop's sync key) it merges with Automerge, so two phones that edited
+/// offline no longer overwrite each other. A relay without the sync key keeps
+/// sealed payloads opaque and stores them as sent. A held document the key
+/// cannot open may still be readable under another key, and one that no
+/// longer loads may still be restored, so either way the push is rejected
+/// and the held copy kept. Merging is CPU-bound, so callers run this on the
+/// blocking pool.
+fn fold_relay_client_update(
+    key: Option<&RelayPayloadKey>,
+    held: Option<&[u8]>,
+    update: RelayUpdate,
+) -> Result<(RelayUpdate, RelayUpdate), RelayFoldError> {
+    if key.is_none() && update.payload().starts_with(&SEALED_PAYLOAD_MAGIC) {
+        return Ok((update.clone(), update));
+    }
+
+    let open = |bytes: &[u8]| match key {
+        Some(key) => key.open(bytes),
+        None => Ok(bytes.to_vec()),
+    };
+    let incoming = match &update {
+        RelayUpdate::FullDoc { doc, heads, .. } => {
+            RelayUpdate::full_doc(&open(doc).map_err(RelayFoldError::Payload)?, heads.clone())
+        }
+        RelayUpdate::Changes { changes, heads, .. } => RelayUpdate::changes(
+            &open(changes).map_err(RelayFoldError::Payload)?,
+            heads.clone(),
+        ),
+    };
+    let held = held.map(open).transpose().map_err(|error| {
+        RelayFoldError::Held(format!("held document could not be opened: {}", error))
+    })?;
+    let (merged, heads) = merge_relay_automerge(held.as_deref(), &incoming)?;
+    let merged = match key {
+        Some(key) => key.seal(&merged).map_err(RelayFoldError::Payload)?,
+        None => merged,
+    };
+    let merged = RelayUpdate::full_doc(&merged, Arc::new(heads));
+    // Change chunks stay deltas on the wire; receivers apply them to their
+    // own copy. A pushed document goes out as the merged result.
+    let broadcast = match update {
+        RelayUpdate::Changes { .. } => update,
+        RelayUpdate::FullDoc { .. } => merged.clone(),
+    };
+    Ok((merged, broadcast))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RelayClientStore {
+    Stored,
+    /// The payload's changes were already held; nothing was stored or fanned out.
+    Unchanged,
+    /// The connection predates a factory reset or updates are paused.
+    Stale,
+    /// The payload could not be merged; the held document is unchanged.
+    Rejected(String),
+    /// The held document could not be opened or loaded, so nothing can be
+    /// merged into it until it is replaced. It is left as it is.
+    HeldUnreadable(String),
+}
+
+/// Folds a client push into the held document and fans it out. The merge
+/// runs on the blocking pool without any relay lock held; the result is only
+/// stored if the held document and sync key are still the ones it was merged
+/// against, and the fold is retried otherwise.
+pub(crate) async fn store_relay_client_update_if_current(
+    state: &RelayState,
+    connection_generation: u64,
+    update: RelayUpdate,
+) -> RelayClientStore {
+    for _ in 0..RELAY_MERGE_ATTEMPTS {
+        let (held, key) = {
+            let _epoch = state.epoch_gate.read().await;
+            if !relay_connection_can_exchange_docs(state, connection_generation) {
+                return RelayClientStore::Stale;
+            }
+            let held = state.current_doc.read().await.clone();
+            (held, state.payload_key.read().unwrap().clone())
+        };
+        let folded = {
+            let (held, key, update) = (held.clone(), key.clone(), update.clone());
+            tokio::task::spawn_blocking(move || {
+                fold_relay_client_update(key.as_deref(), held.as_deref(), update)
+            })
+            .await
+        };
+        let (merged, broadcast) = match folded {
+            Ok(Ok(folded)) => folded,
+            Ok(Err(RelayFoldError::Payload(reason))) => return RelayClientStore::Rejected(reason),
+            Ok(Err(RelayFoldError::Held(reason))) => {
+                return RelayClientStore::HeldUnreadable(reason)
+            }
+            Err(error) => return RelayClientStore::Rejected(error.to_string()),
+        };
+
+        let _epoch = state.epoch_gate.read().await;
+        let mut current_doc = state.current_doc.write().await;
+        if !relay_connection_can_exchange_docs(state, connection_generation) {
+            return RelayClientStore::Stale;
+        }
+        let key_unchanged = match (&key, &*state.payload_key.read().unwrap()) {
+            (Some(ours), Some(current)) => Arc::ptr_eq(ours, current),
+            (None, None) => true,
+            _ => false,
+        };
+        if *current_doc != held || !key_unchanged {
+            continue;
+        }
+        // Peers echo back what they were sent; fanning that out again would
+        // bounce it between relays forever.
+        if relay_heads_match(&state.current_heads.read().unwrap(), merged.heads()) {
+            return RelayClientStore::Unchanged;
+        }
+        let doc = apply_relay_update(state, &mut current_doc, &merged);
+        state.snapshot_tx.send_replace(Some(doc));
+        let _ = state.broadcast_tx.send(broadcast);
+        return RelayClientStore::Stored;
+    }
+    RelayClientStore::Rejected("the held document kept changing while merging".to_string())
+}
+
+pub(crate) async fn store_relay_client_doc_if_current(
+    state: &RelayState,
+    connection_generation: u64,
+    bytes: &[u8],
+) -> RelayClientStore {
+    store_relay_client_update_if_current(
+        state,
+        connection_generation,
+        RelayUpdate::full_doc(bytes, Arc::default()),
+    )
+    .await
+}
+
+/// Delivery statistics for one connection, reported to runtime health when
+/// the client falls behind and again when it disconnects.
+#[derive(Debug, Default)]
+pub(crate) struct RelayClientMetrics {
+    sends: u64,
+    total_send_ms: u64,
+    max_send_ms: u64,
+    /// Updates still queued for this client when the latest one was taken.
+    max_queue_depth: usize,
+    lag_events: u64,
+    skipped_updates: u64,
+}
+
+impl RelayClientMetrics {
+    fn note_send(&mut self, queue_depth: usize, elapsed: Duration) {
+        let elapsed_ms = elapsed.as_millis() as u64;
+        self.sends += 1;
+        self.total_send_ms += elapsed_ms;
+        self.max_send_ms = self.max_send_ms.max(elapsed_ms);
+        self.max_queue_depth = self.max_queue_depth.max(queue_depth);
+    }
+
+    fn health_event(&self, event: &str, addr: SocketAddr) -> serde_json::Value {
+        serde_json::json!({
+            "event": event,
+            "peer": addr.ip().to_string(),
+            "sends": self.sends,
+            "avgSendMs": self.total_send_ms.checked_div(self.sends).unwrap_or(0),
+            "maxSendMs": self.max_send_ms,
+            "maxQueueDepth": self.max_queue_depth,
+            "lagEvents": self.lag_events,
+            "skippedUpdates": self.skipped_updates,
+        })
+    }
+}
+
+pub(crate) enum RelayDelivery {
+    Update(RelayUpdate),
+    /// The client fell behind and missed updates; send it the held document.
+    CatchUp,
+    Closed,
+}
+
+/// Takes the next broadcast for one connection. A lagged receiver is
+/// resubscribed before the catch-up, so the held document the caller sends
+/// next already covers every update it skipped and none of the stale
+/// backlog is replayed after it.
+pub(crate) async fn recv_relay_delivery(
+    broadcast_rx: &mut broadcast::Receiver<RelayUpdate>,
+    metrics: &mut RelayClientMetrics,
+) -> RelayDelivery {
+    match broadcast_rx.recv().await {
+        Ok(update) => RelayDelivery::Update(update),
+        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+            *broadcast_rx = broadcast_rx.resubscribe();
+            metrics.lag_events += 1;
+            metrics.skipped_updates += skipped;
+            RelayDelivery::CatchUp
+        }
+        Err(broadcast::error::RecvError::Closed) => RelayDelivery::Closed,
+    }
+}
+
+fn reject_relay_payload(events: &dyn RelayEventSink, addr: SocketAddr, bytes: usize, reason: &str) {
+    warn!("[Sync] Rejected payload from {}: {}", addr, reason);
+    events.record_health(serde_json::json!({
+        "event": "relay_rejected_payload",
+        "peer": addr.ip().to_string(),
+        "bytes": bytes,
+        "reason": reason,
+    }));
+}
+
+/// Reports a push from `from` that was turned away because the held document
+/// itself is unreadable. The desktop answers by broadcasting its own copy,
+/// which replaces the held one.
+pub(crate) fn report_unreadable_held_doc(events: &dyn RelayEventSink, from: &str, reason: &str) {
+    error!(
+        "[Sync] Kept unreadable held document, rejected push from {}: {}",
+        from, reason
+    );
+    events.record_health(serde_json::json!({
+        "event": "relay_held_doc_unreadable",
+        "peer": from,
+        "reason": reason,
+    }));
+    events.held_doc_unreadable();
+}
+
+// Relay HTTP endpoints
+//
+// The relay port also answers plain HTTP, so a phone that just woke up can
+// check for changes without opening a WebSocket:
+//
+//   GET /health   client count, generation, held document size and ETag
+//   GET /doc      the held document; 304 when `If-None-Match` matches
+//   PUT /doc      one-shot upload, merged like a WebSocket push
+//
+// Requests carry the same `t=` or `d=&t=` credentials as the upgrade URI and
+// count toward the same lockout. Pairing codes are only redeemed over
+// WebSocket. Every response closes the connection.
+
+/// Request line and headers, which must fit in this many bytes.
+const RELAY_HTTP_MAX_HEAD_BYTES: usize = 16 * 1024;
+const RELAY_HTTP_MAX_HEADERS: usize = 32;
+/// Time allowed to send the request head, and separately the body.
+pub(crate) const RELAY_HTTP_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct RelayHttpRequest {
+    method: String,
+    pub(crate) path: String,
+    pub(crate) query: Option<String>,
+    headers: Vec<(String, String)>,
+    /// Length of the request head; bytes after it belong to the body.
+    head_len: usize,
+}
+
+impl RelayHttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn is_websocket_upgrade(&self) -> bool {
+        self.header("upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+/// Parses a request head. `Ok(None)` means more bytes are needed.
+fn parse_relay_http_request(bytes: &[u8]) -> Result<Option<RelayHttpRequest>, String> {
+    let mut headers = [httparse::EMPTY_HEADER; RELAY_HTTP_MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+    let head_len = match request.parse(bytes).map_err(|e| e.to_string())? {
+        httparse::Status::Complete(head_len) => head_len,
+        httparse::Status::Partial => return Ok(None),
+    };
+    let target = request.path.unwrap_or("/");
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    Ok(Some(RelayHttpRequest {
+        method: request.method.unwrap_or_default().to_string(),
+        path: path.to_string(),
+        query,
+        headers: request
+            .headers
+            .iter()
+            .map(|header| {
+                (
+                    header.name.to_string(),
+                    String::from_utf8_lossy(header.value).trim().to_string(),
+                )
+            })
+            .collect(),
+        head_len,
+    }))
+}
+
+/// Reads until a full request head has arrived. Returns every byte read,
+/// which may run past the head into a body.
+pub(crate) async fn read_relay_http_request<S>(
+    stream: &mut S,
+) -> Result<(Vec<u8>, RelayHttpRequest), String>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        let read = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("connection closed before the request head".to_string());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(request) = parse_relay_http_request(&buffer)? {
+            return Ok((buffer, request));
+        }
+        if buffer.len() > RELAY_HTTP_MAX_HEAD_BYTES {
+            return Err("request head too large".to_string());
+        }
+    }
+}
+
+/// Strong ETag for a held document: its SHA-256, quoted.
+fn relay_doc_etag(doc: &[u8]) -> String {
+    format!("\"{}\"", URL_SAFE_NO_PAD.encode(Sha256::digest(doc)))
+}
+
+/// Whether an `If-None-Match` header matches `etag`, weak tags included.
+fn relay_etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+#[derive(Debug)]
+pub(crate) struct RelayHttpResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Bytes,
+}
+
+impl RelayHttpResponse {
+    fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    fn json(status: u16, body: serde_json::Value) -> Self {
+        Self::new(status)
+            .header("Content-Type", "application/json")
+            .body(Bytes::from(body.to_string()))
+    }
+
+    pub(crate) fn error(status: u16, reason: &str) -> Self {
+        Self::json(status, serde_json::json!({ "error": reason }))
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn body(mut self, body: Bytes) -> Self {
+        self.body = body;
+        self
+    }
+
+    fn head(&self) -> String {
+        let reason = match self.status {
+            200 => "OK",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            411 => "Length Required",
+            413 => "Payload Too Large",
+            422 => "Unprocessable Entity",
+            _ => "Service Unavailable",
+        };
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        ));
+        head
+    }
+}
+
+/// Checks HTTP credentials and feeds the result into the lockout.
+fn authorize_relay_http(
+    state: &SyncRelayState,
+    events: &dyn RelayEventSink,
+    addr: SocketAddr,
+    query: Option<&str>,
+) -> Option<RelayClientAuth> {
+    let now_ms = now_unix_ms();
+    let auth = if relay_query_param(query, "pc").is_some() {
+        None
+    } else {
+        authorize_relay_request(
+            query,
+            &state.pairing_token.read().unwrap(),
+            &mut state.devices.lock().unwrap(),
+            now_ms,
+        )
+    };
+    note_relay_auth_attempt(state, events, addr, auth.is_some(), now_ms);
+    auth
+}
+
+/// Answers one authenticated HTTP request. `body` is the complete PUT body.
+async fn answer_relay_http(
+    state: &RelayState,
+    events: &dyn RelayEventSink,
+    addr: SocketAddr,
+    request: &RelayHttpRequest,
+    body: &[u8],
+) -> RelayHttpResponse {
+    let _epoch = state.epoch_gate.read().await;
+    let generation = state.generation.load(std::sync::atomic::Ordering::SeqCst);
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => {
+            let doc = state.current_doc.read().await.clone();
+            RelayHttpResponse::json(
+                200,
+                serde_json::json!({
+                    "clientCount": *state.client_count.read().await,
+                    "generation": generation,
+                    "docBytes": doc.as_ref().map_or(0, |doc| doc.len()),
+                    "etag": doc.as_deref().map(relay_doc_etag),
+                }),
+            )
+        }
+        ("GET", "/doc") => {
+            if !relay_connection_can_exchange_docs(state, generation) {
+                return RelayHttpResponse::error(503, "relay is resetting");
+            }
+            let Some(doc) = state.current_doc.read().await.clone() else {
+                return RelayHttpResponse::error(404, "relay holds no document");
+            };
+            let etag = relay_doc_etag(&doc);
+            if request
+                .header("if-none-match")
+                .is_some_and(|tags| relay_etag_matches(tags, &etag))
+            {
+                return RelayHttpResponse::new(304).header("ETag", etag);
+            }
+            RelayHttpResponse::new(200)
+                .header("Content-Type", "application/octet-stream")
+                .header("ETag", etag)
+                .body(doc)
+        }
+        ("PUT", "/doc") => {
+            if !relay_payload_is_acceptable(state, body) {
+                let reason = "payload is unsealed or sealed under a stale sync key";
+                reject_relay_payload(events, addr, body.len(), reason);
+                return RelayHttpResponse::error(422, reason);
+            }
+            match store_relay_client_doc_if_current(state, generation, body).await {
+                RelayClientStore::Stored | RelayClientStore::Unchanged => {
+                    let doc = state.current_doc.read().await.clone().unwrap_or_default();
+                    let etag = relay_doc_etag(&doc);
+                    RelayHttpResponse::json(
+                        200,
+                        serde_json::json!({ "etag": etag, "docBytes": doc.len() }),
+                    )
+                    .header("ETag", etag)
+                }
+                RelayClientStore::Stale => RelayHttpResponse::error(409, "relay was reset"),
+                RelayClientStore::Rejected(reason) => {
+                    reject_relay_payload(events, addr, body.len(), &reason);
+                    RelayHttpResponse::error(422, &reason)
+                }
+                RelayClientStore::HeldUnreadable(reason) => {
+                    report_unreadable_held_doc(events, &addr.ip().to_string(), &reason);
+                    RelayHttpResponse::error(503, &reason)
+                }
+            }
+        }
+        (_, "/health" | "/doc") => RelayHttpResponse::error(405, "method not allowed"),
+        _ => RelayHttpResponse::error(404, "not found"),
+    }
+}
+
+/// Serves one plain HTTP request. `received` holds every byte read so far.
+async fn serve_relay_http<S>(
+    mut stream: S,
+    mut received: Vec<u8>,
+    request: RelayHttpRequest,
+    addr: SocketAddr,
+    state: RelayState,
+    events: RelayEvents,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let response = match authorize_relay_http(&state, &*events, addr, request.query.as_deref()) {
+        None => {
+            error!("[Sync] Rejected unauthorized HTTP request from {}", addr);
+            RelayHttpResponse::error(401, "rescan the QR code to pair")
+        }
+        Some(auth) => {
+            let body =
+                match read_relay_http_body(&mut stream, &mut received, &request, &state).await {
+                    Ok(()) => &received[request.head_len..],
+                    Err(response) => {
+                        write_relay_http_response(&mut stream, addr, &response).await;
+                        return;
+                    }
+                };
+            let response = answer_relay_http(&state, &*events, addr, &request, body).await;
+            if let RelayClientAuth::Device(id) = &auth {
+                state.devices.lock().unwrap().note_request(
+                    id,
+                    addr,
+                    body.len() as u64,
+                    response.body.len() as u64,
+                    now_unix_ms(),
+                );
+            }
+            response
+        }
+    };
+    write_relay_http_response(&mut stream, addr, &response).await;
+}
+
+/// Reads the rest of a PUT body into `received`. GET bodies are ignored.
+async fn read_relay_http_body<S>(
+    stream: &mut S,
+    received: &mut Vec<u8>,
+    request: &RelayHttpRequest,
+    state: &SyncRelayState,
+) -> Result<(), RelayHttpResponse>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    if request.method != "PUT" {
+        received.truncate(request.head_len);
+        return Ok(());
+    }
+    let length = request
+        .header("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .ok_or_else(|| RelayHttpResponse::error(411, "Content-Length is required"))?;
+    if length > state.abuse_policy.max_frame_bytes {
+        return Err(RelayHttpResponse::error(
+            413,
+            "document exceeds the relay's size limit",
+        ));
+    }
+    let total = request.head_len + length;
+    received.truncate(total);
+    let missing = total - received.len();
+    let mut rest = stream.take(missing as u64);
+    timeout(RELAY_HTTP_READ_TIMEOUT, rest.read_to_end(received))
+        .await
+        .map_err(|_| RelayHttpResponse::error(400, "timed out reading the body"))?
+        .map_err(|_| RelayHttpResponse::error(400, "failed to read the body"))?;
+    if received.len() < total {
+        return Err(RelayHttpResponse::error(
+            400,
+            "body shorter than Content-Length",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) async fn write_relay_http_response<S>(
+    stream: &mut S,
+    addr: SocketAddr,
+    response: &RelayHttpResponse,
+) where
+    S: tokio::io::AsyncWrite + Unpin,
+{
+    let write = async {
+        stream.write_all(response.head().as_bytes()).await?;
+        stream.write_all(&response.body).await?;
+        stream.shutdown().await
+    };
+    match timeout(SYNC_RELAY_DOC_SEND_TIMEOUT, write).await {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => warn!(
+            "[Sync] Failed to answer HTTP request from {}: {}",
+            addr, error
+        ),
+        Err(_) => warn!("[Sync] Timed out answering HTTP request from {}", addr),
+    }
+}
+
+/// Refuses locked-out addresses and connections beyond the limit. The permit
+/// is the connection's slot.
+pub(crate) fn admit_relay_connection(
+    state: &SyncRelayState,
+    events: &dyn RelayEventSink,
+    addr: SocketAddr,
+) -> Option<tokio::sync::OwnedSemaphorePermit> {
+    let now_ms = now_unix_ms();
+    if let Some(until_ms) = state
+        .auth_limiter
+        .lock()
+        .unwrap()
+        .locked_until(addr.ip(), now_ms)
+    {
+        warn!(
+            "[Sync] Refused {}: locked out for {}s after failed pairing attempts",
+            addr,
+            (until_ms - now_ms).div_ceil(1000)
+        );
+        return None;
+    }
+    let Ok(slot) = state.connection_slots.clone().try_acquire_owned() else {
+        let limit = state.abuse_policy.max_connections;
+        warn!(
+            "[Sync] Refused {}: {} connections already open",
+            addr, limit
+        );
+        events.record_health(serde_json::json!({
+            "event": "relay_connection_limit",
+            "peer": addr.ip().to_string(),
+            "limit": limit,
+        }));
+        return None;
+    };
+    Some(slot)
+}
+
+/// Terminate TLS (when the relay has a certificate) and serve one connection.
+#[cfg_attr(feature = "perf", tracing::instrument(skip(stream, state, events), fields(addr = %addr)))]
+pub(crate) async fn handle_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    state: RelayState,
+    events: RelayEvents,
+) {
+    info!("[Sync] New connection from: {}", addr);
+    if !state.network_tx.borrow().admits(addr.ip()) {
+        warn!(
+            "[Sync] Refused {}: not in the relay's allowed subnets",
+            addr
+        );
+        return;
+    }
+    // Held until the connection closes.
+    let Some(_slot) = admit_relay_connection(&state, &*events, addr) else {
+        return;
+    };
+
+    let allow_plaintext = state.network_tx.borrow().allow_plaintext;
+    let acceptor = state
+        .tls
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|tls| tls.acceptor.clone());
+    let Some(acceptor) = acceptor else {
+        if allow_plaintext {
+            serve_relay_connection(stream, addr, state, events).await;
+        } else {
+            warn!(
+                "[Sync] Refused {}: no relay certificate and plaintext is off",
+                addr
+            );
+        }
+        return;
+    };
+    // TLS and, when allowed, plain ws:// share the port. Browsers cannot pin
+    // the relay's self-signed certificate, so they connect in plaintext and
+    // rely on sealed payloads; clients that can pin `fp` open with a
+    // ClientHello. Plaintext exposes the upgrade URI's credentials, so it is
+    // only served when the network settings opt in.
+    let mut first = [0u8; 1];
+    match timeout(RELAY_HTTP_READ_TIMEOUT, stream.peek(&mut first)).await {
+        Ok(Ok(1)) if first[0] == TLS_HANDSHAKE_RECORD => {}
+        Ok(Ok(1)) if allow_plaintext => {
+            serve_relay_connection(stream, addr, state, events).await;
+            return;
+        }
+        Ok(Ok(1)) => {
+            warn!("[Sync] Refused plaintext connection from {}", addr);
+            return;
+        }
+        Ok(_) => return,
+        Err(_) => {
+            warn!("[Sync] Timed out waiting for a request from {}", addr);
+            return;
+        }
+    }
+    match timeout(SYNC_RELAY_TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(tls_stream)) => serve_relay_connection(tls_stream, addr, state, events).await,
+        Ok(Err(error)) => warn!("[Sync] TLS handshake with {} failed: {}", addr, error),
+        Err(_) => warn!("[Sync] TLS handshake with {} timed out", addr),
+    }
+}
+
+/// Authenticate and handle a single WebSocket connection, or answer a plain
+/// HTTP request (see "Relay HTTP endpoints").
+///
+/// The upgrade URI must carry credentials accepted by `authorize_relay_request`.
+/// Any connection that omits them or presents an incorrect value is rejected
+/// with HTTP 401 before the WebSocket handshake completes. No data is exchanged.
+pub(crate) async fn serve_relay_connection<S>(
+    mut stream: S,
+    addr: SocketAddr,
+    state: RelayState,
+    events: RelayEvents,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (received, request) = match timeout(
+        RELAY_HTTP_READ_TIMEOUT,
+        read_relay_http_request(&mut stream),
+    )
+    .await
+    {
+        Ok(Ok(head)) => head,
+        Ok(Err(error)) => {
+            warn!("[Sync] Unreadable request from {}: {}", addr, error);
+            return;
+        }
+        Err(_) => {
+            warn!("[Sync] Timed out waiting for a request from {}", addr);
+            return;
+        }
+    };
+    if !request.is_websocket_upgrade() {
+        serve_relay_http(stream, received, request, addr, state, events).await;
+        return;
+    }
+    // Replay the head to the WebSocket handshake.
+    let (read_half, write_half) = tokio::io::split(stream);
+    let stream = tokio::io::join(std::io::Cursor::new(received).chain(read_half), write_half);
+
+    let (expected_token, connection_generation) = {
+        let _epoch = state.epoch_gate.read().await;
+        (
+            state.pairing_token.read().unwrap().clone(),
+            state.generation.load(std::sync::atomic::Ordering::SeqCst),
+        )
+    };
+    let handshake_token = expected_token.clone();
+    let handshake_state = state.clone();
+    let handshake_events = events.clone();
+    let mut disconnect_rx = state.disconnect_tx.subscribe();
+    let upgrade = Arc::new(StdMutex::new(
+        None::<(Option<String>, RelayClientAuth, Option<String>)>,
+    ));
+    let handshake_upgrade = upgrade.clone();
+
+    let ws_stream = match accept_hdr_async_with_config(
+        stream,
+        move |req: &WsRequest, resp: WsResponse| -> Result<WsResponse, ErrorResponse> {
+            let now_ms = now_unix_ms();
+            let auth = authorize_relay_request(
+                req.uri().query(),
+                &handshake_token,
+                &mut handshake_state.devices.lock().unwrap(),
+                now_ms,
+            );
+
+            note_relay_auth_attempt(
+                &handshake_state,
+                &*handshake_events,
+                addr,
+                auth.is_some(),
+                now_ms,
+            );
+            if let Some(auth) = auth {
+                let user_agent = req
+                    .headers()
+                    .get("user-agent")
+                    .and_then(|agent| agent.to_str().ok())
+                    .map(str::to_string);
+                *handshake_upgrade.lock().unwrap() =
+                    Some((req.uri().query().map(str::to_string), auth, user_agent));
+                Ok(resp)
+            } else {
+                error!("[Sync] Rejected unauthorized connection from {}", addr);
+                Err(tokio_tungstenite::tungstenite::http::Response::builder()
+                    .status(401)
+                    .body(Some("Unauthorized: rescan the QR code to pair".to_owned()))
+                    .unwrap())
+            }
+        },
+        Some(relay_websocket_config(&state.abuse_policy)),
+    )
+    .await
+    {
+        Ok(ws) => ws,
+        Err(e) => {
+            // 401 rejections are normal; log everything else
+            if !e.to_string().contains("HTTP error") {
+                error!("[Sync] WebSocket handshake failed: {}", e);
+            }
+            return;
+        }
+    };
+
+    let Some((upgrade_query, auth, user_agent)) = upgrade.lock().unwrap().take() else {
+        return;
+    };
+    let device_id = match &auth {
+        RelayClientAuth::Device(id) => Some(id.clone()),
+        RelayClientAuth::SharedToken => None,
+    };
+    let mut framed = relay_query_param(upgrade_query.as_deref(), "v")
+        .and_then(|version| version.parse::<u8>().ok())
+        == Some(RELAY_PROTOCOL_VERSION);
+    let client_heads = relay_query_param(upgrade_query.as_deref(), "h")
+        .and_then(decode_relay_heads_param)
+        .unwrap_or_default();
+
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let mut broadcast_rx = state.broadcast_tx.subscribe();
+    let mut bytes_received = 0u64;
+    let mut bytes_sent = 0u64;
+    let mut docs_pushed = 0u64;
+    let mut docs_pulled = 0u64;
+    let mut last_message_at_ms = None;
+    let mut metrics = RelayClientMetrics::default();
+    let mut client_count_rx = state.client_count_tx.subscribe();
+    let mut handshake: Option<RelayHandshake> = None;
+    let mut initial_doc_sent = false;
+
+    let connected_count = {
+        let _epoch = state.epoch_gate.read().await;
+        let auth_is_current = match &auth {
+            RelayClientAuth::SharedToken => {
+                state.pairing_token.read().unwrap().as_str() == expected_token.as_str()
+            }
+            RelayClientAuth::Device(id) => state.devices.lock().unwrap().contains(id),
+        };
+        if !auth_is_current || !relay_connection_can_exchange_docs(&state, connection_generation) {
+            let close = relay_rejection_close(&auth, auth_is_current);
+            let _ = timeout(SYNC_RELAY_DOC_SEND_TIMEOUT, ws_sender.send(close)).await;
+            info!("[Sync] Client {} rejected during relay reset", addr);
+            return;
+        }
+
+        let initial_doc = {
+            let current_doc = state.current_doc.read().await;
+            let heads = state.current_heads.read().unwrap().clone();
+            current_doc.clone().map(|doc| (doc, heads))
+        };
+        if let Some((doc, heads)) =
+            initial_doc.filter(|(_, heads)| !(framed && relay_heads_match(heads, &client_heads)))
+        {
+            let message = relay_full_doc_message(framed, &doc, &heads);
+            bytes_sent += message.len() as u64;
+            match timeout(SYNC_RELAY_DOC_SEND_TIMEOUT, ws_sender.send(message)).await {
+                Ok(Ok(())) => {
+                    initial_doc_sent = true;
+                    docs_pulled += 1;
+                }
+                Ok(Err(error)) => {
+                    error!("[Sync] Failed to send initial doc: {}", error);
+                    return;
+                }
+                Err(_) => {
+                    error!("[Sync] Timed out sending initial doc to {}", addr);
+                    return;
+                }
+            }
+        }
+
+        let mut count = state.client_count.write().await;
+        *count += 1;
+        state.client_count_tx.send_replace(*count);
+        *count
+    };
+    info!("[Sync] Client connected. Total: {}", connected_count);
+    events.client_count_changed(connected_count);
+    let device = device_id.as_ref().map(|id| {
+        let mut devices = state.devices.lock().unwrap();
+        devices.note_connected(id, addr, now_unix_ms());
+        (id.clone(), devices.name(id).unwrap_or_default().to_string())
+    });
+    let connection_id = state
+        .clients
+        .lock()
+        .unwrap()
+        .open(addr, device, user_agent, now_unix_ms());
+
+    loop {
+        state
+            .clients
+            .lock()
+            .unwrap()
+            .update(connection_id, |client| {
+                client.last_message_at_ms = last_message_at_ms;
+                client.bytes_received = bytes_received;
+                client.bytes_sent = bytes_sent;
+                client.docs_pushed = docs_pushed;
+                client.docs_pulled = docs_pulled;
+                if client.client_id.is_none() {
+                    client.client_id = handshake.as_ref().map(|h| h.client_id.clone());
+                }
+            });
+        tokio::select! {
+            msg = ws_receiver.next() => {
+                last_message_at_ms = Some(now_unix_ms());
+                match msg {
+                    Some(Ok(Message::Binary(data))) => {
+                        bytes_received += data.len() as u64;
+                        // The client pushed a document update. Store and rebroadcast it.
+                        let update = match parse_relay_client_message(framed, &data) {
+                            RelayClientMessage::Update(update) => update,
+                            RelayClientMessage::FullDocRequest => {
+                                match send_relay_held_doc(
+                                    &state,
+                                    connection_generation,
+                                    framed,
+                                    &mut ws_sender,
+                                    addr,
+                                ).await {
+                                    Some(sent) => {
+                                        bytes_sent += sent;
+                                        docs_pulled += 1;
+                                        continue;
+                                    }
+                                    None => break,
+                                }
+                            }
+                            RelayClientMessage::Malformed(error) => {
+                                warn!("[Sync] Ignored malformed frame from {}: {}", addr, error);
+                                continue;
+                            }
+                        };
+                        let bytes = update.payload().len();
+                        let control = handshake.as_ref().is_some_and(|h| h.control);
+                        if !relay_payload_is_acceptable(&state, update.payload()) {
+                            let reason = "payload is unsealed or sealed under a stale sync key";
+                            reject_relay_payload(&*events, addr, bytes, reason);
+                            let error = RelayServerControl::Error { reason: reason.to_string() };
+                            if !send_relay_control(&mut ws_sender, addr, control, &error).await {
+                                break;
+                            }
+                            continue;
+                        }
+                        match store_relay_client_update_if_current(
+                            &state,
+                            connection_generation,
+                            update,
+                        ).await {
+                            RelayClientStore::Stored | RelayClientStore::Unchanged => {
+                                docs_pushed += 1;
+                                let heads = state.current_heads.read().unwrap().clone();
+                                let ack = RelayServerControl::HeadsAck {
+                                    heads: format_relay_heads_hex(&heads),
+                                };
+                                if !send_relay_control(&mut ws_sender, addr, control, &ack).await {
+                                    break;
+                                }
+                            }
+                            RelayClientStore::Stale => {
+                                info!("[Sync] Ignored stale client update after relay reset");
+                                break;
+                            }
+                            RelayClientStore::Rejected(reason) => {
+                                reject_relay_payload(&*events, addr, bytes, &reason);
+                                // Scripted clients wait for an answer to every push.
+                                let error = RelayServerControl::Error { reason };
+                                if !send_relay_control(&mut ws_sender, addr, control, &error).await {
+                                    break;
+                                }
+                            }
+                            RelayClientStore::HeldUnreadable(reason) => {
+                                report_unreadable_held_doc(&*events, &addr.ip().to_string(), &reason);
+                                let error = RelayServerControl::Error { reason };
+                                if !send_relay_control(&mut ws_sender, addr, control, &error).await {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        let hello = match serde_json::from_str::<RelayClientControl>(&text) {
+                            Ok(RelayClientControl::Hello(hello)) => hello,
+                            Err(error) => {
+                                warn!("[Sync] Ignored malformed control message from {}: {}", addr, error);
+                                continue;
+                            }
+                        };
+                        let held_heads = state.current_heads.read().unwrap().clone();
+                        let client_count = *state.client_count.read().await;
+                        let (reply, needs_doc) =
+                            match answer_relay_hello(hello, &held_heads, client_count) {
+                                Ok((negotiated, ack)) => {
+                                    info!(
+                                        "[Sync] Client {} introduced itself as {}",
+                                        addr, negotiated.client_id
+                                    );
+                                    framed |= negotiated.frames;
+                                    // Connections whose `h=` matched got no
+                                    // initial doc; catch them up if the held
+                                    // doc has moved past the hello's heads.
+                                    let needs_doc = !initial_doc_sent
+                                        && !held_heads.is_empty()
+                                        && !relay_heads_match(&held_heads, &negotiated.heads);
+                                    handshake = Some(negotiated);
+                                    (ack, needs_doc)
+                                }
+                                Err(reason) => {
+                                    warn!("[Sync] Rejected hello from {}: {}", addr, reason);
+                                    (RelayServerControl::Error { reason }, false)
+                                }
+                            };
+                        // The reply always goes out; it is how a client learns
+                        // whether the relay speaks the control channel.
+                        if !send_relay_control(&mut ws_sender, addr, true, &reply).await {
+                            break;
+                        }
+                        if needs_doc {
+                            match send_relay_held_doc(
+                                &state,
+                                connection_generation,
+                                framed,
+                                &mut ws_sender,
+                                addr,
+                            ).await {
+                                Some(sent) => {
+                                    bytes_sent += sent;
+                                    docs_pulled += 1;
+                                    initial_doc_sent = true;
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => {
+                        info!("[Sync] Client {} disconnected", addr);
+                        break;
+                    }
+                    Some(Ok(Message::Ping(data))) => {
+                        let _ = ws_sender.send(Message::Pong(data)).await;
+                    }
+                    Some(Err(WsError::Capacity(CapacityError::MessageTooLong { size, max_size }))) => {
+                        warn!(
+                            "[Sync] Closing {}: {} byte frame exceeds the {} byte limit",
+                            addr, size, max_size
+                        );
+                        events.record_health(serde_json::json!({
+                            "event": "relay_frame_too_large",
+                            "peer": addr.ip().to_string(),
+                            "bytes": size,
+                            "limit": max_size,
+                        }));
+                        let close = relay_close_message(RELAY_CLOSE_FRAME_TOO_LARGE, "frame_too_large");
+                        let _ = timeout(SYNC_RELAY_DOC_SEND_TIMEOUT, ws_sender.send(close)).await;
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        error!("[Sync] Error from {}: {}", addr, e);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            delivery = recv_relay_delivery(&mut broadcast_rx, &mut metrics) => {
+                let update = match delivery {
+                    RelayDelivery::Update(update) => update,
+                    RelayDelivery::CatchUp => {
+                        warn!("[Sync] Client {} lagged behind; resending held doc", addr);
+                        events.record_health(metrics.health_event("relay_client_lagged", addr));
+                        match send_relay_held_doc(
+                            &state,
+                            connection_generation,
+                            framed,
+                            &mut ws_sender,
+                            addr,
+                        ).await {
+                            Some(sent) => {
+                                bytes_sent += sent;
+                                docs_pulled += 1;
+                                continue;
+                            }
+                            None => break,
+                        }
+                    }
+                    RelayDelivery::Closed => break,
+                };
+                let _epoch = state.epoch_gate.read().await;
+                if !relay_connection_can_exchange_docs(&state, connection_generation) {
+                    let control = handshake.as_ref().is_some_and(|h| h.control);
+                    let generation = state.generation.load(std::sync::atomic::Ordering::SeqCst);
+                    let notice = RelayServerControl::FactoryReset { generation };
+                    send_relay_control(&mut ws_sender, addr, control, &notice).await;
+                    let close = relay_close_message(RELAY_CLOSE_FACTORY_RESET, "factory_reset");
+                    let _ = timeout(SYNC_RELAY_DOC_SEND_TIMEOUT, ws_sender.send(close)).await;
+                    info!("[Sync] Client {} rejected a broadcast after relay reset", addr);
+                    break;
+                }
+                let message = match &update {
+                    RelayUpdate::FullDoc { doc, .. } if !framed => Message::Binary(doc.clone()),
+                    RelayUpdate::Changes { .. } if !framed => {
+                        // Legacy clients only understand whole documents.
+                        let Some(doc) = state.current_doc.read().await.clone() else {
+                            continue;
+                        };
+                        Message::Binary(doc)
+                    }
+                    update => Message::Binary(update.frame().clone()),
+                };
+                bytes_sent += message.len() as u64;
+                let queue_depth = broadcast_rx.len();
+                let started = Instant::now();
+                match timeout(
+                    SYNC_RELAY_DOC_SEND_TIMEOUT,
+                    ws_sender.send(message),
+                ).await {
+                    Ok(Ok(())) => {
+                        docs_pulled += 1;
+                        metrics.note_send(queue_depth, started.elapsed());
+                    }
+                    Ok(Err(error)) => {
+                        error!("[Sync] Failed to send to {}: {}", addr, error);
+                        break;
+                    }
+                    Err(_) => {
+                        error!("[Sync] Timed out sending to {}", addr);
+                        break;
+                    }
+                }
+            }
+            changed = client_count_rx.changed() => {
+                if changed.is_err() {
+                    continue;
+                }
+                let count = *client_count_rx.borrow_and_update();
+                let control = handshake.as_ref().is_some_and(|h| h.control);
+                let notice = RelayServerControl::ClientCount { count };
+                if !send_relay_control(&mut ws_sender, addr, control, &notice).await {
+                    break;
+                }
+            }
+            reset = disconnect_rx.recv() => {
+                let control = handshake.as_ref().is_some_and(|h| h.control);
+                match reset {
+                    Ok(RelayDisconnect::FactoryReset(generation)) => {
+                        let notice = RelayServerControl::FactoryReset { generation };
+                        send_relay_control(&mut ws_sender, addr, control, &notice).await;
+                        let _ = ws_sender
+                            .send(relay_close_message(RELAY_CLOSE_FACTORY_RESET, "factory_reset"))
+                            .await;
+                        info!("[Sync] Client {} disconnected by factory reset", addr);
+                        break;
+                    }
+                    Ok(RelayDisconnect::Device(revoked))
+                        if device_id.as_deref() == Some(revoked.as_str()) =>
+                    {
+                        let notice = RelayServerControl::DeviceRevoked;
+                        send_relay_control(&mut ws_sender, addr, control, &notice).await;
+                        let _ = ws_sender
+                            .send(relay_close_message(RELAY_CLOSE_DEVICE_REVOKED, "device_revoked"))
+                            .await;
+                        info!("[Sync] Client {} disconnected by device revocation", addr);
+                        break;
+                    }
+                    Ok(RelayDisconnect::Client(id)) if id == connection_id => {
+                        let _ = ws_sender
+                            .send(relay_close_message(RELAY_CLOSE_DISCONNECTED, "disconnected"))
+                            .await;
+                        info!("[Sync] Client {} disconnected from the desktop", addr);
+                        break;
+                    }
+                    Ok(RelayDisconnect::SharedToken) if device_id.is_none() => {
+                        let _ = ws_sender
+                            .send(relay_close_message(RELAY_CLOSE_FACTORY_RESET, "pairing_rotated"))
+                            .await;
+                        info!("[Sync] Client {} disconnected by pairing token rotation", addr);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    state.clients.lock().unwrap().close(connection_id);
+    if metrics.sends > 0 || metrics.lag_events > 0 {
+        events.record_health(metrics.health_event("relay_client_delivery", addr));
+    }
+    if let Some(id) = &device_id {
+        state.devices.lock().unwrap().note_disconnected(
+            id,
+            bytes_received,
+            bytes_sent,
+            now_unix_ms(),
+        );
+    }
+
+    // Decrement client count and notify frontend
+    {
+        let mut count = state.client_count.write().await;
+        *count = count.saturating_sub(1);
+        let new_count = *count;
+        state.client_count_tx.send_replace(new_count);
+        info!("[Sync] Client disconnected. Total: {}", new_count);
+        events.client_count_changed(new_count);
+    }
+}
+
+/// Binds every address the settings ask for. Fails if any of them fails, so
+/// the relay never silently serves a subset.
+async fn bind_relay_listeners(
+    settings: &RelayNetworkSettings,
+    port: u16,
+) -> Result<Vec<TcpListener>, String> {
+    let interfaces = local_ip_address::list_afinet_netifas().unwrap_or_default();
+    let mut listeners = Vec::new();
+    for addr in relay_bind_addresses(settings, port, &interfaces)? {
+        let (listener, retries_used) =
+            bind_sync_relay_listener_with_policy(addr, DEFAULT_SYNC_RELAY_BIND_RETRY_POLICY)
+                .await
+                .map_err(|e| format!("failed to bind to {}: {}", addr, e))?;
+        if retries_used == 0 {
+            info!("[Sync] Relay server listening on {}", addr);
+        } else {
+            info!(
+                "[Sync] Relay server listening on {} after {} retry attempt(s)",
+                addr, retries_used
+            );
+        }
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Accepts on every listener until all of them fail.
+async fn accept_relay_connections(
+    listeners: Vec<TcpListener>,
+    state: RelayState,
+    events: RelayEvents,
+) {
+    let mut accepting = tokio::task::JoinSet::new();
+    for listener in listeners {
+        let state = state.clone();
+        let events = events.clone();
+        accepting.spawn(async move {
+            while let Ok((stream, addr)) = listener.accept().await {
+                tokio::spawn(handle_connection(
+                    stream,
+                    addr,
+                    state.clone(),
+                    events.clone(),
+                ));
+            }
+        });
+    }
+    while accepting.join_next().await.is_some() {}
+}
+
+/// Serves the relay on the LAN and, when configured, through a rendezvous
+/// server.
+pub(crate) async fn start_sync_relay(state: RelayState, events: RelayEvents) {
+    tokio::select! {
+        _ = serve_relay_listeners(state.clone(), events.clone()) => {}
+        _ = run_relay_rendezvous(state, events) => {}
+    }
+}
+
+/// Accepts LAN connections, rebinding whenever the network settings change.
+async fn serve_relay_listeners(state: RelayState, events: RelayEvents) {
+    let mut network_rx = state.network_tx.subscribe();
+    loop {
+        let settings = network_rx.borrow_and_update().clone();
+        match bind_relay_listeners(&settings, state.listen_port()).await {
+            Ok(listeners) => {
+                tokio::select! {
+                    _ = accept_relay_connections(listeners, state.clone(), events.clone()) => return,
+                    changed = network_rx.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                        info!("[Sync] Relay network settings changed, rebinding");
+                    }
+                }
+            }
+            // Keep waiting: the next settings change may fix the binding.
+            Err(error) => {
+                error!("[Sync] {}", error);
+                if network_rx.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) struct LoggedRelayEvents;
+
+impl RelayEventSink for LoggedRelayEvents {
+    fn client_count_changed(&self, count: usize) {
+        info!("[Sync] Connected clients: {}", count);
+    }
+
+    fn record_health(&self, event: serde_json::Value) {
+        info!("[Sync] {}", event);
+    }
+
+    #[cfg(feature = "desktop")]
+    fn sync_peers_changed(&self, peers: &[SyncPeerSummary]) {
+        info!("[Sync] Known peers: {}", peers.len());
+    }
+
+    #[cfg(feature = "desktop")]
+    fn sync_peer_doc_merged(&self, fingerprint: &str) {
+        info!("[Sync] Merged document from peer {}", fingerprint);
+    }
+
+    fn held_doc_unreadable(&self) {
+        warn!("[Sync] Restore the held document from a snapshot before clients can push again");
+    }
+}
+
+/// Runs the sync relay without a webview, for a home server or NAS.
+///
+/// The device registry, certificate, and held document live in `data_dir`, as
+/// they do in the desktop app's data directory. Build it without Tauri with
+/// `cargo build --no-default-features --bin freed-relay`. The headless
+/// relay never holds a sync key, so it stores and forwards sealed documents it
+/// cannot read.
+pub fn run_headless_relay(data_dir: PathBuf, port: Option<u16>) -> Result<(), String> {
+    std::fs::create_dir_all(&data_dir)
+        .map_err(|e| format!("failed to create {}: {}", data_dir.display(), e))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+    runtime.block_on(async move {
+        let state: RelayState = Arc::new(SyncRelayState::new(sync_relay_port()));
+        load_relay_credentials(&state, &data_dir);
+        // `--port` beats the persisted settings for this run only.
+        if let Some(port) = port {
+            state
+                .network_tx
+                .send_modify(|settings| settings.port = Some(port));
+        }
+        restore_held_relay_doc(&state, &data_dir).await;
+
+        let doc_state = state.clone();
+        let doc_dir = data_dir.clone();
+        tokio::spawn(run_debounced_doc_writer(
+            state.snapshot_tx.subscribe(),
+            DEFAULT_SNAPSHOT_WRITER_POLICY,
+            move |_| persist_held_relay_doc(&doc_state, &doc_dir),
+        ));
+
+        let fingerprint = state
+            .tls
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|tls| tls.fingerprint.clone());
+        let _mdns = advertise_mdns(state.listen_port(), "Freed Relay", fingerprint.as_deref());
+        // Same pairing-code URL as the desktop QR; the code pairs one device.
+        let hosts = local_relay_candidate_hosts(&state.network_tx.borrow());
+        let code = state
+            .devices
+            .lock()
+            .unwrap()
+            .issue_pairing_code(now_unix_ms());
+        info!(
+            "[Sync] Pairing URL (pairs one device, expires in {} minutes; restart for another): {}",
+            PAIRING_CODE_TTL.as_secs() / 60,
+            sync_pairing_url(&state, &hosts, &code)
+        );
+
+        tokio::select! {
+            _ = start_sync_relay(state.clone(), Arc::new(LoggedRelayEvents)) => {
+                Err("sync relay stopped accepting connections".to_string())
+            }
+            _ = tokio::signal::ctrl_c() => {
+                // The debounced writer may still be waiting; flush before exit.
+                let flush_state = state.clone();
+                tokio::task::spawn_blocking(move || persist_held_relay_doc(&flush_state, &data_dir))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                info!("[Sync] Relay stopped");
+                Ok(())
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sync_cli::{open_relay_client_stream, parse_sync_cli_url};
+
+    pub(crate) fn snapshot_test_relay_state(current_doc: Option<Vec<u8>>) -> RelayState {
+        let (broadcast_tx, _) = broadcast::channel::<RelayUpdate>(16);
+        let (disconnect_tx, _) = broadcast::channel::<RelayDisconnect>(16);
+        Arc::new(SyncRelayState {
+            port: DEFAULT_SYNC_RELAY_PORT,
+            epoch_gate: RwLock::new(()),
+            broadcast_tx,
+            current_doc: RwLock::new(current_doc.map(Bytes::from)),
+            current_heads: StdRwLock::new(Arc::default()),
+            snapshot_tx: tokio::sync::watch::channel(None).0,
+            #[cfg(feature = "desktop")]
+            snapshot_retention_tx: tokio::sync::watch::channel(SnapshotRetention::default()).0,
+            #[cfg(feature = "desktop")]
+            backup_schedule_tx: tokio::sync::watch::channel(BackupSchedule::default()).0,
+            disconnect_tx,
+            generation: std::sync::atomic::AtomicU64::new(0),
+            accepting_doc_updates: std::sync::atomic::AtomicBool::new(true),
+            client_count: RwLock::new(0),
+            client_count_tx: tokio::sync::watch::channel(0).0,
+            pairing_token: StdRwLock::new("token".to_string()),
+            tls: StdRwLock::new(None),
+            devices: StdMutex::new(RelayDeviceRegistry::default()),
+            payload_key: StdRwLock::new(None),
+            #[cfg(feature = "desktop")]
+            peers: StdMutex::new(SyncPeerRegistry::default()),
+            network_tx: tokio::sync::watch::channel(RelayNetworkSettings::default()).0,
+            abuse_policy: DEFAULT_RELAY_ABUSE_POLICY,
+            connection_slots: Arc::new(tokio::sync::Semaphore::new(
+                DEFAULT_RELAY_ABUSE_POLICY.max_connections,
+            )),
+            auth_limiter: StdMutex::new(RelayAuthLimiter::default()),
+            clients: StdMutex::new(RelayClientRegistry::default()),
+            rendezvous_tx: tokio::sync::watch::channel(RelayRendezvousSettings::default()).0,
+            rendezvous_connected: std::sync::atomic::AtomicBool::new(false),
+        })
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn pairing_code_registers_device_once_and_device_credential_authorizes() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let registry_path = data_dir.path().join(PAIRED_DEVICES_FILE);
+        let mut devices = RelayDeviceRegistry::load(registry_path.clone());
+        let code = devices.issue_pairing_code(1_000);
+        let pairing_query = format!("pc={code}&n=Kitchen%20iPad");
+
+        let auth = authorize_relay_request(Some(&pairing_query), "token", &mut devices, 2_000);
+        let device_id = pairing_code_device_id(&code);
+        assert_eq!(auth, Some(RelayClientAuth::Device(device_id.clone())));
+        assert_eq!(
+            authorize_relay_request(Some(&pairing_query), "token", &mut devices, 2_000),
+            None
+        );
+
+        let credential = pairing_code_device_credential(&code);
+        let device_query = format!("d={device_id}&t={credential}");
+        assert_eq!(
+            authorize_relay_request(Some(&device_query), "token", &mut devices, 3_000),
+            Some(RelayClientAuth::Device(device_id.clone()))
+        );
+        let wrong_query = format!("d={device_id}&t=token");
+        assert_eq!(
+            authorize_relay_request(Some(&wrong_query), "token", &mut devices, 3_000),
+            None
+        );
+        assert_eq!(
+            authorize_relay_request(Some("t=token"), "token", &mut devices, 3_000),
+            Some(RelayClientAuth::SharedToken)
+        );
+
+        devices.note_connected(&device_id, "192.168.1.30:5000".parse().unwrap(), 4_000);
+        assert!(devices.summaries()[0].connected);
+        devices.note_disconnected(&device_id, 120, 340, 5_000);
+
+        let persisted = std::fs::read_to_string(&registry_path).unwrap();
+        assert!(!persisted.contains(&credential));
+        let reloaded = RelayDeviceRegistry::load(registry_path);
+        let summaries = reloaded.summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].name, "Kitchen iPad");
+        assert_eq!(summaries[0].first_seen_at_ms, 2_000);
+        assert_eq!(summaries[0].last_seen_at_ms, 5_000);
+        assert_eq!(summaries[0].last_address.as_deref(), Some("192.168.1.30"));
+        assert_eq!(
+            (summaries[0].bytes_received, summaries[0].bytes_sent),
+            (120, 340)
+        );
+        assert!(!summaries[0].connected);
+        assert!(reloaded.verify_device(&device_id, &credential));
+
+        // Pinned so the PWA's WebCrypto derivation can be checked against it.
+        assert_eq!(pairing_code_device_id("code-1"), "nbnHHJyujj2NWsA_qAn_4w");
+        assert_eq!(
+            pairing_code_device_credential("code-1"),
+            "-eI7-LTftt5IhdLbH0Rg35T-iZL_piqbfH6sKH7q2eY"
+        );
+    }
+
+    #[test]
+    fn pairing_codes_expire_and_stay_bounded() {
+        let mut devices = RelayDeviceRegistry::default();
+        let expired = devices.issue_pairing_code(0);
+        let expires_at = PAIRING_CODE_TTL.as_millis() as u64;
+        assert_eq!(
+            devices.redeem_pairing_code(&expired, "Phone", expires_at),
+            None
+        );
+
+        let codes: Vec<String> = (0..MAX_PENDING_PAIRING_CODES + 2)
+            .map(|_| devices.issue_pairing_code(expires_at))
+            .collect();
+        assert_eq!(devices.pending_codes.len(), MAX_PENDING_PAIRING_CODES);
+        assert_eq!(
+            devices.redeem_pairing_code(&codes[0], "Phone", expires_at),
+            None
+        );
+        assert!(devices
+            .redeem_pairing_code(codes.last().unwrap(), "Phone", expires_at)
+            .is_some());
+    }
+
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn persisted_relay_doc_is_ignored_when_damaged_or_superseded() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let doc = automerge_like_bytes(3);
+        write_relay_doc(data_dir.path(), &doc, &[]).unwrap();
+        let path = data_dir.path().join(RELAY_DOC_FILE);
+        let intact = std::fs::read(&path).unwrap();
+        assert_eq!(
+            decode_relay_doc_file(&intact).unwrap(),
+            (doc.clone(), vec![])
+        );
+
+        let mut flipped = intact.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(decode_relay_doc_file(&flipped).is_err());
+        assert!(decode_relay_doc_file(&intact[..intact.len() - 1]).is_err());
+        assert!(decode_relay_doc_file(&doc).is_err());
+
+        std::fs::write(&path, &flipped).unwrap();
+        let state = snapshot_test_relay_state(None);
+        restore_held_relay_doc(&state, data_dir.path()).await;
+        assert!(state.current_doc.read().await.is_none());
+
+        std::fs::write(&path, &intact).unwrap();
+        let state = snapshot_test_relay_state(Some(automerge_like_bytes(9)));
+        restore_held_relay_doc(&state, data_dir.path()).await;
+        assert_eq!(
+            state.current_doc.read().await.as_deref(),
+            Some(&automerge_like_bytes(9)[..])
+        );
+
+        *state.payload_key.write().unwrap() = Some(Arc::new(RelayPayloadKey::generate()));
+        *state.current_doc.write().await = None;
+        restore_held_relay_doc(&state, data_dir.path()).await;
+        assert!(state.current_doc.read().await.is_none());
+    }
+
+    pub(crate) fn automerge_doc_bytes(key: &str, value: &str) -> Vec<u8> {
+        use automerge::transaction::Transactable;
+
+        let mut doc = automerge::AutoCommit::new();
+        doc.put(automerge::ROOT, key, value).unwrap();
+        doc.save()
+    }
+
+    pub(crate) fn automerge_doc_entries(bytes: &[u8]) -> Vec<(String, String)> {
+        use automerge::ReadDoc;
+
+        let doc = automerge::AutoCommit::load(bytes).unwrap();
+        doc.map_range(automerge::ROOT, ..)
+            .map(|item| {
+                (
+                    item.key.to_string(),
+                    item.value.to_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) fn automerge_like_bytes(marker: u8) -> Vec<u8> {
+        let mut bytes = AUTOMERGE_MAGIC_BYTES.to_vec();
+        bytes.extend_from_slice(&[marker; 8]);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn relay_doc_updates_feed_snapshot_writer() {
+        let state = snapshot_test_relay_state(None);
+        let snapshot_rx = state.snapshot_tx.subscribe();
+
+        let pushed = automerge_doc_bytes("title", "pushed");
+        assert_eq!(
+            store_relay_client_doc_if_current(&state, 0, &pushed).await,
+            RelayClientStore::Stored
+        );
+        let written = snapshot_rx.borrow().clone().unwrap();
+        assert_eq!(
+            automerge_doc_entries(&written),
+            automerge_doc_entries(&pushed)
+        );
+    }
+
+    #[test]
+    fn relay_tls_identity_persists_and_regenerates_when_unusable() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let first = load_or_create_relay_tls(data_dir.path()).unwrap();
+        let reloaded = load_or_create_relay_tls(data_dir.path()).unwrap();
+        assert_eq!(first.fingerprint, reloaded.fingerprint);
+        assert_eq!(first.fingerprint.len(), 43);
+
+        std::fs::write(data_dir.path().join(RELAY_TLS_KEY_FILE), b"garbage").unwrap();
+        let regenerated = load_or_create_relay_tls(data_dir.path()).unwrap();
+        assert_ne!(regenerated.fingerprint, first.fingerprint);
+        assert_eq!(
+            RelayTlsIdentity::load(data_dir.path())
+                .unwrap()
+                .fingerprint(),
+            regenerated.fingerprint
+        );
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn relay_payload_key_seals_appendable_envelopes() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let key = load_or_create_payload_key(data_dir.path()).unwrap();
+        let reloaded = load_or_create_payload_key(data_dir.path()).unwrap();
+        assert_eq!(reloaded.key_id, key.key_id);
+
+        let doc = automerge_like_bytes(1);
+        let sealed_doc = key.seal(&doc).unwrap();
+        assert!(!sealed_doc
+            .windows(doc.len())
+            .any(|window| window == doc.as_slice()));
+        let mut held = sealed_doc.clone();
+        held.extend_from_slice(&key.seal(&[7, 7, 7]).unwrap());
+
+        let mut expected = doc.clone();
+        expected.extend_from_slice(&[7, 7, 7]);
+        assert_eq!(reloaded.open(&held).unwrap(), expected);
+        assert_eq!(sealed_payload_key_id(&held).unwrap(), key.key_id);
+
+        let mut tampered = sealed_doc.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open(&tampered).is_err());
+        assert!(key.open(&sealed_doc[..sealed_doc.len() - 1]).is_err());
+        assert!(RelayPayloadKey::generate().open(&sealed_doc).is_err());
+        assert!(sealed_payload_key_id(&doc).is_err());
+
+        let mut mixed = sealed_doc;
+        mixed.extend_from_slice(&RelayPayloadKey::generate().seal(&[1]).unwrap());
+        assert!(sealed_payload_key_id(&mixed).is_err());
+
+        // Pinned so the PWA's WebCrypto sealing can be checked against it.
+        let pinned = RelayPayloadKey::from_secret([7; 32]);
+        let vector = URL_SAFE_NO_PAD
+            .decode("RlJTAej3QlZ5Nt71AAAAIQEBAQEBAQEBAQEBAdWgXCHtXHBMa8psYj2c8Mu2Xa2JLg")
+            .unwrap();
+        assert_eq!(pinned.open(&vector).unwrap(), b"freed");
+    }
+
+    #[tokio::test]
+    async fn relay_tls_acceptor_completes_handshake_with_pinned_certificate() {
+        use rustls::pki_types::{CertificateDer, ServerName};
+
+        let identity = RelayTlsIdentity::generate().unwrap();
+        let tls = identity.to_relay_tls().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls.acceptor.accept(stream).await.unwrap();
+            stream.write_all(b"relay").await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(CertificateDer::from(identity.cert_der.clone()))
+            .unwrap();
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"relay");
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn relay_frames_round_trip_and_reject_truncation() {
+        let heads = [[0xab; RELAY_HEAD_BYTES], [0x01; RELAY_HEAD_BYTES]];
+        let encoded = encode_relay_frame(RelayFrameKind::Changes, &heads, b"chunk");
+        assert_eq!(encoded[0], RELAY_PROTOCOL_VERSION);
+        assert_eq!(
+            decode_relay_frame(&encoded).unwrap(),
+            RelayFrame {
+                kind: RelayFrameKind::Changes,
+                heads: heads.to_vec(),
+                payload: b"chunk",
+            }
+        );
+
+        let request = encode_relay_frame(RelayFrameKind::FullDocRequest, &[], &[]);
+        assert_eq!(request, vec![RELAY_PROTOCOL_VERSION, 2, 0, 0]);
+        assert!(decode_relay_frame(&encoded[..encoded.len() - 6 - RELAY_HEAD_BYTES]).is_err());
+        assert!(decode_relay_frame(&[RELAY_PROTOCOL_VERSION, 9, 0, 0]).is_err());
+        assert!(decode_relay_frame(&[2, 0, 0, 0]).is_err());
+        assert!(decode_relay_frame(&[RELAY_PROTOCOL_VERSION]).is_err());
+    }
+
+    #[test]
+    fn relay_client_messages_keep_legacy_clients_on_full_documents() {
+        let doc = automerge_like_bytes(3);
+        assert!(matches!(
+            parse_relay_client_message(
+                false,
+                &encode_relay_frame(RelayFrameKind::Changes, &[], b"x").into()
+            ),
+            RelayClientMessage::Update(RelayUpdate::FullDoc { .. })
+        ));
+        assert!(matches!(
+            parse_relay_client_message(true, &doc.into()),
+            RelayClientMessage::Update(RelayUpdate::FullDoc { .. })
+        ));
+        assert!(matches!(
+            parse_relay_client_message(
+                true,
+                &encode_relay_frame(RelayFrameKind::Changes, &[], b"x").into()
+            ),
+            RelayClientMessage::Update(RelayUpdate::Changes { .. })
+        ));
+        assert!(matches!(
+            parse_relay_client_message(
+                true,
+                &Bytes::from_static(&[RELAY_PROTOCOL_VERSION, 2, 0, 0])
+            ),
+            RelayClientMessage::FullDocRequest
+        ));
+        assert!(matches!(
+            parse_relay_client_message(true, &Bytes::from_static(&[7, 7])),
+            RelayClientMessage::Malformed(_)
+        ));
+    }
+
+    #[test]
+    fn relay_heads_parse_from_hex_and_query_param() {
+        let hex = "ab".repeat(RELAY_HEAD_BYTES);
+        let parsed = parse_relay_heads_hex(&[hex]).unwrap();
+        assert_eq!(parsed, vec![[0xab; RELAY_HEAD_BYTES]]);
+        assert!(parse_relay_heads_hex(&["abc".to_string()]).is_err());
+        assert!(parse_relay_heads_hex(&["zz".repeat(RELAY_HEAD_BYTES)]).is_err());
+
+        let first = [0x01; RELAY_HEAD_BYTES];
+        let second = [0x02; RELAY_HEAD_BYTES];
+        let param = URL_SAFE_NO_PAD.encode([second, first].concat());
+        let client = decode_relay_heads_param(&param).unwrap();
+        assert!(relay_heads_match(&[first, second], &client));
+        assert!(!relay_heads_match(&[first], &client));
+        assert!(!relay_heads_match(&[], &[]));
+        assert!(decode_relay_heads_param("AAAA").is_none());
+    }
+
+    #[tokio::test]
+    async fn relay_merges_offline_edits_instead_of_replacing_the_held_document() {
+        use automerge::transaction::Transactable;
+
+        let state = snapshot_test_relay_state(Some(automerge_doc_bytes("desktop", "a")));
+        let mut broadcast_rx = state.broadcast_tx.subscribe();
+
+        // Two phones edited offline from an empty document; neither push may
+        // erase the other or the desktop's edit.
+        let first = automerge_doc_bytes("phone-1", "b");
+        let second = automerge_doc_bytes("phone-2", "c");
+        for doc in [&first, &second] {
+            assert_eq!(
+                store_relay_client_doc_if_current(&state, 0, doc).await,
+                RelayClientStore::Stored
+            );
+        }
+        let held = state.current_doc.read().await.clone().unwrap();
+        let expected = vec![
+            ("desktop".to_string(), "a".to_string()),
+            ("phone-1".to_string(), "b".to_string()),
+            ("phone-2".to_string(), "c".to_string()),
+        ];
+        assert_eq!(automerge_doc_entries(&held), expected);
+        assert_eq!(state.current_heads.read().unwrap().len(), 3);
+        assert_eq!(state.snapshot_tx.borrow().as_deref(), Some(&*held));
+        broadcast_rx.recv().await.unwrap();
+        assert_eq!(broadcast_rx.recv().await.unwrap().payload(), &held);
+
+        // Change chunks merge into the held document and fan out unchanged.
+        let mut phone = automerge::AutoCommit::load(&held).unwrap();
+        phone.save();
+        phone.put(automerge::ROOT, "phone-1", "d").unwrap();
+        let changes = phone.save_incremental();
+        assert_eq!(
+            store_relay_client_update_if_current(
+                &state,
+                0,
+                RelayUpdate::changes(&changes, Arc::new(vec![[0x42; RELAY_HEAD_BYTES]])),
+            )
+            .await,
+            RelayClientStore::Stored
+        );
+        let update = broadcast_rx.recv().await.unwrap();
+        assert!(matches!(update, RelayUpdate::Changes { .. }));
+        assert_eq!(update.payload(), &changes);
+        let held = state.current_doc.read().await.clone().unwrap();
+        assert_eq!(automerge_doc_entries(&held)[1].1, "d");
+        assert_eq!(
+            *state.current_heads.read().unwrap().as_slice(),
+            [phone.get_heads()[0].0]
+        );
+
+        // Bytes that are not Automerge leave the held document untouched.
+        assert!(matches!(
+            store_relay_client_doc_if_current(&state, 0, &automerge_like_bytes(2)).await,
+            RelayClientStore::Rejected(_)
+        ));
+        assert_eq!(state.current_doc.read().await.as_deref(), Some(&*held));
+        assert!(broadcast_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn relay_updates_share_one_frame_buffer_across_sends() {
+        let heads = Arc::new(vec![[0x11; RELAY_HEAD_BYTES]]);
+        let update = RelayUpdate::changes(b"change", heads.clone());
+        let frame = update.frame().clone();
+        assert_eq!(
+            frame,
+            encode_relay_frame(RelayFrameKind::Changes, &heads, b"change")
+        );
+        assert_eq!(update.payload(), &b"change"[..]);
+        assert_eq!(update.payload().as_ptr(), frame[frame.len() - 6..].as_ptr());
+
+        // A framed client push is forwarded as the buffer it arrived in.
+        let data = Bytes::from(encode_relay_frame(RelayFrameKind::FullDoc, &heads, b"doc"));
+        let RelayClientMessage::Update(update) = parse_relay_client_message(true, &data) else {
+            panic!("expected an update");
+        };
+        assert_eq!(update.frame().as_ptr(), data.as_ptr());
+        assert_eq!(update.payload(), &b"doc"[..]);
+        assert_eq!(update.heads(), &heads);
+    }
+
+    #[tokio::test]
+    async fn lagged_relay_client_catches_up_without_replaying_the_backlog() {
+        let (broadcast_tx, mut broadcast_rx) = broadcast::channel::<RelayUpdate>(2);
+        let mut metrics = RelayClientMetrics::default();
+        for marker in 0..5u8 {
+            broadcast_tx
+                .send(RelayUpdate::full_doc(&[marker], Arc::default()))
+                .unwrap();
+        }
+
+        assert!(matches!(
+            recv_relay_delivery(&mut broadcast_rx, &mut metrics).await,
+            RelayDelivery::CatchUp
+        ));
+        assert_eq!(metrics.lag_events, 1);
+        assert_eq!(metrics.skipped_updates, 3);
+        assert!(broadcast_rx.is_empty());
+
+        broadcast_tx
+            .send(RelayUpdate::full_doc(&[9], Arc::default()))
+            .unwrap();
+        let RelayDelivery::Update(update) =
+            recv_relay_delivery(&mut broadcast_rx, &mut metrics).await
+        else {
+            panic!("expected the update sent after the catch-up");
+        };
+        assert_eq!(update.payload(), &[9][..]);
+
+        drop(broadcast_tx);
+        assert!(matches!(
+            recv_relay_delivery(&mut broadcast_rx, &mut metrics).await,
+            RelayDelivery::Closed
+        ));
+
+        metrics.note_send(4, Duration::from_millis(30));
+        metrics.note_send(1, Duration::from_millis(10));
+        let addr: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+        let event = metrics.health_event("relay_client_delivery", addr);
+        assert_eq!(event["peer"], "192.168.1.20");
+        assert_eq!(event["sends"], 2);
+        assert_eq!(event["avgSendMs"], 20);
+        assert_eq!(event["maxSendMs"], 30);
+        assert_eq!(event["maxQueueDepth"], 4);
+        assert_eq!(event["skippedUpdates"], 3);
+    }
+
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn relay_merges_sealed_payloads_when_it_holds_the_sync_key() {
+        let key = Arc::new(RelayPayloadKey::generate());
+        let state = snapshot_test_relay_state(Some(
+            key.seal(&automerge_doc_bytes("desktop", "a")).unwrap(),
+        ));
+        *state.payload_key.write().unwrap() = Some(key.clone());
+
+        let pushed = key.seal(&automerge_doc_bytes("phone", "b")).unwrap();
+        assert_eq!(
+            store_relay_client_doc_if_current(&state, 0, &pushed).await,
+            RelayClientStore::Stored
+        );
+        let held = state.current_doc.read().await.clone().unwrap();
+        assert!(relay_payload_is_acceptable(&state, &held));
+        assert_eq!(
+            automerge_doc_entries(&key.open(&held).unwrap()),
+            vec![
+                ("desktop".to_string(), "a".to_string()),
+                ("phone".to_string(), "b".to_string()),
+            ]
+        );
+
+        let garbage = key.seal(b"not automerge").unwrap();
+        assert!(matches!(
+            store_relay_client_doc_if_current(&state, 0, &garbage).await,
+            RelayClientStore::Rejected(_)
+        ));
+        assert_eq!(state.current_doc.read().await.as_deref(), Some(&*held));
+
+        // Concurrent pushes merge off the lock and neither overwrites the other.
+        let pushes = ["tablet", "laptop"].map(|name| {
+            let state = state.clone();
+            let pushed = key.seal(&automerge_doc_bytes(name, "c")).unwrap();
+            tokio::spawn(async move { store_relay_client_doc_if_current(&state, 0, &pushed).await })
+        });
+        for push in pushes {
+            assert_eq!(push.await.unwrap(), RelayClientStore::Stored);
+        }
+        let held = state.current_doc.read().await.clone().unwrap();
+        let names: Vec<String> = automerge_doc_entries(&key.open(&held).unwrap())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["desktop", "laptop", "phone", "tablet"]);
+
+        // A held document the relay cannot open is kept, not replaced.
+        let foreign = RelayPayloadKey::generate()
+            .seal(&automerge_doc_bytes("other", "d"))
+            .unwrap();
+        *state.current_doc.write().await = Some(Bytes::from(foreign.clone()));
+        let pushed = key.seal(&automerge_doc_bytes("phone", "e")).unwrap();
+        let RelayClientStore::HeldUnreadable(reason) =
+            store_relay_client_doc_if_current(&state, 0, &pushed).await
+        else {
+            panic!("expected the push to be rejected");
+        };
+        assert!(reason.contains("held document"), "{}", reason);
+        assert_eq!(state.current_doc.read().await.as_deref(), Some(&*foreign));
+    }
+
+    #[test]
+    fn relay_hello_negotiates_version_capabilities_and_heads() {
+        let held = [[0xab; RELAY_HEAD_BYTES]];
+        let hello = |json: serde_json::Value| {
+            let RelayClientControl::Hello(hello) = serde_json::from_value(json).unwrap();
+            hello
+        };
+
+        let (handshake, ack) = answer_relay_hello(
+            hello(serde_json::json!({
+                "type": "hello",
+                "version": 3,
+                "clientId": "phone-1",
+                "heads": ["cd".repeat(RELAY_HEAD_BYTES)],
+                "capabilities": ["control", "frames", "video"],
+            })),
+            &held,
+            2,
+        )
+        .unwrap();
+        assert_eq!(
+            handshake,
+            RelayHandshake {
+                client_id: "phone-1".to_string(),
+                heads: vec![[0xcd; RELAY_HEAD_BYTES]],
+                control: true,
+                frames: true,
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&ack).unwrap(),
+            serde_json::json!({
+                "type": "ack",
+                "version": RELAY_CONTROL_VERSION,
+                "clientId": "phone-1",
+                "heads": ["ab".repeat(RELAY_HEAD_BYTES)],
+                "capabilities": ["frames", "control"],
+                "clientCount": 2,
+            })
+        );
+
+        // A bare hello keeps the connection on plain binary documents.
+        let (handshake, _) = answer_relay_hello(
+            hello(serde_json::json!({"type": "hello", "version": 1, "clientId": "pwa"})),
+            &[],
+            1,
+        )
+        .unwrap();
+        assert!(!handshake.control && !handshake.frames);
+
+        for bad in [
+            serde_json::json!({"type": "hello", "version": 0, "clientId": "pwa"}),
+            serde_json::json!({"type": "hello", "version": 1, "clientId": ""}),
+            serde_json::json!({"type": "hello", "version": 1, "clientId": "x".repeat(65)}),
+            serde_json::json!({"type": "hello", "version": 1, "clientId": "pwa", "heads": ["zz"]}),
+        ] {
+            assert!(answer_relay_hello(hello(bad), &held, 1).is_err());
+        }
+        assert!(serde_json::from_str::<RelayClientControl>(r#"{"type":"bye"}"#).is_err());
+    }
+
+    #[test]
+    fn relay_control_notices_and_close_codes_are_distinguishable() {
+        let notice = |control: RelayServerControl| match relay_control_message(&control) {
+            Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            other => panic!("expected a text frame, got {other:?}"),
+        };
+        assert_eq!(
+            notice(RelayServerControl::FactoryReset { generation: 8 }),
+            serde_json::json!({"type": "factoryReset", "generation": 8})
+        );
+        assert_eq!(
+            notice(RelayServerControl::ClientCount { count: 3 }),
+            serde_json::json!({"type": "clientCount", "count": 3})
+        );
+        assert_eq!(
+            notice(RelayServerControl::HeadsAck {
+                heads: format_relay_heads_hex(&[[0x01; RELAY_HEAD_BYTES]]),
+            }),
+            serde_json::json!({"type": "headsAck", "heads": ["01".repeat(RELAY_HEAD_BYTES)]})
+        );
+        assert_eq!(
+            notice(RelayServerControl::DeviceRevoked),
+            serde_json::json!({"type": "deviceRevoked"})
+        );
+
+        let Message::Close(Some(frame)) =
+            relay_close_message(RELAY_CLOSE_FACTORY_RESET, "factory_reset")
+        else {
+            panic!("expected a close frame");
+        };
+        assert_eq!(u16::from(frame.code), RELAY_CLOSE_FACTORY_RESET);
+        assert_eq!(frame.reason.as_str(), "factory_reset");
+
+        let close_code = |auth: RelayClientAuth, current: bool| {
+            let Message::Close(Some(frame)) = relay_rejection_close(&auth, current) else {
+                panic!("expected a close frame");
+            };
+            (u16::from(frame.code), frame.reason.to_string())
+        };
+        assert_eq!(
+            close_code(RelayClientAuth::SharedToken, false),
+            (RELAY_CLOSE_FACTORY_RESET, "pairing_rotated".to_string())
+        );
+        assert_eq!(
+            close_code(RelayClientAuth::Device("phone".to_string()), false),
+            (RELAY_CLOSE_DEVICE_REVOKED, "device_revoked".to_string())
+        );
+        assert_eq!(
+            close_code(RelayClientAuth::SharedToken, true),
+            (RELAY_CLOSE_FACTORY_RESET, "factory_reset".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn relay_does_not_fan_out_pushes_it_already_holds() {
+        let state = snapshot_test_relay_state(None);
+        let doc = automerge_doc_bytes("title", "a");
+        assert_eq!(
+            store_relay_client_doc_if_current(&state, 0, &doc).await,
+            RelayClientStore::Stored
+        );
+        let mut broadcast_rx = state.broadcast_tx.subscribe();
+
+        let held = state.current_doc.read().await.clone().unwrap();
+        for echo in [&held[..], &doc[..]] {
+            assert_eq!(
+                store_relay_client_doc_if_current(&state, 0, echo).await,
+                RelayClientStore::Unchanged
+            );
+        }
+        assert!(broadcast_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn sync_relay_bind_retry_delay_only_retries_addr_in_use() {
+        let policy = SyncRelayBindRetryPolicy {
+            max_retries: 3,
+            retry_delay: Duration::from_millis(25),
+        };
+
+        let addr_in_use = std::io::Error::new(std::io::ErrorKind::AddrInUse, "busy");
+        assert_eq!(
+            sync_relay_bind_retry_delay(policy, &addr_in_use, 0),
+            Some(Duration::from_millis(25))
+        );
+        assert_eq!(sync_relay_bind_retry_delay(policy, &addr_in_use, 3), None);
+
+        let permission_denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "nope");
+        assert_eq!(
+            sync_relay_bind_retry_delay(policy, &permission_denied, 0),
+            None
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn sync_relay_bind_listener_retries_until_the_port_is_released() {
+        let policy = SyncRelayBindRetryPolicy {
+            max_retries: 10,
+            retry_delay: Duration::from_millis(10),
+        };
+        let occupied = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = occupied.local_addr().unwrap();
+
+        let release_task = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(35)).await;
+            drop(occupied);
+        });
+
+        let (listener, retries_used) = bind_sync_relay_listener_with_policy(addr, policy)
+            .await
+            .unwrap();
+        assert!(retries_used > 0);
+        drop(listener);
+        release_task.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn sync_relay_bind_listener_stops_after_retry_budget() {
+        let policy = SyncRelayBindRetryPolicy {
+            max_retries: 2,
+            retry_delay: Duration::from_millis(5),
+        };
+        let occupied = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = occupied.local_addr().unwrap();
+
+        let error = bind_sync_relay_listener_with_policy(addr, policy)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+        drop(occupied);
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn relay_network_settings_validate_and_persist() {
+        let data_dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            RelayNetworkSettings::load(data_dir.path()),
+            RelayNetworkSettings::default()
+        );
+
+        let settings = RelayNetworkSettings {
+            interface: Some("en0".to_string()),
+            ipv6: true,
+            port: Some(9100),
+            allowed_subnets: vec!["192.168.1.0/24".to_string(), "fd00::/8".to_string()],
+            ..RelayNetworkSettings::default()
+        };
+        settings.validate().unwrap();
+        settings.persist(data_dir.path()).unwrap();
+        assert_eq!(RelayNetworkSettings::load(data_dir.path()), settings);
+
+        for invalid in [
+            RelayNetworkSettings {
+                bind_address: Some("192.168.1.20".parse().unwrap()),
+                ..settings.clone()
+            },
+            RelayNetworkSettings {
+                interface: Some(" ".to_string()),
+                ..settings.clone()
+            },
+            RelayNetworkSettings {
+                port: Some(0),
+                ..settings.clone()
+            },
+            RelayNetworkSettings {
+                allowed_subnets: vec!["192.168.1.0/33".to_string()],
+                ..settings.clone()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+
+        std::fs::write(
+            data_dir.path().join(RELAY_NETWORK_FILE),
+            br#"{"allowedSubnets":["nonsense"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            RelayNetworkSettings::load(data_dir.path()),
+            RelayNetworkSettings::default()
+        );
+    }
+
+    #[test]
+    fn relay_subnets_match_prefixes_and_mapped_addresses() {
+        let lan = RelaySubnet::parse("192.168.1.0/24").unwrap();
+        assert!(lan.contains("192.168.1.77".parse().unwrap()));
+        assert!(lan.contains("::ffff:192.168.1.77".parse().unwrap()));
+        assert!(!lan.contains("192.168.2.1".parse().unwrap()));
+        assert!(!lan.contains("fd00::1".parse().unwrap()));
+
+        let host = RelaySubnet::parse("10.0.0.5").unwrap();
+        assert!(host.contains("10.0.0.5".parse().unwrap()));
+        assert!(!host.contains("10.0.0.6".parse().unwrap()));
+        assert!(RelaySubnet::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+
+        let ula = RelaySubnet::parse("fd00::/8").unwrap();
+        assert!(ula.contains("fd12:3456::1".parse().unwrap()));
+        assert!(!ula.contains("fe80::1".parse().unwrap()));
+        assert!(RelaySubnet::parse("fd00::/129").is_err());
+        assert!(RelaySubnet::parse("lan").is_err());
+
+        let settings = RelayNetworkSettings {
+            allowed_subnets: vec!["192.168.1.0/24".to_string()],
+            ..RelayNetworkSettings::default()
+        };
+        assert!(settings.admits("::ffff:192.168.1.9".parse().unwrap()));
+        assert!(!settings.admits("127.0.0.1".parse().unwrap()));
+        assert!(RelayNetworkSettings::default().admits("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn relay_binds_and_advertises_addresses_from_network_settings() {
+        let interfaces: Vec<(String, std::net::IpAddr)> = vec![
+            ("lo".to_string(), "127.0.0.1".parse().unwrap()),
+            ("utun3".to_string(), "10.8.0.2".parse().unwrap()),
+            ("en0".to_string(), "fe80::1".parse().unwrap()),
+            ("en0".to_string(), "2001:db8::20".parse().unwrap()),
+            ("en0".to_string(), "192.168.1.20".parse().unwrap()),
+            ("en1".to_string(), "100.64.0.3".parse().unwrap()),
+        ];
+        let addrs = |settings: &RelayNetworkSettings| {
+            relay_bind_addresses(settings, 9100, &interfaces)
+                .unwrap()
+                .into_iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut settings = RelayNetworkSettings::default();
+        assert_eq!(addrs(&settings), ["0.0.0.0:9100"]);
+        assert_eq!(
+            relay_candidate_hosts(&settings, &interfaces),
+            ["192.168.1.20", "100.64.0.3", "10.8.0.2"]
+        );
+
+        settings.ipv6 = true;
+        assert_eq!(addrs(&settings), ["[::]:9100"]);
+        assert_eq!(
+            relay_candidate_hosts(&settings, &interfaces),
+            ["192.168.1.20", "100.64.0.3", "10.8.0.2", "[2001:db8::20]"]
+        );
+
+        settings.interface = Some("en0".to_string());
+        assert_eq!(
+            addrs(&settings),
+            ["[2001:db8::20]:9100", "192.168.1.20:9100"]
+        );
+        assert_eq!(
+            relay_candidate_hosts(&settings, &interfaces),
+            ["192.168.1.20", "[2001:db8::20]"]
+        );
+        settings.interface = Some("wlan9".to_string());
+        assert!(relay_bind_addresses(&settings, 9100, &interfaces).is_err());
+
+        let pinned = RelayNetworkSettings {
+            bind_address: Some("100.64.0.3".parse().unwrap()),
+            port: Some(9200),
+            ..RelayNetworkSettings::default()
+        };
+        assert_eq!(addrs(&pinned), ["100.64.0.3:9100"]);
+        let hosts = relay_candidate_hosts(&pinned, &interfaces);
+        assert_eq!(hosts, ["100.64.0.3"]);
+
+        let state = snapshot_test_relay_state(None);
+        state.network_tx.send_replace(pinned);
+        assert_eq!(state.listen_port(), 9200);
+        assert_eq!(
+            sync_pairing_url(
+                &state,
+                &["192.168.1.20", "[2001:db8::20]", "10.8.0.2"],
+                "code"
+            ),
+            "ws://192.168.1.20:9200?pc=code&a=[2001:db8::20],10.8.0.2"
+        );
+    }
+
+    #[tokio::test]
+    async fn relay_rebinds_when_network_settings_change() {
+        let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = probe.local_addr().unwrap().port();
+        drop(probe);
+        let state: RelayState = Arc::new(SyncRelayState::new(port));
+        state.network_tx.send_replace(RelayNetworkSettings {
+            bind_address: Some("127.0.0.1".parse().unwrap()),
+            ..RelayNetworkSettings::default()
+        });
+        let relay = tokio::spawn(start_sync_relay(state.clone(), Arc::new(LoggedRelayEvents)));
+
+        let connects = |addr: &'static str| async move {
+            for _ in 0..100 {
+                if TcpStream::connect((addr, port)).await.is_ok() {
+                    return true;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            false
+        };
+        assert!(connects("127.0.0.1").await);
+
+        let next = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let next_port = next.local_addr().unwrap().port();
+        drop(next);
+        state
+            .network_tx
+            .send_modify(|settings| settings.port = Some(next_port));
+        let mut rebound = false;
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", next_port)).await.is_ok() {
+                rebound = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(rebound);
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+        relay.abort();
+    }
+
+    #[tokio::test]
+    async fn relay_refuses_clients_outside_the_allowed_subnets() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = snapshot_test_relay_state(None);
+        state.network_tx.send_replace(RelayNetworkSettings {
+            allowed_subnets: vec!["10.0.0.0/8".to_string()],
+            ..RelayNetworkSettings::default()
+        });
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap_or_default();
+            received
+        });
+        let (stream, peer) = listener.accept().await.unwrap();
+        timeout(
+            Duration::from_secs(5),
+            handle_connection(stream, peer, state, Arc::new(LoggedRelayEvents)),
+        )
+        .await
+        .expect("refused connections close immediately");
+        assert!(client.await.unwrap().is_empty());
+    }
+
+    #[derive(Default)]
+    pub(crate) struct RecordedRelayEvents(StdMutex<Vec<serde_json::Value>>);
+
+    impl RecordedRelayEvents {
+        pub(crate) fn health_events(&self) -> Vec<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|event| event["event"].as_str().unwrap_or_default().to_string())
+                .collect()
+        }
+    }
+
+    impl RelayEventSink for RecordedRelayEvents {
+        fn client_count_changed(&self, _count: usize) {}
+
+        fn record_health(&self, event: serde_json::Value) {
+            self.0.lock().unwrap().push(event);
+        }
+
+        #[cfg(feature = "desktop")]
+        fn sync_peers_changed(&self, _peers: &[SyncPeerSummary]) {}
+
+        #[cfg(feature = "desktop")]
+        fn sync_peer_doc_merged(&self, _fingerprint: &str) {}
+
+        fn held_doc_unreadable(&self) {}
+    }
+
+    /// Plain `ws://` relay with `policy`, serving on a loopback port.
+    pub(crate) async fn loopback_test_relay(
+        policy: RelayAbusePolicy,
+    ) -> (RelayState, Arc<RecordedRelayEvents>, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state: RelayState = Arc::new(SyncRelayState::with_abuse_policy(addr.port(), policy));
+        *state.pairing_token.write().unwrap() = "token".to_string();
+        state
+            .network_tx
+            .send_modify(|settings| settings.allow_plaintext = true);
+        let recorded = Arc::new(RecordedRelayEvents::default());
+        let events: RelayEvents = recorded.clone();
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, addr)) = listener.accept().await {
+                tokio::spawn(handle_connection(
+                    stream,
+                    addr,
+                    server_state.clone(),
+                    events.clone(),
+                ));
+            }
+        });
+        (state, recorded, addr)
+    }
+
+    pub(crate) async fn loopback_test_upgrade(
+        addr: SocketAddr,
+        token: &str,
+    ) -> Result<tokio_tungstenite::WebSocketStream<TcpStream>, tokio_tungstenite::tungstenite::Error>
+    {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        tokio_tungstenite::client_async(format!("ws://{}/?t={}", addr, token), stream)
+            .await
+            .map(|(ws_stream, _)| ws_stream)
+    }
+
+    #[test]
+    fn relay_auth_limiter_backs_off_exponentially_per_address() {
+        let policy = RelayAbusePolicy {
+            auth_failures_before_lockout: 3,
+            auth_lockout_base: Duration::from_secs(10),
+            auth_lockout_max: Duration::from_secs(60),
+            ..DEFAULT_RELAY_ABUSE_POLICY
+        };
+        let attacker: std::net::IpAddr = "192.168.1.66".parse().unwrap();
+        let mut limiter = RelayAuthLimiter::default();
+        assert_eq!(limiter.note_failure(attacker, 1_000, &policy), None);
+        assert_eq!(limiter.note_failure(attacker, 2_000, &policy), None);
+        assert_eq!(
+            limiter.note_failure(attacker, 3_000, &policy),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(limiter.locked_until(attacker, 3_000), Some(13_000));
+        assert_eq!(
+            limiter.locked_until("::ffff:192.168.1.66".parse().unwrap(), 3_000),
+            Some(13_000)
+        );
+        assert_eq!(
+            limiter.locked_until("192.168.1.67".parse().unwrap(), 3_000),
+            None
+        );
+        assert_eq!(limiter.locked_until(attacker, 13_000), None);
+
+        assert_eq!(
+            limiter.note_failure(attacker, 14_000, &policy),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            limiter.note_failure(attacker, 35_000, &policy),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(
+            limiter.note_failure(attacker, 76_000, &policy),
+            Some(Duration::from_secs(60))
+        );
+
+        // A quiet `auth_lockout_max` forgets the address.
+        assert_eq!(limiter.note_failure(attacker, 200_000, &policy), None);
+        limiter.note_success(attacker);
+        assert!(limiter.failures.is_empty());
+
+        for host in 0..RELAY_AUTH_TRACKED_ADDRESSES as u32 + 10 {
+            let ip = std::net::Ipv4Addr::from(0x0a00_0000 + host).into();
+            limiter.note_failure(ip, u64::from(host), &policy);
+        }
+        assert_eq!(limiter.failures.len(), RELAY_AUTH_TRACKED_ADDRESSES);
+        assert!(!limiter
+            .failures
+            .contains_key(&std::net::Ipv4Addr::from(0x0a00_0000).into()));
+    }
+
+    #[tokio::test]
+    async fn relay_locks_out_addresses_after_repeated_failed_upgrades() {
+        use tokio_tungstenite::tungstenite::Error as WsError;
+
+        let (state, recorded, addr) = loopback_test_relay(RelayAbusePolicy {
+            auth_failures_before_lockout: 2,
+            ..DEFAULT_RELAY_ABUSE_POLICY
+        })
+        .await;
+        for _ in 0..2 {
+            match loopback_test_upgrade(addr, "guess").await {
+                Err(WsError::Http(response)) => assert_eq!(response.status(), 401),
+                other => panic!("expected 401, got {:?}", other.map(|_| ())),
+            }
+        }
+        assert_eq!(recorded.health_events(), ["relay_auth_lockout"]);
+        assert!(state
+            .auth_limiter
+            .lock()
+            .unwrap()
+            .locked_until(addr.ip(), now_unix_ms())
+            .is_some());
+
+        // Locked out, even with the right token, and without a TLS or HTTP reply.
+        assert!(!matches!(
+            loopback_test_upgrade(addr, "token").await,
+            Ok(_) | Err(WsError::Http(_))
+        ));
+
+        state.auth_limiter.lock().unwrap().note_success(addr.ip());
+        assert!(loopback_test_upgrade(addr, "token").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn relay_closes_connections_that_send_oversized_frames() {
+        let (_state, recorded, addr) = loopback_test_relay(RelayAbusePolicy {
+            max_frame_bytes: 1024,
+            ..DEFAULT_RELAY_ABUSE_POLICY
+        })
+        .await;
+        let mut ws_stream = loopback_test_upgrade(addr, "token").await.unwrap();
+        ws_stream
+            .send(Message::Binary(vec![0u8; 4096].into()))
+            .await
+            .unwrap();
+
+        let close = timeout(Duration::from_secs(5), async {
+            loop {
+                match ws_stream.next().await {
+                    Some(Ok(Message::Close(frame))) => return frame,
+                    Some(Ok(_)) => continue,
+                    other => panic!("expected a close frame, got {:?}", other),
+                }
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(u16::from(close.code), RELAY_CLOSE_FRAME_TOO_LARGE);
+        let events = recorded.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "relay_frame_too_large");
+        assert_eq!(events[0]["limit"], 1024);
+    }
+
+    #[tokio::test]
+    async fn relay_refuses_connections_beyond_the_limit() {
+        let (state, recorded, addr) = loopback_test_relay(RelayAbusePolicy {
+            max_connections: 1,
+            ..DEFAULT_RELAY_ABUSE_POLICY
+        })
+        .await;
+        let first = loopback_test_upgrade(addr, "token").await.unwrap();
+        assert!(loopback_test_upgrade(addr, "token").await.is_err());
+        assert_eq!(recorded.health_events(), ["relay_connection_limit"]);
+
+        drop(first);
+        timeout(Duration::from_secs(5), async {
+            while state.connection_slots.available_permits() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("closing a connection frees its slot");
+        assert!(loopback_test_upgrade(addr, "token").await.is_ok());
+    }
+
+    #[test]
+    fn relay_http_requests_parse_and_match_etags() {
+        let head = b"GET /doc?t=token HTTP/1.1\r\nHost: relay\r\nIf-None-Match: W/\"a\", \"b\"\r\n\r\nrest";
+        assert_eq!(parse_relay_http_request(&head[..20]).unwrap(), None);
+        let request = parse_relay_http_request(head).unwrap().unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/doc");
+        assert_eq!(request.query.as_deref(), Some("t=token"));
+        assert_eq!(&head[request.head_len..], b"rest");
+        assert!(!request.is_websocket_upgrade());
+        let tags = request.header("if-none-match").unwrap();
+        assert!(relay_etag_matches(tags, "\"a\""));
+        assert!(relay_etag_matches(tags, "\"b\""));
+        assert!(!relay_etag_matches(tags, "\"c\""));
+        assert!(relay_etag_matches("*", "\"c\""));
+
+        let upgrade =
+            parse_relay_http_request(b"GET /?t=token HTTP/1.1\r\nUpgrade: WebSocket\r\n\r\n")
+                .unwrap()
+                .unwrap();
+        assert!(upgrade.is_websocket_upgrade());
+        assert_eq!(upgrade.path, "/");
+        assert!(parse_relay_http_request(b"\x16\x03\x01 not http\r\n\r\n").is_err());
+
+        let doc = automerge_like_bytes(1);
+        assert_eq!(relay_doc_etag(&doc), relay_doc_etag(&doc.clone()));
+        assert_ne!(
+            relay_doc_etag(&doc),
+            relay_doc_etag(&automerge_like_bytes(2))
+        );
+    }
+
+    /// Sends one raw HTTP request and returns the status, headers, and body.
+    pub(crate) async fn relay_http(
+        addr: SocketAddr,
+        request: &str,
+        body: &[u8],
+    ) -> (u16, Vec<(String, String)>, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+
+        let mut headers = [httparse::EMPTY_HEADER; 16];
+        let mut parsed = httparse::Response::new(&mut headers);
+        let head_len = parsed.parse(&response).unwrap().unwrap();
+        let headers = parsed
+            .headers
+            .iter()
+            .map(|header| {
+                (
+                    header.name.to_ascii_lowercase(),
+                    String::from_utf8_lossy(header.value).to_string(),
+                )
+            })
+            .collect();
+        (parsed.code.unwrap(), headers, response[head_len..].to_vec())
+    }
+
+    fn relay_http_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[tokio::test]
+    async fn relay_serves_health_and_conditional_doc_downloads_over_http() {
+        let (state, recorded, addr) = loopback_test_relay(RelayAbusePolicy {
+            max_frame_bytes: 64 * 1024,
+            ..DEFAULT_RELAY_ABUSE_POLICY
+        })
+        .await;
+        let get = |target: &str| format!("GET {} HTTP/1.1\r\nHost: relay\r\n\r\n", target);
+
+        let (status, _, _) = relay_http(addr, &get("/health?t=guess"), b"").await;
+        assert_eq!(status, 401);
+        let (status, _, body) = relay_http(addr, &get("/health?t=token"), b"").await;
+        assert_eq!(status, 200);
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["clientCount"], 0);
+        assert_eq!(health["docBytes"], 0);
+        assert!(health["etag"].is_null());
+        assert_eq!(relay_http(addr, &get("/doc?t=token"), b"").await.0, 404);
+        assert_eq!(relay_http(addr, &get("/nowhere?t=token"), b"").await.0, 404);
+        let pc = state
+            .devices
+            .lock()
+            .unwrap()
+            .issue_pairing_code(now_unix_ms());
+        assert_eq!(
+            relay_http(addr, &get(&format!("/health?pc={}", pc)), b"")
+                .await
+                .0,
+            401
+        );
+
+        // A WebSocket client on the same port sees the HTTP upload.
+        let mut ws_stream = loopback_test_upgrade(addr, "token").await.unwrap();
+        let doc = automerge_doc_bytes("title", "uploaded");
+        let put = format!(
+            "PUT /doc?t=token HTTP/1.1\r\nHost: relay\r\nContent-Length: {}\r\n\r\n",
+            doc.len()
+        );
+        let (status, headers, _) = relay_http(addr, &put, &doc).await;
+        assert_eq!(status, 200);
+        let etag = relay_http_header(&headers, "etag").unwrap().to_string();
+        let pushed = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(Ok(Message::Binary(data))) = ws_stream.next().await {
+                    return data;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(automerge_doc_entries(&pushed), automerge_doc_entries(&doc));
+
+        let (status, headers, body) = relay_http(addr, &get("/doc?t=token"), b"").await;
+        assert_eq!(status, 200);
+        assert_eq!(relay_http_header(&headers, "etag"), Some(etag.as_str()));
+        assert_eq!(
+            body,
+            state.current_doc.read().await.clone().unwrap().to_vec()
+        );
+        let conditional = format!(
+            "GET /doc?t=token HTTP/1.1\r\nHost: relay\r\nIf-None-Match: {}\r\n\r\n",
+            etag
+        );
+        let (status, _, body) = relay_http(addr, &conditional, b"").await;
+        assert_eq!(status, 304);
+        assert!(body.is_empty());
+
+        let (_, _, body) = relay_http(addr, &get("/health?t=token"), b"").await;
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["clientCount"], 1);
+        assert_eq!(health["etag"], etag);
+
+        let unsized_put = "PUT /doc?t=token HTTP/1.1\r\nHost: relay\r\n\r\n";
+        assert_eq!(relay_http(addr, unsized_put, b"").await.0, 411);
+        let huge_put =
+            "PUT /doc?t=token HTTP/1.1\r\nHost: relay\r\nContent-Length: 1000000\r\n\r\n";
+        assert_eq!(relay_http(addr, huge_put, b"").await.0, 413);
+        let garbage = b"not an automerge document";
+        let garbage_put = format!(
+            "PUT /doc?t=token HTTP/1.1\r\nHost: relay\r\nContent-Length: {}\r\n\r\n",
+            garbage.len()
+        );
+        assert_eq!(relay_http(addr, &garbage_put, garbage).await.0, 422);
+        assert_eq!(recorded.health_events(), ["relay_rejected_payload"]);
+        assert_eq!(
+            relay_http(addr, "DELETE /doc?t=token HTTP/1.1\r\n\r\n", b"")
+                .await
+                .0,
+            405
+        );
+
+        // Paused for a factory reset: no downloads, uploads are stale.
+        state
+            .accepting_doc_updates
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(relay_http(addr, &get("/doc?t=token"), b"").await.0, 503);
+        assert_eq!(relay_http(addr, &put, &doc).await.0, 409);
+    }
+
+    #[tokio::test]
+    async fn relay_keeps_a_corrupt_held_doc_and_reports_it() {
+        let (state, recorded, addr) = loopback_test_relay(RelayAbusePolicy {
+            max_frame_bytes: 64 * 1024,
+            ..DEFAULT_RELAY_ABUSE_POLICY
+        })
+        .await;
+        let corrupt = Bytes::from_static(b"\x85\x6f\x4a\x83 truncated");
+        *state.current_doc.write().await = Some(corrupt.clone());
+
+        let doc = automerge_doc_bytes("title", "pushed");
+        let put = format!(
+            "PUT /doc?t=token HTTP/1.1\r\nHost: relay\r\nContent-Length: {}\r\n\r\n",
+            doc.len()
+        );
+        let (status, _, body) = relay_http(addr, &put, &doc).await;
+        assert_eq!(status, 503);
+        assert!(String::from_utf8_lossy(&body).contains("held document"));
+        assert_eq!(state.current_doc.read().await.as_deref(), Some(&*corrupt));
+        assert_eq!(recorded.health_events(), ["relay_held_doc_unreadable"]);
+    }
+
+    #[tokio::test]
+    async fn relay_closes_with_a_reset_notice_when_a_broadcast_follows_a_reset() {
+        let (state, _recorded, addr) = loopback_test_relay(DEFAULT_RELAY_ABUSE_POLICY).await;
+        let mut phone = loopback_test_upgrade(addr, "token").await.unwrap();
+        let hello = serde_json::json!({
+            "type": "hello",
+            "version": RELAY_CONTROL_VERSION,
+            "clientId": "phone-1",
+            "capabilities": ["control"],
+        });
+        phone
+            .send(Message::Text(hello.to_string().into()))
+            .await
+            .unwrap();
+        async fn next_control(
+            ws: &mut tokio_tungstenite::WebSocketStream<TcpStream>,
+        ) -> serde_json::Value {
+            loop {
+                if let Some(Ok(Message::Text(text))) = ws.next().await {
+                    return serde_json::from_str(&text).unwrap();
+                }
+            }
+        }
+        let ack = timeout(Duration::from_secs(5), next_control(&mut phone))
+            .await
+            .unwrap();
+        assert_eq!(ack["type"], "ack");
+
+        // The generation moved on, but this connection never heard the reset.
+        state
+            .generation
+            .store(3, std::sync::atomic::Ordering::SeqCst);
+        let doc = automerge_doc_bytes("title", "after reset");
+        let _ = state
+            .broadcast_tx
+            .send(RelayUpdate::full_doc(&doc, Arc::default()));
+
+        let notice = timeout(Duration::from_secs(5), async {
+            loop {
+                let control = next_control(&mut phone).await;
+                if control["type"] != "clientCount" {
+                    return control;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            notice,
+            serde_json::json!({"type": "factoryReset", "generation": 3})
+        );
+        let close = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(Ok(Message::Close(frame))) = phone.next().await {
+                    return frame;
+                }
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(u16::from(close.code), RELAY_CLOSE_FACTORY_RESET);
+        assert_eq!(close.reason.as_str(), "factory_reset");
+    }
+
+    #[test]
+    fn sync_pairing_url_pins_certificate_when_tls_is_available() {
+        let state = snapshot_test_relay_state(None);
+        assert_eq!(
+            sync_pairing_url(&state, &["192.168.1.20"], "code"),
+            format!("ws://192.168.1.20:{}?pc=code", DEFAULT_SYNC_RELAY_PORT)
+        );
+
+        let tls = RelayTlsIdentity::generate()
+            .unwrap()
+            .to_relay_tls()
+            .unwrap();
+        let fingerprint = tls.fingerprint.clone();
+        *state.tls.write().unwrap() = Some(tls);
+        let url = sync_pairing_url(&state, &["192.168.1.20"], "code");
+        assert_eq!(
+            url,
+            format!(
+                "wss://192.168.1.20:{}?pc=code&fp={}",
+                DEFAULT_SYNC_RELAY_PORT, fingerprint
+            )
+        );
+
+        // Browsers need ws:// once plaintext is allowed; a client that can
+        // pin `fp` still uses TLS.
+        state
+            .network_tx
+            .send_modify(|settings| settings.allow_plaintext = true);
+        let url = sync_pairing_url(&state, &["192.168.1.20"], "code");
+        assert_eq!(
+            url,
+            format!(
+                "ws://192.168.1.20:{}?pc=code&fp={}",
+                DEFAULT_SYNC_RELAY_PORT, fingerprint
+            )
+        );
+        let target = parse_sync_cli_url(&url, "freed-sync (test)").unwrap();
+        assert!(target.secure);
+        assert_eq!(target.fingerprint, Some(fingerprint));
+    }
+
+    #[tokio::test]
+    async fn tls_relay_serves_plain_ws_clients_on_the_same_port_only_when_allowed() {
+        let (state, _, addr) = loopback_test_relay(DEFAULT_RELAY_ABUSE_POLICY).await;
+        let tls = RelayTlsIdentity::generate()
+            .unwrap()
+            .to_relay_tls()
+            .unwrap();
+        let fingerprint = tls.fingerprint.clone();
+        *state.tls.write().unwrap() = Some(tls);
+
+        // A browser cannot pin the certificate and connects in plaintext.
+        let mut plain = loopback_test_upgrade(addr, "token").await.unwrap();
+        plain.close(None).await.unwrap();
+
+        // Without the opt-in, plaintext never reaches authentication.
+        state
+            .network_tx
+            .send_modify(|settings| settings.allow_plaintext = false);
+        assert!(!matches!(
+            loopback_test_upgrade(addr, "token").await,
+            Ok(_) | Err(tokio_tungstenite::tungstenite::Error::Http(_))
+        ));
+
+        // A client holding `fp` completes TLS on the same port.
+        let stream = open_relay_client_stream("127.0.0.1", addr.port(), true, Some(&fingerprint))
+            .await
+            .unwrap();
+        let (mut pinned, _) =
+            tokio_tungstenite::client_async(format!("wss://{}/?t=token", addr), stream)
+                .await
+                .unwrap();
+        pinned.close(None).await.unwrap();
+    }
+}
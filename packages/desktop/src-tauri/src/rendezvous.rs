@@ -0,0 +1,1008 @@
+//! Relay rendezvous: the `freed-rendezvous` server and the relay's tunnel to it.
+//!
+//! A phone away from the LAN cannot reach the relay, so the relay can keep an
+//! outbound WebSocket open to a user-hosted rendezvous server
+//! (`freed-rendezvous`) that gives it a room. Phones connect to
+//! `/client/<room>` on the server, which forwards every byte of the connection,
+//! request head included, through the relay's tunnel. The relay serves each
+//! forwarded connection exactly like a LAN one, so pairing codes, device
+//! credentials, lockouts, and the HTTP endpoints behave the same. The server
+//! only ever sees upgrade credentials and sealed payloads.
+//!
+//! Tunnel messages are binary: a kind byte, a big-endian u64 session id, then
+//! the body (`open`: the phone's address; `data`: connection bytes; `close`:
+//! nothing).
+
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use crate::relay::{
+    admit_relay_connection, read_relay_http_request, relay_query_param, relay_websocket_config,
+    serve_relay_connection, write_relay_http_response, RelayEvents, RelayHttpRequest,
+    RelayHttpResponse, RelayState, DEFAULT_RELAY_ABUSE_POLICY, RELAY_HTTP_READ_TIMEOUT,
+};
+#[cfg(feature = "desktop")]
+use crate::relay::{generate_token, write_private_file, SyncRelayState};
+use crate::sync_cli::open_relay_client_stream;
+use crate::{SYNC_PEER_CONNECT_TIMEOUT, SYNC_PEER_RETRY_MAX_DELAY, SYNC_PEER_RETRY_MIN_DELAY};
+
+const RELAY_RENDEZVOUS_FILE: &str = "relay-rendezvous.json";
+const DEFAULT_RENDEZVOUS_PORT: u16 = 8766;
+/// Rooms one rendezvous server keeps; each is a relay that connected once.
+const RENDEZVOUS_MAX_ROOMS: usize = 1024;
+/// Phone connections one room forwards at a time.
+const RENDEZVOUS_MAX_SESSIONS: usize = 64;
+/// Bytes read from a forwarded connection per `data` message.
+const RENDEZVOUS_CHUNK_BYTES: usize = 16 * 1024;
+/// Pings keep an idle tunnel open through NATs and proxies.
+const RENDEZVOUS_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Persisted rendezvous settings. Every field is optional in the file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct RelayRendezvousSettings {
+    /// Base URL of the rendezvous server, e.g. `wss://freed.example.com`.
+    /// `None` keeps the relay on the LAN.
+    url: Option<String>,
+    /// Room this relay claims on the server. Created on first use.
+    room: String,
+    /// Proves to the server that the room is this relay's.
+    secret: String,
+}
+
+impl RelayRendezvousSettings {
+    pub(crate) fn load(data_dir: &Path) -> Self {
+        let Ok(raw) = std::fs::read(data_dir.join(RELAY_RENDEZVOUS_FILE)) else {
+            return Self::default();
+        };
+        match serde_json::from_slice::<Self>(&raw)
+            .map_err(|e| e.to_string())
+            .and_then(|settings| settings.validate().map(|()| settings))
+        {
+            Ok(settings) => settings,
+            Err(error) => {
+                warn!("[Sync] Ignoring unusable rendezvous settings: {}", error);
+                Self::default()
+            }
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    fn persist(&self, data_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_private_file(&data_dir.join(RELAY_RENDEZVOUS_FILE), &json)
+            .map_err(|e| format!("failed to persist rendezvous settings: {}", e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let Some(raw) = &self.url else {
+            return Ok(());
+        };
+        let url = url::Url::parse(raw).map_err(|e| format!("invalid rendezvous URL: {}", e))?;
+        if !matches!(url.scheme(), "ws" | "wss") {
+            return Err("rendezvous URL must be ws:// or wss://".to_string());
+        }
+        if url.host_str().is_none() {
+            return Err("rendezvous URL has no host".to_string());
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err("rendezvous URL must not have a query or fragment".to_string());
+        }
+        if !is_rendezvous_room(&self.room) || self.secret.len() < 16 {
+            return Err("rendezvous room is missing".to_string());
+        }
+        Ok(())
+    }
+
+    /// `path` on the server, with an explicit port so clients that default to
+    /// the relay port still find it.
+    fn endpoint(&self, path: &str) -> Option<String> {
+        let url = url::Url::parse(self.url.as_deref()?).ok()?;
+        Some(format!(
+            "{}://{}:{}{}{}",
+            url.scheme(),
+            url.host_str()?,
+            url.port_or_known_default()?,
+            url.path().trim_end_matches('/'),
+            path
+        ))
+    }
+}
+
+fn is_rendezvous_room(room: &str) -> bool {
+    (1..=64).contains(&room.len())
+        && room
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+/// Rendezvous state for the desktop UI. The room secret never leaves Rust.
+#[cfg(feature = "desktop")]
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RelayRendezvousStatus {
+    url: Option<String>,
+    room: Option<String>,
+    connected: bool,
+}
+
+#[cfg(feature = "desktop")]
+pub(crate) fn relay_rendezvous_status(state: &SyncRelayState) -> RelayRendezvousStatus {
+    let settings = state.rendezvous_tx.borrow();
+    RelayRendezvousStatus {
+        url: settings.url.clone(),
+        room: (!settings.room.is_empty()).then(|| settings.room.clone()),
+        connected: state
+            .rendezvous_connected
+            .load(std::sync::atomic::Ordering::SeqCst),
+    }
+}
+
+/// Sets or clears the rendezvous server, creating the room on first use. The
+/// room survives clearing, so re-enabling keeps previously shared links.
+#[cfg(feature = "desktop")]
+pub(crate) fn set_relay_rendezvous_in(
+    state: &SyncRelayState,
+    data_dir: &Path,
+    url: Option<String>,
+) -> Result<RelayRendezvousStatus, String> {
+    let mut settings = state.rendezvous_tx.borrow().clone();
+    settings.url = url
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty());
+    if settings.url.is_some() && settings.room.is_empty() {
+        settings.room = generate_token();
+        settings.secret = generate_token();
+    }
+    settings.validate()?;
+    settings.persist(data_dir)?;
+    state.rendezvous_tx.send_replace(settings);
+    Ok(relay_rendezvous_status(state))
+}
+
+/// Pairing URL that reaches the relay through its rendezvous server. It has
+/// no `fp=`: the phone verifies the server's own certificate, and payloads
+/// stay sealed under the sync key.
+#[cfg(feature = "desktop")]
+pub(crate) fn rendezvous_pairing_url(
+    state: &SyncRelayState,
+    settings: &RelayRendezvousSettings,
+    pairing_code: &str,
+) -> Option<String> {
+    let mut url = format!(
+        "{}?pc={}",
+        settings.endpoint(&format!("/client/{}", settings.room))?,
+        pairing_code
+    );
+    if let Some(key) = state.payload_key.read().unwrap().as_ref() {
+        url.push_str("#k=");
+        url.push_str(&key.secret_param());
+    }
+    Some(url)
+}
+
+/// One tunnel message.
+#[derive(Debug, PartialEq, Eq)]
+enum RendezvousPacket {
+    /// A phone connected to the server from `address`.
+    Open {
+        session: u64,
+        address: String,
+    },
+    Data {
+        session: u64,
+        data: Bytes,
+    },
+    /// Either side hung up.
+    Close {
+        session: u64,
+    },
+}
+
+type RendezvousSender = tokio::sync::mpsc::UnboundedSender<RendezvousPacket>;
+
+impl RendezvousPacket {
+    fn session(&self) -> u64 {
+        match self {
+            Self::Open { session, .. } | Self::Data { session, .. } | Self::Close { session } => {
+                *session
+            }
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let (kind, body): (u8, &[u8]) = match self {
+            Self::Open { address, .. } => (0, address.as_bytes()),
+            Self::Data { data, .. } => (1, data),
+            Self::Close { .. } => (2, &[]),
+        };
+        let mut message = Vec::with_capacity(9 + body.len());
+        message.push(kind);
+        message.extend_from_slice(&self.session().to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn decode(message: &Bytes) -> Result<Self, String> {
+        if message.len() < 9 {
+            return Err(format!(
+                "tunnel message too short ({} bytes)",
+                message.len()
+            ));
+        }
+        let session = u64::from_be_bytes(message[1..9].try_into().unwrap());
+        let body = message.slice(9..);
+        match message[0] {
+            0 => Ok(Self::Open {
+                session,
+                address: String::from_utf8(body.to_vec())
+                    .map_err(|_| "open address is not UTF-8".to_string())?,
+            }),
+            1 => Ok(Self::Data {
+                session,
+                data: body,
+            }),
+            2 if body.is_empty() => Ok(Self::Close { session }),
+            kind => Err(format!("unknown tunnel message kind {}", kind)),
+        }
+    }
+}
+
+/// Serves one phone connection that arrived through the tunnel as if it had
+/// arrived on the LAN.
+async fn serve_relay_rendezvous_session(
+    state: RelayState,
+    events: RelayEvents,
+    session: u64,
+    addr: SocketAddr,
+    mut inbound: tokio::sync::mpsc::UnboundedReceiver<Bytes>,
+    outbound: RendezvousSender,
+) {
+    let Some(slot) = admit_relay_connection(&state, &*events, addr) else {
+        let _ = outbound.send(RendezvousPacket::Close { session });
+        return;
+    };
+    info!("[Sync] New rendezvous connection from: {}", addr);
+    let (relay_side, tunnel_side) = tokio::io::duplex(4 * RENDEZVOUS_CHUNK_BYTES);
+    tokio::spawn(async move {
+        let _slot = slot;
+        serve_relay_connection(relay_side, addr, state, events).await;
+    });
+
+    let (mut reader, mut writer) = tokio::io::split(tunnel_side);
+    // A separate task, so a relay busy writing never waits on a phone upload.
+    let forward = tokio::spawn(async move {
+        while let Some(data) = inbound.recv().await {
+            if writer.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+    let mut buffer = vec![0u8; RENDEZVOUS_CHUNK_BYTES];
+    loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                let data = Bytes::copy_from_slice(&buffer[..read]);
+                if outbound
+                    .send(RendezvousPacket::Data { session, data })
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = outbound.send(RendezvousPacket::Close { session });
+    forward.abort();
+}
+
+/// Runs one tunnel to the rendezvous server until it drops.
+async fn serve_relay_rendezvous_tunnel(
+    state: &RelayState,
+    events: &RelayEvents,
+    settings: &RelayRendezvousSettings,
+) -> Result<(), String> {
+    let endpoint = settings
+        .endpoint(&format!("/relay/{}", settings.room))
+        .ok_or("rendezvous URL is invalid")?;
+    let url = url::Url::parse(&endpoint).map_err(|e| e.to_string())?;
+    let stream = open_relay_client_stream(
+        url.host_str().unwrap_or_default(),
+        url.port().unwrap_or_default(),
+        url.scheme() == "wss",
+        None,
+    )
+    .await?;
+    let upgrade = tokio_tungstenite::client_async_with_config(
+        format!("{}?s={}", endpoint, settings.secret),
+        stream,
+        Some(relay_websocket_config(&state.abuse_policy)),
+    );
+    let ws_stream = match timeout(SYNC_PEER_CONNECT_TIMEOUT, upgrade).await {
+        Ok(Ok((ws_stream, _))) => ws_stream,
+        Ok(Err(WsError::Http(response))) => {
+            return Err(format!(
+                "rendezvous server refused the tunnel ({})",
+                response.status()
+            ))
+        }
+        Ok(Err(error)) => return Err(format!("upgrade failed: {}", error)),
+        Err(_) => return Err("upgrade timed out".to_string()),
+    };
+    state
+        .rendezvous_connected
+        .store(true, std::sync::atomic::Ordering::SeqCst);
+    info!("[Sync] Rendezvous tunnel open at {}", endpoint);
+    events.record_health(serde_json::json!({
+        "event": "relay_rendezvous_connected",
+        "url": settings.url,
+    }));
+
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let (outbound_tx, mut outbound_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut sessions: HashMap<u64, tokio::sync::mpsc::UnboundedSender<Bytes>> = HashMap::new();
+    let mut keepalive = tokio::time::interval(RENDEZVOUS_KEEPALIVE_INTERVAL);
+    keepalive.tick().await;
+    loop {
+        tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(Ok(Message::Binary(data))) => match RendezvousPacket::decode(&data) {
+                    Ok(RendezvousPacket::Open { session, address }) => {
+                        let addr = address
+                            .parse()
+                            .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+                        let (inbound_tx, inbound_rx) = tokio::sync::mpsc::unbounded_channel();
+                        sessions.insert(session, inbound_tx);
+                        tokio::spawn(serve_relay_rendezvous_session(
+                            state.clone(),
+                            events.clone(),
+                            session,
+                            addr,
+                            inbound_rx,
+                            outbound_tx.clone(),
+                        ));
+                    }
+                    Ok(RendezvousPacket::Data { session, data }) => {
+                        if let Some(inbound) = sessions.get(&session) {
+                            let _ = inbound.send(data);
+                        }
+                    }
+                    Ok(RendezvousPacket::Close { session }) => {
+                        sessions.remove(&session);
+                    }
+                    Err(error) => warn!("[Sync] Ignored malformed tunnel message: {}", error),
+                },
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err("rendezvous server closed the tunnel".to_string())
+                }
+                Some(Ok(_)) => {}
+                Some(Err(error)) => return Err(error.to_string()),
+            },
+            Some(packet) = outbound_rx.recv() => {
+                if let RendezvousPacket::Close { session } = &packet {
+                    sessions.remove(session);
+                }
+                ws_sender
+                    .send(Message::Binary(packet.encode().into()))
+                    .await
+                    .map_err(|e| format!("tunnel send failed: {}", e))?;
+            }
+            _ = keepalive.tick() => {
+                ws_sender
+                    .send(Message::Ping(Bytes::new()))
+                    .await
+                    .map_err(|e| format!("tunnel send failed: {}", e))?;
+            }
+        }
+    }
+}
+
+/// Keeps the tunnel to the configured rendezvous server open, reconnecting
+/// with backoff and whenever the settings change.
+pub(crate) async fn run_relay_rendezvous(state: RelayState, events: RelayEvents) {
+    let mut settings_rx = state.rendezvous_tx.subscribe();
+    loop {
+        let settings = settings_rx.borrow_and_update().clone();
+        if settings.url.is_some() {
+            let mut delay = SYNC_PEER_RETRY_MIN_DELAY;
+            loop {
+                let started = Instant::now();
+                let (reason, changed) = tokio::select! {
+                    result = serve_relay_rendezvous_tunnel(&state, &events, &settings) => {
+                        (result.err().unwrap_or_default(), false)
+                    }
+                    _ = settings_rx.changed() => ("rendezvous settings changed".to_string(), true),
+                };
+                if state
+                    .rendezvous_connected
+                    .swap(false, std::sync::atomic::Ordering::SeqCst)
+                {
+                    events.record_health(serde_json::json!({
+                        "event": "relay_rendezvous_disconnected",
+                        "reason": reason,
+                    }));
+                }
+                if changed {
+                    break;
+                }
+                warn!(
+                    "[Sync] Rendezvous server unreachable: {}; retrying in {}s",
+                    reason,
+                    delay.as_secs()
+                );
+                if started.elapsed() >= SYNC_PEER_RETRY_MAX_DELAY {
+                    delay = SYNC_PEER_RETRY_MIN_DELAY;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = settings_rx.changed() => break,
+                }
+                delay = (delay * 2).min(SYNC_PEER_RETRY_MAX_DELAY);
+            }
+        } else if settings_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Rooms and their tunnels on a rendezvous server.
+#[derive(Default)]
+struct RendezvousServer {
+    rooms: StdMutex<HashMap<String, RendezvousRoom>>,
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+struct RendezvousRoom {
+    /// SHA-256 of the secret of the relay that created the room.
+    secret_digest: [u8; 32],
+    /// Current tunnel id and its sender, while the relay is connected.
+    relay: Option<(u64, RendezvousSender)>,
+    /// Forwarded phone connections.
+    sessions: HashMap<u64, RendezvousSender>,
+}
+
+impl RendezvousServer {
+    fn next_id(&self) -> u64 {
+        self.next_id
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+            + 1
+    }
+
+    /// Attaches a relay's tunnel to `room`, creating the room for a new
+    /// secret. A relay that reconnects before its old tunnel was noticed
+    /// dropping replaces it; phones on the old tunnel reconnect.
+    fn claim(
+        &self,
+        room: &str,
+        secret: &str,
+        tunnel: u64,
+        relay: RendezvousSender,
+    ) -> Result<(), RelayHttpResponse> {
+        let digest: [u8; 32] = Sha256::digest(secret.as_bytes()).into();
+        let mut rooms = self.rooms.lock().unwrap();
+        if !rooms.contains_key(room) && rooms.len() >= RENDEZVOUS_MAX_ROOMS {
+            return Err(RelayHttpResponse::error(503, "rendezvous server is full"));
+        }
+        let entry = rooms
+            .entry(room.to_string())
+            .or_insert_with(|| RendezvousRoom {
+                secret_digest: digest,
+                relay: None,
+                sessions: HashMap::new(),
+            });
+        if entry.secret_digest != digest {
+            return Err(RelayHttpResponse::error(
+                401,
+                "room belongs to another relay",
+            ));
+        }
+        entry.relay = Some((tunnel, relay));
+        entry.sessions.clear();
+        Ok(())
+    }
+
+    /// Detaches `tunnel` unless a newer one has replaced it.
+    fn release(&self, room: &str, tunnel: u64) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(entry) = rooms.get_mut(room) {
+            if entry.relay.as_ref().is_some_and(|(id, _)| *id == tunnel) {
+                entry.relay = None;
+                entry.sessions.clear();
+            }
+        }
+    }
+
+    /// Registers a phone connection. Returns its session id, the relay's
+    /// sender, and the receiver for the relay's replies.
+    fn open_session(
+        &self,
+        room: &str,
+    ) -> Result<
+        (
+            u64,
+            RendezvousSender,
+            tokio::sync::mpsc::UnboundedReceiver<RendezvousPacket>,
+        ),
+        RelayHttpResponse,
+    > {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(entry) = rooms.get_mut(room) else {
+            return Err(RelayHttpResponse::error(404, "unknown room"));
+        };
+        let Some((_, relay)) = &entry.relay else {
+            return Err(RelayHttpResponse::error(503, "desktop is offline"));
+        };
+        if entry.sessions.len() >= RENDEZVOUS_MAX_SESSIONS {
+            return Err(RelayHttpResponse::error(
+                503,
+                "too many connections to this room",
+            ));
+        }
+        let relay = relay.clone();
+        let session = self.next_id();
+        let (phone_tx, phone_rx) = tokio::sync::mpsc::unbounded_channel();
+        entry.sessions.insert(session, phone_tx);
+        Ok((session, relay, phone_rx))
+    }
+
+    fn session(&self, room: &str, session: u64) -> Option<RendezvousSender> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms.get(room)?.sessions.get(&session).cloned()
+    }
+
+    fn close_session(&self, room: &str, session: u64) {
+        if let Some(entry) = self.rooms.lock().unwrap().get_mut(room) {
+            entry.sessions.remove(&session);
+        }
+    }
+}
+
+/// Drops the `/client/<room>` prefix from the request line, so the relay sees
+/// its own paths (`/`, `/health`, `/doc`).
+fn strip_rendezvous_client_prefix(received: &[u8], room: &str) -> Vec<u8> {
+    let prefix = format!("/client/{}", room);
+    let line_end = received
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .unwrap_or(received.len());
+    let Some(start) = received[..line_end]
+        .windows(prefix.len())
+        .position(|window| window == prefix.as_bytes())
+    else {
+        return received.to_vec();
+    };
+    let rest = &received[start + prefix.len()..];
+    let mut stripped = Vec::with_capacity(received.len());
+    stripped.extend_from_slice(&received[..start]);
+    if !rest.starts_with(b"/") {
+        stripped.push(b'/');
+    }
+    stripped.extend_from_slice(rest);
+    stripped
+}
+
+/// Holds a relay's tunnel open and routes its replies to phones.
+async fn serve_rendezvous_relay(
+    server: Arc<RendezvousServer>,
+    mut stream: TcpStream,
+    received: Vec<u8>,
+    request: &RelayHttpRequest,
+    room: &str,
+    addr: SocketAddr,
+) {
+    let secret = relay_query_param(request.query.as_deref(), "s").unwrap_or_default();
+    if secret.len() < 16 {
+        let response = RelayHttpResponse::error(401, "room secret is missing");
+        write_relay_http_response(&mut stream, addr, &response).await;
+        return;
+    }
+    let tunnel = server.next_id();
+    let (relay_tx, mut relay_rx) = tokio::sync::mpsc::unbounded_channel();
+    if let Err(response) = server.claim(room, secret, tunnel, relay_tx) {
+        warn!("[Rendezvous] Refused relay {} for room {}", addr, room);
+        write_relay_http_response(&mut stream, addr, &response).await;
+        return;
+    }
+    let (read_half, write_half) = tokio::io::split(stream);
+    let stream = tokio::io::join(std::io::Cursor::new(received).chain(read_half), write_half);
+    let upgrade = tokio_tungstenite::accept_async_with_config(
+        stream,
+        Some(relay_websocket_config(&DEFAULT_RELAY_ABUSE_POLICY)),
+    );
+    let ws_stream = match timeout(SYNC_PEER_CONNECT_TIMEOUT, upgrade).await {
+        Ok(Ok(ws_stream)) => ws_stream,
+        _ => {
+            warn!("[Rendezvous] Tunnel upgrade from {} failed", addr);
+            server.release(room, tunnel);
+            return;
+        }
+    };
+    info!("[Rendezvous] Relay {} joined room {}", addr, room);
+
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    loop {
+        tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(Ok(Message::Binary(data))) => match RendezvousPacket::decode(&data) {
+                    Ok(RendezvousPacket::Open { .. }) => {}
+                    Ok(packet) => {
+                        if let Some(phone) = server.session(room, packet.session()) {
+                            let _ = phone.send(packet);
+                        }
+                    }
+                    Err(error) => {
+                        warn!("[Rendezvous] Ignored malformed tunnel message in room {}: {}", room, error);
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            packet = relay_rx.recv() => {
+                // `None` once a newer tunnel has replaced this one.
+                let Some(packet) = packet else { break };
+                if ws_sender.send(Message::Binary(packet.encode().into())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    server.release(room, tunnel);
+    info!("[Rendezvous] Relay {} left room {}", addr, room);
+}
+
+/// Forwards one phone connection through its room's tunnel.
+async fn serve_rendezvous_client(
+    server: Arc<RendezvousServer>,
+    mut stream: TcpStream,
+    received: Vec<u8>,
+    room: &str,
+    addr: SocketAddr,
+) {
+    let (session, relay, mut phone_rx) = match server.open_session(room) {
+        Ok(opened) => opened,
+        Err(response) => {
+            write_relay_http_response(&mut stream, addr, &response).await;
+            return;
+        }
+    };
+    let head = strip_rendezvous_client_prefix(&received, room);
+    let _ = relay.send(RendezvousPacket::Open {
+        session,
+        address: addr.to_string(),
+    });
+    let _ = relay.send(RendezvousPacket::Data {
+        session,
+        data: head.into(),
+    });
+
+    let (mut read_half, mut write_half) = stream.into_split();
+    let upload = async {
+        let mut buffer = vec![0u8; RENDEZVOUS_CHUNK_BYTES];
+        loop {
+            match read_half.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    let data = Bytes::copy_from_slice(&buffer[..read]);
+                    if relay
+                        .send(RendezvousPacket::Data { session, data })
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+    };
+    let download = async {
+        while let Some(RendezvousPacket::Data { data, .. }) = phone_rx.recv().await {
+            if write_half.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+        let _ = write_half.shutdown().await;
+    };
+    tokio::select! {
+        _ = upload => {}
+        _ = download => {}
+    }
+    let _ = relay.send(RendezvousPacket::Close { session });
+    server.close_session(room, session);
+}
+
+async fn handle_rendezvous_connection(
+    server: Arc<RendezvousServer>,
+    mut stream: TcpStream,
+    addr: SocketAddr,
+) {
+    let (received, request) = match timeout(
+        RELAY_HTTP_READ_TIMEOUT,
+        read_relay_http_request(&mut stream),
+    )
+    .await
+    {
+        Ok(Ok(head)) => head,
+        Ok(Err(error)) => {
+            warn!("[Rendezvous] Unreadable request from {}: {}", addr, error);
+            return;
+        }
+        Err(_) => return,
+    };
+    let segments: Vec<&str> = request
+        .path
+        .trim_start_matches('/')
+        .splitn(3, '/')
+        .collect();
+    match segments[..] {
+        ["relay", room] if is_rendezvous_room(room) && request.is_websocket_upgrade() => {
+            serve_rendezvous_relay(server, stream, received, &request, room, addr).await
+        }
+        ["client", room, ..] if is_rendezvous_room(room) => {
+            serve_rendezvous_client(server, stream, received, room, addr).await
+        }
+        _ => {
+            let response = RelayHttpResponse::error(404, "not found");
+            write_relay_http_response(&mut stream, addr, &response).await;
+        }
+    }
+}
+
+async fn serve_rendezvous(listener: TcpListener, server: Arc<RendezvousServer>) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        tokio::spawn(handle_rendezvous_connection(server.clone(), stream, addr));
+    }
+}
+
+/// Runs a rendezvous server on `listen` (default `0.0.0.0:8766`) until
+/// interrupted.
+///
+/// It holds no state on disk: rooms are claimed again when relays reconnect.
+/// It serves plain `ws://`; put it behind a TLS-terminating proxy for phones.
+pub fn run_rendezvous_server(listen: Option<SocketAddr>) -> Result<(), String> {
+    let listen =
+        listen.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], DEFAULT_RENDEZVOUS_PORT)));
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+    runtime.block_on(async move {
+        let listener = TcpListener::bind(listen)
+            .await
+            .map_err(|e| format!("failed to bind {}: {}", listen, e))?;
+        info!("[Rendezvous] Listening on {}", listen);
+        tokio::select! {
+            _ = serve_rendezvous(listener, Arc::new(RendezvousServer::default())) => {
+                Err("rendezvous server stopped accepting connections".to_string())
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("[Rendezvous] Stopped");
+                Ok(())
+            }
+        }
+    })
+}
+
+#[cfg(all(test, feature = "desktop"))]
+mod tests {
+    use super::*;
+    use crate::now_unix_ms;
+    use crate::relay::{
+        tests::{automerge_doc_bytes, automerge_doc_entries, relay_http, RecordedRelayEvents},
+        RelayPayloadKey, DEFAULT_SYNC_RELAY_PORT,
+    };
+    use crate::sync_cli::{parse_sync_cli_url, sync_cli_pull};
+
+    #[test]
+    fn rendezvous_packets_settings_and_paths_round_trip() {
+        for packet in [
+            RendezvousPacket::Open {
+                session: 7,
+                address: "203.0.113.9:51000".to_string(),
+            },
+            RendezvousPacket::Data {
+                session: u64::MAX,
+                data: Bytes::from_static(b"GET / HTTP/1.1\r\n\r\n"),
+            },
+            RendezvousPacket::Close { session: 1 },
+        ] {
+            assert_eq!(
+                RendezvousPacket::decode(&Bytes::from(packet.encode())).unwrap(),
+                packet
+            );
+        }
+        for malformed in [
+            &b"\x01\x00\x00"[..],
+            b"\x09\x00\x00\x00\x00\x00\x00\x00\x01",
+        ] {
+            assert!(RendezvousPacket::decode(&Bytes::copy_from_slice(malformed)).is_err());
+        }
+
+        assert_eq!(
+            strip_rendezvous_client_prefix(
+                b"GET /client/room-1?v=1&t=x HTTP/1.1\r\nHost: rv\r\n\r\n",
+                "room-1"
+            ),
+            b"GET /?v=1&t=x HTTP/1.1\r\nHost: rv\r\n\r\n"
+        );
+        assert_eq!(
+            strip_rendezvous_client_prefix(
+                b"PUT /client/room-1/doc?t=x HTTP/1.1\r\n\r\n",
+                "room-1"
+            ),
+            b"PUT /doc?t=x HTTP/1.1\r\n\r\n"
+        );
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let state = SyncRelayState::new(DEFAULT_SYNC_RELAY_PORT);
+        for invalid in [
+            "https://rv.example.com",
+            "wss://rv.example.com/?room=1",
+            "not a url",
+        ] {
+            assert!(
+                set_relay_rendezvous_in(&state, data_dir.path(), Some(invalid.to_string()))
+                    .is_err(),
+                "{}",
+                invalid
+            );
+        }
+        let status = set_relay_rendezvous_in(
+            &state,
+            data_dir.path(),
+            Some("wss://rv.example.com/freed/".to_string()),
+        )
+        .unwrap();
+        let room = status.room.clone().unwrap();
+        assert_eq!(
+            status,
+            RelayRendezvousStatus {
+                url: Some("wss://rv.example.com/freed".to_string()),
+                room: Some(room.clone()),
+                connected: false,
+            }
+        );
+        let settings = RelayRendezvousSettings::load(data_dir.path());
+        assert_eq!(settings, *state.rendezvous_tx.borrow());
+        assert_eq!(
+            rendezvous_pairing_url(&state, &settings, "code-1").unwrap(),
+            format!("wss://rv.example.com:443/freed/client/{}?pc=code-1", room)
+        );
+
+        // Clearing keeps the room, so re-enabling keeps shared links working.
+        set_relay_rendezvous_in(&state, data_dir.path(), None).unwrap();
+        let cleared = RelayRendezvousSettings::load(data_dir.path());
+        assert_eq!(cleared.url, None);
+        assert_eq!(cleared.room, room);
+        assert!(rendezvous_pairing_url(&state, &cleared, "code-1").is_none());
+    }
+
+    #[tokio::test]
+    async fn relay_serves_phones_through_a_rendezvous_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_rendezvous(
+            listener,
+            Arc::new(RendezvousServer::default()),
+        ));
+
+        let state: RelayState = Arc::new(SyncRelayState::new(DEFAULT_SYNC_RELAY_PORT));
+        *state.pairing_token.write().unwrap() = "token".to_string();
+        let key = Arc::new(RelayPayloadKey::generate());
+        *state.payload_key.write().unwrap() = Some(key.clone());
+        let data_dir = tempfile::tempdir().unwrap();
+        let room = set_relay_rendezvous_in(
+            &state,
+            data_dir.path(),
+            Some(format!("ws://{}", server_addr)),
+        )
+        .unwrap()
+        .room
+        .unwrap();
+        let recorded = Arc::new(RecordedRelayEvents::default());
+        let events: RelayEvents = recorded.clone();
+        let relay = tokio::spawn(run_relay_rendezvous(state.clone(), events));
+        timeout(Duration::from_secs(10), async {
+            while !relay_rendezvous_status(&state).connected {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the relay opens its tunnel");
+
+        let upgrade = |path: String| async move {
+            let stream = TcpStream::connect(server_addr).await.unwrap();
+            tokio_tungstenite::client_async(format!("ws://{}{}", server_addr, path), stream)
+                .await
+                .map(|(ws_stream, _)| ws_stream)
+        };
+        // Another relay cannot take the room over.
+        let hijack = upgrade(format!("/relay/{}?s={}", room, "x".repeat(43))).await;
+        assert!(matches!(hijack, Err(WsError::Http(response)) if response.status() == 401));
+
+        // The relay checks credentials exactly as it does on the LAN.
+        let rejected = upgrade(format!("/client/{}?t=wrong", room)).await;
+        assert!(matches!(rejected, Err(WsError::Http(response)) if response.status() == 401));
+        let mut phone = upgrade(format!("/client/{}?t=token", room)).await.unwrap();
+        let sealed = key.seal(&automerge_doc_bytes("title", "a")).unwrap();
+        phone.send(Message::Binary(sealed.into())).await.unwrap();
+        timeout(Duration::from_secs(10), async {
+            while state.current_doc.read().await.is_none() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the phone's push reaches the relay");
+        let clients = state.clients.lock().unwrap().summaries();
+        assert_eq!(clients.len(), 1);
+        assert!(clients[0].address.starts_with("127.0.0.1"));
+
+        let (status, _, body) = relay_http(
+            server_addr,
+            &format!(
+                "GET /client/{}/health?t=token HTTP/1.1\r\nHost: rv\r\n\r\n",
+                room
+            ),
+            b"",
+        )
+        .await;
+        assert_eq!(status, 200);
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["clientCount"], 1);
+
+        let code = state
+            .devices
+            .lock()
+            .unwrap()
+            .issue_pairing_code(now_unix_ms());
+        let settings = state.rendezvous_tx.borrow().clone();
+        let link = rendezvous_pairing_url(&state, &settings, &code).unwrap();
+        let target = parse_sync_cli_url(&link, "freed-sync (away)").unwrap();
+        let pulled = data_dir.path().join("pulled.automerge");
+        sync_cli_pull(&target, &pulled).await.unwrap();
+        assert_eq!(
+            automerge_doc_entries(&std::fs::read(&pulled).unwrap()),
+            vec![("title".to_string(), "a".to_string())]
+        );
+
+        // Clearing the setting closes the tunnel and every forwarded session.
+        set_relay_rendezvous_in(&state, data_dir.path(), None).unwrap();
+        timeout(Duration::from_secs(10), async {
+            while let Some(Ok(_)) = phone.next().await {}
+        })
+        .await
+        .expect("the phone is disconnected");
+        let (status, _, _) = relay_http(
+            server_addr,
+            &format!("GET /client/{}/health?t=token HTTP/1.1\r\n\r\n", room),
+            b"",
+        )
+        .await;
+        assert_eq!(status, 503);
+        assert!(!relay_rendezvous_status(&state).connected);
+        let rendezvous_events: Vec<String> = recorded
+            .health_events()
+            .into_iter()
+            .filter(|event| event.starts_with("relay_rendezvous_"))
+            .collect();
+        assert_eq!(
+            rendezvous_events,
+            [
+                "relay_rendezvous_connected",
+                "relay_rendezvous_disconnected"
+            ]
+        );
+        relay.abort();
+    }
+}
@@ -0,0 +1,1242 @@
+//! Local snapshot rotation (grandfather-father-son): the content-addressed
+//! snapshot store, its retention policy, and the debounced writer.
+//!
+//! Each snapshot is a small `freed-{ts}.json` record naming a zstd-compressed
+//! blob in `blobs/` by the SHA-256 of the document, so an unchanged document
+//! costs one record instead of another full copy. Plain `freed-{ts}.automerge`
+//! files from earlier versions are still listed, restored, and pruned.
+
+use bytes::Bytes;
+#[cfg(feature = "desktop")]
+use log::info;
+use log::{error, warn};
+#[cfg(feature = "desktop")]
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::time::Duration;
+
+use crate::relay::AUTOMERGE_MAGIC_BYTES;
+#[cfg(feature = "desktop")]
+use crate::relay::{format_relay_heads_hex, RelayEventSink, RelayEvents, RelayHead};
+#[cfg(feature = "desktop")]
+use crate::write_file_atomically;
+
+const SNAPSHOT_RETENTION_FILE: &str = "snapshot-retention.json";
+
+const SNAPSHOT_WRITE_DEBOUNCE: Duration = Duration::from_secs(10);
+const SNAPSHOT_WRITE_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Subdirectory of `snapshots/` holding compressed, content-addressed documents.
+const SNAPSHOT_BLOB_DIR: &str = "blobs";
+#[cfg(feature = "desktop")]
+const SNAPSHOT_ZSTD_LEVEL: i32 = 3;
+/// Largest document `read_snapshot` will decompress. Records are not trusted
+/// to size the output buffer on their own.
+#[cfg(feature = "desktop")]
+const SNAPSHOT_MAX_DOC_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[cfg(feature = "desktop")]
+pub(crate) fn snapshot_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("snapshots")
+}
+
+/// Parses the Unix-seconds timestamp out of a snapshot name: a `freed-{ts}.json`
+/// index record, or a plain `freed-{ts}.automerge` document written before
+/// snapshots were content-addressed. Anything else, including names with path
+/// separators, yields `None`.
+fn snapshot_file_timestamp(name: &str) -> Option<u64> {
+    let stem = name.strip_prefix("freed-")?;
+    let digits = stem
+        .strip_suffix(".json")
+        .or_else(|| stem.strip_suffix(".automerge"))?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Cheap structural check before a stored document is put back on the relay.
+pub(crate) fn validate_snapshot_bytes(bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() <= AUTOMERGE_MAGIC_BYTES.len() {
+        return Err(format!("snapshot is too small ({} bytes)", bytes.len()));
+    }
+    if bytes[..AUTOMERGE_MAGIC_BYTES.len()] != AUTOMERGE_MAGIC_BYTES {
+        return Err("snapshot is not an Automerge document".to_string());
+    }
+    Ok(())
+}
+
+/// A `freed-{ts}.json` index record. The document itself lives in
+/// `blobs/{sha256}.zst`, shared by every record with identical bytes.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SnapshotRecord {
+    /// Lowercase hex SHA-256 of the uncompressed document.
+    sha256: String,
+    /// Uncompressed document length.
+    bytes: u64,
+}
+
+impl SnapshotRecord {
+    fn load(path: &Path) -> Result<Self, String> {
+        let raw = std::fs::read(path).map_err(|e| e.to_string())?;
+        let record: Self = serde_json::from_slice(&raw).map_err(|e| e.to_string())?;
+        if record.sha256.len() != 64
+            || !record
+                .sha256
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        {
+            return Err("snapshot record has an invalid digest".to_string());
+        }
+        Ok(record)
+    }
+
+    fn blob_path(&self, snapshot_dir: &Path) -> PathBuf {
+        snapshot_dir
+            .join(SNAPSHOT_BLOB_DIR)
+            .join(format!("{}.zst", self.sha256))
+    }
+}
+
+/// Keeps a blob from being collected between being reused and referenced.
+static SNAPSHOT_STORE_LOCK: StdMutex<()> = StdMutex::new(());
+
+/// Stores `doc` compressed under its digest, unless an identical document is
+/// already stored, and points a `freed-{ts}.json` record at it.
+#[cfg(feature = "desktop")]
+pub(crate) fn store_snapshot(
+    snapshot_dir: &Path,
+    ts: u64,
+    doc: &[u8],
+) -> Result<SnapshotRecord, String> {
+    let record = SnapshotRecord {
+        sha256: Sha256::digest(doc)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        bytes: doc.len() as u64,
+    };
+    let _store = SNAPSHOT_STORE_LOCK.lock().unwrap();
+    let blob_path = record.blob_path(snapshot_dir);
+    if !blob_path.exists() {
+        std::fs::create_dir_all(snapshot_dir.join(SNAPSHOT_BLOB_DIR))
+            .map_err(|e| format!("failed to create blob dir: {}", e))?;
+        let compressed = zstd::bulk::compress(doc, SNAPSHOT_ZSTD_LEVEL)
+            .map_err(|e| format!("failed to compress snapshot: {}", e))?;
+        write_file_atomically(&blob_path, &compressed)?;
+    }
+    let json = serde_json::to_vec(&record).map_err(|e| e.to_string())?;
+    write_file_atomically(&snapshot_dir.join(format!("freed-{}.json", ts)), &json)?;
+    Ok(record)
+}
+
+/// Reads snapshot `name` back as a plain Automerge document. Blobs are
+/// checked against their digest, so a damaged one is never restored.
+#[cfg(feature = "desktop")]
+pub(crate) fn read_snapshot(snapshot_dir: &Path, name: &str) -> Result<Vec<u8>, String> {
+    if snapshot_file_timestamp(name).is_none() {
+        return Err(format!("invalid snapshot name: {name}"));
+    }
+    let path = snapshot_dir.join(name);
+    if name.ends_with(".automerge") {
+        return std::fs::read(&path)
+            .map_err(|error| format!("failed to read snapshot {name}: {error}"));
+    }
+    let record = SnapshotRecord::load(&path)
+        .map_err(|error| format!("failed to read snapshot {name}: {error}"))?;
+    if record.bytes > SNAPSHOT_MAX_DOC_BYTES {
+        return Err(format!("snapshot {name} is larger than the snapshot limit"));
+    }
+    let compressed = std::fs::read(record.blob_path(snapshot_dir))
+        .map_err(|error| format!("failed to read snapshot {name}: {error}"))?;
+    let doc = zstd::bulk::decompress(&compressed, record.bytes as usize)
+        .map_err(|error| format!("failed to decompress snapshot {name}: {error}"))?;
+    let digest: String = Sha256::digest(&doc)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if doc.len() as u64 != record.bytes || digest != record.sha256 {
+        return Err(format!("snapshot {name} failed its integrity check"));
+    }
+    Ok(doc)
+}
+
+/// One stored snapshot, as listed by `list_snapshots`.
+#[cfg(feature = "desktop")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SnapshotSummary {
+    /// Pass to `restore_snapshot`.
+    pub(crate) name: String,
+    /// Unix seconds.
+    pub(crate) timestamp: u64,
+    /// Uncompressed document size.
+    logical_bytes: u64,
+    /// The record plus its compressed blob. A blob shared with other
+    /// snapshots is counted for each of them.
+    disk_bytes: u64,
+}
+
+/// Stored snapshots, newest first. Records whose blob is missing are skipped.
+#[cfg(feature = "desktop")]
+pub(crate) fn list_snapshots_in(snapshot_dir: &Path) -> Vec<SnapshotSummary> {
+    let mut snapshots: Vec<SnapshotSummary> = std::fs::read_dir(snapshot_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let timestamp = snapshot_file_timestamp(&name)?;
+            let file_bytes = e.metadata().ok()?.len();
+            let (logical_bytes, blob_bytes) = if name.ends_with(".automerge") {
+                (file_bytes, 0)
+            } else {
+                let record = SnapshotRecord::load(&e.path()).ok()?;
+                let blob = std::fs::metadata(record.blob_path(snapshot_dir)).ok()?;
+                (record.bytes, blob.len())
+            };
+            Some(SnapshotSummary {
+                name,
+                timestamp,
+                logical_bytes,
+                disk_bytes: file_bytes + blob_bytes,
+            })
+        })
+        .collect();
+    snapshots.sort_unstable_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.name.cmp(&a.name)));
+    snapshots
+}
+
+/// What a stored snapshot holds, from `inspect_snapshot`.
+#[cfg(feature = "desktop")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SnapshotInspection {
+    name: String,
+    /// Hex change hashes, in the form `heads=` takes.
+    heads: Vec<String>,
+    change_count: usize,
+    /// Uncompressed document size.
+    logical_bytes: u64,
+    /// Entries in each map at the document root: `feedItems`, `persons`
+    /// (friends), `rssFeeds` (sources), `preferences`, and so on.
+    collections: std::collections::BTreeMap<String, usize>,
+}
+
+/// Entries one collection gained and lost between two snapshots.
+#[cfg(feature = "desktop")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotCollectionDiff {
+    added: usize,
+    removed: usize,
+}
+
+/// How `target` differs from `base`, from `diff_snapshots`.
+#[cfg(feature = "desktop")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SnapshotDiff {
+    base: SnapshotInspection,
+    target: SnapshotInspection,
+    /// Changes only `target` has.
+    changes_added: usize,
+    /// Changes only `base` has, lost by restoring `target` over it.
+    changes_removed: usize,
+    /// Collections present in either snapshot, by key.
+    collections: std::collections::BTreeMap<String, SnapshotCollectionDiff>,
+}
+
+/// Loads snapshot `name` as an Automerge document.
+#[cfg(feature = "desktop")]
+pub(crate) fn load_snapshot_doc(
+    snapshot_dir: &Path,
+    name: &str,
+) -> Result<(automerge::Automerge, u64), String> {
+    let bytes = read_snapshot(snapshot_dir, name)?;
+    validate_snapshot_bytes(&bytes)?;
+    let doc = automerge::Automerge::load(&bytes)
+        .map_err(|error| format!("failed to load snapshot {name}: {error}"))?;
+    Ok((doc, bytes.len() as u64))
+}
+
+/// The keys of each map at the document root.
+#[cfg(feature = "desktop")]
+fn snapshot_collection_keys(
+    doc: &automerge::Automerge,
+) -> std::collections::BTreeMap<String, HashSet<String>> {
+    use automerge::{ObjType, ReadDoc, Value};
+
+    doc.map_range(automerge::ROOT, ..)
+        .filter(|item| matches!(item.value, Value::Object(ObjType::Map)))
+        .map(|item| (item.key.to_string(), doc.keys(&item.id).collect()))
+        .collect()
+}
+
+#[cfg(feature = "desktop")]
+fn inspect_snapshot_doc(
+    name: &str,
+    doc: &automerge::Automerge,
+    logical_bytes: u64,
+) -> SnapshotInspection {
+    let heads: Vec<RelayHead> = doc.get_heads().into_iter().map(|hash| hash.0).collect();
+    SnapshotInspection {
+        name: name.to_string(),
+        heads: format_relay_heads_hex(&heads),
+        change_count: doc.get_changes(&[]).len(),
+        logical_bytes,
+        collections: snapshot_collection_keys(doc)
+            .into_iter()
+            .map(|(collection, keys)| (collection, keys.len()))
+            .collect(),
+    }
+}
+
+#[cfg(feature = "desktop")]
+pub(crate) fn inspect_snapshot_in(
+    snapshot_dir: &Path,
+    name: &str,
+) -> Result<SnapshotInspection, String> {
+    let (doc, logical_bytes) = load_snapshot_doc(snapshot_dir, name)?;
+    Ok(inspect_snapshot_doc(name, &doc, logical_bytes))
+}
+
+#[cfg(feature = "desktop")]
+pub(crate) fn diff_snapshots_in(
+    snapshot_dir: &Path,
+    base: &str,
+    target: &str,
+) -> Result<SnapshotDiff, String> {
+    let (base_doc, base_bytes) = load_snapshot_doc(snapshot_dir, base)?;
+    let (target_doc, target_bytes) = load_snapshot_doc(snapshot_dir, target)?;
+    let base_keys = snapshot_collection_keys(&base_doc);
+    let target_keys = snapshot_collection_keys(&target_doc);
+    let empty = HashSet::new();
+    let collections = base_keys
+        .keys()
+        .chain(target_keys.keys())
+        .map(|collection| {
+            let before = base_keys.get(collection).unwrap_or(&empty);
+            let after = target_keys.get(collection).unwrap_or(&empty);
+            let diff = SnapshotCollectionDiff {
+                added: after.difference(before).count(),
+                removed: before.difference(after).count(),
+            };
+            (collection.clone(), diff)
+        })
+        .collect();
+    Ok(SnapshotDiff {
+        changes_added: base_doc.get_changes_added(&target_doc).len(),
+        changes_removed: target_doc.get_changes_added(&base_doc).len(),
+        base: inspect_snapshot_doc(base, &base_doc, base_bytes),
+        target: inspect_snapshot_doc(target, &target_doc, target_bytes),
+        collections,
+    })
+}
+
+/// Sizes and change counts before and after `compact_document`.
+#[cfg(feature = "desktop")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DocumentCompaction {
+    pub(crate) bytes_before: u64,
+    pub(crate) bytes_after: u64,
+    pub(crate) changes_before: usize,
+    pub(crate) changes_after: usize,
+    /// The document was rebuilt from its materialized state, so it has new
+    /// heads and no history.
+    pub(crate) history_dropped: bool,
+}
+
+/// Saves an Automerge document in compacted form, folding appended change
+/// chunks back into one document chunk. With `drop_history_before`, a document
+/// holding any change older than that (Unix seconds) is rebuilt from its
+/// materialized state as a single change stamped `now_secs`. Automerge cannot
+/// keep part of a history, so the rebuild drops all of it. It does not stay
+/// dropped: a device that still holds the old history merges it back on its
+/// next push, and sees each root field set concurrently and keeps one side.
+#[cfg(feature = "desktop")]
+pub(crate) fn compact_automerge_doc(
+    bytes: &[u8],
+    drop_history_before: Option<u64>,
+    now_secs: u64,
+) -> Result<(Vec<u8>, Vec<RelayHead>, DocumentCompaction), String> {
+    let doc = automerge::Automerge::load(bytes)
+        .map_err(|error| format!("failed to load document: {error}"))?;
+    let changes = doc.get_changes(&[]);
+    let changes_before = changes.len();
+    let history_dropped = drop_history_before.is_some_and(|cutoff| {
+        changes
+            .iter()
+            .any(|change| change.timestamp() < cutoff as i64)
+    });
+    let (compacted, heads, changes_after) = if history_dropped {
+        let mut rebuilt = automerge::AutoCommit::new();
+        copy_automerge_object(&doc, &automerge::ROOT, &mut rebuilt, &automerge::ROOT)
+            .map_err(|error| format!("failed to rebuild document: {error}"))?;
+        rebuilt.commit_with(
+            automerge::transaction::CommitOptions::default().with_time(now_secs as i64),
+        );
+        let heads = rebuilt.get_heads();
+        let changes_after = rebuilt.get_changes(&[]).len();
+        (rebuilt.save(), heads, changes_after)
+    } else {
+        (doc.save(), doc.get_heads(), changes_before)
+    };
+    let compaction = DocumentCompaction {
+        bytes_before: bytes.len() as u64,
+        bytes_after: compacted.len() as u64,
+        changes_before,
+        changes_after,
+        history_dropped,
+    };
+    let heads = heads.into_iter().map(|hash| hash.0).collect();
+    Ok((compacted, heads, compaction))
+}
+
+/// Copies the current value of `from` in `source` into `to` in `target`.
+/// Counters keep their value; text marks are not carried over.
+#[cfg(feature = "desktop")]
+fn copy_automerge_object(
+    source: &automerge::Automerge,
+    from: &automerge::ObjId,
+    target: &mut automerge::AutoCommit,
+    to: &automerge::ObjId,
+) -> Result<(), automerge::AutomergeError> {
+    use automerge::{transaction::Transactable, ObjType, ReadDoc, ScalarValue, Value};
+
+    let scalar = |value: std::borrow::Cow<'_, ScalarValue>| match value.as_ref() {
+        ScalarValue::Counter(counter) => ScalarValue::counter(i64::from(counter)),
+        _ => value.into_owned(),
+    };
+    match source.object_type(from)? {
+        ObjType::Map | ObjType::Table => {
+            for item in source.map_range(from, ..) {
+                match item.value {
+                    Value::Object(kind) => {
+                        let child = target.put_object(to, item.key, kind)?;
+                        copy_automerge_object(source, &item.id, target, &child)?;
+                    }
+                    Value::Scalar(value) => target.put(to, item.key, scalar(value))?,
+                }
+            }
+        }
+        ObjType::List => {
+            for item in source.list_range(from, ..) {
+                match item.value {
+                    Value::Object(kind) => {
+                        let child = target.insert_object(to, item.index, kind)?;
+                        copy_automerge_object(source, &item.id, target, &child)?;
+                    }
+                    Value::Scalar(value) => target.insert(to, item.index, scalar(value))?,
+                }
+            }
+        }
+        ObjType::Text => target.splice_text(to, 0, 0, &source.text(from)?)?,
+    }
+    Ok(())
+}
+
+/// How many snapshots `prune_snapshots` keeps per bucket, and the most disk
+/// they may use together. Every field is optional in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct SnapshotRetention {
+    /// Snapshots kept from the last hour.
+    pub(crate) minutely: usize,
+    /// One per hour kept from the last day.
+    pub(crate) hourly: usize,
+    /// One per day kept beyond that.
+    pub(crate) daily: usize,
+    /// Total size of `snapshots/`. `None` only applies the bucket counts.
+    pub(crate) max_total_bytes: Option<u64>,
+}
+
+impl Default for SnapshotRetention {
+    fn default() -> Self {
+        Self {
+            minutely: 60,
+            hourly: 24,
+            daily: 30,
+            max_total_bytes: Some(2 * 1024 * 1024 * 1024),
+        }
+    }
+}
+
+impl SnapshotRetention {
+    pub(crate) fn load(data_dir: &Path) -> Self {
+        let Ok(raw) = std::fs::read(data_dir.join(SNAPSHOT_RETENTION_FILE)) else {
+            return Self::default();
+        };
+        match serde_json::from_slice::<Self>(&raw)
+            .map_err(|e| e.to_string())
+            .and_then(|retention| retention.validate().map(|()| retention))
+        {
+            Ok(retention) => retention,
+            Err(error) => {
+                warn!("[Snapshot] Ignoring unusable retention settings: {}", error);
+                Self::default()
+            }
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    pub(crate) fn persist(&self, data_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_file_atomically(&data_dir.join(SNAPSHOT_RETENTION_FILE), &json)
+            .map_err(|e| format!("failed to persist snapshot retention: {}", e))
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.minutely + self.hourly + self.daily == 0 {
+            return Err("keep at least one snapshot bucket".to_string());
+        }
+        if self.max_total_bytes == Some(0) {
+            return Err("snapshot quota must be more than 0 bytes".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What one `prune_snapshots` pass removed and kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SnapshotPruneSummary {
+    removed: usize,
+    removed_bytes: u64,
+    /// Of `removed`, snapshots the buckets kept but the quota could not.
+    over_quota: usize,
+    kept: usize,
+    kept_bytes: u64,
+}
+
+impl SnapshotPruneSummary {
+    #[cfg(feature = "desktop")]
+    fn health_event(&self) -> serde_json::Value {
+        serde_json::json!({
+            "event": "snapshot_pruned",
+            "removed": self.removed,
+            "removedBytes": self.removed_bytes,
+            "overQuota": self.over_quota,
+            "kept": self.kept,
+            "keptBytes": self.kept_bytes,
+        })
+    }
+}
+
+/// Store a timestamped Automerge snapshot in `{app_data}/snapshots/` and
+/// prune old ones with `retention`. Returns what pruning did, or `None` if
+/// the snapshot could not be written.
+#[cfg(feature = "desktop")]
+#[cfg_attr(feature = "perf", tracing::instrument(skip(doc_bytes, retention), fields(bytes = doc_bytes.len())))]
+fn write_snapshot(
+    snapshot_dir: &std::path::Path,
+    doc_bytes: &[u8],
+    retention: &SnapshotRetention,
+) -> Option<SnapshotPruneSummary> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if let Err(e) = std::fs::create_dir_all(snapshot_dir) {
+        error!("[Snapshot] Failed to create dir: {}", e);
+        return None;
+    }
+
+    if let Err(e) = store_snapshot(snapshot_dir, ts, doc_bytes) {
+        error!("[Snapshot] Failed to write: {}", e);
+        return None;
+    }
+
+    Some(prune_snapshots(snapshot_dir, ts, retention))
+}
+
+/// Thins snapshots with a grandfather-father-son scheme:
+///   - the newest `minutely`           (≤ 1 hour old)
+///   - one per hour, newest `hourly`   (1–24 hours old)
+///   - one per day, newest `daily`     (> 24 hours old)
+///
+/// If the survivors still exceed `max_total_bytes`, whole buckets are evicted
+/// least valuable first: minutely, then hourly, then daily, oldest first
+/// within each. The newest snapshot is always kept. A blob counts once however
+/// many records share it, and is deleted with the last of them.
+#[cfg_attr(feature = "perf", tracing::instrument(skip(snapshot_dir)))]
+pub(crate) fn prune_snapshots(
+    snapshot_dir: &std::path::Path,
+    now_secs: u64,
+    retention: &SnapshotRetention,
+) -> SnapshotPruneSummary {
+    use std::cmp::Reverse;
+
+    struct Entry {
+        ts: u64,
+        path: PathBuf,
+        bytes: u64,
+        /// `None` for a plain `.automerge` snapshot or an unreadable record.
+        blob: Option<PathBuf>,
+    }
+
+    let _store = SNAPSHOT_STORE_LOCK.lock().unwrap();
+    let mut entries: Vec<Entry> = std::fs::read_dir(snapshot_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let ts = snapshot_file_timestamp(&name)?;
+            let bytes = e.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            let blob = name
+                .ends_with(".json")
+                .then(|| SnapshotRecord::load(&e.path()).ok())
+                .flatten()
+                .map(|record| record.blob_path(snapshot_dir));
+            Some(Entry {
+                ts,
+                path: e.path(),
+                bytes,
+                blob,
+            })
+        })
+        .collect();
+
+    entries.sort_by_key(|entry| Reverse(entry.ts));
+
+    // Eviction rank per kept snapshot: 0 minutely, 1 hourly, 2 daily.
+    let mut kept: HashMap<std::path::PathBuf, u8> = Default::default();
+    let (mut minutely, mut hourly, mut daily) = (0usize, 0usize, 0usize);
+    let mut last_hour_bucket = u64::MAX;
+    let mut last_day_bucket = u64::MAX;
+
+    for Entry { ts, path, .. } in &entries {
+        let age = now_secs.saturating_sub(*ts);
+        if age < 3_600 && minutely < retention.minutely {
+            kept.insert(path.clone(), 0);
+            minutely += 1;
+        } else if age < 86_400 {
+            let bucket = age / 3_600;
+            if bucket != last_hour_bucket && hourly < retention.hourly {
+                kept.insert(path.clone(), 1);
+                last_hour_bucket = bucket;
+                hourly += 1;
+            }
+        } else {
+            let bucket = age / 86_400;
+            if bucket != last_day_bucket && daily < retention.daily {
+                kept.insert(path.clone(), 2);
+                last_day_bucket = bucket;
+                daily += 1;
+            }
+        }
+    }
+    if let Some(newest) = entries.first() {
+        kept.insert(newest.path.clone(), u8::MAX);
+    }
+
+    let blob_bytes: HashMap<&PathBuf, u64> = entries
+        .iter()
+        .filter_map(|entry| entry.blob.as_ref())
+        .map(|blob| {
+            let bytes = std::fs::metadata(blob).map(|m| m.len()).unwrap_or(0);
+            (blob, bytes)
+        })
+        .collect();
+    let disk_bytes = |kept: &HashMap<PathBuf, u8>| -> u64 {
+        let kept_entries = entries
+            .iter()
+            .filter(|entry| kept.contains_key(&entry.path));
+        let blobs: HashSet<&PathBuf> = kept_entries
+            .clone()
+            .filter_map(|entry| entry.blob.as_ref())
+            .collect();
+        kept_entries.map(|entry| entry.bytes).sum::<u64>()
+            + blobs.into_iter().map(|blob| blob_bytes[blob]).sum::<u64>()
+    };
+
+    let mut summary = SnapshotPruneSummary::default();
+    if let Some(max_total_bytes) = retention.max_total_bytes {
+        let mut evictable: Vec<&Entry> = entries
+            .iter()
+            .filter(|entry| kept.get(&entry.path).is_some_and(|rank| *rank != u8::MAX))
+            .collect();
+        evictable.sort_by_key(|entry| (kept[&entry.path], entry.ts));
+        for entry in evictable {
+            if disk_bytes(&kept) <= max_total_bytes {
+                break;
+            }
+            kept.remove(&entry.path);
+            summary.over_quota += 1;
+        }
+    }
+
+    let all: HashMap<PathBuf, u8> = entries
+        .iter()
+        .map(|entry| (entry.path.clone(), 0))
+        .collect();
+    let before = disk_bytes(&all);
+    for entry in &entries {
+        if kept.contains_key(&entry.path) {
+            continue;
+        }
+        if std::fs::remove_file(&entry.path).is_ok() {
+            summary.removed += 1;
+        } else {
+            kept.insert(entry.path.clone(), 0);
+        }
+    }
+    let referenced: HashSet<&PathBuf> = entries
+        .iter()
+        .filter(|entry| kept.contains_key(&entry.path))
+        .filter_map(|entry| entry.blob.as_ref())
+        .collect();
+    for blob in blob_bytes.keys() {
+        if !referenced.contains(blob) {
+            let _ = std::fs::remove_file(blob);
+        }
+    }
+    summary.kept = kept.len();
+    summary.kept_bytes = disk_bytes(&kept);
+    summary.removed_bytes = before - summary.kept_bytes;
+    summary
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SnapshotWriterPolicy {
+    /// Write once updates have been quiet for this long.
+    pub(crate) debounce: Duration,
+    /// Upper bound between the first unsaved update and its snapshot, so a
+    /// steady stream of mutations cannot postpone the write forever.
+    pub(crate) max_delay: Duration,
+}
+
+pub(crate) const DEFAULT_SNAPSHOT_WRITER_POLICY: SnapshotWriterPolicy = SnapshotWriterPolicy {
+    debounce: SNAPSHOT_WRITE_DEBOUNCE,
+    max_delay: SNAPSHOT_WRITE_MAX_DELAY,
+};
+
+/// Coalesces accepted relay documents into debounced blocking `write` calls.
+///
+/// `broadcast_doc` and mobile pushes publish the latest bytes on
+/// `SyncRelayState::snapshot_tx`; only the newest value at write time is
+/// persisted. A `None` value (factory reset) cancels the pending write.
+pub(crate) async fn run_debounced_doc_writer<W>(
+    mut doc_rx: tokio::sync::watch::Receiver<Option<Bytes>>,
+    policy: SnapshotWriterPolicy,
+    write: W,
+) where
+    W: Fn(&[u8]) + Send + Sync + 'static,
+{
+    let write = Arc::new(write);
+    while doc_rx.changed().await.is_ok() {
+        let deadline = tokio::time::Instant::now() + policy.max_delay;
+        let mut sender_closed = false;
+        loop {
+            let quiet_until = (tokio::time::Instant::now() + policy.debounce).min(deadline);
+            match tokio::time::timeout_at(quiet_until, doc_rx.changed()).await {
+                Ok(Ok(())) => continue,
+                Ok(Err(_)) => {
+                    sender_closed = true;
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+
+        let pending = doc_rx.borrow_and_update().clone();
+        if let Some(doc) = pending {
+            let write = write.clone();
+            if let Err(error) = tokio::task::spawn_blocking(move || write(&doc)).await {
+                error!("[Snapshot] Writer task failed: {}", error);
+            }
+        }
+        if sender_closed {
+            return;
+        }
+    }
+}
+
+/// Records a `snapshot_pruned` health event when a pass removed anything.
+#[cfg(feature = "desktop")]
+pub(crate) fn report_snapshot_prune(events: &dyn RelayEventSink, summary: SnapshotPruneSummary) {
+    if summary.removed == 0 {
+        return;
+    }
+    info!(
+        "[Snapshot] Pruned {} snapshots ({} bytes, {} over quota); {} kept ({} bytes)",
+        summary.removed,
+        summary.removed_bytes,
+        summary.over_quota,
+        summary.kept,
+        summary.kept_bytes
+    );
+    events.record_health(summary.health_event());
+}
+
+/// Writes debounced `write_snapshot` files after `open` turns the relay-held
+/// payload back into a plain Automerge document. Each write prunes with the
+/// latest `retention`.
+#[cfg(feature = "desktop")]
+pub(crate) async fn run_snapshot_writer<F>(
+    snapshot_dir: PathBuf,
+    snapshot_rx: tokio::sync::watch::Receiver<Option<Bytes>>,
+    policy: SnapshotWriterPolicy,
+    retention: tokio::sync::watch::Receiver<SnapshotRetention>,
+    events: RelayEvents,
+    open: F,
+) where
+    F: Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+{
+    run_debounced_doc_writer(snapshot_rx, policy, move |doc| match open(doc) {
+        Ok(doc) => {
+            let retention = *retention.borrow();
+            if let Some(summary) = write_snapshot(&snapshot_dir, &doc, &retention) {
+                report_snapshot_prune(&*events, summary);
+            }
+        }
+        Err(error) => warn!("[Snapshot] Skipped unreadable relay document: {}", error),
+    })
+    .await;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    #[cfg(feature = "desktop")]
+    use crate::relay::{
+        tests::{automerge_like_bytes, snapshot_test_relay_state},
+        LoggedRelayEvents,
+    };
+    #[cfg(feature = "desktop")]
+    use crate::restore_snapshot_in;
+
+    #[test]
+    fn snapshot_file_timestamp_accepts_only_plain_snapshot_names() {
+        assert_eq!(
+            snapshot_file_timestamp("freed-1700000000.automerge"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(snapshot_file_timestamp("freed-.automerge"), None);
+        assert_eq!(snapshot_file_timestamp("freed-+12.automerge"), None);
+        assert_eq!(snapshot_file_timestamp("freed-12.automerge.tmp"), None);
+        assert_eq!(snapshot_file_timestamp("../freed-12.automerge"), None);
+        assert_eq!(snapshot_file_timestamp("freed-1/../2.automerge"), None);
+        assert_eq!(snapshot_file_timestamp("freed-12.json"), Some(12));
+        assert_eq!(snapshot_file_timestamp("freed-12.json.tmp"), None);
+        assert_eq!(snapshot_file_timestamp("freed-12.zst"), None);
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn snapshot_store_compresses_and_shares_identical_documents() {
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let dir = snapshot_dir.path();
+        let mut doc = automerge_like_bytes(1);
+        doc.extend_from_slice(&[0; 4_096]);
+        let first = store_snapshot(dir, 100, &doc).unwrap();
+        assert_eq!(store_snapshot(dir, 200, &doc).unwrap(), first);
+        store_snapshot(dir, 300, &automerge_like_bytes(3)).unwrap();
+        std::fs::write(dir.join("freed-50.automerge"), automerge_like_bytes(5)).unwrap();
+
+        let blobs = std::fs::read_dir(dir.join(SNAPSHOT_BLOB_DIR))
+            .unwrap()
+            .count();
+        assert_eq!(blobs, 2);
+        let blob_bytes = std::fs::metadata(first.blob_path(dir)).unwrap().len();
+        assert!(blob_bytes < doc.len() as u64);
+
+        let listed = list_snapshots_in(dir);
+        let names: Vec<&str> = listed
+            .iter()
+            .map(|snapshot| snapshot.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "freed-300.json",
+                "freed-200.json",
+                "freed-100.json",
+                "freed-50.automerge"
+            ]
+        );
+        assert_eq!(listed[1].timestamp, 200);
+        assert_eq!(listed[1].logical_bytes, doc.len() as u64);
+        assert!(listed[1].disk_bytes > blob_bytes && listed[1].disk_bytes < doc.len() as u64);
+        assert_eq!(listed[3].disk_bytes, automerge_like_bytes(5).len() as u64);
+
+        assert_eq!(read_snapshot(dir, "freed-100.json").unwrap(), doc);
+        assert_eq!(
+            read_snapshot(dir, "freed-50.automerge").unwrap(),
+            automerge_like_bytes(5)
+        );
+        assert!(read_snapshot(dir, "../freed-100.json").is_err());
+
+        // Pruning one of two records that share a blob keeps the blob.
+        let retention = SnapshotRetention {
+            minutely: 2,
+            hourly: 0,
+            daily: 0,
+            max_total_bytes: None,
+        };
+        let summary = prune_snapshots(dir, 300, &retention);
+        assert_eq!((summary.removed, summary.kept), (2, 2));
+        assert_eq!(read_snapshot(dir, "freed-200.json").unwrap(), doc);
+        let summary = prune_snapshots(
+            dir,
+            300,
+            &SnapshotRetention {
+                minutely: 1,
+                ..retention
+            },
+        );
+        assert_eq!(summary.removed, 1);
+        assert!(summary.removed_bytes > blob_bytes);
+        assert!(!first.blob_path(dir).exists());
+
+        // A damaged blob is refused instead of restored.
+        let record = SnapshotRecord::load(&dir.join("freed-300.json")).unwrap();
+        let damaged = zstd::bulk::compress(b"tampered", SNAPSHOT_ZSTD_LEVEL).unwrap();
+        std::fs::write(record.blob_path(dir), damaged).unwrap();
+        assert!(read_snapshot(dir, "freed-300.json").is_err());
+
+        // A record claiming an oversized document is refused before decompressing.
+        let oversized = SnapshotRecord {
+            bytes: SNAPSHOT_MAX_DOC_BYTES + 1,
+            ..record
+        };
+        std::fs::write(
+            dir.join("freed-400.json"),
+            serde_json::to_vec(&oversized).unwrap(),
+        )
+        .unwrap();
+        let error = read_snapshot(dir, "freed-400.json").unwrap_err();
+        assert!(error.contains("snapshot limit"), "{}", error);
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn snapshot_pruning_applies_retention_counts_then_the_quota() {
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let now = 10 * 86_400;
+        let files = [
+            (now, 100),
+            (now - 60, 100),
+            (now - 120, 100),
+            (now - 180, 100),
+            (now - 7_200, 100),
+            (now - 7_260, 100),
+            (now - 10_800, 100),
+            (now - 18_000, 100),
+            (now - 2 * 86_400, 1_000),
+            (now - 3 * 86_400, 1_000),
+        ];
+        for (ts, bytes) in files {
+            std::fs::write(
+                snapshot_dir.path().join(format!("freed-{}.automerge", ts)),
+                vec![0u8; bytes],
+            )
+            .unwrap();
+        }
+        let remaining = || {
+            let mut names: Vec<u64> = std::fs::read_dir(snapshot_dir.path())
+                .unwrap()
+                .filter_map(|entry| {
+                    snapshot_file_timestamp(&entry.unwrap().file_name().into_string().unwrap())
+                })
+                .map(|ts| now - ts)
+                .collect();
+            names.sort_unstable();
+            names
+        };
+
+        let mut retention = SnapshotRetention {
+            minutely: 3,
+            hourly: 2,
+            daily: 1,
+            max_total_bytes: None,
+        };
+        let summary = prune_snapshots(snapshot_dir.path(), now, &retention);
+        assert_eq!(
+            summary,
+            SnapshotPruneSummary {
+                removed: 4,
+                removed_bytes: 1_300,
+                over_quota: 0,
+                kept: 6,
+                kept_bytes: 1_500,
+            }
+        );
+        assert_eq!(remaining(), [0, 60, 120, 180, 7_200, 2 * 86_400]);
+
+        // Over quota, minutely snapshots go first, then hourly, oldest first.
+        retention.max_total_bytes = Some(1_100);
+        let summary = prune_snapshots(snapshot_dir.path(), now, &retention);
+        assert_eq!(summary.over_quota, 4);
+        assert_eq!(summary.kept_bytes, 1_100);
+        assert_eq!(remaining(), [0, 2 * 86_400]);
+        assert_eq!(summary.health_event()["event"], "snapshot_pruned");
+
+        // The newest snapshot survives even a quota it alone exceeds.
+        retention.max_total_bytes = Some(1);
+        prune_snapshots(snapshot_dir.path(), now, &retention);
+        assert_eq!(remaining(), [0]);
+
+        let data_dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            SnapshotRetention::load(data_dir.path()),
+            SnapshotRetention::default()
+        );
+        retention.persist(data_dir.path()).unwrap();
+        assert_eq!(SnapshotRetention::load(data_dir.path()), retention);
+        assert!(SnapshotRetention {
+            max_total_bytes: Some(0),
+            ..retention
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn snapshots_are_inspected_and_diffed_natively() {
+        use automerge::transaction::Transactable;
+        use automerge::ObjType;
+
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let dir = snapshot_dir.path();
+        let mut doc = automerge::AutoCommit::new();
+        let feed_items = doc
+            .put_object(automerge::ROOT, "feedItems", ObjType::Map)
+            .unwrap();
+        let persons = doc
+            .put_object(automerge::ROOT, "persons", ObjType::Map)
+            .unwrap();
+        doc.put(automerge::ROOT, "title", "library").unwrap();
+        for id in ["a", "b", "c"] {
+            doc.put(&feed_items, id, id).unwrap();
+        }
+        doc.put(&persons, "ada", "Ada").unwrap();
+        doc.commit();
+        store_snapshot(dir, 100, &doc.save()).unwrap();
+
+        doc.delete(&feed_items, "a").unwrap();
+        doc.put(&feed_items, "d", "d").unwrap();
+        doc.put(&feed_items, "e", "e").unwrap();
+        doc.put_object(automerge::ROOT, "rssFeeds", ObjType::Map)
+            .unwrap();
+        doc.commit();
+        store_snapshot(dir, 200, &doc.save()).unwrap();
+
+        let inspection = inspect_snapshot_in(dir, "freed-200.json").unwrap();
+        assert_eq!(
+            inspection.heads,
+            format_relay_heads_hex(&[doc.get_heads()[0].0])
+        );
+        assert_eq!(inspection.change_count, 2);
+        assert_eq!(inspection.logical_bytes, doc.save().len() as u64);
+        assert_eq!(
+            inspection.collections,
+            [("feedItems", 4), ("persons", 1), ("rssFeeds", 0)]
+                .into_iter()
+                .map(|(collection, count)| (collection.to_string(), count))
+                .collect()
+        );
+
+        let diff = diff_snapshots_in(dir, "freed-100.json", "freed-200.json").unwrap();
+        assert_eq!((diff.changes_added, diff.changes_removed), (1, 0));
+        assert_eq!(diff.base.collections["feedItems"], 3);
+        assert_eq!(
+            diff.collections["feedItems"],
+            SnapshotCollectionDiff {
+                added: 2,
+                removed: 1
+            }
+        );
+        assert_eq!(
+            diff.collections["persons"],
+            SnapshotCollectionDiff::default()
+        );
+        assert_eq!(
+            diff.collections["rssFeeds"],
+            SnapshotCollectionDiff::default()
+        );
+        let reverse = diff_snapshots_in(dir, "freed-200.json", "freed-100.json").unwrap();
+        assert_eq!((reverse.changes_added, reverse.changes_removed), (0, 1));
+
+        std::fs::write(dir.join("freed-300.automerge"), automerge_like_bytes(3)).unwrap();
+        assert!(inspect_snapshot_in(dir, "freed-300.automerge").is_err());
+        assert!(inspect_snapshot_in(dir, "freed-400.json").is_err());
+    }
+
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn snapshot_writer_coalesces_updates_and_skips_reset() {
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let (snapshot_tx, snapshot_rx) = tokio::sync::watch::channel::<Option<Bytes>>(None);
+        let writer = tokio::spawn(run_snapshot_writer(
+            snapshot_dir.path().to_path_buf(),
+            snapshot_rx,
+            SnapshotWriterPolicy {
+                debounce: Duration::from_millis(20),
+                max_delay: Duration::from_secs(5),
+            },
+            tokio::sync::watch::channel(SnapshotRetention::default()).1,
+            Arc::new(LoggedRelayEvents),
+            |doc: &[u8]| Ok(doc.to_vec()),
+        ));
+
+        snapshot_tx.send_replace(Some(Bytes::from(automerge_like_bytes(1))));
+        snapshot_tx.send_replace(Some(Bytes::from(automerge_like_bytes(2))));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let written = list_snapshots_in(snapshot_dir.path());
+        assert_eq!(written.len(), 1);
+        assert_eq!(
+            read_snapshot(snapshot_dir.path(), &written[0].name).unwrap(),
+            automerge_like_bytes(2)
+        );
+
+        // A factory reset replaces the pending document with `None`; the
+        // writer must not persist the document it superseded.
+        std::fs::remove_file(snapshot_dir.path().join(&written[0].name)).unwrap();
+        snapshot_tx.send_replace(Some(Bytes::from(automerge_like_bytes(3))));
+        snapshot_tx.send_replace(None);
+        drop(snapshot_tx);
+        writer.await.unwrap();
+        assert!(list_snapshots_in(snapshot_dir.path()).is_empty());
+    }
+
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn restore_snapshot_swaps_current_doc_and_rebroadcasts() {
+        let snapshot_dir = tempfile::tempdir().unwrap();
+        let name = "freed-1700000000.automerge";
+        std::fs::write(snapshot_dir.path().join(name), automerge_like_bytes(7)).unwrap();
+        std::fs::write(
+            snapshot_dir.path().join("freed-1700000001.automerge"),
+            b"not automerge",
+        )
+        .unwrap();
+
+        let state = snapshot_test_relay_state(Some(automerge_like_bytes(1)));
+        let mut broadcast_rx = state.broadcast_tx.subscribe();
+
+        let restored = restore_snapshot_in(snapshot_dir.path(), &state, name)
+            .await
+            .unwrap();
+        assert_eq!(restored, automerge_like_bytes(7).len() as u64);
+        assert_eq!(
+            state.current_doc.read().await.as_deref(),
+            Some(&automerge_like_bytes(7)[..])
+        );
+        assert_eq!(
+            broadcast_rx.recv().await.unwrap().payload(),
+            &automerge_like_bytes(7)
+        );
+
+        assert!(
+            restore_snapshot_in(snapshot_dir.path(), &state, "freed-1700000001.automerge")
+                .await
+                .is_err()
+        );
+        assert!(
+            restore_snapshot_in(snapshot_dir.path(), &state, "../pairing-token")
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            state.current_doc.read().await.as_deref(),
+            Some(&automerge_like_bytes(7)[..])
+        );
+
+        state
+            .accepting_doc_updates
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        assert!(restore_snapshot_in(snapshot_dir.path(), &state, name)
+            .await
+            .is_err());
+    }
+
+    /// A document with a nested map, a list, and a counter, committed at
+    /// `time`, followed by `appended` single-change chunks committed at
+    /// `time + 100`, the way the relay appends pushed changes.
+    #[cfg(feature = "desktop")]
+    pub(crate) fn appended_history_doc(time: i64, appended: usize) -> Vec<u8> {
+        use automerge::transaction::{CommitOptions, Transactable};
+
+        let mut doc = automerge::AutoCommit::new();
+        let items = doc
+            .put_object(automerge::ROOT, "feedItems", automerge::ObjType::Map)
+            .unwrap();
+        let item = doc
+            .put_object(&items, "a", automerge::ObjType::Map)
+            .unwrap();
+        doc.put(&item, "title", "first").unwrap();
+        let tags = doc
+            .put_object(&item, "tags", automerge::ObjType::List)
+            .unwrap();
+        doc.insert(&tags, 0, "news").unwrap();
+        doc.put(&item, "views", automerge::ScalarValue::counter(1))
+            .unwrap();
+        doc.commit_with(CommitOptions::default().with_time(time));
+        let mut bytes = doc.save();
+        for round in 0..appended {
+            doc.increment(&item, "views", 1).unwrap();
+            doc.put(&item, "title", format!("edit {round}")).unwrap();
+            doc.commit_with(CommitOptions::default().with_time(time + 100));
+            bytes.extend(doc.save_incremental());
+        }
+        bytes
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn compact_automerge_doc_keeps_heads_unless_history_is_dropped() {
+        use automerge::ReadDoc;
+
+        let bytes = appended_history_doc(100, 20);
+        let original = automerge::Automerge::load(&bytes).unwrap();
+        let original_heads: Vec<RelayHead> = original
+            .get_heads()
+            .into_iter()
+            .map(|hash| hash.0)
+            .collect();
+
+        let (compacted, heads, compaction) = compact_automerge_doc(&bytes, None, 500).unwrap();
+        assert_eq!(heads, original_heads);
+        assert_eq!(compaction.changes_before, 21);
+        assert_eq!(compaction.changes_after, 21);
+        assert!(!compaction.history_dropped);
+        assert_eq!(compaction.bytes_before, bytes.len() as u64);
+        assert!(compaction.bytes_after < compaction.bytes_before);
+        assert_eq!(compacted.len() as u64, compaction.bytes_after);
+
+        let (_, heads, compaction) = compact_automerge_doc(&bytes, Some(50), 500).unwrap();
+        assert_eq!(heads, original_heads, "nothing predates the cutoff");
+        assert!(!compaction.history_dropped);
+
+        let (rebuilt, heads, compaction) = compact_automerge_doc(&bytes, Some(150), 500).unwrap();
+        assert!(compaction.history_dropped);
+        assert_eq!(compaction.changes_after, 1);
+        assert_ne!(heads, original_heads);
+        let rebuilt = automerge::Automerge::load(&rebuilt).unwrap();
+        assert_eq!(rebuilt.get_changes(&[])[0].timestamp(), 500);
+        let (_, items) = rebuilt.get(automerge::ROOT, "feedItems").unwrap().unwrap();
+        let (_, item) = rebuilt.get(&items, "a").unwrap().unwrap();
+        let value = |key: &str| rebuilt.get(&item, key).unwrap().unwrap().0.to_string();
+        assert_eq!(value("title"), "\"edit 19\"");
+        let (views, _) = rebuilt.get(&item, "views").unwrap().unwrap();
+        assert_eq!(views, automerge::Value::counter(21));
+        let (_, tags) = rebuilt.get(&item, "tags").unwrap().unwrap();
+        assert_eq!(rebuilt.length(&tags), 1);
+
+        // A device that still holds the old history merges it straight back.
+        let mut remerged = rebuilt.clone();
+        remerged.merge(&mut original.clone()).unwrap();
+        assert_eq!(remerged.get_changes(&[]).len(), 22);
+
+        assert!(compact_automerge_doc(b"not automerge", None, 500).is_err());
+    }
+}
@@ -0,0 +1,861 @@
+//! Command-line sync client, plus the pinned TLS connector it shares with
+//! desktop peers.
+//!
+//! `freed-sync` talks to a relay the way a phone does, from the pairing URL
+//! `get_sync_url` returns, so scripts can pull, push, and back up the document
+//! without a browser. The first run redeems the URL's pairing code; later runs
+//! present the device credential derived from the same code, so one URL keeps
+//! working for a cron job. Payloads are opened and sealed with the URL's `#k=`
+//! sync key, and files on disk are always plain Automerge.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use crate::relay::{
+    format_relay_heads_hex, pairing_code_device_credential, pairing_code_device_id,
+    parse_relay_client_message, relay_query_param, relay_websocket_config, RelayClientMessage,
+    RelayHead, RelayPayloadKey, RelayServerControl, RelayUpdate, DEFAULT_RELAY_ABUSE_POLICY,
+    DEFAULT_SYNC_RELAY_PORT, RELAY_CAPABILITIES, RELAY_CLOSE_DEVICE_REVOKED,
+    RELAY_CLOSE_FACTORY_RESET, RELAY_CONTROL_VERSION, RELAY_PROTOCOL_VERSION, SEALED_PAYLOAD_MAGIC,
+    SYNC_RELAY_TLS_HANDSHAKE_TIMEOUT,
+};
+use crate::snapshots::{prune_snapshots, validate_snapshot_bytes, SnapshotRetention};
+use crate::{
+    now_unix_ms, write_file_atomically, SYNC_PEER_CONNECT_TIMEOUT, SYNC_PEER_RETRY_MAX_DELAY,
+    SYNC_PEER_RETRY_MIN_DELAY,
+};
+
+/// Accepts exactly the certificate whose SHA-256 the pairing link pinned.
+/// Peer relays use self-signed certificates, so there is no chain to check.
+#[derive(Debug)]
+struct PinnedRelayCertVerifier {
+    fingerprint: String,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedRelayCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if URL_SAFE_NO_PAD.encode(Sha256::digest(end_entity)) == self.fingerprint {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "peer certificate does not match the pinned fingerprint".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+pub(crate) fn pinned_relay_connector(
+    fingerprint: &str,
+) -> Result<tokio_rustls::TlsConnector, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedRelayCertVerifier {
+            fingerprint: fingerprint.to_string(),
+            provider,
+        }))
+        .with_no_client_auth();
+    Ok(tokio_rustls::TlsConnector::from(Arc::new(config)))
+}
+
+/// What `freed-sync` was asked to do.
+pub enum SyncCliCommand {
+    /// Write the relay's document to `out`.
+    Pull { url: String, out: PathBuf },
+    /// Merge the document in `file` into the relay's.
+    Push { url: String, file: PathBuf },
+    /// Write a `freed-{ts}.automerge` snapshot to `dir` whenever the document
+    /// changes, until interrupted. Pruning follows the retention settings in
+    /// `data_dir`, or in `dir` when none is given.
+    Watch {
+        url: String,
+        dir: PathBuf,
+        data_dir: Option<PathBuf>,
+    },
+}
+
+/// How long a pull or push waits for the relay's answer.
+const SYNC_CLI_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A relay as described by a pairing URL.
+pub(crate) struct SyncCliTarget {
+    /// The URL's host, then its `a=` alternates. IPv6 hosts are bracketed.
+    hosts: Vec<String>,
+    port: u16,
+    /// `/` for a relay, `/client/<room>` through a rendezvous server.
+    path: String,
+    /// `wss://`, or any URL with a fingerprint to pin.
+    pub(crate) secure: bool,
+    /// Pinned relay certificate. A `wss://` URL without one is verified
+    /// against the public web PKI.
+    pub(crate) fingerprint: Option<String>,
+    /// Upgrade credentials to try, in order.
+    credentials: Vec<String>,
+    sync_key: Option<RelayPayloadKey>,
+}
+
+pub(crate) fn parse_sync_cli_url(link: &str, name: &str) -> Result<SyncCliTarget, String> {
+    let url = url::Url::parse(link.trim()).map_err(|e| format!("invalid pairing URL: {}", e))?;
+    let query = url.query();
+    let scheme_secure = match url.scheme() {
+        "wss" => true,
+        "ws" => false,
+        scheme => {
+            return Err(format!(
+                "pairing URL must be ws:// or wss://, not {}://",
+                scheme
+            ))
+        }
+    };
+    let fingerprint = match relay_query_param(query, "fp") {
+        Some(fp)
+            if URL_SAFE_NO_PAD
+                .decode(fp)
+                .is_ok_and(|digest| digest.len() == 32) =>
+        {
+            Some(fp.to_string())
+        }
+        Some(_) => return Err("pairing URL has an invalid certificate fingerprint".to_string()),
+        None => None,
+    };
+    // A LAN pairing URL is ws:// for browsers; with a fingerprint to pin,
+    // this client upgrades to TLS on the same port.
+    let secure = scheme_secure || fingerprint.is_some();
+    let mut hosts = vec![url.host_str().ok_or("pairing URL has no host")?.to_string()];
+    // The desktop UI percent-encodes alternates; the relay's own links do not.
+    if let Some((_, alternates)) = url.query_pairs().find(|(key, _)| key == "a") {
+        for host in alternates.split(',').filter(|host| !host.is_empty()) {
+            if !hosts.iter().any(|known| known == host) {
+                hosts.push(host.to_string());
+            }
+        }
+    }
+    let credentials = match (
+        relay_query_param(query, "pc").filter(|code| !code.is_empty()),
+        relay_query_param(query, "d"),
+        relay_query_param(query, "t"),
+    ) {
+        (_, Some(device_id), Some(token)) => vec![format!("d={}&t={}", device_id, token)],
+        (Some(code), _, _) => vec![
+            format!(
+                "d={}&t={}",
+                pairing_code_device_id(code),
+                pairing_code_device_credential(code)
+            ),
+            format!(
+                "pc={}&n={}",
+                code,
+                url::form_urlencoded::byte_serialize(name.as_bytes()).collect::<String>()
+            ),
+        ],
+        (None, None, Some(token)) => vec![format!("t={}", token)],
+        _ => return Err("pairing URL has no pairing code or credentials".to_string()),
+    };
+    let sync_key = match url
+        .fragment()
+        .and_then(|fragment| fragment.strip_prefix("k="))
+    {
+        Some(param) => Some(
+            RelayPayloadKey::from_secret_param(param)
+                .ok_or("pairing URL has an invalid sync key")?,
+        ),
+        None => None,
+    };
+    Ok(SyncCliTarget {
+        hosts,
+        port: url.port().unwrap_or(DEFAULT_SYNC_RELAY_PORT),
+        path: url.path().to_string(),
+        secure,
+        fingerprint,
+        credentials,
+        sync_key,
+    })
+}
+
+fn sync_cli_local_name() -> String {
+    match hostname::get().ok().and_then(|h| h.into_string().ok()) {
+        Some(hostname) => format!("freed-sync ({})", hostname),
+        None => "freed-sync".to_string(),
+    }
+}
+
+/// Why a `freed-sync` connection ended.
+#[derive(Debug, PartialEq, Eq)]
+enum SyncCliError {
+    /// Network trouble; `watch` reconnects after a backoff.
+    Retry(String),
+    /// The relay will not take this client back without a fresh pairing URL.
+    Fatal(String),
+}
+
+impl SyncCliError {
+    fn into_message(self) -> String {
+        match self {
+            Self::Retry(message) | Self::Fatal(message) => message,
+        }
+    }
+}
+
+pub(crate) trait RelayIo:
+    tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send
+{
+}
+
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> RelayIo for T {}
+
+/// Verifies servers against the public web PKI, for rendezvous servers behind
+/// an ordinary certificate.
+fn public_tls_connector() -> Result<tokio_rustls::TlsConnector, String> {
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| e.to_string())?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    Ok(tokio_rustls::TlsConnector::from(Arc::new(config)))
+}
+
+/// Connects to `host` (IPv6 may be bracketed) and, when `secure`, completes
+/// TLS: pinned to `fingerprint` when there is one, else verified against the
+/// public web PKI.
+pub(crate) async fn open_relay_client_stream(
+    host: &str,
+    port: u16,
+    secure: bool,
+    fingerprint: Option<&str>,
+) -> Result<Box<dyn RelayIo>, String> {
+    use rustls::pki_types::ServerName;
+
+    let bare_host = host.trim_start_matches('[').trim_end_matches(']');
+    let stream = match timeout(
+        SYNC_PEER_CONNECT_TIMEOUT,
+        TcpStream::connect((bare_host, port)),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(error)) => return Err(format!("connect failed: {}", error)),
+        Err(_) => return Err("connect timed out".to_string()),
+    };
+    if !secure {
+        return Ok(Box::new(stream));
+    }
+    let connector = match fingerprint {
+        Some(fingerprint) => pinned_relay_connector(fingerprint)?,
+        None => public_tls_connector()?,
+    };
+    let server_name =
+        ServerName::try_from(bare_host.to_string()).map_err(|e| format!("invalid host: {}", e))?;
+    match timeout(
+        SYNC_RELAY_TLS_HANDSHAKE_TIMEOUT,
+        connector.connect(server_name, stream),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => Ok(Box::new(stream)),
+        Ok(Err(error)) => Err(format!("TLS handshake failed: {}", error)),
+        Err(_) => Err("TLS handshake timed out".to_string()),
+    }
+}
+
+pub(crate) type SyncCliSocket = tokio_tungstenite::WebSocketStream<Box<dyn RelayIo>>;
+
+/// Connects to one host with one set of credentials.
+async fn connect_sync_cli_host(
+    target: &SyncCliTarget,
+    host: &str,
+    credentials: &str,
+) -> Result<SyncCliSocket, SyncCliError> {
+    let stream = open_relay_client_stream(
+        host,
+        target.port,
+        target.secure,
+        target.fingerprint.as_deref(),
+    )
+    .await
+    .map_err(SyncCliError::Retry)?;
+    let scheme = if target.secure { "wss" } else { "ws" };
+    let upgrade = tokio_tungstenite::client_async_with_config(
+        format!(
+            "{}://{}:{}{}?v={}&{}",
+            scheme, host, target.port, target.path, RELAY_PROTOCOL_VERSION, credentials
+        ),
+        stream,
+        Some(relay_websocket_config(&DEFAULT_RELAY_ABUSE_POLICY)),
+    );
+    match timeout(SYNC_PEER_CONNECT_TIMEOUT, upgrade).await {
+        Ok(Ok((socket, _))) => Ok(socket),
+        Ok(Err(WsError::Http(response))) if response.status() == 401 => Err(SyncCliError::Fatal(
+            "relay rejected the pairing URL's credentials".to_string(),
+        )),
+        Ok(Err(error)) => Err(SyncCliError::Retry(format!("upgrade failed: {}", error))),
+        Err(_) => Err(SyncCliError::Retry("upgrade timed out".to_string())),
+    }
+}
+
+/// Tries every host with every credential, sends a `hello` carrying `heads`,
+/// and returns the first connection that opens.
+async fn connect_sync_cli(
+    target: &SyncCliTarget,
+    heads: &[RelayHead],
+) -> Result<SyncCliSocket, SyncCliError> {
+    let mut last_error = SyncCliError::Retry("pairing URL has no host".to_string());
+    'hosts: for host in &target.hosts {
+        for credentials in &target.credentials {
+            match connect_sync_cli_host(target, host, credentials).await {
+                Ok(mut socket) => {
+                    let hello = serde_json::json!({
+                        "type": "hello",
+                        "version": RELAY_CONTROL_VERSION,
+                        "clientId": "freed-sync",
+                        "heads": format_relay_heads_hex(heads),
+                        "capabilities": RELAY_CAPABILITIES,
+                    });
+                    socket
+                        .send(Message::Text(hello.to_string().into()))
+                        .await
+                        .map_err(|e| SyncCliError::Retry(format!("hello failed: {}", e)))?;
+                    info!("[Sync] Connected to relay at {}", host);
+                    return Ok(socket);
+                }
+                // A redeemed pairing code fails once its derived credential
+                // is also refused; fall through to the next form.
+                Err(SyncCliError::Fatal(error)) => last_error = SyncCliError::Fatal(error),
+                Err(SyncCliError::Retry(error)) => {
+                    warn!("[Sync] Relay at {} unreachable: {}", host, error);
+                    last_error = SyncCliError::Retry(format!("{}: {}", host, error));
+                    continue 'hosts;
+                }
+            }
+        }
+    }
+    Err(last_error)
+}
+
+enum SyncCliMessage {
+    /// A whole document or change chunks, sealed when the relay's are.
+    Payload(Vec<u8>),
+    Control(RelayServerControl),
+}
+
+/// Waits for the next document payload or control message.
+async fn next_sync_cli_message(socket: &mut SyncCliSocket) -> Result<SyncCliMessage, SyncCliError> {
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Binary(data))) => {
+                let update = match parse_relay_client_message(true, &data) {
+                    RelayClientMessage::Update(update) => update,
+                    RelayClientMessage::FullDocRequest => continue,
+                    RelayClientMessage::Malformed(error) => {
+                        warn!("[Sync] Ignored malformed frame from relay: {}", error);
+                        continue;
+                    }
+                };
+                return Ok(SyncCliMessage::Payload(update.payload().to_vec()));
+            }
+            Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                Ok(RelayServerControl::FactoryReset { .. }) => {
+                    return Err(SyncCliError::Fatal("relay was factory reset".to_string()))
+                }
+                Ok(RelayServerControl::DeviceRevoked) => {
+                    return Err(SyncCliError::Fatal("relay revoked this client".to_string()))
+                }
+                Ok(control) => return Ok(SyncCliMessage::Control(control)),
+                Err(error) => warn!("[Sync] Ignored control message from relay: {}", error),
+            },
+            Some(Ok(Message::Close(frame))) => {
+                return Err(match frame.map(|frame| u16::from(frame.code)) {
+                    Some(RELAY_CLOSE_FACTORY_RESET) => SyncCliError::Fatal(
+                        "relay was reset or rotated its pairing; pair again".to_string(),
+                    ),
+                    Some(RELAY_CLOSE_DEVICE_REVOKED) => {
+                        SyncCliError::Fatal("relay revoked this client".to_string())
+                    }
+                    _ => SyncCliError::Retry("relay closed the connection".to_string()),
+                })
+            }
+            Some(Ok(_)) => {}
+            Some(Err(error)) => return Err(SyncCliError::Retry(error.to_string())),
+            None => {
+                return Err(SyncCliError::Retry(
+                    "relay closed the connection".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+/// Plain Automerge bytes of a payload from the relay.
+fn open_sync_cli_payload(target: &SyncCliTarget, payload: &[u8]) -> Result<Vec<u8>, String> {
+    match &target.sync_key {
+        Some(key) => key.open(payload),
+        None if payload.starts_with(&SEALED_PAYLOAD_MAGIC) => {
+            Err("document is sealed and the pairing URL has no sync key".to_string())
+        }
+        None => Ok(payload.to_vec()),
+    }
+}
+
+/// Downloads the relay's document to `out`. Returns the bytes written.
+pub(crate) async fn sync_cli_pull(target: &SyncCliTarget, out: &Path) -> Result<usize, String> {
+    let mut socket = connect_sync_cli(target, &[])
+        .await
+        .map_err(SyncCliError::into_message)?;
+    let mut doc = automerge::AutoCommit::new();
+    let received = timeout(SYNC_CLI_REPLY_TIMEOUT, async {
+        loop {
+            match next_sync_cli_message(&mut socket)
+                .await
+                .map_err(SyncCliError::into_message)?
+            {
+                SyncCliMessage::Payload(payload) => {
+                    doc.load_incremental(&open_sync_cli_payload(target, &payload)?)
+                        .map_err(|e| format!("relay sent an unreadable document: {}", e))?;
+                    return Ok(());
+                }
+                SyncCliMessage::Control(RelayServerControl::Ack { heads, .. })
+                    if heads.is_empty() =>
+                {
+                    return Err("relay holds no document".to_string());
+                }
+                SyncCliMessage::Control(_) => {}
+            }
+        }
+    })
+    .await;
+    let _ = socket.close(None).await;
+    received.map_err(|_| "timed out waiting for the relay's document".to_string())??;
+    let bytes = doc.save();
+    write_file_atomically(out, &bytes)?;
+    Ok(bytes.len())
+}
+
+/// Merges the document in `file` into the relay's. Returns the relay's heads
+/// afterwards.
+async fn sync_cli_push(target: &SyncCliTarget, file: &Path) -> Result<Vec<String>, String> {
+    let bytes =
+        std::fs::read(file).map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+    validate_snapshot_bytes(&bytes)?;
+    let mut doc = automerge::AutoCommit::load(&bytes)
+        .map_err(|e| format!("{} is not an Automerge document: {}", file.display(), e))?;
+    let heads: Vec<RelayHead> = doc.get_heads().into_iter().map(|hash| hash.0).collect();
+    let payload = match &target.sync_key {
+        Some(key) => key.seal(&bytes)?,
+        None => bytes,
+    };
+
+    let mut socket = connect_sync_cli(target, &heads)
+        .await
+        .map_err(SyncCliError::into_message)?;
+    let update = RelayUpdate::full_doc(&payload, Arc::new(heads));
+    let acked = timeout(SYNC_CLI_REPLY_TIMEOUT, async {
+        socket
+            .send(Message::Binary(update.frame().clone()))
+            .await
+            .map_err(|e| format!("send failed: {}", e))?;
+        loop {
+            match next_sync_cli_message(&mut socket)
+                .await
+                .map_err(SyncCliError::into_message)?
+            {
+                SyncCliMessage::Control(RelayServerControl::HeadsAck { heads }) => {
+                    return Ok(heads)
+                }
+                SyncCliMessage::Control(RelayServerControl::Error { reason }) => {
+                    return Err(format!("relay rejected the document: {}", reason))
+                }
+                _ => {}
+            }
+        }
+    })
+    .await;
+    let _ = socket.close(None).await;
+    acked.map_err(|_| "timed out waiting for the relay to store the document".to_string())?
+}
+
+/// One `watch` connection: snapshots every update that moves `doc`'s heads.
+async fn watch_sync_cli_session(
+    target: &SyncCliTarget,
+    dir: &Path,
+    data_dir: &Path,
+    doc: &mut automerge::AutoCommit,
+) -> Result<(), SyncCliError> {
+    let heads: Vec<RelayHead> = doc.get_heads().into_iter().map(|hash| hash.0).collect();
+    let mut socket = connect_sync_cli(target, &heads).await?;
+    loop {
+        let SyncCliMessage::Payload(payload) = next_sync_cli_message(&mut socket).await? else {
+            continue;
+        };
+        let bytes = open_sync_cli_payload(target, &payload).map_err(SyncCliError::Fatal)?;
+        let before = doc.get_heads();
+        if let Err(error) = doc.load_incremental(&bytes) {
+            warn!("[Sync] Ignored unreadable update from relay: {}", error);
+            continue;
+        }
+        if doc.get_heads() == before {
+            continue;
+        }
+        let snapshot = doc.save();
+        info!("[Snapshot] Writing {} byte snapshot", snapshot.len());
+        let (dir, data_dir) = (dir.to_path_buf(), data_dir.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            let ts = now_unix_ms() / 1000;
+            write_file_atomically(&dir.join(format!("freed-{}.automerge", ts)), &snapshot)?;
+            prune_snapshots(&dir, ts, &SnapshotRetention::load(&data_dir));
+            Ok::<_, String>(())
+        })
+        .await
+        .map_err(|e| SyncCliError::Fatal(e.to_string()))?
+        .map_err(SyncCliError::Fatal)?;
+    }
+}
+
+/// Snapshots the relay's document into `dir` until the relay turns this
+/// client away. Reconnects with backoff across network trouble.
+async fn sync_cli_watch(
+    target: &SyncCliTarget,
+    dir: &Path,
+    data_dir: Option<&Path>,
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    let mut doc = automerge::AutoCommit::new();
+    let mut delay = SYNC_PEER_RETRY_MIN_DELAY;
+    loop {
+        let started = Instant::now();
+        match watch_sync_cli_session(target, dir, data_dir.unwrap_or(dir), &mut doc).await {
+            Ok(()) => return Ok(()),
+            Err(SyncCliError::Fatal(reason)) => return Err(reason),
+            Err(SyncCliError::Retry(reason)) => {
+                warn!(
+                    "[Sync] Lost the relay: {}; retrying in {}s",
+                    reason,
+                    delay.as_secs()
+                );
+            }
+        }
+        if started.elapsed() >= SYNC_PEER_RETRY_MAX_DELAY {
+            delay = SYNC_PEER_RETRY_MIN_DELAY;
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(SYNC_PEER_RETRY_MAX_DELAY);
+    }
+}
+
+/// Runs one `freed-sync` command to completion.
+pub fn run_sync_cli(command: SyncCliCommand) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let name = sync_cli_local_name();
+    runtime.block_on(async move {
+        match command {
+            SyncCliCommand::Pull { url, out } => {
+                let target = parse_sync_cli_url(&url, &name)?;
+                let bytes = sync_cli_pull(&target, &out).await?;
+                info!("[Sync] Pulled {} bytes to {}", bytes, out.display());
+                Ok(())
+            }
+            SyncCliCommand::Push { url, file } => {
+                let target = parse_sync_cli_url(&url, &name)?;
+                let heads = sync_cli_push(&target, &file).await?;
+                info!(
+                    "[Sync] Pushed {}; relay now holds {} head(s)",
+                    file.display(),
+                    heads.len()
+                );
+                Ok(())
+            }
+            SyncCliCommand::Watch { url, dir, data_dir } => {
+                let target = parse_sync_cli_url(&url, &name)?;
+                info!("[Sync] Writing snapshots to {}", dir.display());
+                tokio::select! {
+                    result = sync_cli_watch(&target, &dir, data_dir.as_deref()) => result,
+                    _ = tokio::signal::ctrl_c() => {
+                        info!("[Sync] Stopped watching");
+                        Ok(())
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::RelayTlsIdentity;
+    #[cfg(feature = "desktop")]
+    use crate::relay::{
+        store_relay_client_doc_if_current, sync_pairing_url,
+        tests::{automerge_doc_bytes, automerge_doc_entries, loopback_test_relay},
+        RelayClientStore, SyncRelayState,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn pinned_relay_connector_accepts_only_the_pinned_certificate() {
+        use rustls::pki_types::ServerName;
+
+        let tls = RelayTlsIdentity::generate()
+            .unwrap()
+            .to_relay_tls()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = tls.acceptor.clone();
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    stream.write_all(b"relay").await.unwrap();
+                    stream.shutdown().await.unwrap();
+                }
+            }
+        });
+
+        let server_name = ServerName::try_from("127.0.0.1").unwrap();
+        let other = RelayTlsIdentity::generate().unwrap().fingerprint();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        assert!(pinned_relay_connector(&other)
+            .unwrap()
+            .connect(server_name.clone(), stream)
+            .await
+            .is_err());
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = pinned_relay_connector(&tls.fingerprint)
+            .unwrap()
+            .connect(server_name, stream)
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"relay");
+        server.await.unwrap();
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn sync_cli_urls_list_hosts_credentials_and_sync_key() {
+        let state = SyncRelayState::new(9001);
+        let tls = RelayTlsIdentity::generate()
+            .unwrap()
+            .to_relay_tls()
+            .unwrap();
+        let fingerprint = tls.fingerprint.clone();
+        *state.tls.write().unwrap() = Some(tls);
+        let key = RelayPayloadKey::generate();
+        let secret = key.secret_param();
+        *state.payload_key.write().unwrap() = Some(Arc::new(key));
+
+        let link = sync_pairing_url(&state, &["192.168.1.20", "[fd00::20]"], "code-1");
+        let target = parse_sync_cli_url(&link, "freed-sync (den)").unwrap();
+        assert_eq!(target.hosts, vec!["192.168.1.20", "[fd00::20]"]);
+        assert_eq!(target.port, 9001);
+        assert_eq!(target.fingerprint, Some(fingerprint));
+        assert_eq!(
+            target.credentials,
+            vec![
+                format!(
+                    "d={}&t={}",
+                    pairing_code_device_id("code-1"),
+                    pairing_code_device_credential("code-1")
+                ),
+                "pc=code-1&n=freed-sync+%28den%29".to_string(),
+            ]
+        );
+        assert_eq!(target.sync_key.unwrap().secret_param(), secret);
+
+        // Alternates percent-encoded by the desktop UI decode the same way.
+        let encoded = link.replace("&a=[fd00::20]", "&a=%5Bfd00%3A%3A20%5D");
+        assert_eq!(
+            parse_sync_cli_url(&encoded, "freed-sync").unwrap().hosts,
+            vec!["192.168.1.20", "[fd00::20]"]
+        );
+        let legacy = parse_sync_cli_url("ws://relay.local?t=token", "freed-sync").unwrap();
+        assert_eq!(legacy.port, DEFAULT_SYNC_RELAY_PORT);
+        assert_eq!(legacy.fingerprint, None);
+        assert_eq!(legacy.credentials, vec!["t=token"]);
+        assert!(legacy.sync_key.is_none());
+
+        for invalid in [
+            link.replace("wss://", "https://"),
+            link.replace("fp=", "fp=x"),
+            link.replace("pc=code-1&", ""),
+            format!("{}x", link),
+        ] {
+            assert!(
+                parse_sync_cli_url(&invalid, "freed-sync").is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    #[tokio::test]
+    async fn sync_cli_pulls_pushes_and_watches_the_relay_document() {
+        let (state, _, _) = loopback_test_relay(DEFAULT_RELAY_ABUSE_POLICY).await;
+        *state.tls.write().unwrap() = Some(
+            RelayTlsIdentity::generate()
+                .unwrap()
+                .to_relay_tls()
+                .unwrap(),
+        );
+        let key = Arc::new(RelayPayloadKey::generate());
+        *state.payload_key.write().unwrap() = Some(key.clone());
+        let code = state
+            .devices
+            .lock()
+            .unwrap()
+            .issue_pairing_code(now_unix_ms());
+        let link = sync_pairing_url(&state, &["127.0.0.1"], &code);
+        let target = parse_sync_cli_url(&link, "freed-sync (test)").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let pulled = dir.path().join("pulled.automerge");
+
+        // The first run redeems the pairing code.
+        assert_eq!(
+            sync_cli_pull(&target, &pulled).await.unwrap_err(),
+            "relay holds no document"
+        );
+        assert!(!pulled.exists());
+
+        let pushed = dir.path().join("pushed.automerge");
+        std::fs::write(&pushed, automerge_doc_bytes("title", "a")).unwrap();
+        let heads = sync_cli_push(&target, &pushed).await.unwrap();
+        assert_eq!(
+            heads,
+            format_relay_heads_hex(&state.current_heads.read().unwrap())
+        );
+        let held = state.current_doc.read().await.clone().unwrap();
+        assert!(held.starts_with(&SEALED_PAYLOAD_MAGIC));
+        let expected = vec![("title".to_string(), "a".to_string())];
+        assert_eq!(automerge_doc_entries(&key.open(&held).unwrap()), expected);
+
+        let unkeyed = SyncCliTarget {
+            sync_key: None,
+            ..parse_sync_cli_url(&link, "freed-sync (test)").unwrap()
+        };
+        let error = sync_cli_push(&unkeyed, &pushed).await.unwrap_err();
+        assert!(error.contains("unsealed"), "{}", error);
+
+        // Later runs reuse the credential derived from the same code.
+        assert!(sync_cli_pull(&target, &pulled).await.unwrap() > 0);
+        assert_eq!(
+            automerge_doc_entries(&std::fs::read(&pulled).unwrap()),
+            expected
+        );
+        let devices = state.devices.lock().unwrap().summaries();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "freed-sync (test)");
+
+        let snapshots = dir.path().join("snapshots");
+        let watch_dir = snapshots.clone();
+        let data_dir = dir.path().to_path_buf();
+        SnapshotRetention {
+            minutely: 1,
+            hourly: 0,
+            daily: 0,
+            max_total_bytes: None,
+        }
+        .persist(&data_dir)
+        .unwrap();
+        let watcher = tokio::spawn(async move {
+            sync_cli_watch(&target, &watch_dir, Some(data_dir.as_path())).await
+        });
+        let mut edited = automerge::AutoCommit::load(&key.open(&held).unwrap()).unwrap();
+        {
+            use automerge::transaction::Transactable;
+            edited.put(automerge::ROOT, "later", "b").unwrap();
+        }
+        assert_eq!(
+            store_relay_client_doc_if_current(&state, 0, &key.seal(&edited.save()).unwrap()).await,
+            RelayClientStore::Stored
+        );
+        let expected = vec![
+            ("later".to_string(), "b".to_string()),
+            ("title".to_string(), "a".to_string()),
+        ];
+        let snapshotted = async {
+            loop {
+                let written = std::fs::read_dir(&snapshots)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter_map(|entry| std::fs::read(entry.path()).ok())
+                    .filter(|bytes| automerge::AutoCommit::load(bytes).is_ok())
+                    .any(|bytes| automerge_doc_entries(&bytes) == expected);
+                if written {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        timeout(Duration::from_secs(10), snapshotted)
+            .await
+            .expect("watch snapshots the relay's update");
+        watcher.abort();
+        // Pruned with the data dir's retention, not the built-in default.
+        assert_eq!(std::fs::read_dir(&snapshots).unwrap().count(), 1);
+    }
+}
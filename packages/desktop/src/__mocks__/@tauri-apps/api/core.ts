@@ -251,6 +251,8 @@ const handlers: Record<string, Handler> = {
   get_desktop_installation_witness: () => "a".repeat(64),
   get_updater_target: () => "darwin-aarch64",
   retry_startup_after_crash: () => null,
+  get_startup_recovery_state: () => ({ recovery_tier: "none", document_check: null }),
+  rollback_to_last_good_snapshot: () => "freed-0.json",
   export_startup_diagnostics: () => "/Users/test/Downloads/freed-diagnostics-test.json",
   clear_factory_reset_runtime_artifacts: () => null,
   factory_reset_sync_relay: () => "factory-reset-pairing-token",
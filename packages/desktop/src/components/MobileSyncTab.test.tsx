@@ -10,11 +10,20 @@ const mocks = vi.hoisted(() => ({
   cancelConnect: vi.fn(),
   disconnect: vi.fn(),
   getAllLocalIPs: vi.fn(async () => []),
+  getRelayNetworkSettings: vi.fn(async () => ({
+    bindAddress: null,
+    interface: null,
+    ipv6: false,
+    port: null,
+    allowedSubnets: [],
+    allowPlaintext: false,
+  })),
   getSyncUrl: vi.fn(async () => "ws://127.0.0.1:1421?t=pairing-token"),
   invoke: vi.fn(async () => false),
   onStatusChange: vi.fn(() => () => {}),
   resetPairingToken: vi.fn(),
   resolveCloudSyncConflict: vi.fn(async () => {}),
+  setRelayNetworkSettings: vi.fn(async (settings: unknown) => settings),
   syncCloudProviderNow: vi.fn(async () => {}),
 }));
 
@@ -40,10 +49,12 @@ vi.mock("../hooks/useCloudProviders", () => ({
 
 vi.mock("../lib/sync", () => ({
   getAllLocalIPs: mocks.getAllLocalIPs,
+  getRelayNetworkSettings: mocks.getRelayNetworkSettings,
   getSyncUrl: mocks.getSyncUrl,
   onStatusChange: mocks.onStatusChange,
   resetPairingToken: mocks.resetPairingToken,
   resolveCloudSyncConflict: mocks.resolveCloudSyncConflict,
+  setRelayNetworkSettings: mocks.setRelayNetworkSettings,
   syncCloudProviderNow: mocks.syncCloudProviderNow,
 }));
 
@@ -268,4 +279,32 @@ describe("MobileSyncTab cloud diagnostics", () => {
     });
     confirmMock.mockRestore();
   });
+
+  it("only serves plaintext to browsers once the user opts in", async () => {
+    await act(async () => {
+      root.render(<MobileSyncTab />);
+    });
+    const qrTab = Array.from(container.querySelectorAll("button")).find(
+      (button) => button.textContent === "Scan QR",
+    );
+    await act(async () => {
+      qrTab?.dispatchEvent(new MouseEvent("click", { bubbles: true }));
+    });
+
+    const toggle = container.querySelector<HTMLInputElement>("[data-testid='relay-allow-plaintext']");
+    expect(toggle?.checked).toBe(false);
+    expect(container.textContent).toContain("cross the network in the clear");
+
+    mocks.getSyncUrl.mockClear();
+    await act(async () => {
+      toggle?.click();
+      await Promise.resolve();
+    });
+
+    expect(mocks.setRelayNetworkSettings).toHaveBeenCalledWith(
+      expect.objectContaining({ allowPlaintext: true }),
+    );
+    expect(mocks.getSyncUrl).toHaveBeenCalled();
+    expect(toggle?.checked).toBe(true);
+  });
 });
@@ -4,13 +4,13 @@
  * Three tabs:
  *   1. Cloud Sync, manage Google Drive / Dropbox connections
  *   2. Scan QR, QR code for LAN pairing (rendered locally)
- *   3. Manual, copyable IP + pairing token for manual entry
+ *   3. Manual, copyable IP, pairing code, and sync key for manual entry
  *
  * If a VPN or multiple network interfaces are detected, an interface
  * picker lets the user regenerate the QR with the correct IP.
  */
 
-import { useState, useEffect, useCallback } from "react";
+import { useState, useEffect, useCallback, useRef } from "react";
 import { flushSync } from "react-dom";
 import { getPwaHostForChannel } from "@freed/shared";
 import type { CloudProvider } from "@freed/ui/components/CloudProviderCard";
@@ -21,6 +21,8 @@ import QRCode from "react-qr-code";
 import {
   getSyncUrl,
   getAllLocalIPs,
+  getRelayNetworkSettings,
+  setRelayNetworkSettings,
   resetPairingToken,
   onStatusChange,
   syncCloudProviderNow,
@@ -28,6 +30,7 @@ import {
   type CloudConflictWinner,
   type SyncStatus,
   type NetworkInterface,
+  type RelayNetworkSettings,
 } from "../lib/sync";
 import { useCloudProviders } from "../hooks/useCloudProviders";
 import { CloudProviderCard } from "./CloudProviderCard";
@@ -42,16 +45,17 @@ import {
 /** Parse the LAN IP from a relay pairing URL. */
 function parseIp(url: string): string {
   try {
-    return new URL(url).hostname;
+    return new URL(url).hostname.replace(/^\[(.*)\]$/, "$1");
   } catch {
     return "";
   }
 }
 
-/** Parse the 43-character pairing token from the ?t= query param. */
+/** Parse the one-time pairing code (?pc=), or a legacy ?t= pairing token. */
 function parseToken(url: string): string {
   try {
-    return new URL(url).searchParams.get("t") ?? "";
+    const params = new URL(url).searchParams;
+    return params.get("pc") ?? params.get("t") ?? "";
   } catch {
     return "";
   }
@@ -106,6 +110,39 @@ function describeUploadGap(state: CloudProviderDebugState | null): string {
   return "No upload has completed yet. Use Sync now to force a full pass.";
 }
 
+/**
+ * Pairing codes are single use and expire after ten minutes, so the tab asks
+ * for a fresh one a little before that and whenever a device pairs.
+ */
+const PAIRING_CODE_REFRESH_MS = 9 * 60 * 1000;
+
+/** Carry a freshly issued pairing code over to the URL on screen, keeping the selected host. */
+function withPairingCode(displayed: string, fresh: string): string {
+  try {
+    const current = new URL(displayed);
+    const issued = new URL(fresh);
+    const code = issued.searchParams.get("pc");
+    if (!code || current.protocol !== issued.protocol || current.pathname !== issued.pathname) {
+      return fresh;
+    }
+    current.searchParams.delete("t");
+    current.searchParams.set("pc", code);
+    current.hash = issued.hash;
+    return current.toString();
+  } catch {
+    return fresh;
+  }
+}
+
+/** Parse the `#k=` sync key the PWA seals payloads with, if the relay has one. */
+function parseSyncKey(url: string): string {
+  try {
+    return new URLSearchParams(new URL(url).hash.replace(/^#/, "")).get("k") ?? "";
+  } catch {
+    return "";
+  }
+}
+
 function isDestructiveMergeWarning(message?: string | null): boolean {
   return message?.includes("blocked a sync merge") ?? false;
 }
@@ -134,6 +171,7 @@ export function MobileSyncTab() {
   const [manualSyncError, setManualSyncError] = useState<string | null>(null);
   const [allIPs, setAllIPs] = useState<NetworkInterface[]>([]);
   const [showIPPicker, setShowIPPicker] = useState(false);
+  const [networkSettings, setNetworkSettings] = useState<RelayNetworkSettings | null>(null);
 
   useEffect(() => {
     setDesktopWarningDismissed(
@@ -149,11 +187,31 @@ export function MobileSyncTab() {
   // Independent copy-flash state for each copyable field
   const [copiedIp, setCopiedIp] = useState(false);
   const [copiedToken, setCopiedToken] = useState(false);
+  const [copiedKey, setCopiedKey] = useState(false);
   const [copiedUrl, setCopiedUrl] = useState(false);
 
+  const refreshPairingCode = useCallback(() => {
+    getSyncUrl().then((fresh) => setSyncUrl((displayed) => withPairingCode(displayed, fresh)));
+  }, []);
+
+  useEffect(() => {
+    refreshPairingCode();
+    const timer = setInterval(refreshPairingCode, PAIRING_CODE_REFRESH_MS);
+    return () => clearInterval(timer);
+  }, [refreshPairingCode]);
+
+  // A new device most likely just used the code on screen.
+  const previousClientCount = useRef(0);
+  useEffect(() => {
+    if (clientCount > previousClientCount.current) refreshPairingCode();
+    previousClientCount.current = clientCount;
+  }, [clientCount, refreshPairingCode]);
+
   useEffect(() => {
-    getSyncUrl().then(setSyncUrl);
     getAllLocalIPs().then(setAllIPs);
+    getRelayNetworkSettings()
+      .then(setNetworkSettings)
+      .catch(() => setNetworkSettings(null));
     invoke<boolean>("get_mdns_active")
       .then(setMdnsActive)
       .catch(() => setMdnsActive(false));
@@ -192,12 +250,30 @@ export function MobileSyncTab() {
     }
   }, []);
 
-  /** Switch to a different interface IP while preserving the port and token. */
+  /** Plaintext switches the pairing URL between wss:// and ws://, so fetch a fresh one. */
+  const handleAllowPlaintext = useCallback(async (allowPlaintext: boolean) => {
+    if (!networkSettings) return;
+    try {
+      setNetworkSettings(await setRelayNetworkSettings({ ...networkSettings, allowPlaintext }));
+      refreshPairingCode();
+    } catch (err) {
+      console.error("Failed to change relay network settings:", err);
+    }
+  }, [networkSettings, refreshPairingCode]);
+
+  /** Switch to a different interface IP while preserving the port and pairing code. */
   const handleSelectInterface = (iface: NetworkInterface) => {
     if (!syncUrl) return;
     try {
       const current = new URL(syncUrl);
-      current.hostname = iface.ip;
+      const host = iface.ip.includes(":") ? `[${iface.ip}]` : iface.ip;
+      // The previous primary host becomes an alternate the phone can fall back to.
+      const alternates = (current.searchParams.get("a") ?? "")
+        .split(",")
+        .filter((candidate) => candidate && candidate !== host);
+      if (current.hostname !== host) alternates.unshift(current.hostname);
+      current.hostname = host;
+      current.searchParams.set("a", alternates.join(","));
       setSyncUrl(current.toString());
     } catch {
       // Fallback: use the raw url from the interface (no token)
@@ -208,6 +284,7 @@ export function MobileSyncTab() {
 
   const ip = parseIp(syncUrl);
   const token = parseToken(syncUrl);
+  const syncKey = parseSyncKey(syncUrl);
   const cancelProviderLabel = cancelProvider === "gdrive" ? "Google Drive" : "Dropbox";
   const activeProvider = providers.gdrive.status === "connected" ? "gdrive" : providers.dropbox.status === "connected" ? "dropbox" : null;
   const activeCloudState = activeProvider ? cloudProviders?.[activeProvider] : null;
@@ -462,6 +539,29 @@ export function MobileSyncTab() {
         </p>
       </div>
 
+      {networkSettings && (
+        <label className="mb-4 flex cursor-pointer items-start gap-3 rounded-xl border border-[var(--theme-border-subtle)] bg-[var(--theme-bg-card)] p-3">
+          <input
+            type="checkbox"
+            data-testid="relay-allow-plaintext"
+            checked={networkSettings.allowPlaintext}
+            onChange={(event) => void handleAllowPlaintext(event.target.checked)}
+            className="mt-0.5 h-4 w-4 rounded border-[color:var(--theme-border-subtle)] bg-[color:var(--theme-bg-input)] text-[var(--theme-accent-secondary)] focus:ring-[color:var(--theme-focus-ring)] focus:ring-offset-0"
+          />
+          <div className="min-w-0">
+            <p className="text-xs font-medium text-[var(--theme-text-primary)]">
+              Allow unencrypted connections
+            </p>
+            <p className="mt-1 text-xs text-[var(--theme-text-muted)]">
+              Browsers can't verify this desktop's certificate, so Freed PWA needs this to
+              connect over your network. Documents stay encrypted with the sync key, but
+              pairing codes and device credentials cross the network in the clear. Leave it
+              off on networks you don't trust.
+            </p>
+          </div>
+        </label>
+      )}
+
       {/* VPN / multi-interface warning with IP picker */}
       {(hasVpn || multipleIPs) && (
         <div className="mb-4 p-3 bg-orange-500/10 border border-orange-500/30 rounded-xl">
@@ -535,18 +635,18 @@ export function MobileSyncTab() {
           </div>
         </div>
 
-        {/* Pairing token */}
+        {/* Pairing code */}
         <div className="mb-3">
           <label className="mb-1.5 block text-xs text-[var(--theme-text-muted)]">
-            Pairing token{" "}
-            <span className="text-[var(--theme-text-soft)]">(43 characters)</span>
+            Pairing code{" "}
+            <span className="text-[var(--theme-text-soft)]">(43 characters, single use, expires in 10 minutes)</span>
           </label>
           <div className="flex gap-2">
             <input
               type="text"
               value={token}
               readOnly
-              aria-label="Pairing token"
+              aria-label="Pairing code"
               className="min-w-0 flex-1 rounded-lg border border-[var(--theme-border-subtle)] bg-[var(--theme-bg-input)] px-3 py-2 font-mono text-sm tracking-wide text-[var(--theme-text-secondary)]"
             />
             <button
@@ -558,11 +658,35 @@ export function MobileSyncTab() {
           </div>
         </div>
 
+        {syncKey && (
+          <div className="mb-3">
+            <label className="mb-1.5 block text-xs text-[var(--theme-text-muted)]">
+              Sync key{" "}
+              <span className="text-[var(--theme-text-soft)]">(43 characters, keep private)</span>
+            </label>
+            <div className="flex gap-2">
+              <input
+                type="text"
+                value={syncKey}
+                readOnly
+                aria-label="Sync key"
+                className="min-w-0 flex-1 rounded-lg border border-[var(--theme-border-subtle)] bg-[var(--theme-bg-input)] px-3 py-2 font-mono text-sm tracking-wide text-[var(--theme-text-secondary)]"
+              />
+              <button
+                onClick={makeCopyHandler(syncKey, setCopiedKey)}
+                className="theme-accent-button min-w-[68px] rounded-lg px-3 py-2 text-sm font-medium transition-colors"
+              >
+                {copiedKey ? "Copied!" : "Copy"}
+              </button>
+            </div>
+          </div>
+        )}
+
         {/* Full URL, for power users or browser-based paste */}
         <div>
           <label className="mb-1.5 block text-xs text-[var(--theme-text-muted)]">
             Full URL{" "}
-            <span className="text-[var(--theme-text-soft)]">(IP + port + token combined)</span>
+            <span className="text-[var(--theme-text-soft)]">(IP + port + pairing code combined)</span>
           </label>
           <div className="flex gap-2">
             <input
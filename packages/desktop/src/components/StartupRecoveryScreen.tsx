@@ -29,9 +29,30 @@ type RecoveryStatus =
   | { kind: "downloading"; percent: number }
   | { kind: "error"; message: string };
 
+type StartupRecoveryTier =
+  | "none"
+  | "scrapers_disabled"
+  | "webkit_caches_cleared"
+  | "document_rollback";
+
+interface StartupRecoveryInfo {
+  recovery_tier: StartupRecoveryTier;
+  document_check: {
+    held_document_error: string | null;
+    last_good_snapshot: string | null;
+    last_good_snapshot_timestamp: number | null;
+  } | null;
+}
+
 const RETRY_COMMAND = "retry_startup_after_crash";
 const DIAGNOSTICS_COMMAND = "export_startup_diagnostics";
+const RECOVERY_STATE_COMMAND = "get_startup_recovery_state";
+const ROLLBACK_COMMAND = "rollback_to_last_good_snapshot";
 const INTEGER_FORMATTER = new Intl.NumberFormat(undefined, { maximumFractionDigits: 0 });
+const SNAPSHOT_TIME_FORMATTER = new Intl.DateTimeFormat(undefined, {
+  dateStyle: "medium",
+  timeStyle: "short",
+});
 
 export function StartupRecoveryScreen() {
   const [releaseChannel, setReleaseChannel] = useState(() => bootstrapDesktopReleaseChannel());
@@ -48,6 +69,23 @@ export function StartupRecoveryScreen() {
   const [retryBusy, setRetryBusy] = useState(false);
   const [downloadBusy, setDownloadBusy] = useState(false);
   const [diagnosticsBusy, setDiagnosticsBusy] = useState(false);
+  const [recoveryInfo, setRecoveryInfo] = useState<StartupRecoveryInfo | null>(null);
+  const [rollbackBusy, setRollbackBusy] = useState(false);
+
+  useEffect(() => {
+    let cancelled = false;
+    void invoke<StartupRecoveryInfo>(RECOVERY_STATE_COMMAND)
+      .then((info) => {
+        if (!cancelled) {
+          setRecoveryInfo(info);
+        }
+      })
+      .catch(() => undefined);
+
+    return () => {
+      cancelled = true;
+    };
+  }, []);
 
   useEffect(() => {
     let cancelled = false;
@@ -163,6 +201,22 @@ export function StartupRecoveryScreen() {
     }
   }, [fallbackDownloadUrl]);
 
+  const handleRollback = useCallback(async () => {
+    setRollbackBusy(true);
+    setActionNotice(null);
+    try {
+      await invoke<string>(ROLLBACK_COMMAND);
+      setActionNotice("Library rolled back. Try opening Freed again.");
+    } catch (error) {
+      setStatus({
+        kind: "error",
+        message: error instanceof Error ? error.message : "Rollback failed.",
+      });
+    } finally {
+      setRollbackBusy(false);
+    }
+  }, []);
+
   const handleDownloadDiagnostics = useCallback(async () => {
     setDiagnosticsBusy(true);
     setActionNotice(null);
@@ -192,6 +246,10 @@ export function StartupRecoveryScreen() {
       : null;
   }, [pendingUpdate]);
 
+  const recoveryLine = recoveryInfo ? getRecoveryLine(recoveryInfo.recovery_tier) : null;
+  const rollbackSnapshotTimestamp =
+    recoveryInfo?.document_check?.last_good_snapshot_timestamp ?? null;
+
   const statusLine = actionNotice ?? getStatusLine(status, pendingUpdate);
   const statusIsError = !actionNotice && status.kind === "error";
 
@@ -210,6 +268,10 @@ export function StartupRecoveryScreen() {
             </p>
           </div>
 
+          {recoveryLine ? (
+            <p className="mt-4 text-sm leading-6 text-[var(--theme-text-muted)]">{recoveryLine}</p>
+          ) : null}
+
           {updateLabel ? (
             <div className="mt-5 rounded-[18px] border border-[rgba(124,92,255,0.24)] bg-[rgba(124,92,255,0.1)] p-4">
               <p className="text-sm font-semibold text-[var(--theme-text-primary)]">{updateLabel}</p>
@@ -244,6 +306,20 @@ export function StartupRecoveryScreen() {
               </button>
             ) : null}
 
+            {rollbackSnapshotTimestamp !== null ? (
+              <button
+                id="rollback"
+                type="button"
+                onClick={handleRollback}
+                disabled={rollbackBusy}
+                className="rounded-[14px] border border-[rgba(255,255,255,0.12)] bg-[rgba(255,255,255,0.07)] px-5 py-3 text-base font-semibold text-[var(--theme-text-primary)] transition-colors hover:bg-[rgba(255,255,255,0.12)] disabled:cursor-wait disabled:opacity-70"
+              >
+                {rollbackBusy
+                  ? "Rolling back..."
+                  : `Roll back to ${SNAPSHOT_TIME_FORMATTER.format(rollbackSnapshotTimestamp * 1000)}`}
+              </button>
+            ) : null}
+
             <button
               id="download"
               type="button"
@@ -284,6 +360,19 @@ export function StartupRecoveryScreen() {
   );
 }
 
+function getRecoveryLine(tier: StartupRecoveryTier): string | null {
+  switch (tier) {
+    case "none":
+      return null;
+    case "scrapers_disabled":
+      return "Scrapers are paused for this launch.";
+    case "webkit_caches_cleared":
+      return "Scrapers are paused for this launch and web caches were cleared.";
+    case "document_rollback":
+      return "Scrapers are paused, web caches were cleared, and your library was checked against its snapshots.";
+  }
+}
+
 function getStatusLine(
   status: RecoveryStatus,
   pendingUpdate: PendingDesktopUpdate | null,
@@ -51,7 +51,9 @@ describe("desktop bug reporting", () => {
           "/Users/alice/Library/Application Support/Freed/logs/app.log",
         ];
       }
-      if (command === "list_snapshots") return ["freed-123.automerge"];
+      if (command === "list_snapshots") {
+        return [{ name: "freed-123.automerge", timestamp: 123, logicalBytes: 10, diskBytes: 10 }];
+      }
       return null;
     });
     recordRuntimeError({
@@ -147,5 +149,7 @@ describe("desktop bug reporting", () => {
     expect(logs).toContain("/Users/[REDACTED]");
     expect(fatal).toContain("/Users/[REDACTED]");
     expect(fatal).not.toContain("alice");
+    const snapshots = await zip.file("diagnostics/snapshots.json")?.async("string");
+    expect(snapshots).toContain("freed-123.automerge");
   });
 });
@@ -58,9 +58,15 @@ async function getRuntimeHealthHistory(days: number): Promise<string[]> {
   }
 }
 
+/** One entry of the Rust `list_snapshots` command. */
+interface RelaySnapshotSummary {
+  name: string;
+}
+
 async function getSnapshotNames(): Promise<string[]> {
   try {
-    return await invoke<string[]>("list_snapshots");
+    const snapshots = await invoke<RelaySnapshotSummary[]>("list_snapshots");
+    return snapshots.map((snapshot) => snapshot.name);
   } catch {
     return [];
   }
@@ -77,6 +77,8 @@ let isServerRunning = false;
 let clientCount = 0;
 let pollInterval: ReturnType<typeof setInterval> | null = null;
 let changeUnsubscribe: (() => void) | null = null;
+let peerDocUnlisten: (() => void) | null = null;
+let heldDocUnlisten: (() => void) | null = null;
 let relayPollTick = 0;
 
 // Status callbacks
@@ -107,7 +109,8 @@ export interface NetworkInterface {
 }
 
 /**
- * Get all non-loopback IPv4 network interfaces with sync URLs.
+ * Get all non-loopback addresses the relay serves, with sync URLs. IPv6
+ * addresses appear only when the relay accepts IPv6.
  * Use this to let the user pick the right IP when a VPN is active.
  */
 export async function getAllLocalIPs(): Promise<NetworkInterface[]> {
@@ -118,9 +121,62 @@ export async function getAllLocalIPs(): Promise<NetworkInterface[]> {
   }
 }
 
+/** Where the relay listens and who may connect. Unset fields use defaults. */
+export interface RelayNetworkSettings {
+  /** Bind this address only. Mutually exclusive with `interface`. */
+  bindAddress: string | null;
+  /** Bind every usable address of this interface, e.g. `en0`. */
+  interface: string | null;
+  ipv6: boolean;
+  port: number | null;
+  /** CIDR blocks allowed to connect. Empty admits everyone. */
+  allowedSubnets: string[];
+  /**
+   * Also serve plain `ws://`. Browsers cannot pin the relay's certificate, so
+   * the PWA needs this on the LAN; pairing codes then travel unencrypted.
+   */
+  allowPlaintext: boolean;
+}
+
+export async function getRelayNetworkSettings(): Promise<RelayNetworkSettings> {
+  return invoke<RelayNetworkSettings>("get_relay_network_settings");
+}
+
+/**
+ * Persist relay network settings. The relay rebinds immediately; a new QR
+ * code is needed if the port or addresses changed.
+ */
+export async function setRelayNetworkSettings(
+  settings: RelayNetworkSettings,
+): Promise<RelayNetworkSettings> {
+  return invoke<RelayNetworkSettings>("set_relay_network_settings", { settings });
+}
+
+/** The rendezvous server phones use to reach the relay away from the LAN. */
+export interface RelayRendezvousStatus {
+  /** `ws://` or `wss://` URL of the server, or null when disabled. */
+  url: string | null;
+  /** Room this desktop claims on the server. */
+  room: string | null;
+  /** Whether the relay's tunnel to the server is currently open. */
+  connected: boolean;
+}
+
+export async function getRelayRendezvous(): Promise<RelayRendezvousStatus> {
+  return invoke<RelayRendezvousStatus>("get_relay_rendezvous");
+}
+
+/**
+ * Set or clear the rendezvous server. While one is set, `getSyncUrl` returns
+ * a pairing URL that goes through it.
+ */
+export async function setRelayRendezvous(url: string | null): Promise<RelayRendezvousStatus> {
+  return invoke<RelayRendezvousStatus>("set_relay_rendezvous", { url });
+}
+
 /**
  * Get the sync relay URL for PWA to connect to.
- * The returned URL includes the pairing token.
+ * The returned URL includes a fresh one-time pairing code.
  */
 export async function getSyncUrl(): Promise<string> {
   try {
@@ -158,6 +214,106 @@ export async function getClientCount(): Promise<number> {
   }
 }
 
+/** One live relay connection. */
+export interface SyncClient {
+  /** Pass to `disconnectSyncClient`. */
+  id: number;
+  address: string;
+  deviceId: string | null;
+  deviceName: string | null;
+  /** `clientId` from the client's hello, when it sent one. */
+  clientId: string | null;
+  userAgent: string | null;
+  connectedAtMs: number;
+  lastMessageAtMs: number | null;
+  bytesReceived: number;
+  bytesSent: number;
+  docsPushed: number;
+  docsPulled: number;
+}
+
+/**
+ * Get every live relay connection with its traffic, e.g. to find a phone
+ * that stopped syncing.
+ */
+export async function getSyncClients(): Promise<SyncClient[]> {
+  try {
+    return await invoke<SyncClient[]>("get_sync_clients");
+  } catch {
+    return [];
+  }
+}
+
+/**
+ * Close one relay connection. The client may reconnect right away. Resolves
+ * `false` when the connection had already closed.
+ */
+export async function disconnectSyncClient(id: number): Promise<boolean> {
+  return invoke<boolean>("disconnect_sync_client", { id });
+}
+
+export type SyncPeerStatus = "discovered" | "connecting" | "connected" | "offline" | "needsTrust";
+
+/** Another Freed desktop: trusted, seen over mDNS, or both. */
+export interface SyncPeer {
+  fingerprint: string | null;
+  name: string;
+  host: string;
+  port: number;
+  trusted: boolean;
+  discovered: boolean;
+  status: SyncPeerStatus;
+  lastError: string | null;
+  lastSyncedAtMs: number | null;
+}
+
+export async function listSyncPeers(): Promise<SyncPeer[]> {
+  try {
+    return await invoke<SyncPeer[]>("list_sync_peers");
+  } catch {
+    return [];
+  }
+}
+
+/**
+ * Trust another desktop using the pairing link copied from its Sync tab.
+ * The relay starts replicating with it immediately.
+ */
+export async function trustSyncPeer(pairingLink: string): Promise<SyncPeer> {
+  return invoke<SyncPeer>("trust_sync_peer", { pairingLink });
+}
+
+export async function forgetSyncPeer(fingerprint: string): Promise<void> {
+  await invoke("forget_sync_peer", { fingerprint });
+}
+
+/**
+ * Subscribe to peer discovery and link status changes.
+ */
+export async function onSyncPeersChange(callback: (peers: SyncPeer[]) => void): Promise<() => void> {
+  const unlisten = await listen<SyncPeer[]>("sync-peers-changed", (event) => callback(event.payload));
+  return () => safeUnlisten(unlisten, "sync-peers-changed");
+}
+
+/**
+ * Merge the relay's held document into the local one after a peer desktop
+ * sent changes. The relay already merged them; this brings them into the UI.
+ */
+async function mergePeerDoc(): Promise<void> {
+  const binary = await invoke<number[]>("get_relay_doc");
+  if (binary.length === 0) return;
+  await mergeDoc(new Uint8Array(binary));
+}
+
+/**
+ * Replace a held relay document that no longer loads with ours. The relay
+ * keeps the unreadable copy and turns pushes away until this lands.
+ */
+async function rebroadcastHeldDoc(): Promise<void> {
+  const binary = await getDocBinary();
+  await invoke("broadcast_doc", { docBytes: Array.from(binary) });
+}
+
 /**
  * Check if sync server is running
  */
@@ -295,6 +451,18 @@ export async function startSync(): Promise<void> {
   // applyChange() the worker posts BROADCAST_REQUEST to the main thread which
   // calls invoke("broadcast_doc") directly. No subscriber needed here.
 
+  peerDocUnlisten = await listen<string>("sync-peer-doc", (event) => {
+    mergePeerDoc().catch((error) => {
+      log.warn(`[sync] failed to merge document from peer ${event.payload}: ${String(error)}`);
+    });
+  });
+  heldDocUnlisten = await listen("sync-held-doc-unreadable", () => {
+    log.warn("[sync] relay held document is unreadable; broadcasting ours");
+    rebroadcastHeldDoc().catch((error) => {
+      log.warn(`[sync] failed to replace the relay's held document: ${String(error)}`);
+    });
+  });
+
   const url = await getSyncUrl();
   log.info(`[sync] relay server running at ${url}`);
 
@@ -311,6 +479,10 @@ export function stopSync(): void {
     changeUnsubscribe();
     changeUnsubscribe = null;
   }
+  safeUnlisten(peerDocUnlisten, "sync-peer-doc");
+  peerDocUnlisten = null;
+  safeUnlisten(heldDocUnlisten, "sync-held-doc-unreadable");
+  heldDocUnlisten = null;
 
   isServerRunning = false;
   clientCount = 0;
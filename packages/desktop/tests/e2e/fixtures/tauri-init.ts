@@ -147,6 +147,8 @@ export function tauriInitScript(): string {
       },
       get_updater_target: () => 'darwin-aarch64',
       retry_startup_after_crash: () => null,
+      get_startup_recovery_state: () => ({ recovery_tier: 'none', document_check: null }),
+      rollback_to_last_good_snapshot: () => 'freed-0.json',
       export_startup_diagnostics: () => '/Users/test/Downloads/freed-diagnostics-test.json',
       reset_pairing_token: () => null,
       clear_factory_reset_runtime_artifacts: () => null,
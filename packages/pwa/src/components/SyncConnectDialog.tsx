@@ -15,6 +15,7 @@ import { CloudProviderCard } from "@freed/ui/components/CloudProviderCard";
 import { initiateGDriveOAuth } from "../lib/cloud-oauth";
 import { capturePwaRuntimeLifecycle } from "../lib/factory-reset-coordinator";
 import { storePwaOAuthRuntimeGeneration } from "../lib/oauth-redirect";
+import { hasRelayCredentials } from "../lib/relay-pairing";
 
 function generateCodeVerifier(): string {
   const array = new Uint8Array(64);
@@ -63,18 +64,6 @@ async function initiateDropboxOAuth(): Promise<void> {
 
 const CONNECT_TIMEOUT_MS = 5_000;
 
-/**
- * Returns true if the given WebSocket URL includes a `?t=` pairing token.
- * URLs without a token will be rejected by the relay with HTTP 401.
- */
-function hasTokenParam(url: string): boolean {
-  try {
-    return new URL(url).searchParams.has("t");
-  } catch {
-    return false;
-  }
-}
-
 function waitForConnection(): Promise<void> {
   return new Promise((resolve, reject) => {
     const timer = setTimeout(() => {
@@ -137,7 +126,7 @@ interface SyncConnectContentProps {
   initialMode?: Mode;
 }
 
-/** Validate a base64url token — exactly 43 chars, URL-safe alphabet only. */
+/** Validate a base64url pairing code — exactly 43 chars, URL-safe alphabet only. */
 function isValidToken(t: string): boolean {
   return t.length === 43 && /^[A-Za-z0-9_-]+$/.test(t);
 }
@@ -151,6 +140,7 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
   const [mode, setMode] = useState<Mode>(initialMode);
   const [ip, setIp] = useState("");
   const [token, setToken] = useState("");
+  const [syncKey, setSyncKey] = useState("");
   const [error, setError] = useState<string | null>(null);
   const [connecting, setConnecting] = useState(false);
   const [scanStatus, setScanStatus] = useState<"waiting" | "found">("waiting");
@@ -236,16 +226,17 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
           setLastQrContent(detected);
 
           if (detected.startsWith("ws://") || detected.startsWith("wss://")) {
-            if (!hasTokenParam(detected)) {
+            if (!hasRelayCredentials(detected)) {
               stopCamera();
               setError(
-                "This QR code has no pairing token. Please update your desktop app and rescan.",
+                "This QR code has no pairing code. Please update your desktop app and rescan.",
               );
               setMode("manual");
               return;
             }
 
-            addDebugEvent("qr_decoded", detected);
+            // The `#k=` sync key stays out of the debug log.
+            addDebugEvent("qr_decoded", detected.split("#")[0]);
             stopCamera();
             storeRelayUrl(detected);
             connect(detected);
@@ -299,23 +290,31 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
     if (!isValidToken(trimmedToken)) {
       setError(
         trimmedToken.length === 0
-          ? "Enter the 43-character pairing token shown in the Freed desktop app."
-          : `Token must be exactly 43 characters (${trimmedToken.length} entered).`,
+          ? "Enter the 43-character pairing code shown in the Freed desktop app."
+          : `Pairing code must be exactly 43 characters (${trimmedToken.length} entered).`,
       );
       return;
     }
 
+    const trimmedKey = syncKey.trim();
+    if (trimmedKey && !isValidToken(trimmedKey)) {
+      setError(`Sync key must be exactly 43 characters (${trimmedKey.length} entered).`);
+      return;
+    }
+
     // Construct the full WebSocket URL — user never has to type the protocol,
-    // port, or query key themselves.
-    const relayUrl = `ws://${trimmedIp}:8765?t=${trimmedToken}`;
+    // port, or query key themselves. The sync key stays in the fragment, which
+    // never leaves this device.
+    const attemptUrl = `ws://${trimmedIp}:8765?pc=${trimmedToken}`;
+    const relayUrl = trimmedKey ? `${attemptUrl}#k=${trimmedKey}` : attemptUrl;
 
     setError(null);
     setConnecting(true);
-    addDebugEvent("connect_attempt", relayUrl);
+    addDebugEvent("connect_attempt", attemptUrl);
 
     // Warn about mixed content — the connection will likely be blocked by the browser.
     if (isMixedContentRisk(relayUrl)) {
-      addDebugEvent("mixed_content_warn", relayUrl);
+      addDebugEvent("mixed_content_warn", attemptUrl);
     }
 
     try {
@@ -324,7 +323,7 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
       await waitForConnection();
       handleClose();
     } catch (e) {
-      addDebugEvent("connect_timeout", relayUrl);
+      addDebugEvent("connect_timeout", attemptUrl);
       setError(e instanceof Error ? e.message : "Failed to connect");
     } finally {
       setConnecting(false);
@@ -360,7 +359,7 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
 
   // Derive the constructed URL for display purposes
   const constructedUrl = ip.trim()
-    ? `ws://${ip.trim()}:8765${token.trim() ? `?t=${token.trim()}` : ""}`
+    ? `ws://${ip.trim()}:8765${token.trim() ? `?pc=${token.trim()}` : ""}`
     : "";
 
   // Show the warning as soon as the user picks a local mode on an HTTPS page —
@@ -417,7 +416,7 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
         <p className="mb-5 text-sm text-[var(--theme-text-muted)]">
           {mode === "scanning"
             ? "Point your camera at the QR code shown in your desktop app."
-            : "Enter your desktop's IP address and pairing token, or scan the QR code."}
+            : "Enter your desktop's IP address and pairing code, or scan the QR code."}
         </p>
       )}
 
@@ -512,7 +511,7 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
           </p>
         </div>
       ) : mode === "manual" ? (
-        /* Manual entry — structured IP + token fields */
+        /* Manual entry — structured IP + pairing code fields */
         <div className="mb-4 space-y-4">
           {/* IP address */}
           <div>
@@ -533,18 +532,18 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
             />
           </div>
 
-          {/* Pairing token */}
+          {/* Pairing code */}
           <div>
             <label htmlFor="sync-token" className="mb-2 block text-sm text-[var(--theme-text-secondary)]">
-              Pairing token{" "}
-              <span className="text-xs text-[var(--theme-text-soft)]">(43 characters)</span>
+              Pairing code{" "}
+              <span className="text-xs text-[var(--theme-text-soft)]">(43 characters, single use)</span>
             </label>
             <input
               id="sync-token"
               type="text"
               value={token}
               onChange={(e) => setToken(e.target.value.trim())}
-              placeholder="43-character token from desktop app"
+              placeholder="43-character code from desktop app"
               maxLength={43}
               className="theme-input w-full rounded-xl px-4 py-3 font-mono text-xs tracking-wide transition-colors"
               disabled={connecting}
@@ -561,6 +560,25 @@ export function SyncConnectContent({ onDone, initialMode = "cloud" }: SyncConnec
             )}
           </div>
 
+          {/* Sync key */}
+          <div>
+            <label htmlFor="sync-key" className="mb-2 block text-sm text-[var(--theme-text-secondary)]">
+              Sync key{" "}
+              <span className="text-xs text-[var(--theme-text-soft)]">(43 characters, if shown)</span>
+            </label>
+            <input
+              id="sync-key"
+              type="text"
+              value={syncKey}
+              onChange={(e) => setSyncKey(e.target.value.trim())}
+              placeholder="43-character key from desktop app"
+              maxLength={43}
+              className="theme-input w-full rounded-xl px-4 py-3 font-mono text-xs tracking-wide transition-colors"
+              disabled={connecting}
+              onKeyDown={(e) => e.key === "Enter" && handleConnect()}
+            />
+          </div>
+
           <p className="text-xs text-[var(--theme-text-muted)]">
             Find these in your desktop app: Settings &rarr; Mobile Sync &rarr; Manual Entry
           </p>
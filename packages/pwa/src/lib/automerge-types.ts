@@ -49,6 +49,15 @@ export interface DocState {
   mapAllContentLocationCount: number;
 }
 
+/** Relay delta for a peer whose document stops at some earlier heads. */
+export interface DocChangesSince {
+  /** Change chunks, or a whole saved document when `full` is set. */
+  binary: Uint8Array;
+  full: boolean;
+  /** Heads of the local doc the changes bring the peer up to. */
+  heads: string[];
+}
+
 // ---------------------------------------------------------------------------
 // Main thread → worker
 // ---------------------------------------------------------------------------
@@ -99,9 +108,12 @@ export type WorkerRequest =
   | { reqId: number; type: "REMOVE_ACCOUNT"; accountId: string }
   | { reqId: number; type: "ADD_STUB_ITEM"; url: string; tags: string[] }
   | { reqId: number; type: "BACKFILL_CONTENT_SIGNALS"; batchSize?: number }
-  | { reqId: number; type: "MERGE_DOC"; binary: Uint8Array }
+  /** `incremental` marks `binary` as change chunks to load onto the current doc. */
+  | { reqId: number; type: "MERGE_DOC"; binary: Uint8Array; incremental?: boolean }
   | { reqId: number; type: "GET_DOC_BINARY" }
   | { reqId: number; type: "GET_HEADS" }
+  | { reqId: number; type: "HAS_HEADS"; heads: string[] }
+  | { reqId: number; type: "GET_CHANGES_SINCE"; heads: string[] }
   | { reqId: number; type: "COMPARE_DOC"; binary: Uint8Array }
   | { reqId: number; type: "GET_ITEM_LEGACY_HTML"; globalId: string }
   | { reqId: number; type: "CLEAR_LOCAL" };
@@ -125,6 +137,12 @@ export type WorkerResponse =
   | { reqId: number; type: "DOC_BINARY"; binary: Uint8Array }
   /** Current Automerge heads for upload-loop accounting; null before INIT. */
   | { reqId: number; type: "DOC_HEADS"; heads: string[] | null }
+  | { reqId: number; type: "DOC_HAS_HEADS"; present: boolean }
+  /**
+   * Changes a peer at the requested heads is missing, or the whole document
+   * (`full`) when those heads are empty or not in the local doc.
+   */
+  | { reqId: number; type: "DOC_CHANGES"; changes: DocChangesSince }
   /** Automerge history containment of an incoming document relative to local. */
   | { reqId: number; type: "DOC_RELATIONSHIP"; relation: DocumentHistoryRelation }
   | { reqId: number; type: "ITEM_LEGACY_HTML"; globalId: string; html: string | null }
@@ -22,6 +22,7 @@ import type {
   UserPreferences,
 } from "@freed/shared";
 import type {
+  DocChangesSince,
   DocState,
   DocumentHistoryRelation,
   WorkerRequest,
@@ -110,6 +111,14 @@ const pendingDocHeads = new Map<
   number,
   GenerationOwnedRequest<string[] | null>
 >();
+const pendingDocHasHeads = new Map<
+  number,
+  GenerationOwnedRequest<boolean>
+>();
+const pendingDocChanges = new Map<
+  number,
+  GenerationOwnedRequest<DocChangesSince>
+>();
 const pendingDocRelationship = new Map<
   number,
   GenerationOwnedRequest<DocumentHistoryRelation>
@@ -125,6 +134,8 @@ const pendingMaps: Array<Map<number, GenerationOwnedRequest<unknown>>> = [
   pendingSampleDataClear as Map<number, GenerationOwnedRequest<unknown>>,
   pendingDocBinary as Map<number, GenerationOwnedRequest<unknown>>,
   pendingDocHeads as Map<number, GenerationOwnedRequest<unknown>>,
+  pendingDocHasHeads as Map<number, GenerationOwnedRequest<unknown>>,
+  pendingDocChanges as Map<number, GenerationOwnedRequest<unknown>>,
   pendingDocRelationship as Map<number, GenerationOwnedRequest<unknown>>,
   pendingLegacyHtml as Map<number, GenerationOwnedRequest<unknown>>,
 ];
@@ -447,6 +458,22 @@ function handleWorkerMessage(
     return;
   }
 
+  if (msg.type === "DOC_HAS_HEADS") {
+    const pendingHasHeads = getOwnedRequest(pendingDocHasHeads, msg.reqId, generation.id);
+    if (!pendingHasHeads) return;
+    pendingDocHasHeads.delete(msg.reqId);
+    pendingHasHeads.resolve(msg.present);
+    return;
+  }
+
+  if (msg.type === "DOC_CHANGES") {
+    const pendingChanges = getOwnedRequest(pendingDocChanges, msg.reqId, generation.id);
+    if (!pendingChanges) return;
+    pendingDocChanges.delete(msg.reqId);
+    pendingChanges.resolve(msg.changes);
+    return;
+  }
+
   if (msg.type === "DOC_RELATIONSHIP") {
     const pendingRelationship = getOwnedRequest(pendingDocRelationship, msg.reqId, generation.id);
     if (!pendingRelationship) return;
@@ -542,6 +569,20 @@ function handleWorkerMessage(
     return;
   }
 
+  const pendingHasHeads = getOwnedRequest(pendingDocHasHeads, msg.reqId, generation.id);
+  if (pendingHasHeads && msg.error) {
+    pendingDocHasHeads.delete(msg.reqId);
+    pendingHasHeads.reject(new Error(msg.error));
+    return;
+  }
+
+  const pendingChanges = getOwnedRequest(pendingDocChanges, msg.reqId, generation.id);
+  if (pendingChanges && msg.error) {
+    pendingDocChanges.delete(msg.reqId);
+    pendingChanges.reject(new Error(msg.error));
+    return;
+  }
+
   const pendingRelationship = getOwnedRequest(
     pendingDocRelationship,
     msg.reqId,
@@ -732,6 +773,27 @@ export async function getDocHeads(): Promise<string[] | null> {
   );
 }
 
+/** True when the current doc contains every one of `heads`. */
+export async function docHasHeads(heads: string[]): Promise<boolean> {
+  const reqId = nextReqId++;
+  return requestResult(
+    pendingDocHasHeads,
+    { reqId, type: "HAS_HEADS", heads } satisfies WorkerRequest,
+  );
+}
+
+/**
+ * Changes a peer that stops at `heads` is missing, for relay deltas. Falls
+ * back to the whole document when `heads` is empty or not in the local doc.
+ */
+export async function getDocChangesSince(heads: string[]): Promise<DocChangesSince> {
+  const reqId = nextReqId++;
+  return requestResult(
+    pendingDocChanges,
+    { reqId, type: "GET_CHANGES_SINCE", heads } satisfies WorkerRequest,
+  );
+}
+
 /** Compare incoming Automerge history with the current local document. */
 export async function compareDoc(
   incoming: Uint8Array,
@@ -761,6 +823,12 @@ export async function mergeDoc(incoming: Uint8Array): Promise<void> {
   return request({ reqId, type: "MERGE_DOC", binary: incoming });
 }
 
+/** Load relay change chunks onto the doc. */
+export async function mergeDocChanges(changes: Uint8Array): Promise<void> {
+  const reqId = nextReqId++;
+  return request({ reqId, type: "MERGE_DOC", binary: changes, incremental: true });
+}
+
 /** Permanently wipe the local IndexedDB store. Reload the page afterwards. */
 export async function clearLocalDoc(): Promise<void> {
   const reqId = nextReqId++;
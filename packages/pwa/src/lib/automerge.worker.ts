@@ -525,6 +525,28 @@ async function handleRequest(req: WorkerRequest): Promise<void> {
         break;
       }
 
+      case "HAS_HEADS": {
+        if (!currentDoc) throw new Error("Document not initialized");
+        send({ reqId: req.reqId, type: "DOC_HAS_HEADS", present: A.hasHeads(currentDoc, req.heads) });
+        break;
+      }
+
+      case "GET_CHANGES_SINCE": {
+        if (!currentDoc) throw new Error("Document not initialized");
+        // A peer holding nothing gets one compact document rather than every change.
+        const full = req.heads.length === 0 || !A.hasHeads(currentDoc, req.heads);
+        send({
+          reqId: req.reqId,
+          type: "DOC_CHANGES",
+          changes: {
+            binary: full ? A.save(currentDoc) : A.saveSince(currentDoc, req.heads),
+            full,
+            heads: A.getHeads(currentDoc),
+          },
+        });
+        break;
+      }
+
       case "COMPARE_DOC": {
         if (!currentDoc) throw new Error("Document not initialized");
         const incomingDoc = A.load<FreedDoc>(req.binary);
@@ -746,7 +768,10 @@ async function handleRequest(req: WorkerRequest): Promise<void> {
           `loading remote document, local feed items: ${beforeCount.toLocaleString()}`,
           req.binary.byteLength,
         );
-        const incomingDoc = A.load<FreedDoc>(req.binary);
+        // Change chunks only load on top of the history they depend on.
+        const incomingDoc = req.incremental
+          ? A.loadIncremental(A.clone(currentDoc), req.binary)
+          : A.load<FreedDoc>(req.binary);
         const incomingCount = Object.keys(incomingDoc.feedItems ?? {}).length;
         sendSyncBreadcrumb(
           `loaded remote document, remote feed items: ${incomingCount.toLocaleString()}`,
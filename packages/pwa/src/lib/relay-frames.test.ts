@@ -0,0 +1,59 @@
+import { describe, expect, it } from "vitest";
+
+import {
+  decodeRelayFrame,
+  encodeRelayFrame,
+  framedRelayUrl,
+  isRelayFrame,
+  RelayFrameKind,
+} from "./relay-frames";
+
+const HEAD_A = "ab".repeat(32);
+const HEAD_B = "01".repeat(32);
+
+describe("relay frames", () => {
+  it("lays frames out the way the relay decodes them", () => {
+    const frame = encodeRelayFrame(
+      RelayFrameKind.Changes,
+      [HEAD_A],
+      new TextEncoder().encode("chunk"),
+    );
+    expect(Array.from(frame.subarray(0, 4))).toEqual([1, 1, 0, 1]);
+    expect(frame.subarray(4, 36)).toEqual(new Uint8Array(32).fill(0xab));
+    expect(new TextDecoder().decode(frame.subarray(36))).toBe("chunk");
+    expect(isRelayFrame(frame)).toBe(true);
+
+    expect(Array.from(encodeRelayFrame(RelayFrameKind.FullDocRequest, []))).toEqual([1, 2, 0, 0]);
+  });
+
+  it("round-trips heads and payload", () => {
+    const payload = new Uint8Array([0x85, 0x6f, 0x4a, 0x83, 9]);
+    const decoded = decodeRelayFrame(
+      encodeRelayFrame(RelayFrameKind.FullDoc, [HEAD_A, HEAD_B], payload),
+    );
+    expect(decoded.kind).toBe(RelayFrameKind.FullDoc);
+    expect(decoded.heads).toEqual([HEAD_A, HEAD_B]);
+    expect(decoded.payload).toEqual(payload);
+  });
+
+  it("tells frames from bare and sealed documents", () => {
+    expect(isRelayFrame(new Uint8Array([0x85, 0x6f, 0x4a, 0x83]))).toBe(false);
+    expect(isRelayFrame(new Uint8Array([0x46, 0x52, 0x53, 0x01]))).toBe(false);
+  });
+
+  it("rejects malformed frames", () => {
+    expect(() => decodeRelayFrame(new Uint8Array([1, 1]))).toThrow("too short");
+    expect(() => decodeRelayFrame(new Uint8Array([2, 1, 0, 0]))).toThrow("version 2");
+    expect(() => decodeRelayFrame(new Uint8Array([1, 7, 0, 0]))).toThrow("kind 7");
+    expect(() => decodeRelayFrame(new Uint8Array([1, 1, 0, 1, 0]))).toThrow("declares 1 heads");
+  });
+
+  it("asks for frames and passes heads on the upgrade URL", () => {
+    expect(framedRelayUrl("ws://relay.local:8765/?t=token", null)).toBe(
+      "ws://relay.local:8765/?t=token&v=1",
+    );
+    const url = new URL(framedRelayUrl("ws://relay.local:8765/?t=token", [HEAD_B]));
+    expect(url.searchParams.get("v")).toBe("1");
+    expect(url.searchParams.get("h")).toBe("AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE");
+  });
+});
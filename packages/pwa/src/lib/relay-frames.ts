@@ -0,0 +1,101 @@
+/**
+ * Framed relay messages (protocol v1).
+ *
+ * Connecting with `?v=1` switches the relay from bare Automerge documents to
+ * framed messages:
+ *
+ *   [0]          RELAY_PROTOCOL_VERSION
+ *   [1]          RelayFrameKind
+ *   [2..4]       head count N (u16, big endian)
+ *   [4..4+32N]   change hashes the frame leaves the sender at
+ *   [4+32N..]    payload, sealed when the pairing URL has a sync key
+ *
+ * `Changes` payloads are Automerge change chunks, so a local edit costs its
+ * own size on the wire instead of the whole document. `?h=` passes the
+ * client's heads so the relay can skip the initial download.
+ */
+
+import { encodeBase64Url } from "./relay-pairing";
+
+export const RELAY_PROTOCOL_VERSION = 1;
+const RELAY_HEAD_BYTES = 32;
+
+export const RelayFrameKind = {
+  FullDoc: 0,
+  Changes: 1,
+  /** Asks the relay for its held document, e.g. after missing a change. */
+  FullDocRequest: 2,
+} as const;
+
+export type RelayFrameKind = (typeof RelayFrameKind)[keyof typeof RelayFrameKind];
+
+const RELAY_FRAME_KINDS: readonly number[] = Object.values(RelayFrameKind);
+
+export interface RelayFrame {
+  kind: RelayFrameKind;
+  /** Hex change hashes, as Automerge's `getHeads()` returns them. */
+  heads: string[];
+  payload: Uint8Array;
+}
+
+function headBytes(head: string): Uint8Array {
+  if (!/^[0-9a-f]{64}$/i.test(head)) throw new Error(`invalid head ${head}`);
+  return Uint8Array.from({ length: RELAY_HEAD_BYTES }, (_, index) =>
+    parseInt(head.slice(index * 2, index * 2 + 2), 16),
+  );
+}
+
+function headHex(bytes: Uint8Array): string {
+  return Array.from(bytes, (byte) => byte.toString(16).padStart(2, "0")).join("");
+}
+
+export function encodeRelayFrame(
+  kind: RelayFrameKind,
+  heads: string[],
+  payload: Uint8Array = new Uint8Array(0),
+): Uint8Array {
+  const frame = new Uint8Array(4 + heads.length * RELAY_HEAD_BYTES + payload.length);
+  frame[0] = RELAY_PROTOCOL_VERSION;
+  frame[1] = kind;
+  new DataView(frame.buffer).setUint16(2, heads.length);
+  heads.forEach((head, index) => frame.set(headBytes(head), 4 + index * RELAY_HEAD_BYTES));
+  frame.set(payload, 4 + heads.length * RELAY_HEAD_BYTES);
+  return frame;
+}
+
+export function decodeRelayFrame(bytes: Uint8Array): RelayFrame {
+  if (bytes.length < 4) throw new Error(`frame too short (${bytes.length} bytes)`);
+  if (bytes[0] !== RELAY_PROTOCOL_VERSION) {
+    throw new Error(`unsupported relay protocol version ${bytes[0]}`);
+  }
+  const kind = bytes[1] as RelayFrameKind;
+  if (!RELAY_FRAME_KINDS.includes(kind)) throw new Error(`unknown frame kind ${kind}`);
+  const headCount = new DataView(bytes.buffer, bytes.byteOffset, bytes.length).getUint16(2);
+  const payloadStart = 4 + headCount * RELAY_HEAD_BYTES;
+  if (bytes.length < payloadStart) {
+    throw new Error(`frame declares ${headCount} heads but carries ${bytes.length - 4} bytes`);
+  }
+  const heads = Array.from({ length: headCount }, (_, index) =>
+    headHex(bytes.subarray(4 + index * RELAY_HEAD_BYTES, 4 + (index + 1) * RELAY_HEAD_BYTES)),
+  );
+  return { kind, heads, payload: bytes.subarray(payloadStart) };
+}
+
+/** True for framed messages; bare documents start with the Automerge or sealing magic. */
+export function isRelayFrame(bytes: Uint8Array): boolean {
+  return bytes[0] === RELAY_PROTOCOL_VERSION;
+}
+
+/** Upgrade URL asking for framed messages, with `heads` as `?h=` when known. */
+export function framedRelayUrl(url: string, heads: string[] | null): string {
+  const parsed = new URL(url);
+  parsed.searchParams.set("v", String(RELAY_PROTOCOL_VERSION));
+  if (heads && heads.length > 0) {
+    const bytes = new Uint8Array(heads.length * RELAY_HEAD_BYTES);
+    heads.forEach((head, index) => bytes.set(headBytes(head), index * RELAY_HEAD_BYTES));
+    parsed.searchParams.set("h", encodeBase64Url(bytes));
+  } else {
+    parsed.searchParams.delete("h");
+  }
+  return parsed.toString();
+}
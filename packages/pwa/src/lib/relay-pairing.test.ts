@@ -0,0 +1,54 @@
+import { describe, expect, it } from "vitest";
+
+import {
+  hasRelayCredentials,
+  pairingCodeCredential,
+  pairingCodeDeviceId,
+  redeemedRelayUrl,
+  relayUpgradeUrls,
+} from "./relay-pairing";
+
+// Pinned in the desktop relay's pairing tests as well.
+const DEVICE_ID = "nbnHHJyujj2NWsA_qAn_4w";
+const CREDENTIAL = "-eI7-LTftt5IhdLbH0Rg35T-iZL_piqbfH6sKH7q2eY";
+
+describe("relay pairing", () => {
+  it("derives the same device credential as the desktop relay", async () => {
+    await expect(pairingCodeDeviceId("code-1")).resolves.toBe(DEVICE_ID);
+    await expect(pairingCodeCredential("code-1")).resolves.toBe(CREDENTIAL);
+  });
+
+  it("redeems the code first and keeps the derived credential as a fallback", async () => {
+    const urls = await relayUpgradeUrls(
+      "ws://192.168.1.20:8765/?pc=code-1&fp=abc#k=secret",
+      "Freed PWA (iPhone)",
+    );
+    expect(urls).toEqual([
+      "ws://192.168.1.20:8765/?pc=code-1&fp=abc&n=Freed+PWA+%28iPhone%29",
+      `ws://192.168.1.20:8765/?fp=abc&d=${DEVICE_ID}&t=${CREDENTIAL}`,
+    ]);
+
+    await expect(relayUpgradeUrls("ws://relay.local:8765/?t=token", "Freed PWA")).resolves.toEqual([
+      "ws://relay.local:8765/?t=token",
+    ]);
+  });
+
+  it("stores the derived credential in place of the redeemed code", async () => {
+    await expect(
+      redeemedRelayUrl("ws://192.168.1.20:8765/?pc=code-1&fp=abc&a=10.0.0.2#k=secret"),
+    ).resolves.toBe(
+      `ws://192.168.1.20:8765/?fp=abc&a=10.0.0.2&d=${DEVICE_ID}&t=${CREDENTIAL}#k=secret`,
+    );
+    await expect(redeemedRelayUrl("ws://relay.local:8765/?t=token")).resolves.toBe(
+      "ws://relay.local:8765/?t=token",
+    );
+  });
+
+  it("accepts pairing codes, device credentials, and legacy tokens", () => {
+    expect(hasRelayCredentials("ws://relay.local:8765?pc=code-1")).toBe(true);
+    expect(hasRelayCredentials(`ws://relay.local:8765?d=${DEVICE_ID}&t=${CREDENTIAL}`)).toBe(true);
+    expect(hasRelayCredentials("ws://relay.local:8765?t=token")).toBe(true);
+    expect(hasRelayCredentials("ws://relay.local:8765")).toBe(false);
+    expect(hasRelayCredentials("not a url")).toBe(false);
+  });
+});
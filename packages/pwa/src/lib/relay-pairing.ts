@@ -0,0 +1,90 @@
+/**
+ * Pairing credentials for the LAN relay.
+ *
+ * A desktop pairing URL carries a one-time pairing code (`pc=`). The PWA
+ * redeems it once, naming itself with `n=`, and from then on presents the
+ * device id and credential derived from the same code (`d=` and `t=`). The
+ * derivation matches the desktop relay and `freed-sync`. Legacy URLs with a
+ * shared `t=` token are used as they are.
+ */
+
+const DEVICE_ID_LABEL = "freed-device-id:v1:";
+const DEVICE_CREDENTIAL_LABEL = "freed-device-credential:v1:";
+const DEVICE_ID_BYTES = 16;
+
+export function encodeBase64Url(bytes: Uint8Array): string {
+  let binary = "";
+  for (const byte of bytes) binary += String.fromCharCode(byte);
+  return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+export function decodeBase64Url(value: string): Uint8Array {
+  const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+  const binary = atob(base64.padEnd(Math.ceil(base64.length / 4) * 4, "="));
+  return Uint8Array.from(binary, (char) => char.charCodeAt(0));
+}
+
+async function labelledDigest(label: string, code: string): Promise<Uint8Array> {
+  const data = new TextEncoder().encode(label + code);
+  return new Uint8Array(await crypto.subtle.digest("SHA-256", data));
+}
+
+export async function pairingCodeDeviceId(code: string): Promise<string> {
+  const digest = await labelledDigest(DEVICE_ID_LABEL, code);
+  return encodeBase64Url(digest.slice(0, DEVICE_ID_BYTES));
+}
+
+export async function pairingCodeCredential(code: string): Promise<string> {
+  return encodeBase64Url(await labelledDigest(DEVICE_CREDENTIAL_LABEL, code));
+}
+
+/**
+ * True when the URL carries something the relay accepts: a pairing code, a
+ * device credential, or a legacy shared token.
+ */
+export function hasRelayCredentials(url: string): boolean {
+  try {
+    const params = new URL(url).searchParams;
+    return Boolean(params.get("pc")) || params.has("t");
+  } catch {
+    return false;
+  }
+}
+
+/** Name the desktop lists this device under, e.g. "Freed PWA (iPhone)". */
+export function relayDeviceName(): string {
+  const platform = typeof navigator === "undefined" ? "" : navigator.platform.trim();
+  return platform ? `Freed PWA (${platform})` : "Freed PWA";
+}
+
+/**
+ * The stored pairing URL once its code has been redeemed. `pc=` is replaced
+ * by the derived `d=` and `t=`; other parameters and the `#k=` sync key are
+ * kept. URLs without a code are returned unchanged.
+ */
+export async function redeemedRelayUrl(url: string): Promise<string> {
+  const parsed = new URL(url);
+  const code = parsed.searchParams.get("pc");
+  if (!code) return url;
+  parsed.searchParams.delete("pc");
+  parsed.searchParams.delete("n");
+  parsed.searchParams.set("d", await pairingCodeDeviceId(code));
+  parsed.searchParams.set("t", await pairingCodeCredential(code));
+  return parsed.toString();
+}
+
+/**
+ * Upgrade URLs for one relay host, in the order to try them. A URL with an
+ * unredeemed code redeems it first, then falls back to the derived
+ * credential in case an earlier redemption went through but was never
+ * stored. The `#k=` fragment never goes on the wire; a WebSocket URL cannot
+ * carry one.
+ */
+export async function relayUpgradeUrls(url: string, deviceName: string): Promise<string[]> {
+  const parsed = new URL(url);
+  parsed.hash = "";
+  if (!parsed.searchParams.get("pc")) return [parsed.toString()];
+  const redeem = new URL(parsed.toString());
+  redeem.searchParams.set("n", deviceName);
+  return [redeem.toString(), await redeemedRelayUrl(parsed.toString())];
+}
@@ -0,0 +1,63 @@
+import { describe, expect, it } from "vitest";
+
+import { decodeBase64Url } from "./relay-pairing";
+import {
+  importRelayPayloadKey,
+  openRelayPayload,
+  relayPayloadKeyParam,
+  sealRelayPayload,
+  type RelayPayloadKey,
+} from "./relay-sealing";
+
+// Pinned in the desktop relay's sealing tests as well.
+const SECRET = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc";
+const SEALED_FREED = "RlJTAej3QlZ5Nt71AAAAIQEBAQEBAQEBAQEBAdWgXCHtXHBMa8psYj2c8Mu2Xa2JLg";
+
+async function pinnedKey(): Promise<RelayPayloadKey> {
+  const key = await importRelayPayloadKey(SECRET);
+  if (!key) throw new Error("pinned secret did not import");
+  return key;
+}
+
+describe("relay payload sealing", () => {
+  it("opens payloads sealed by the desktop relay", async () => {
+    const opened = await openRelayPayload(await pinnedKey(), decodeBase64Url(SEALED_FREED));
+    expect(new TextDecoder().decode(opened)).toBe("freed");
+  });
+
+  it("opens appended envelopes as one payload", async () => {
+    const key = await pinnedKey();
+    const doc = await sealRelayPayload(key, new Uint8Array([1, 2, 3]));
+    const change = await sealRelayPayload(key, new Uint8Array([7, 7]));
+    expect(doc.subarray(0, 4)).toEqual(new Uint8Array([0x46, 0x52, 0x53, 0x01]));
+
+    const held = new Uint8Array(doc.length + change.length);
+    held.set(doc);
+    held.set(change, doc.length);
+    await expect(openRelayPayload(key, held)).resolves.toEqual(new Uint8Array([1, 2, 3, 7, 7]));
+  });
+
+  it("rejects tampered, truncated, foreign, and plain payloads", async () => {
+    const key = await pinnedKey();
+    const sealed = await sealRelayPayload(key, new Uint8Array([1, 2, 3]));
+
+    const tampered = sealed.slice();
+    tampered[tampered.length - 1] ^= 1;
+    await expect(openRelayPayload(key, tampered)).rejects.toThrow("failed authentication");
+    await expect(openRelayPayload(key, sealed.subarray(0, sealed.length - 1))).rejects.toThrow(
+      "truncated",
+    );
+
+    const other = await importRelayPayloadKey("AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE");
+    await expect(openRelayPayload(other!, sealed)).rejects.toThrow("different sync key");
+    await expect(openRelayPayload(key, new Uint8Array([0x85, 0x6f, 0x4a, 0x83]))).rejects.toThrow(
+      "not sealed",
+    );
+  });
+
+  it("reads the sync key from the pairing URL fragment", async () => {
+    expect(relayPayloadKeyParam(`ws://192.168.1.20:8765/?pc=code#k=${SECRET}`)).toBe(SECRET);
+    expect(relayPayloadKeyParam("ws://192.168.1.20:8765/?pc=code")).toBeNull();
+    await expect(importRelayPayloadKey("too-short")).resolves.toBeNull();
+  });
+});
@@ -0,0 +1,157 @@
+/**
+ * Payload sealing for the LAN relay.
+ *
+ * When the desktop has a sync key, its pairing URL carries it as `#k=` and
+ * the relay only accepts documents sealed with it. A sealed payload is one
+ * or more self-delimiting envelopes:
+ *
+ *   [0..4]       "FRS\x01"
+ *   [4..12]      key id, the first 8 bytes of SHA-256("freed-relay-key-id:v1:" + secret)
+ *   [12..16]     body length L (u32, big endian)
+ *   [16..16+L]   12-byte nonce, then AES-256-GCM ciphertext and tag
+ *
+ * The 16-byte header is the additional authenticated data, and the AES key is
+ * HKDF-SHA256 of the secret with info "freed-relay-payload:v1". Opening a
+ * payload concatenates the plaintext of every envelope.
+ */
+
+import { decodeBase64Url } from "./relay-pairing";
+
+const SEALED_PAYLOAD_MAGIC = [0x46, 0x52, 0x53, 0x01];
+const SEALED_PAYLOAD_HEADER_BYTES = 16;
+const SEALED_PAYLOAD_KEY_ID_BYTES = 8;
+const SEALED_PAYLOAD_NONCE_BYTES = 12;
+const SECRET_BYTES = 32;
+
+const KEY_ID_LABEL = "freed-relay-key-id:v1:";
+const PAYLOAD_KEY_INFO = "freed-relay-payload:v1";
+
+export interface RelayPayloadKey {
+  keyId: Uint8Array;
+  key: CryptoKey;
+}
+
+/** The `#k=` sync key from a pairing URL, or null when it carries none. */
+export function relayPayloadKeyParam(url: string): string | null {
+  try {
+    const fragment = new URL(url).hash.replace(/^#/, "");
+    return new URLSearchParams(fragment).get("k") || null;
+  } catch {
+    return null;
+  }
+}
+
+/** Derive the payload key from a base64url secret; null if it is malformed. */
+export async function importRelayPayloadKey(secretParam: string): Promise<RelayPayloadKey | null> {
+  let secret: Uint8Array;
+  try {
+    secret = decodeBase64Url(secretParam.trim());
+  } catch {
+    return null;
+  }
+  if (secret.length !== SECRET_BYTES) return null;
+
+  const label = new TextEncoder().encode(KEY_ID_LABEL);
+  const idInput = new Uint8Array(label.length + secret.length);
+  idInput.set(label);
+  idInput.set(secret, label.length);
+  const digest = new Uint8Array(await crypto.subtle.digest("SHA-256", idInput));
+
+  const ikm = await crypto.subtle.importKey("raw", secret, "HKDF", false, ["deriveKey"]);
+  const key = await crypto.subtle.deriveKey(
+    {
+      name: "HKDF",
+      hash: "SHA-256",
+      salt: new Uint8Array(0),
+      info: new TextEncoder().encode(PAYLOAD_KEY_INFO),
+    },
+    ikm,
+    { name: "AES-GCM", length: 256 },
+    false,
+    ["encrypt", "decrypt"],
+  );
+  return { keyId: digest.slice(0, SEALED_PAYLOAD_KEY_ID_BYTES), key };
+}
+
+export async function sealRelayPayload(
+  payloadKey: RelayPayloadKey,
+  plaintext: Uint8Array,
+): Promise<Uint8Array> {
+  const nonce = crypto.getRandomValues(new Uint8Array(SEALED_PAYLOAD_NONCE_BYTES));
+  const header = new Uint8Array(SEALED_PAYLOAD_HEADER_BYTES);
+  header.set(SEALED_PAYLOAD_MAGIC);
+  header.set(payloadKey.keyId, SEALED_PAYLOAD_MAGIC.length);
+  // The GCM tag adds 16 bytes to the ciphertext.
+  new DataView(header.buffer).setUint32(12, SEALED_PAYLOAD_NONCE_BYTES + plaintext.length + 16);
+
+  const ciphertext = new Uint8Array(
+    await crypto.subtle.encrypt(
+      { name: "AES-GCM", iv: nonce, additionalData: header },
+      payloadKey.key,
+      plaintext,
+    ),
+  );
+  const sealed = new Uint8Array(header.length + nonce.length + ciphertext.length);
+  sealed.set(header);
+  sealed.set(nonce, header.length);
+  sealed.set(ciphertext, header.length + nonce.length);
+  return sealed;
+}
+
+/** Open every envelope and return the concatenated plaintext. */
+export async function openRelayPayload(
+  payloadKey: RelayPayloadKey,
+  sealed: Uint8Array,
+): Promise<Uint8Array> {
+  if (sealed.length === 0) throw new Error("sealed payload is empty");
+  const parts: Uint8Array[] = [];
+  let offset = 0;
+  while (offset < sealed.length) {
+    const header = sealed.subarray(offset, offset + SEALED_PAYLOAD_HEADER_BYTES);
+    if (
+      header.length < SEALED_PAYLOAD_HEADER_BYTES ||
+      SEALED_PAYLOAD_MAGIC.some((byte, index) => header[index] !== byte)
+    ) {
+      throw new Error("payload is not sealed");
+    }
+    const bodyLength = new DataView(header.buffer, header.byteOffset, header.length).getUint32(12);
+    const bodyStart = offset + SEALED_PAYLOAD_HEADER_BYTES;
+    if (bodyLength < SEALED_PAYLOAD_NONCE_BYTES || sealed.length - bodyStart < bodyLength) {
+      throw new Error("sealed payload is truncated");
+    }
+    const keyId = header.subarray(
+      SEALED_PAYLOAD_MAGIC.length,
+      SEALED_PAYLOAD_MAGIC.length + SEALED_PAYLOAD_KEY_ID_BYTES,
+    );
+    if (keyId.some((byte, index) => byte !== payloadKey.keyId[index])) {
+      throw new Error("payload was sealed with a different sync key");
+    }
+    const nonce = sealed.subarray(bodyStart, bodyStart + SEALED_PAYLOAD_NONCE_BYTES);
+    const ciphertext = sealed.subarray(
+      bodyStart + SEALED_PAYLOAD_NONCE_BYTES,
+      bodyStart + bodyLength,
+    );
+    try {
+      parts.push(
+        new Uint8Array(
+          await crypto.subtle.decrypt(
+            { name: "AES-GCM", iv: nonce, additionalData: header },
+            payloadKey.key,
+            ciphertext,
+          ),
+        ),
+      );
+    } catch {
+      throw new Error("sealed payload failed authentication");
+    }
+    offset = bodyStart + bodyLength;
+  }
+
+  const plaintext = new Uint8Array(parts.reduce((total, part) => total + part.length, 0));
+  let written = 0;
+  for (const part of parts) {
+    plaintext.set(part, written);
+    written += part.length;
+  }
+  return plaintext;
+}
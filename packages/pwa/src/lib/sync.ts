@@ -12,7 +12,17 @@
  * protected by optimistic locking — see @freed/sync/cloud for details.
  */
 
-import { compareDoc, getDocBinary, getDocHeads, initDoc, mergeDoc, subscribe } from "./automerge";
+import {
+  compareDoc,
+  docHasHeads,
+  getDocBinary,
+  getDocChangesSince,
+  getDocHeads,
+  initDoc,
+  mergeDoc,
+  mergeDocChanges,
+  subscribe,
+} from "./automerge";
 import {
   addDebugEvent,
   recordCloudProviderEvent,
@@ -40,6 +50,21 @@ import {
   capturePwaRuntimeLifecycle,
   registerPwaFactoryResetQuiesceHandler,
 } from "./factory-reset-coordinator";
+import {
+  decodeRelayFrame,
+  encodeRelayFrame,
+  framedRelayUrl,
+  isRelayFrame,
+  RelayFrameKind,
+} from "./relay-frames";
+import { redeemedRelayUrl, relayDeviceName, relayUpgradeUrls } from "./relay-pairing";
+import {
+  importRelayPayloadKey,
+  openRelayPayload,
+  relayPayloadKeyParam,
+  sealRelayPayload,
+  type RelayPayloadKey,
+} from "./relay-sealing";
 
 const syncRuntimeLifecycle = capturePwaRuntimeLifecycle();
 
@@ -49,6 +74,17 @@ let reconnectTimer: ReturnType<typeof setTimeout> | null = null;
 let isRelayConnectedState = false;
 let currentUrl: string | null = null;
 let reconnectCount = 0;
+// Upgrade URLs for each host in the pairing URL, tried in turn until one opens.
+let candidateUrls: string[] = [];
+let candidateIndex = 0;
+// Sync key from the pairing URL's `#k=`; null for relays that take plain documents.
+let payloadKey: RelayPayloadKey | null = null;
+// Heads the relay holds, learned from its `ack` and the frames since. Pushes
+// send only the changes after them; null means push the whole document.
+let relayHeads: string[] | null = null;
+let relayPushQueue: Promise<void> = Promise.resolve();
+const RELAY_ACK_TIMEOUT_MS = 1_000;
+const RELAY_CLIENT_ID_KEY = "freed_relay_client_id";
 
 // Cloud sync connection state — set by startCloudSync/stopCloudSync so the
 // toolbar reflects "Connected" as soon as either channel is active.
@@ -78,16 +114,7 @@ export function broadcastDoc(): void {
   if (!syncRuntimeLifecycle.isCurrent()) return;
   const runtimeLifecycle = capturePwaRuntimeLifecycle();
   if (ws && ws.readyState === WebSocket.OPEN) {
-    const socket = ws;
-    void getDocBinary().then((doc) => {
-      if (socket.readyState !== WebSocket.OPEN || !runtimeLifecycle.isCurrent()) return;
-      socket.send(doc);
-      console.log("[Sync] Broadcast document (%d bytes)", doc.byteLength);
-      addDebugEvent("sent", undefined, doc.byteLength);
-    }).catch((error) => {
-      console.error("[Sync] Failed to broadcast:", error);
-      addDebugEvent("error", error instanceof Error ? error.message : String(error));
-    });
+    queueRelayPush(ws, runtimeLifecycle);
   }
 
   // Cloud backup — debounced to batch rapid changes.
@@ -98,41 +125,162 @@ export function broadcastDoc(): void {
   }
 }
 
+/**
+ * Expand a pairing URL into one URL per candidate host. The desktop lists
+ * hosts besides the primary one in the `a` parameter, most likely first.
+ */
+export function relayCandidateUrls(url: string): string[] {
+  try {
+    const parsed = new URL(url);
+    const alternates = (parsed.searchParams.get("a") ?? "").split(",").filter(Boolean);
+    parsed.searchParams.delete("a");
+    const urls = [parsed.toString()];
+    for (const host of alternates) {
+      const candidate = new URL(parsed.toString());
+      candidate.hostname = host;
+      const candidateUrl = candidate.toString();
+      if (!urls.includes(candidateUrl)) urls.push(candidateUrl);
+    }
+    return urls;
+  } catch {
+    return [url];
+  }
+}
+
 /**
  * Connect to sync relay
  */
 export function connect(url: string): void {
   if (!syncRuntimeLifecycle.isCurrent()) return;
   const runtimeLifecycle = capturePwaRuntimeLifecycle();
-  if (ws) {
-    ws.close();
-  }
 
+  if (url !== currentUrl) {
+    candidateUrls = [];
+    candidateIndex = 0;
+  }
   currentUrl = url;
-  console.log(`[Sync] Connecting to ${url}...`);
-  addDebugEvent("connect_attempt", url);
+  if (candidateUrls.length > 0) {
+    openRelaySocket(url, runtimeLifecycle);
+    return;
+  }
+  void Promise.all([relayConnectionUrls(url), relayPayloadKeyFor(url)])
+    .then(([urls, key]) => {
+      if (currentUrl !== url || !runtimeLifecycle.isCurrent()) return;
+      candidateUrls = urls;
+      candidateIndex = 0;
+      payloadKey = key;
+      openRelaySocket(url, runtimeLifecycle);
+    })
+    .catch((error) => {
+      console.error("[Sync] Failed to read pairing URL:", error);
+      addDebugEvent("error", error instanceof Error ? error.message : String(error));
+    });
+}
+
+/** Every upgrade URL to try, per candidate host, with the pairing credential applied. */
+async function relayConnectionUrls(url: string): Promise<string[]> {
+  const urls: string[] = [];
+  for (const hostUrl of relayCandidateUrls(url)) {
+    urls.push(...(await relayUpgradeUrls(hostUrl, relayDeviceName())));
+  }
+  return urls;
+}
+
+async function relayPayloadKeyFor(url: string): Promise<RelayPayloadKey | null> {
+  const param = relayPayloadKeyParam(url);
+  if (!param) return null;
+  const key = await importRelayPayloadKey(param);
+  if (!key) addDebugEvent("error", "Pairing URL has a malformed sync key; sending unsealed documents");
+  return key;
+}
+
+/**
+ * After the relay accepts a pairing code, keep the derived device credential
+ * instead: the code is single use, so reconnects present `d=` and `t=`.
+ */
+async function rememberRedeemedPairing(
+  url: string,
+  runtimeLifecycle: ReturnType<typeof capturePwaRuntimeLifecycle>,
+): Promise<void> {
+  const redeemed = await redeemedRelayUrl(url);
+  if (currentUrl !== url || !runtimeLifecycle.isCurrent()) return;
+  storeRelayUrl(redeemed);
+  currentUrl = redeemed;
+  candidateUrls = [];
+  candidateIndex = 0;
+  addDebugEvent("connected", "Pairing code redeemed; stored the device credential");
+}
+
+function openRelaySocket(
+  url: string,
+  runtimeLifecycle: ReturnType<typeof capturePwaRuntimeLifecycle>,
+): void {
+  const previous = ws;
+  ws = null;
+  previous?.close();
+  relayHeads = null;
+  const attemptUrl = candidateUrls[candidateIndex] ?? url;
+  // Our heads let the relay skip the initial download when nothing changed.
+  void getDocHeads()
+    .catch(() => null)
+    .then((heads) => {
+      if (currentUrl !== url || ws || !runtimeLifecycle.isCurrent()) return;
+      openRelaySocketAt(url, attemptUrl, heads ?? [], runtimeLifecycle);
+    });
+}
+
+function openRelaySocketAt(
+  url: string,
+  attemptUrl: string,
+  heads: string[],
+  runtimeLifecycle: ReturnType<typeof capturePwaRuntimeLifecycle>,
+): void {
+  let opened = false;
+  console.log(`[Sync] Connecting to ${attemptUrl}...`);
+  addDebugEvent("connect_attempt", attemptUrl);
 
   try {
-    ws = new WebSocket(url);
-    ws.binaryType = "arraybuffer"; // Receive binary as ArrayBuffer
+    const socket = new WebSocket(framedRelayUrl(attemptUrl, heads));
+    ws = socket;
+    socket.binaryType = "arraybuffer"; // Receive binary as ArrayBuffer
 
-    ws.onopen = () => {
+    socket.onopen = () => {
       if (!runtimeLifecycle.isCurrent()) {
-        ws?.close();
+        socket.close();
         return;
       }
       console.log("[Sync] Connected to relay");
+      opened = true;
       isRelayConnectedState = true;
       reconnectCount = 0;
       notifyStatus();
-      addDebugEvent("connected", url);
+      addDebugEvent("connected", attemptUrl);
+      if (new URL(attemptUrl).searchParams.has("pc")) {
+        void rememberRedeemedPairing(url, runtimeLifecycle);
+      }
 
-      // Send our current doc so the relay stores it and can serve new clients
-      setTimeout(() => broadcastDoc(), 100);
+      // The relay's `ack` names the heads it holds, so only the changes it
+      // lacks go out. A relay that never answers gets the whole document.
+      socket.send(
+        JSON.stringify({
+          type: "hello",
+          version: 1,
+          clientId: getRelayClientId(),
+          heads,
+          capabilities: ["control", "frames"],
+        }),
+      );
+      setTimeout(() => {
+        if (ws === socket && relayHeads === null) broadcastDoc();
+      }, RELAY_ACK_TIMEOUT_MS);
     };
 
-    ws.onmessage = async (event) => {
+    socket.onmessage = async (event) => {
       if (!runtimeLifecycle.isCurrent()) return;
+      if (typeof event.data === "string") {
+        handleRelayControl(socket, event.data, runtimeLifecycle);
+        return;
+      }
       if (!(event.data instanceof ArrayBuffer)) return;
       const bytes = new Uint8Array(event.data);
       if (bytes.length === 0) return;
@@ -140,7 +288,7 @@ export function connect(url: string): void {
       addDebugEvent("received", undefined, bytes.length);
 
       try {
-        await mergeDoc(bytes);
+        await mergeRelayMessage(socket, bytes, runtimeLifecycle);
         if (!runtimeLifecycle.isCurrent()) return;
         console.log("[Sync] Received and merged document (%d bytes)", bytes.length);
       } catch (error) {
@@ -150,12 +298,20 @@ export function connect(url: string): void {
       }
     };
 
-    ws.onclose = () => {
+    socket.onclose = () => {
+      // A newer connection already replaced this one.
+      if (ws !== socket) return;
       console.log("[Sync] Disconnected from relay");
       isRelayConnectedState = false;
       ws = null;
+      relayHeads = null;
       notifyStatus();
-      addDebugEvent("disconnected", currentUrl ?? undefined);
+      addDebugEvent("disconnected", attemptUrl);
+      // An upgrade that never opened, from an unreachable host or a spent
+      // pairing code, moves on to the next URL.
+      if (!opened && candidateUrls.length > 1) {
+        candidateIndex = (candidateIndex + 1) % candidateUrls.length;
+      }
 
       // Auto-reconnect after delay
       if (currentUrl && reconnectTimer === null && runtimeLifecycle.isCurrent()) {
@@ -170,7 +326,7 @@ export function connect(url: string): void {
       }
     };
 
-    ws.onerror = (error) => {
+    socket.onerror = (error) => {
       console.error("[Sync] WebSocket error:", error);
       addDebugEvent("error", "WebSocket error — check browser console for details");
     };
@@ -180,11 +336,119 @@ export function connect(url: string): void {
   }
 }
 
+/** Stable id the relay lists this browser under in its connection diagnostics. */
+function getRelayClientId(): string {
+  let clientId = localStorage.getItem(RELAY_CLIENT_ID_KEY);
+  if (!clientId) {
+    clientId = `pwa-${crypto.randomUUID()}`;
+    localStorage.setItem(RELAY_CLIENT_ID_KEY, clientId);
+  }
+  return clientId;
+}
+
+/** JSON control messages; only the ones that change what we push matter here. */
+function handleRelayControl(
+  socket: WebSocket,
+  text: string,
+  runtimeLifecycle: ReturnType<typeof capturePwaRuntimeLifecycle>,
+): void {
+  let control: { type?: string; heads?: string[]; capabilities?: string[]; reason?: string };
+  try {
+    control = JSON.parse(text);
+  } catch {
+    return;
+  }
+  if (ws !== socket) return;
+  if (control.type === "ack" && control.capabilities?.includes("frames")) {
+    relayHeads = control.heads ?? [];
+    queueRelayPush(socket, runtimeLifecycle);
+  } else if (control.type === "error") {
+    // The relay did not take our last push; the next one sends everything.
+    relayHeads = null;
+    addDebugEvent("error", `[Relay] ${control.reason ?? "push rejected"}`);
+  }
+}
+
+/** Merge one binary message: a frame on framed connections, else a whole document. */
+async function mergeRelayMessage(
+  socket: WebSocket,
+  bytes: Uint8Array,
+  runtimeLifecycle: ReturnType<typeof capturePwaRuntimeLifecycle>,
+): Promise<void> {
+  const open = (payload: Uint8Array) =>
+    payloadKey ? openRelayPayload(payloadKey, payload) : Promise.resolve(payload);
+  if (!isRelayFrame(bytes)) {
+    await mergeDoc(await open(bytes));
+    return;
+  }
+
+  const frame = decodeRelayFrame(bytes);
+  if (frame.kind === RelayFrameKind.Changes) {
+    await mergeDocChanges(await open(frame.payload));
+    // Changes that build on one we never saw cannot apply; fetch everything.
+    if (!(await docHasHeads(frame.heads)) && socket.readyState === WebSocket.OPEN) {
+      socket.send(encodeRelayFrame(RelayFrameKind.FullDocRequest, []));
+    }
+  } else if (frame.kind === RelayFrameKind.FullDoc) {
+    await mergeDoc(await open(frame.payload));
+    if (ws !== socket || !runtimeLifecycle.isCurrent()) return;
+    // The relay now holds exactly this document; send whatever it lacks.
+    relayHeads = frame.heads;
+    queueRelayPush(socket, runtimeLifecycle);
+  }
+}
+
+/** Serialize pushes so each delta is computed against the heads the last one sent. */
+function queueRelayPush(
+  socket: WebSocket,
+  runtimeLifecycle: ReturnType<typeof capturePwaRuntimeLifecycle>,
+): void {
+  relayPushQueue = relayPushQueue.then(() => pushToRelay(socket, runtimeLifecycle));
+}
+
+/**
+ * Send the relay what it is missing. Once the relay's heads are known, that
+ * is a `Changes` frame with only the new changes; before then, or on a relay
+ * without frames, it is the whole document.
+ */
+async function pushToRelay(
+  socket: WebSocket,
+  runtimeLifecycle: ReturnType<typeof capturePwaRuntimeLifecycle>,
+): Promise<void> {
+  const key = payloadKey;
+  const seal = (bytes: Uint8Array) => (key ? sealRelayPayload(key, bytes) : Promise.resolve(bytes));
+  try {
+    let message: Uint8Array;
+    const since = relayHeads;
+    if (since) {
+      const changes = await getDocChangesSince(since);
+      if (!changes.full && changes.binary.byteLength === 0) return;
+      const kind = changes.full ? RelayFrameKind.FullDoc : RelayFrameKind.Changes;
+      message = encodeRelayFrame(kind, changes.heads, await seal(changes.binary));
+      if (ws !== socket || relayHeads !== since) return;
+      relayHeads = changes.heads;
+    } else {
+      message = await seal(await getDocBinary());
+    }
+    if (socket.readyState !== WebSocket.OPEN || !runtimeLifecycle.isCurrent()) return;
+    socket.send(message);
+    console.log("[Sync] Sent %s (%d bytes)", since ? "relay frame" : "document", message.byteLength);
+    addDebugEvent("sent", undefined, message.byteLength);
+  } catch (error) {
+    console.error("[Sync] Failed to broadcast:", error);
+    addDebugEvent("error", error instanceof Error ? error.message : String(error));
+  }
+}
+
 /**
  * Disconnect from sync relay
  */
 export function disconnect(): void {
   currentUrl = null;
+  candidateUrls = [];
+  candidateIndex = 0;
+  payloadKey = null;
+  relayHeads = null;
   if (reconnectTimer) {
     clearTimeout(reconnectTimer);
     reconnectTimer = null;